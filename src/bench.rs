@@ -0,0 +1,560 @@
+use std::fs;
+use std::path::{
+  Path,
+  PathBuf
+};
+use std::sync::Arc;
+use std::time::{
+  Duration,
+  Instant
+};
+
+use anyhow::{
+  Context,
+  Result,
+  anyhow
+};
+use clap::Args;
+use reqwest::Client;
+use serde::Serialize;
+use serde_json::json;
+use tracing::{
+  Instrument,
+  info,
+  warn
+};
+use uuid::Uuid;
+
+use crate::chunk;
+use crate::config::{
+  ChunkFieldNames,
+  Config,
+  InsertQuickwitConfig,
+  InsertTarget,
+  LexicalStore,
+  VectorStore
+};
+use crate::insert::{
+  self,
+  BatchTimings,
+  ChunkRecord,
+  Inserter
+};
+
+#[derive(Debug, Args)]
+pub struct BenchArgs {
+  /// Number of chunks to push through
+  /// the pipeline
+  #[arg(long, default_value_t = 1000)]
+  pub count: usize,
+
+  /// Characters per synthetic chunk
+  /// (defaults to
+  /// chunk.target_chunk_chars)
+  #[arg(long)]
+  pub text_chars: Option<usize>,
+
+  /// Read chunks from this JSONL file
+  /// instead of generating them
+  #[arg(long)]
+  pub input: Option<PathBuf>,
+
+  /// Keep the disposable collection
+  /// and index after the run
+  #[arg(long, default_value_t = false)]
+  pub keep: bool
+}
+
+#[derive(Debug, Serialize)]
+struct StageSummary {
+  p50_ms:   f64,
+  p95_ms:   f64,
+  total_ms: f64
+}
+
+#[derive(Debug, Serialize)]
+struct BenchReport {
+  chunks:             usize,
+  batches:            usize,
+  text_chars:         usize,
+  elapsed_ms:         f64,
+  embeddings_per_sec: f64,
+  chunks_per_sec:     f64,
+  embed:              StageSummary,
+  qdrant:             StageSummary,
+  quickwit:           StageSummary
+}
+
+pub async fn run(
+  config: &Config,
+  args: &BenchArgs
+) -> Result<()> {
+  let suffix = Uuid::new_v4()
+    .simple()
+    .to_string()[..8]
+    .to_string();
+  let mut bench = config.clone();
+  bench.insert.qdrant.collection = format!(
+    "{}_bench_{suffix}",
+    config.insert.qdrant.collection
+  );
+  bench.insert.quickwit.index_id = format!(
+    "{}_bench_{suffix}",
+    config.insert.quickwit.index_id
+  );
+  bench
+    .insert
+    .embeddings
+    .cache_max_entries = 0;
+  // Cleanup only knows the one
+  // disposable collection.
+  bench.insert.qdrant.route_by = None;
+  bench.insert.vector_store =
+    VectorStore::Qdrant;
+  bench.insert.lexical_store =
+    LexicalStore::Quickwit;
+  bench.insert.targets = vec![
+    InsertTarget::Vector,
+    InsertTarget::Lexical,
+  ];
+
+  let text_chars = args
+    .text_chars
+    .unwrap_or(
+      config.chunk.target_chunk_chars
+    )
+    .max(1);
+  let records =
+    if let Some(input) = &args.input {
+      read_records(
+        input,
+        args.count,
+        &config.chunk.field_names
+      )?
+    } else {
+      synthetic_records(
+        args.count, text_chars
+      )
+    };
+  if records.is_empty() {
+    return Err(anyhow!(
+      "bench has no chunks to process"
+    ));
+  }
+
+  let client =
+    insert::build_client(&bench)?;
+  info!(
+    collection = %bench.insert.qdrant.collection,
+    index_id = %bench.insert.quickwit.index_id,
+    chunks = records.len(),
+    text_chars,
+    "bench starting"
+  );
+  insert::ensure_qdrant_collection(
+    &client,
+    &bench.insert.qdrant
+  )
+  .await?;
+  create_quickwit_index(
+    &client,
+    &bench.insert.quickwit
+  )
+  .await?;
+
+  let chunks = records.len();
+  let result = run_batches(
+    &bench, &client, records
+  )
+  .await;
+
+  if args.keep {
+    info!(
+      "bench keeping disposable \
+       targets"
+    );
+  } else {
+    cleanup(&client, &bench).await;
+  }
+
+  let (timings, embed_elapsed, elapsed) =
+    result?;
+  let report = BenchReport {
+    chunks,
+    batches: timings.len(),
+    text_chars,
+    elapsed_ms: millis(elapsed),
+    embeddings_per_sec: per_sec(
+      chunks,
+      embed_elapsed
+    ),
+    chunks_per_sec: per_sec(
+      chunks, elapsed
+    ),
+    embed: summarize(
+      timings.iter().map(|t| t.embed)
+    ),
+    qdrant: summarize(
+      timings.iter().map(|t| t.qdrant)
+    ),
+    quickwit: summarize(
+      timings
+        .iter()
+        .map(|t| t.quickwit)
+    )
+  };
+  info!(
+    chunks = report.chunks,
+    embeddings_per_sec =
+      report.embeddings_per_sec,
+    chunks_per_sec =
+      report.chunks_per_sec,
+    "bench complete"
+  );
+  println!(
+    "{}",
+    serde_json::to_string_pretty(
+      &report
+    )?
+  );
+  Ok(())
+}
+
+/// Runs `records` through the
+/// inserter, returning each batch's
+/// timings, the wall-clock time until
+/// the last batch was embedded, and
+/// the total wall-clock time.
+async fn run_batches(
+  config: &Config,
+  client: &Client,
+  records: Vec<ChunkRecord>
+) -> Result<(
+  Vec<BatchTimings>,
+  Duration,
+  Duration
+)> {
+  let batch_size =
+    config.insert.batch_size.max(1);
+  let streams = config
+    .insert
+    .max_parallel_files
+    .max(1);
+  let mut lanes: Vec<
+    Vec<Vec<ChunkRecord>>
+  > = vec![Vec::new(); streams];
+  for (idx, batch) in records
+    .chunks(batch_size)
+    .enumerate()
+  {
+    lanes[idx % streams]
+      .push(batch.to_vec());
+  }
+
+  let inserter = Arc::new(
+    Inserter::start(config, client)?
+  );
+  let started = Instant::now();
+  let mut tasks = Vec::new();
+  for (lane_idx, lane) in
+    lanes.into_iter().enumerate()
+  {
+    let inserter = inserter.clone();
+    tasks.push(tokio::spawn(
+      async move {
+        let label = PathBuf::from(
+          format!("bench-{lane_idx}")
+        );
+        let mut pending = Vec::new();
+        let mut seen = 0usize;
+        for (idx, batch) in
+          lane.into_iter().enumerate()
+        {
+          seen += batch.len();
+          pending.push(
+            inserter
+              .submit(
+                &label,
+                idx + 1,
+                seen,
+                batch
+              )
+              .await?
+          );
+        }
+        let mut timings = Vec::new();
+        for write in pending {
+          timings
+            .push(write.wait().await?);
+        }
+        Ok::<
+          Vec<BatchTimings>,
+          anyhow::Error
+        >(timings)
+      }
+      .in_current_span()
+    ));
+  }
+
+  let mut timings = Vec::new();
+  for task in tasks {
+    timings.extend(task.await??);
+  }
+  let elapsed = started.elapsed();
+  let embed_elapsed = timings
+    .iter()
+    .map(|t| {
+      t.embedded_at
+        .saturating_duration_since(
+          started
+        )
+    })
+    .max()
+    .unwrap_or_default();
+  Arc::try_unwrap(inserter)
+    .map_err(|_| {
+      anyhow!(
+        "bench inserter still in use"
+      )
+    })?
+    .finish()
+    .await?;
+  Ok((timings, embed_elapsed, elapsed))
+}
+
+fn synthetic_records(
+  count: usize,
+  text_chars: usize
+) -> Vec<ChunkRecord> {
+  const WORDS: &[&str] = &[
+    "regulation",
+    "section",
+    "federal",
+    "agency",
+    "provision",
+    "shall",
+    "applicable",
+    "requirement",
+    "program",
+    "department",
+    "authority",
+    "compliance",
+    "standard",
+    "notice",
+    "paragraph",
+    "definition",
+    "review",
+    "petition",
+    "hearing",
+    "record",
+    "statute",
+    "amendment",
+    "order",
+    "permit",
+    "inspection",
+    "report",
+    "filing",
+    "exemption",
+    "schedule",
+    "district",
+    "official",
+    "procedure"
+  ];
+  let mut state = 0x9e3779b97f4a7c15u64;
+  (0..count)
+    .map(|idx| {
+      let mut text =
+        format!("bench {idx}");
+      while text.len() < text_chars {
+        state = state
+          .wrapping_mul(
+            6364136223846793005
+          )
+          .wrapping_add(
+            1442695040888963407
+          );
+        let word = WORDS[(state >> 33)
+          as usize
+          % WORDS.len()];
+        text.push(' ');
+        text.push_str(word);
+      }
+      text.truncate(text_chars);
+      ChunkRecord {
+        id: Uuid::new_v4().to_string(),
+        text,
+        metadata: json!({
+            "bench": true,
+            "chunk_index": idx,
+        }),
+        embedding: None,
+        sparse_embedding: None
+      }
+    })
+    .collect()
+}
+
+fn read_records(
+  path: &Path,
+  count: usize,
+  names: &ChunkFieldNames
+) -> Result<Vec<ChunkRecord>> {
+  let raw = fs::read_to_string(path)
+    .with_context(|| {
+      format!("read {}", path.display())
+    })?;
+  let mut out = Vec::new();
+  for line in raw.lines() {
+    if out.len() >= count {
+      break;
+    }
+    if line.trim().is_empty() {
+      continue;
+    }
+    let mut record: ChunkRecord =
+      serde_json::from_value(
+        chunk::canonical_record(
+          serde_json::from_str(line)?,
+          names
+        )
+      )?;
+    // Fresh ids so the disposable
+    // targets never collide with reused
+    // records.
+    record.id =
+      Uuid::new_v4().to_string();
+    out.push(record);
+  }
+  Ok(out)
+}
+
+async fn create_quickwit_index(
+  client: &Client,
+  cfg: &InsertQuickwitConfig
+) -> Result<()> {
+  let mut field_mappings = vec![
+    json!({ "name": "id", "type": "text", "tokenizer": "raw", "stored": true }),
+    json!({ "name": "text", "type": "text", "tokenizer": "default", "stored": true }),
+    json!({ "name": "metadata", "type": "json", "stored": true }),
+  ];
+  for field in &cfg.lowercase_fields {
+    field_mappings.push(json!({
+        "name": format!("{field}_lc"),
+        "type": "array<text>",
+        "tokenizer": "raw",
+        "stored": true,
+    }));
+  }
+  let body = json!({
+      "version": "0.7",
+      "index_id": cfg.index_id,
+      "doc_mapping": {
+          "field_mappings": field_mappings,
+          "timestamp_field": null
+      },
+      "search_settings": {
+          "default_search_fields": ["text"]
+      }
+  });
+  let resp = client
+    .post(format!(
+      "{}/api/v1/indexes",
+      cfg.url.trim_end_matches('/')
+    ))
+    .json(&body)
+    .send()
+    .await?;
+  if !resp.status().is_success() {
+    let status = resp.status();
+    let text = resp
+      .text()
+      .await
+      .unwrap_or_default();
+    return Err(anyhow!(
+      "quickwit index create failed: \
+       {} {}",
+      status,
+      text
+    ));
+  }
+  Ok(())
+}
+
+async fn cleanup(
+  client: &Client,
+  config: &Config
+) {
+  let qdrant = &config.insert.qdrant;
+  let mut req = client.delete(format!(
+    "{}/collections/{}",
+    qdrant.url.trim_end_matches('/'),
+    qdrant.collection
+  ));
+  if let Some(key) = qdrant
+    .api_key
+    .as_ref()
+    .filter(|k| !k.is_empty())
+  {
+    req = req.header("api-key", key);
+  }
+  if let Err(err) = req.send().await {
+    warn!(error = %err, "bench qdrant cleanup failed");
+  }
+  let quickwit =
+    &config.insert.quickwit;
+  if let Err(err) = client
+    .delete(format!(
+      "{}/api/v1/indexes/{}",
+      quickwit
+        .url
+        .trim_end_matches('/'),
+      quickwit.index_id
+    ))
+    .send()
+    .await
+  {
+    warn!(error = %err, "bench quickwit cleanup failed");
+  }
+}
+
+fn summarize(
+  durations: impl Iterator<Item = Duration>
+) -> StageSummary {
+  let mut ms: Vec<f64> =
+    durations.map(millis).collect();
+  ms.sort_by(|a, b| a.total_cmp(b));
+  StageSummary {
+    p50_ms:   percentile(&ms, 0.50),
+    p95_ms:   percentile(&ms, 0.95),
+    total_ms: ms.iter().sum()
+  }
+}
+
+fn percentile(
+  sorted: &[f64],
+  p: f64
+) -> f64 {
+  if sorted.is_empty() {
+    return 0.0;
+  }
+  let rank = (p
+    * (sorted.len() - 1) as f64)
+    .round() as usize;
+  sorted[rank.min(sorted.len() - 1)]
+}
+
+fn millis(d: Duration) -> f64 {
+  d.as_secs_f64() * 1000.0
+}
+
+fn per_sec(
+  count: usize,
+  elapsed: Duration
+) -> f64 {
+  let secs = elapsed.as_secs_f64();
+  if secs == 0.0 {
+    0.0
+  } else {
+    count as f64 / secs
+  }
+}
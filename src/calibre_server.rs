@@ -0,0 +1,395 @@
+use std::fs;
+use std::future::Future;
+use std::path::{
+  Path,
+  PathBuf
+};
+
+use anyhow::{
+  Context,
+  Result,
+  anyhow
+};
+use reqwest::{
+  Client,
+  RequestBuilder,
+  Url
+};
+use serde_json::Value;
+use tokio::runtime::Handle;
+use tracing::info;
+
+use crate::config::{
+  CalibreConfig,
+  Config
+};
+
+/// Book ids and metadata requested per
+/// `/ajax/search` and `/ajax/books`
+/// call.
+const PAGE_SIZE: usize = 200;
+
+/// File next to each staged download
+/// holding the server's metadata for
+/// the book.
+pub const BOOK_METADATA: &str =
+  "calibre_book.json";
+
+/// A Calibre content server reached
+/// through its REST API, as set by
+/// `calibre.library_url`
+/// (`http://host:8080/#library_id`).
+pub struct CalibreServer {
+  client:     Client,
+  base:       String,
+  library_id: Option<String>,
+  username:   Option<String>,
+  password:   Option<String>,
+  runtime:    Handle
+}
+
+/// One format of one book on the
+/// server.
+#[derive(Debug, Clone)]
+pub struct ServerBook {
+  pub id:       u64,
+  /// Lowercased extension, e.g.
+  /// `epub`.
+  pub format:   String,
+  pub metadata: Value
+}
+
+impl CalibreServer {
+  pub fn connect(
+    calibre: &CalibreConfig
+  ) -> Result<Self> {
+    let url = calibre
+      .library_url
+      .as_deref()
+      .map(str::trim)
+      .filter(|url| !url.is_empty())
+      .ok_or_else(|| {
+        anyhow!(
+          "extract.source = \
+           \"calibre_server\" needs \
+           calibre.library_url"
+        )
+      })?;
+    let (base, library_id) = match url
+      .split_once('#')
+    {
+      | Some((base, id)) => {
+        (
+          base,
+          Some(id.to_string()).filter(
+            |id| !id.is_empty()
+          )
+        )
+      }
+      | None => (url, None)
+    };
+    let runtime = Handle::try_current()
+      .context(
+        "calibre server source needs \
+         a tokio runtime"
+      )?;
+    let server =
+      &calibre.content_server;
+    Ok(Self {
+      client: Client::new(),
+      base: base
+        .trim_end_matches('/')
+        .to_string(),
+      library_id,
+      username: server.username.clone(),
+      password: server.password.clone(),
+      runtime
+    })
+  }
+
+  /// Every book format on the server
+  /// whose extension is in
+  /// `extensions`.
+  pub fn list_books(
+    &self,
+    extensions: &[String]
+  ) -> Result<Vec<ServerBook>> {
+    let ids = self.book_ids()?;
+    let mut books = Vec::new();
+    for page in ids.chunks(PAGE_SIZE) {
+      let ids = page
+        .iter()
+        .map(u64::to_string)
+        .collect::<Vec<_>>()
+        .join(",");
+      let found = self.fetch_json(
+        self.get("/ajax/books", &[
+          ("ids", ids)
+        ])?
+      )?;
+      for id in page {
+        let Some(book) = found
+          .get(id.to_string())
+          .filter(|b| b.is_object())
+        else {
+          continue;
+        };
+        let formats = book
+          .get("formats")
+          .and_then(Value::as_array)
+          .cloned()
+          .unwrap_or_default();
+        for format in formats {
+          let Some(format) =
+            format.as_str().map(|f| {
+              f.to_ascii_lowercase()
+            })
+          else {
+            continue;
+          };
+          if extensions
+            .contains(&format)
+          {
+            books.push(ServerBook {
+              id: *id,
+              format,
+              metadata: book.clone()
+            });
+          }
+        }
+      }
+    }
+    info!(
+      ids = ids.len(),
+      formats = books.len(),
+      base = %self.base,
+      "calibre server books listed"
+    );
+    Ok(books)
+  }
+
+  fn book_ids(
+    &self
+  ) -> Result<Vec<u64>> {
+    let mut ids = Vec::new();
+    loop {
+      let page = self.fetch_json(
+        self.get("/ajax/search", &[
+          (
+            "num",
+            PAGE_SIZE.to_string()
+          ),
+          (
+            "offset",
+            ids.len().to_string()
+          ),
+          ("query", String::new())
+        ])?
+      )?;
+      let batch = page
+        .get("book_ids")
+        .and_then(Value::as_array)
+        .map(|ids| {
+          ids
+            .iter()
+            .filter_map(Value::as_u64)
+            .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+      let total = page
+        .get("total_num")
+        .and_then(Value::as_u64)
+        .unwrap_or(0)
+        as usize;
+      if batch.is_empty() {
+        break;
+      }
+      ids.extend(batch);
+      if ids.len() >= total {
+        break;
+      }
+    }
+    Ok(ids)
+  }
+
+  /// Downloads `book` to `dest`,
+  /// replacing it atomically.
+  pub fn download(
+    &self,
+    book: &ServerBook,
+    dest: &Path
+  ) -> Result<()> {
+    let url = self.book_url(book);
+    let bytes = self
+      .block_on(async {
+        let resp = self
+          .auth(self.client.get(&url))
+          .send()
+          .await?
+          .error_for_status()?;
+        resp.bytes().await
+      })
+      .with_context(|| {
+        format!("download {url}")
+      })?;
+    if let Some(parent) = dest.parent()
+    {
+      fs::create_dir_all(parent)?;
+    }
+    let tmp = dest.with_extension(
+      format!("{}.part", book.format)
+    );
+    fs::write(&tmp, &bytes)?;
+    fs::rename(&tmp, dest)?;
+    Ok(())
+  }
+
+  /// Where `book` is downloaded from.
+  pub fn book_url(
+    &self,
+    book: &ServerBook
+  ) -> String {
+    let mut url = format!(
+      "{}/get/{}/{}",
+      self.base,
+      book.format.to_ascii_uppercase(),
+      book.id
+    );
+    if let Some(library) =
+      &self.library_id
+    {
+      url.push('/');
+      url.push_str(library);
+    }
+    url
+  }
+
+  fn get(
+    &self,
+    path: &str,
+    query: &[(&str, String)]
+  ) -> Result<RequestBuilder> {
+    let mut url = Url::parse(&format!(
+      "{}{path}",
+      self.base
+    ))
+    .with_context(|| {
+      format!(
+        "invalid calibre.library_url \
+         {}",
+        self.base
+      )
+    })?;
+    {
+      let mut pairs =
+        url.query_pairs_mut();
+      for (key, value) in query {
+        pairs.append_pair(key, value);
+      }
+      if let Some(library) =
+        &self.library_id
+      {
+        pairs.append_pair(
+          "library_id",
+          library
+        );
+      }
+    }
+    Ok(self.auth(self.client.get(url)))
+  }
+
+  fn auth(
+    &self,
+    req: RequestBuilder
+  ) -> RequestBuilder {
+    match &self.username {
+      | Some(user) => {
+        req.basic_auth(
+          user,
+          self.password.as_ref()
+        )
+      }
+      | None => req
+    }
+  }
+
+  fn fetch_json(
+    &self,
+    req: RequestBuilder
+  ) -> Result<Value> {
+    self.block_on(async {
+      let resp = req.send().await?;
+      let status = resp.status();
+      if !status.is_success() {
+        let text = resp
+          .text()
+          .await
+          .unwrap_or_default();
+        return Err(anyhow!(
+          "calibre server request \
+           failed: {status} {text}"
+        ));
+      }
+      Ok(resp.json::<Value>().await?)
+    })
+  }
+
+  /// Runs `fut` to completion from
+  /// the synchronous extract code,
+  /// whether on the runtime's thread or
+  /// a worker of the extract pool.
+  fn block_on<F: Future>(
+    &self,
+    fut: F
+  ) -> F::Output {
+    tokio::task::block_in_place(|| {
+      self.runtime.block_on(fut)
+    })
+  }
+}
+
+/// Where `book` is staged for
+/// extraction:
+/// `state_dir/calibre_server/<id>/<id>.
+/// <format>`.
+pub fn staged_path(
+  config: &Config,
+  book: &ServerBook
+) -> PathBuf {
+  config
+    .paths
+    .state_dir
+    .join("calibre_server")
+    .join(book.id.to_string())
+    .join(format!(
+      "{}.{}",
+      book.id, book.format
+    ))
+}
+
+/// Writes the server metadata and
+/// download URL next to the staged
+/// path, where `extract` reads them in
+/// place of `metadata.opf`.
+pub fn write_book_metadata(
+  staged: &Path,
+  server: &CalibreServer,
+  book: &ServerBook
+) -> Result<()> {
+  let dir = staged
+    .parent()
+    .ok_or_else(|| {
+      anyhow!("missing staging parent")
+    })?;
+  fs::create_dir_all(dir)?;
+  let entry = serde_json::json!({
+      "id": book.id,
+      "source_url": server.book_url(book),
+      "book": book.metadata,
+  });
+  fs::write(
+    dir.join(BOOK_METADATA),
+    serde_json::to_vec_pretty(&entry)?
+  )?;
+  Ok(())
+}
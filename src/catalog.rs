@@ -0,0 +1,181 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use anyhow::Context;
+use serde::Serialize;
+use serde_json::Value;
+use tracing::{
+  info,
+  warn
+};
+
+use crate::config::ChunkFieldNames;
+use crate::util::walk_files;
+
+/// One document in `catalog.json`.
+#[derive(Debug, Default, Serialize)]
+pub struct CatalogEntry {
+  pub source_rel:  String,
+  #[serde(
+    skip_serializing_if = "Option::is_none"
+  )]
+  pub source_path: Option<String>,
+  pub title:       Option<String>,
+  pub authors:     Vec<String>,
+  pub language:    Option<String>,
+  pub chunk_count: usize
+}
+
+/// Aggregates chunk metadata under
+/// `chunk_root` into one entry per
+/// `source_rel`, sorted by title.
+pub fn build_catalog(
+  chunk_root: &Path,
+  names: &ChunkFieldNames
+) -> anyhow::Result<Vec<CatalogEntry>> {
+  let mut docs: BTreeMap<
+    String,
+    CatalogEntry
+  > = BTreeMap::new();
+  let walked =
+    walk_files(chunk_root, true);
+  if walked.errors > 0 {
+    warn!(
+      walk_errors = walked.errors,
+      "catalog walk skipped \
+       unreadable entries"
+    );
+  }
+  for path in &walked.files {
+    let path = path.as_path();
+    if path
+      .extension()
+      .and_then(|s| s.to_str())
+      != Some("jsonl")
+    {
+      continue;
+    }
+    // Without `include_source_path`
+    // chunks carry no `source_rel`;
+    // fall back to the chunk file
+    // itself.
+    let fallback_rel = path
+      .strip_prefix(chunk_root)
+      .unwrap_or(path)
+      .display()
+      .to_string();
+    let raw = fs::read_to_string(path)
+      .with_context(|| {
+        format!(
+          "read {}",
+          path.display()
+        )
+      })?;
+    for line in raw.lines() {
+      if line.trim().is_empty() {
+        continue;
+      }
+      let record: Value =
+        match serde_json::from_str(line)
+        {
+          | Ok(v) => v,
+          | Err(err) => {
+            warn!(path = %path.display(), error = %err, "skip unparsable chunk line");
+            continue;
+          }
+        };
+      let meta =
+        &record[&names.metadata];
+      let source_rel = meta
+        ["source_rel"]
+        .as_str()
+        .map(str::to_string)
+        .unwrap_or_else(|| {
+          fallback_rel.clone()
+        });
+      let doc = docs
+        .entry(source_rel.clone())
+        .or_insert_with(|| {
+          CatalogEntry {
+            source_rel,
+            ..Default::default()
+          }
+        });
+      doc.chunk_count += 1;
+      if doc.source_path.is_none() {
+        doc.source_path = meta
+          ["source_path"]
+          .as_str()
+          .map(str::to_string);
+      }
+      if doc.title.is_none() {
+        doc.title = meta["title"]
+          .as_str()
+          .map(str::to_string);
+      }
+      if doc.language.is_none() {
+        doc.language = meta["language"]
+          .as_str()
+          .map(str::to_string);
+      }
+      if doc.authors.is_empty()
+        && let Some(authors) =
+          meta["authors"].as_array()
+      {
+        doc.authors = authors
+          .iter()
+          .filter_map(Value::as_str)
+          .map(str::to_string)
+          .collect();
+      }
+    }
+  }
+
+  let mut entries: Vec<CatalogEntry> =
+    docs.into_values().collect();
+  entries.sort_by(|a, b| {
+    a.title
+      .as_deref()
+      .unwrap_or("")
+      .cmp(
+        b.title
+          .as_deref()
+          .unwrap_or("")
+      )
+      .then_with(|| {
+        a.source_rel.cmp(&b.source_rel)
+      })
+  });
+  Ok(entries)
+}
+
+/// Writes the catalog for `chunk_root`
+/// to `out` and returns the number of
+/// documents.
+pub fn write_catalog(
+  chunk_root: &Path,
+  names: &ChunkFieldNames,
+  out: &Path
+) -> anyhow::Result<usize> {
+  let entries =
+    build_catalog(chunk_root, names)?;
+  if let Some(parent) = out.parent() {
+    fs::create_dir_all(parent)?;
+  }
+  fs::write(
+    out,
+    serde_json::to_vec_pretty(
+      &entries
+    )?
+  )
+  .with_context(|| {
+    format!("write {}", out.display())
+  })?;
+  info!(
+    documents = entries.len(),
+    path = %out.display(),
+    "catalog written"
+  );
+  Ok(entries.len())
+}
@@ -1,9 +1,30 @@
+use std::collections::BTreeMap;
 use std::fs;
 use std::io::Write;
-use std::path::Path;
+use std::path::{
+  Path,
+  PathBuf
+};
+use std::sync::atomic::{
+  AtomicUsize,
+  Ordering
+};
+use std::sync::{
+  Mutex,
+  MutexGuard
+};
 
-use anyhow::Context;
-use serde::Serialize;
+use anyhow::{
+  Context,
+  anyhow
+};
+use clap::Args;
+use rayon::prelude::*;
+use regex::Regex;
+use serde::{
+  Deserialize,
+  Serialize
+};
 use serde_json::{
   Map,
   Value,
@@ -16,107 +37,1508 @@ use tracing::{
 };
 use unicode_normalization::UnicodeNormalization;
 use uuid::Uuid;
-use walkdir::WalkDir;
 
 use crate::config::{
   ChunkConfig,
-  Config
+  ChunkFieldNames,
+  ChunkIdMode,
+  ChunkOutputFormat,
+  ChunkStrategy,
+  Config,
+  MissingMetadataPolicy,
+  NormalizeOrder,
+  OverlapUnit,
+  ParagraphMode,
+  SplitterMode,
+  UnicodeForm
+};
+use crate::parquet::{
+  self,
+  Column,
+  Values
+};
+use crate::sections::{
+  self,
+  Section,
+  SectionedDoc
 };
-use crate::util::replace_extension;
+use crate::tokenizer::{
+  self,
+  CharCount,
+  Tokenizer,
+  Utf8Len
+};
+use crate::util::{
+  PhaseTimers,
+  WalkedFiles,
+  replace_extension,
+  walk_files
+};
+use crate::{
+  extract,
+  language
+};
+
+/// Per-phase time for the current chunk
+/// run, logged with the summary.
+pub(crate) static TIMERS: PhaseTimers =
+  PhaseTimers::new();
 
-#[derive(Debug, Serialize)]
-struct ChunkRecord {
-  id:       String,
-  text:     String,
-  metadata: Value
+#[derive(Debug, Clone, Serialize)]
+pub struct ChunkRecord {
+  pub id:       String,
+  pub text:     String,
+  pub metadata: Value
 }
 
-pub fn run(
+/// Outcome of chunking one file.
+#[derive(Debug, Default, Clone)]
+pub struct ChunkedFile {
+  pub chunks:           usize,
+  /// The file had no usable metadata
+  /// while `chunk.require_metadata`
+  /// was set.
+  pub missing_metadata: bool,
+  /// Length in characters of each
+  /// chunk written.
+  pub chunk_chars:      Vec<usize>
+}
+
+#[derive(Debug, Default, Args)]
+pub struct ChunkArgs {
+  /// Write the normalized text as
+  /// `.txt` instead of chunking it
+  #[arg(long)]
+  pub normalize_only: bool,
+
+  /// Chunk only the files the last
+  /// extract run wrote, as listed in
+  /// its manifest, instead of walking
+  /// extract_root
+  #[arg(long)]
+  pub only_new: bool,
+
+  /// Ignore chunk.skip_existing and
+  /// chunk.skip_unchanged and chunk
+  /// every file again
+  #[arg(long)]
+  pub force: bool
+}
+
+const STATE_FILE: &str =
+  "chunk_state.json";
+
+/// Written under `chunk_root` with
+/// `chunk.write_stats`.
+const STATS_FILE: &str = "stats.json";
+
+/// Text and sidecar hashes per
+/// extracted file, used by
+/// `chunk.skip_unchanged`.
+#[derive(
+  Debug, Default, Serialize, Deserialize,
+)]
+struct ChunkState {
+  entries: BTreeMap<String, StateEntry>
+}
+
+#[derive(
+  Debug, Clone, Serialize, Deserialize,
+)]
+struct StateEntry {
+  text_hash:     String,
+  metadata_hash: String,
+  config_hash:   String,
+  /// Sidecar keys copied into the
+  /// chunks, replaced on a metadata
+  /// refresh.
+  metadata_keys: Vec<String>
+}
+
+impl ChunkState {
+  fn load(path: &Path) -> Self {
+    let Ok(raw) = fs::read(path) else {
+      return Self::default();
+    };
+    serde_json::from_slice(&raw)
+      .unwrap_or_else(|err| {
+        warn!(path = %path.display(), error = %err, "ignoring unreadable chunk state");
+        Self::default()
+      })
+  }
+
+  fn save(
+    &self,
+    path: &Path
+  ) -> anyhow::Result<()> {
+    if let Some(parent) = path.parent()
+    {
+      fs::create_dir_all(parent)?;
+    }
+    fs::write(
+      path,
+      serde_json::to_vec_pretty(self)?
+    )?;
+    Ok(())
+  }
+}
+
+/// Chunk lengths and per-file counts
+/// gathered for `chunk.write_stats`.
+#[derive(Debug, Default)]
+struct ChunkStats {
+  chunk_chars: Vec<usize>,
+  per_file:    BTreeMap<String, usize>
+}
+
+/// `stats.json`: the shape of the
+/// chunks written by one run.
+#[derive(Serialize)]
+struct StatsReport {
+  files:                     usize,
+  chunks:                    usize,
+  min_chars:                 usize,
+  median_chars:              usize,
+  p95_chars:                 usize,
+  max_chars:                 usize,
+  /// Chunks shorter than
+  /// `min_paragraph_chars`.
+  below_min_paragraph_chars: usize,
+  per_file: BTreeMap<String, usize>
+}
+
+impl ChunkStats {
+  fn add(
+    &mut self,
+    rel: String,
+    chunk_chars: &[usize]
+  ) {
+    self
+      .chunk_chars
+      .extend_from_slice(chunk_chars);
+    self
+      .per_file
+      .insert(rel, chunk_chars.len());
+  }
+
+  fn write(
+    mut self,
+    path: &Path,
+    cfg: &ChunkConfig
+  ) -> anyhow::Result<()> {
+    self.chunk_chars.sort_unstable();
+    let sorted = &self.chunk_chars;
+    // Nearest rank, like `bench`.
+    let at = |p: f64| {
+      if sorted.is_empty() {
+        return 0;
+      }
+      let rank =
+        (p * (sorted.len() - 1) as f64)
+          .round() as usize;
+      sorted[rank.min(sorted.len() - 1)]
+    };
+    let report = StatsReport {
+      files:                     self
+        .per_file
+        .len(),
+      chunks:                    sorted
+        .len(),
+      min_chars:                 at(
+        0.0
+      ),
+      median_chars:              at(
+        0.5
+      ),
+      p95_chars:                 at(
+        0.95
+      ),
+      max_chars:                 at(
+        1.0
+      ),
+      below_min_paragraph_chars: sorted
+        .partition_point(|&n| {
+          n < cfg.min_paragraph_chars
+        }),
+      per_file:                  self
+        .per_file
+    };
+    if let Some(parent) = path.parent()
+    {
+      fs::create_dir_all(parent)?;
+    }
+    fs::write(
+      path,
+      serde_json::to_vec_pretty(
+        &report
+      )?
+    )
+    .with_context(|| {
+      format!(
+        "write {}",
+        path.display()
+      )
+    })
+  }
+}
+
+/// What `skip_unchanged` did with one
+/// file.
+enum ChunkAction {
+  Chunked(ChunkedFile),
+  Unchanged,
+  MetadataRefreshed
+}
+
+/// Hash of the settings that shape
+/// chunk output; any change forces a
+/// full re-chunk under
+/// `skip_unchanged`. Scheduling and
+/// skip settings (`jobs`,
+/// `skip_existing`, `write_stats`, …)
+/// are left out.
+fn chunk_config_hash(
   config: &Config
-) -> anyhow::Result<()> {
-  let mut total_files = 0usize;
-  let mut total_chunks = 0usize;
-  for entry in WalkDir::new(
-    &config.paths.extract_root
-  )
-  .into_iter()
-  .filter_map(|e| e.ok())
-  .filter(|e| e.file_type().is_file())
+) -> String {
+  let chunk = &config.chunk;
+  let fields: [(&str, String); 32] = [
+    (
+      "extract_root",
+      config
+        .paths
+        .extract_root
+        .display()
+        .to_string()
+    ),
+    (
+      "unicode_form",
+      format!(
+        "{:?}",
+        chunk.unicode_form
+      )
+    ),
+    (
+      "normalize_order",
+      format!(
+        "{:?}",
+        chunk.normalize_order
+      )
+    ),
+    (
+      "collapse_whitespace",
+      chunk
+        .collapse_whitespace
+        .to_string()
+    ),
+    (
+      "strip_headers",
+      chunk.strip_headers.to_string()
+    ),
+    (
+      "heading_context",
+      chunk.heading_context.to_string()
+    ),
+    (
+      "min_paragraph_chars",
+      chunk
+        .min_paragraph_chars
+        .to_string()
+    ),
+    (
+      "max_paragraph_chars",
+      chunk
+        .max_paragraph_chars
+        .to_string()
+    ),
+    (
+      "size_unit",
+      format!("{:?}", chunk.size_unit)
+    ),
+    (
+      "target_chunk_chars",
+      chunk
+        .target_chunk_chars
+        .to_string()
+    ),
+    (
+      "max_chunk_chars",
+      chunk.max_chunk_chars.to_string()
+    ),
+    (
+      "min_chunk_chars",
+      chunk.min_chunk_chars.to_string()
+    ),
+    (
+      "chunk_overlap_chars",
+      chunk
+        .chunk_overlap_chars
+        .to_string()
+    ),
+    (
+      "overlap_unit",
+      format!(
+        "{:?}",
+        chunk.overlap_unit
+      )
+    ),
+    (
+      "chunk_overlap_sentences",
+      chunk
+        .chunk_overlap_sentences
+        .to_string()
+    ),
+    (
+      "join_separator",
+      chunk.join_separator.clone()
+    ),
+    (
+      "splitter",
+      format!("{:?}", chunk.splitter)
+    ),
+    (
+      "paragraph_mode",
+      format!(
+        "{:?}",
+        chunk.paragraph_mode
+      )
+    ),
+    (
+      "strategy",
+      format!("{:?}", chunk.strategy)
+    ),
+    (
+      "split_on_formfeed",
+      chunk
+        .split_on_formfeed
+        .to_string()
+    ),
+    (
+      "preserve_blocks",
+      chunk.preserve_blocks.to_string()
+    ),
+    (
+      "strip_boilerplate",
+      chunk
+        .strip_boilerplate
+        .to_string()
+    ),
+    (
+      "boilerplate_start",
+      format!(
+        "{:?}",
+        chunk.boilerplate_start
+      )
+    ),
+    (
+      "boilerplate_end",
+      format!(
+        "{:?}",
+        chunk.boilerplate_end
+      )
+    ),
+    (
+      "detect_chunk_language",
+      chunk
+        .detect_chunk_language
+        .to_string()
+    ),
+    (
+      "detect_language",
+      chunk.detect_language.to_string()
+    ),
+    (
+      "detect_language_threshold",
+      chunk
+        .detect_language_threshold
+        .to_string()
+    ),
+    (
+      "id_mode",
+      format!("{:?}", chunk.id_mode)
+    ),
+    (
+      "output_format",
+      format!(
+        "{:?}",
+        chunk.output_format
+      )
+    ),
+    (
+      "skip_metadata",
+      chunk.skip_metadata.to_string()
+    ),
+    (
+      "field_names",
+      format!(
+        "{:?}",
+        chunk.field_names
+      )
+    ),
+    (
+      "metadata",
+      format!("{:?}", chunk.metadata)
+    )
+  ];
+  let mut hasher =
+    blake3::Hasher::new();
+  for (name, value) in fields {
+    hasher.update(name.as_bytes());
+    hasher.update(b"=");
+    hasher.update(value.as_bytes());
+    hasher.update(b"\n");
+  }
+  hasher.finalize().to_hex().to_string()
+}
+
+fn hash_str(raw: &str) -> String {
+  blake3::hash(raw.as_bytes())
+    .to_hex()
+    .to_string()
+}
+
+/// Chunks `path` unless neither its
+/// text nor its included metadata
+/// changed; a metadata-only change
+/// rewrites the existing JSONL with
+/// the new sidecar values, keeping
+/// ids and text.
+fn chunk_file_with_state(
+  raw: &str,
+  file_meta: &Value,
+  path: &Path,
+  config: &Config,
+  state: &Mutex<ChunkState>,
+  config_hash: &str,
+  force: bool
+) -> anyhow::Result<ChunkAction> {
+  let (rel, out_path) =
+    output_path(path, config);
+  let key = rel.display().to_string();
+  let included = included_metadata(
+    file_meta,
+    &config.chunk
+  );
+  let text_hash = hash_str(raw);
+  let metadata_hash = hash_str(
+    &serde_json::to_string(&included)?
+  );
+
+  let prev = if force {
+    None
+  } else {
+    lock_state(state)?
+      .entries
+      .get(&key)
+      .cloned()
+  };
+  if let Some(prev) = prev
+    && prev.text_hash == text_hash
+    && prev.config_hash == config_hash
+    && out_path.exists()
   {
-    let path = entry.path();
-    if path
-      .extension()
-      .and_then(|s| s.to_str())
-      != Some("txt")
+    if prev.metadata_hash
+      == metadata_hash
     {
-      continue;
+      return Ok(ChunkAction::Unchanged);
+    }
+    // An emptied sidecar goes through
+    // the missing-metadata policy, and
+    // Parquet output is rewritten
+    // whole.
+    if !(requires_metadata(
+      &config.chunk
+    ) && included.is_empty())
+      && config.chunk.output_format
+        == ChunkOutputFormat::Jsonl
+    {
+      TIMERS.time("write", || {
+        refresh_metadata(
+          &out_path,
+          &config
+            .chunk
+            .field_names
+            .metadata,
+          &prev.metadata_keys,
+          &included
+        )
+      })?;
+      info!(path = %path.display(), "chunk metadata refreshed");
+      lock_state(state)?
+        .entries
+        .insert(key, StateEntry {
+          metadata_hash,
+          metadata_keys: included
+            .keys()
+            .cloned()
+            .collect(),
+          ..prev
+        });
+      return Ok(
+        ChunkAction::MetadataRefreshed
+      );
     }
-    total_files += 1;
-    let chunks =
-      chunk_file(path, config)?;
-    total_chunks += chunks;
   }
-  info!(
-    total_files,
-    total_chunks, "chunk complete"
+
+  let outcome = chunk_and_write(
+    raw, file_meta, path, config
+  )?;
+  lock_state(state)?.entries.insert(
+    key,
+    StateEntry {
+      text_hash,
+      metadata_hash,
+      config_hash: config_hash
+        .to_string(),
+      metadata_keys: included
+        .keys()
+        .cloned()
+        .collect()
+    }
   );
+  Ok(ChunkAction::Chunked(outcome))
+}
+
+/// Whether `path`'s JSONL was written
+/// after both the text and its sidecar
+/// last changed.
+fn output_is_fresh(
+  path: &Path,
+  config: &Config
+) -> bool {
+  let mtime = |p: &Path| {
+    fs::metadata(p)
+      .and_then(|m| m.modified())
+      .ok()
+  };
+  let (_, out_path) =
+    output_path(path, config);
+  let Some(written) = mtime(&out_path)
+  else {
+    return false;
+  };
+  mtime(path)
+    .is_some_and(|t| written > t)
+    && mtime(&sections::sidecar_path(
+      path
+    ))
+    .is_none_or(|t| written > t)
+}
+
+fn lock_state(
+  state: &Mutex<ChunkState>
+) -> anyhow::Result<
+  MutexGuard<'_, ChunkState>
+> {
+  state.lock().map_err(|_| {
+    anyhow!("chunk state lock poisoned")
+  })
+}
+
+/// Swaps the sidecar keys in every
+/// record of `out_path`: `old_keys`
+/// are dropped and `metadata` merged
+/// in. Ids, text, and chunk-level keys
+/// are left as they were.
+fn refresh_metadata(
+  out_path: &Path,
+  metadata_field: &str,
+  old_keys: &[String],
+  metadata: &Map<String, Value>
+) -> anyhow::Result<()> {
+  let raw =
+    fs::read_to_string(out_path)
+      .with_context(|| {
+        format!(
+          "read {}",
+          out_path.display()
+        )
+      })?;
+  let mut out = String::new();
+  for line in raw.lines() {
+    if line.trim().is_empty() {
+      continue;
+    }
+    let mut record: Value =
+      serde_json::from_str(line)
+        .with_context(|| {
+          format!(
+            "parse {}",
+            out_path.display()
+          )
+        })?;
+    if let Some(meta) = record
+      .get_mut(metadata_field)
+      .and_then(Value::as_object_mut)
+    {
+      for key in old_keys {
+        meta.remove(key);
+      }
+      meta.extend(metadata.iter().map(
+        |(k, v)| (k.clone(), v.clone())
+      ));
+    }
+    out.push_str(
+      &serde_json::to_string(&record)?
+    );
+    out.push('\n');
+  }
+  let tmp =
+    replace_extension(out_path, "tmp");
+  fs::write(&tmp, out)?;
+  fs::rename(&tmp, out_path)?;
   Ok(())
 }
 
-fn chunk_file(
+pub fn run(
+  config: &Config,
+  args: &ChunkArgs
+) -> anyhow::Result<()> {
+  TIMERS.reset();
+  let walked = if args.only_new {
+    let files =
+      extract::manifest_outputs(
+        config
+      )?;
+    info!(
+      files = files.len(),
+      "chunking files from the \
+       extract manifest"
+    );
+    WalkedFiles {
+      files,
+      errors: 0
+    }
+  } else {
+    TIMERS.time("walk", || {
+      walk_files(
+        &config.paths.extract_root,
+        config.paths.log_walk_errors
+      )
+    })
+  };
+  let inputs = walked
+    .files
+    .iter()
+    .filter(|path| {
+      is_chunk_input(path, config)
+    })
+    .filter(|path| {
+      if args.only_new && !path.is_file()
+      {
+        warn!(path = %path.display(), "manifest output missing");
+        return false;
+      }
+      true
+    })
+    .collect::<Vec<_>>();
+  if args.normalize_only {
+    worker_pool(config)?.install(
+      || {
+        inputs.par_iter().try_for_each(
+          |path| {
+            TIMERS
+              .time("normalize", || {
+                normalize_file(
+                  path, config
+                )
+              })
+              .map(|_| ())
+          }
+        )
+      }
+    )?;
+    info!(
+      total_files = inputs.len(),
+      walk_errors = walked.errors,
+      timings = %TIMERS,
+      "normalize complete"
+    );
+    return Ok(());
+  }
+  let run =
+    ChunkRun::new(config, args.force)?;
+  run.pool().install(|| {
+    inputs.par_iter().try_for_each(
+      |path| run.chunk_path(path)
+    )
+  })?;
+  run.finish(walked.errors)
+}
+
+fn worker_pool(
+  config: &Config
+) -> anyhow::Result<rayon::ThreadPool> {
+  rayon::ThreadPoolBuilder::new()
+    .num_threads(config.chunk.jobs)
+    .build()
+    .context("build chunk worker pool")
+}
+
+/// One chunk run's state, worker pool,
+/// counters, and statistics, shared by
+/// `run` and the in-memory pipeline so
+/// both apply `skip_existing`,
+/// `skip_unchanged`, `jobs`, and
+/// `write_stats` alike.
+pub struct ChunkRun<'a> {
+  config:             &'a Config,
+  force:              bool,
+  pool:               rayon::ThreadPool,
+  state_path:         PathBuf,
+  state: Option<Mutex<ChunkState>>,
+  config_hash:        String,
+  total_files:        AtomicUsize,
+  total_chunks:       AtomicUsize,
+  missing_metadata:   AtomicUsize,
+  skipped_unchanged:  AtomicUsize,
+  skipped_existing:   AtomicUsize,
+  metadata_refreshed: AtomicUsize,
+  stats: Option<Mutex<ChunkStats>>
+}
+
+impl<'a> ChunkRun<'a> {
+  /// Loads the chunk state; `force`
+  /// ignores `skip_existing` and
+  /// `skip_unchanged`.
+  pub fn new(
+    config: &'a Config,
+    force: bool
+  ) -> anyhow::Result<Self> {
+    let state_path = config
+      .paths
+      .state_dir
+      .join(STATE_FILE);
+    let state = config
+      .chunk
+      .skip_unchanged
+      .then(|| {
+        Mutex::new(ChunkState::load(
+          &state_path
+        ))
+      });
+    Ok(Self {
+      config,
+      force,
+      pool: worker_pool(config)?,
+      state_path,
+      state,
+      config_hash: chunk_config_hash(
+        config
+      ),
+      total_files: AtomicUsize::new(0),
+      total_chunks: AtomicUsize::new(0),
+      missing_metadata:
+        AtomicUsize::new(0),
+      skipped_unchanged:
+        AtomicUsize::new(0),
+      skipped_existing:
+        AtomicUsize::new(0),
+      metadata_refreshed:
+        AtomicUsize::new(0),
+      stats: config
+        .chunk
+        .write_stats
+        .then(|| {
+          Mutex::new(
+            ChunkStats::default()
+          )
+        })
+    })
+  }
+
+  /// The `chunk.jobs` workers to run
+  /// the per-file calls on.
+  pub fn pool(
+    &self
+  ) -> &rayon::ThreadPool {
+    &self.pool
+  }
+
+  /// Chunks the extracted file at
+  /// `path`, read from disk.
+  pub fn chunk_path(
+    &self,
+    path: &Path
+  ) -> anyhow::Result<()> {
+    self
+      .total_files
+      .fetch_add(1, Ordering::Relaxed);
+    if self.config.chunk.skip_existing
+      && !self.force
+      && output_is_fresh(
+        path,
+        self.config
+      )
+    {
+      self.skipped_existing.fetch_add(
+        1,
+        Ordering::Relaxed
+      );
+      return Ok(());
+    }
+    let (raw, file_meta) = TIMERS
+      .time("read", || {
+        read_source(
+          path,
+          &self.config.chunk
+        )
+      })?;
+    self.chunk(&raw, &file_meta, path)
+  }
+
+  /// Chunks `raw`, the text just
+  /// extracted for `path`, without
+  /// reading it back. `skip_existing`
+  /// does not apply: the text is new.
+  pub fn chunk_text(
+    &self,
+    raw: &str,
+    file_meta: &Value,
+    path: &Path
+  ) -> anyhow::Result<()> {
+    self
+      .total_files
+      .fetch_add(1, Ordering::Relaxed);
+    self.chunk(raw, file_meta, path)
+  }
+
+  fn chunk(
+    &self,
+    raw: &str,
+    file_meta: &Value,
+    path: &Path
+  ) -> anyhow::Result<()> {
+    let outcome = match &self.state {
+      | Some(state) => {
+        match chunk_file_with_state(
+          raw,
+          file_meta,
+          path,
+          self.config,
+          state,
+          &self.config_hash,
+          self.force
+        )? {
+          | ChunkAction::Chunked(
+            outcome
+          ) => outcome,
+          | ChunkAction::Unchanged => {
+            self
+              .skipped_unchanged
+              .fetch_add(
+                1,
+                Ordering::Relaxed
+              );
+            return Ok(());
+          }
+          | ChunkAction::MetadataRefreshed => {
+            self
+              .metadata_refreshed
+              .fetch_add(
+                1,
+                Ordering::Relaxed
+              );
+            return Ok(());
+          }
+        }
+      }
+      | None => {
+        chunk_and_write(
+          raw,
+          file_meta,
+          path,
+          self.config
+        )?
+      }
+    };
+    self.total_chunks.fetch_add(
+      outcome.chunks,
+      Ordering::Relaxed
+    );
+    if outcome.missing_metadata {
+      self.missing_metadata.fetch_add(
+        1,
+        Ordering::Relaxed
+      );
+    }
+    if let Some(stats) = &self.stats {
+      let (rel, _) =
+        output_path(path, self.config);
+      stats
+        .lock()
+        .map_err(|_| {
+          anyhow!(
+            "chunk stats lock poisoned"
+          )
+        })?
+        .add(
+          rel.display().to_string(),
+          &outcome.chunk_chars
+        );
+    }
+    Ok(())
+  }
+
+  /// Saves the state and statistics and
+  /// logs the run summary.
+  pub fn finish(
+    self,
+    walk_errors: usize
+  ) -> anyhow::Result<()> {
+    if let Some(state) = self.state {
+      state
+        .into_inner()
+        .map_err(|_| {
+          anyhow!(
+            "chunk state lock poisoned"
+          )
+        })?
+        .save(&self.state_path)?;
+    }
+    if let Some(stats) = self.stats {
+      stats
+        .into_inner()
+        .map_err(|_| {
+          anyhow!(
+            "chunk stats lock poisoned"
+          )
+        })?
+        .write(
+          &self
+            .config
+            .paths
+            .chunk_root
+            .join(STATS_FILE),
+          &self.config.chunk
+        )?;
+    }
+    info!(
+      total_files =
+        self.total_files.into_inner(),
+      total_chunks =
+        self.total_chunks.into_inner(),
+      missing_metadata = self
+        .missing_metadata
+        .into_inner(),
+      skipped_unchanged = self
+        .skipped_unchanged
+        .into_inner(),
+      skipped_existing = self
+        .skipped_existing
+        .into_inner(),
+      metadata_refreshed = self
+        .metadata_refreshed
+        .into_inner(),
+      walk_errors,
+      timings = %TIMERS,
+      "chunk complete"
+    );
+    Ok(())
+  }
+}
+
+/// Writes the normalized text of `path`
+/// to `chunk.normalized_root` (default
+/// `chunk_root`) under the same
+/// relative path, skipping paragraph
+/// splitting and JSONL.
+pub fn normalize_file(
   path: &Path,
   config: &Config
-) -> anyhow::Result<usize> {
+) -> anyhow::Result<PathBuf> {
+  let raw = fs::read_to_string(path)
+    .with_context(|| {
+      format!("read {}", path.display())
+    })?;
   let rel = path
     .strip_prefix(
       &config.paths.extract_root
     )
     .unwrap_or(path);
-  let out_path = replace_extension(
-    &config.paths.chunk_root.join(rel),
-    "jsonl"
-  );
+  let root = config
+    .chunk
+    .normalized_root
+    .as_ref()
+    .unwrap_or(
+      &config.paths.chunk_root
+    );
+  // Sectioned input keeps its
+  // structure with each section's text
+  // normalized.
+  let (out_path, normalized) =
+    if sections::is_sectioned(path) {
+      let mut doc: SectionedDoc =
+        serde_json::from_str(&raw)
+          .with_context(|| {
+            format!(
+              "parse {}",
+              path.display()
+            )
+          })?;
+      for section in &mut doc.sections {
+        section.text = normalize_text(
+          &section.text,
+          &config.chunk
+        );
+      }
+      (
+        root.join(rel),
+        serde_json::to_string_pretty(
+          &doc
+        )?
+      )
+    } else {
+      (
+        replace_extension(
+          &root.join(rel),
+          "txt"
+        ),
+        normalize_text(
+          &raw,
+          &config.chunk
+        )
+      )
+    };
   if let Some(parent) =
     out_path.parent()
   {
     fs::create_dir_all(parent)?;
   }
-
-  let raw = fs::read_to_string(path)
+  fs::write(&out_path, normalized)
     .with_context(|| {
-      format!("read {}", path.display())
+      format!(
+        "write {}",
+        out_path.display()
+      )
     })?;
-  let cleaned =
-    normalize_text(&raw, &config.chunk);
-  if cleaned.trim().is_empty() {
-    warn!(path = %path.display(), "empty text after normalization");
-    return Ok(0);
+  debug!(path = %path.display(), out = %out_path.display(), "normalized file");
+  Ok(out_path)
+}
+
+/// Extracted text (`.txt` or
+/// `extract.output_extension`) and
+/// sectioned JSON.
+pub fn is_chunk_input(
+  path: &Path,
+  config: &Config
+) -> bool {
+  let ext = path
+    .extension()
+    .and_then(|s| s.to_str());
+  ext == Some("txt")
+    || ext
+      == Some(
+        config
+          .extract
+          .output_extension
+          .as_str()
+      )
+    || sections::is_sectioned(path)
+}
+
+/// Path of `path` relative to
+/// `paths.extract_root`, and the JSONL
+/// or Parquet file its chunks are
+/// written to.
+pub fn output_path(
+  path: &Path,
+  config: &Config
+) -> (PathBuf, PathBuf) {
+  let rel = path
+    .strip_prefix(
+      &config.paths.extract_root
+    )
+    .unwrap_or(path)
+    .to_path_buf();
+  let extension =
+    match config.chunk.output_format {
+      | ChunkOutputFormat::Jsonl => {
+        "jsonl"
+      }
+      | ChunkOutputFormat::Parquet => {
+        "parquet"
+      }
+    };
+  let out_path = replace_extension(
+    &config.paths.chunk_root.join(&rel),
+    extension
+  );
+  (rel, out_path)
+}
+
+pub fn chunk_file(
+  path: &Path,
+  config: &Config
+) -> anyhow::Result<ChunkedFile> {
+  let (raw, file_meta) = TIMERS
+    .time("read", || {
+      read_source(path, &config.chunk)
+    })?;
+  chunk_and_write(
+    &raw, &file_meta, path, config
+  )
+}
+
+/// Applies the missing-metadata policy,
+/// chunks `raw`, and writes the JSONL
+/// output for `path`.
+pub fn chunk_and_write(
+  raw: &str,
+  file_meta: &Value,
+  path: &Path,
+  config: &Config
+) -> anyhow::Result<ChunkedFile> {
+  let missing_metadata =
+    requires_metadata(&config.chunk)
+      && file_meta
+        .as_object()
+        .is_none_or(Map::is_empty);
+  if missing_metadata {
+    match config.chunk.on_missing_metadata
+    {
+      | MissingMetadataPolicy::Warn => {
+        warn!(path = %path.display(), "missing metadata sidecar");
+      }
+      | MissingMetadataPolicy::Skip => {
+        warn!(path = %path.display(), "missing metadata sidecar, skipping");
+        return Ok(ChunkedFile {
+          chunks: 0,
+          missing_metadata,
+          chunk_chars: Vec::new()
+        });
+      }
+      | MissingMetadataPolicy::Error => {
+        return Err(anyhow!(
+          "missing metadata sidecar for \
+           {}",
+          path.display()
+        ));
+      }
+    }
   }
 
-  let paragraphs = split_paragraphs(
-    &cleaned,
-    &config.chunk
+  let records =
+    TIMERS.time("chunk", || {
+      chunk_text(
+        raw, file_meta, path, config
+      )
+    });
+  let (_, out_path) =
+    output_path(path, config);
+  TIMERS.time("write", || {
+    match config.chunk.output_format {
+      | ChunkOutputFormat::Jsonl => {
+        write_chunks(
+          &out_path,
+          &records,
+          &config.chunk.field_names
+        )
+      }
+      | ChunkOutputFormat::Parquet => {
+        write_chunks_parquet(
+          &out_path,
+          &records,
+          &config.chunk.field_names
+        )
+      }
+    }
+  })?;
+  Ok(ChunkedFile {
+    chunks: records.len(),
+    missing_metadata,
+    chunk_chars: records
+      .iter()
+      .map(|r| r.text.chars().count())
+      .collect()
+  })
+}
+
+/// Chunks already-extracted text.
+/// `path` is the extracted file the
+/// text belongs to (it need not exist
+/// on disk) and `file_meta` its sidecar
+/// metadata.
+pub fn chunk_text(
+  raw: &str,
+  file_meta: &Value,
+  path: &Path,
+  config: &Config
+) -> Vec<ChunkRecord> {
+  let (rel, _) =
+    output_path(path, config);
+  let sections =
+    if sections::is_sectioned(path) {
+      match serde_json::from_str::<
+        SectionedDoc
+      >(raw)
+      {
+        | Ok(doc) => doc.sections,
+        | Err(err) => {
+          warn!(path = %path.display(), error = %err, "unreadable sectioned json");
+          return Vec::new();
+        }
+      }
+    } else {
+      vec![Section {
+        heading: None,
+        level:   0,
+        text:    raw.to_string()
+      }]
+    };
+
+  // Sections are chunked on their own
+  // so no chunk spans a heading;
+  // paragraph indices stay
+  // document-wide.
+  let forced = forced_script(
+    config.chunk.splitter,
+    file_meta
   );
-  let chunks = build_chunks(
-    &paragraphs,
-    &config.chunk
+  let mut chunks = Vec::new();
+  let mut para_offset = 0usize;
+  let mut headings = Vec::new();
+  // Page of each paragraph, from the
+  // page markers or form feeds before
+  // it, under `include_pages`.
+  let mut para_pages = Vec::new();
+  let mut page = None;
+  let mut source = ChunkSource::new(
+    &config.chunk.join_separator
   );
+  for section in &sections {
+    if config.chunk.heading_context
+      && let Some(heading) =
+        &section.heading
+    {
+      let level =
+        usize::from(section.level);
+      headings.retain(|(open, _)| {
+        *open < level
+      });
+      headings
+        .push((level, heading.clone()));
+    }
+    let body =
+      if config.chunk.strip_boilerplate
+      {
+        strip_boilerplate(
+          &section.text,
+          &config.chunk
+        )
+      } else {
+        &section.text
+      };
+    let mut pieces = Vec::new();
+    for (page, text) in split_pages(
+      body,
+      &mut page,
+      &config.chunk
+    ) {
+      let segments =
+        if config.chunk.preserve_blocks
+        {
+          split_blocks(text)
+        } else {
+          vec![(BlockType::Prose, text)]
+        };
+      for (block_type, text) in segments
+      {
+        // Blocks keep their line breaks
+        // and indentation, whole.
+        if block_type
+          != BlockType::Prose
+        {
+          let text = unicode_normalize(
+            text,
+            config.chunk.unicode_form
+          );
+          if !text.trim().is_empty() {
+            pieces.push(Piece::Block(
+              block_type, text, page
+            ));
+          }
+          continue;
+        }
+        let cleaned = normalize_text(
+          text,
+          &config.chunk
+        );
+        let paragraphs =
+          split_paragraphs(
+            &cleaned,
+            &config.chunk
+          )
+          .into_iter()
+          .map(|p| (p, page));
+        // Prose runs on across page
+        // breaks, so chunks can too.
+        match pieces.last_mut() {
+          | Some(Piece::Prose(run)) => {
+            run.extend(paragraphs)
+          }
+          | _ => {
+            pieces.push(Piece::Prose(
+              paragraphs.collect()
+            ))
+          }
+        }
+      }
+    }
+    for piece in pieces {
+      match piece {
+        | Piece::Block(
+          block_type,
+          text,
+          page
+        ) => {
+          source.push(&text);
+          chunks.push((
+            BuiltChunk {
+              text,
+              para_start: para_offset,
+              para_end: para_offset,
+              overlap: 0,
+              section_path:
+                section_path(&headings),
+              block_type
+            },
+            section
+          ));
+          para_pages.push(page);
+          para_offset += 1;
+        }
+        | Piece::Prose(run) => {
+          let (paragraphs, pages): (
+            Vec<_>,
+            Vec<_>
+          ) = run.into_iter().unzip();
+          for paragraph in &paragraphs {
+            source.push(paragraph);
+          }
+          let built = match config
+            .chunk
+            .strategy
+          {
+            | ChunkStrategy::Paragraph => {
+              build_chunks(
+                &paragraphs,
+                &config.chunk,
+                forced,
+                &mut headings
+              )
+            }
+            | ChunkStrategy::SlidingWindow => {
+              sliding_windows(
+                &paragraphs,
+                &config.chunk,
+                &mut headings
+              )
+            }
+          };
+          for mut built in built {
+            built.para_start +=
+              para_offset;
+            built.para_end +=
+              para_offset;
+            chunks
+              .push((built, section));
+          }
+          para_pages.extend(pages);
+          para_offset +=
+            paragraphs.len();
+        }
+      }
+    }
+  }
+  if para_offset == 0 {
+    warn!(path = %path.display(), "empty text after normalization");
+    return Vec::new();
+  }
   if chunks.is_empty() {
     warn!(path = %path.display(), "no chunks emitted");
-    return Ok(0);
+    return Vec::new();
   }
 
-  let mut file_meta =
-    load_metadata(path)?;
-  let mut writer =
-    fs::File::create(&out_path)?;
-
-  let mut total = 0usize;
-  let mut cursor = 0usize;
-  for (idx, chunk_text) in
+  // Hashed once, over the same bytes
+  // `dups` would hash for this file.
+  let source_hash = config
+    .chunk
+    .metadata
+    .include_source_hash
+    .then(|| hash_str(raw));
+  let mut records = Vec::new();
+  for (idx, (built, section)) in
     chunks.into_iter().enumerate()
   {
+    // Offsets count characters, like
+    // overlap, not UTF-8 bytes, and
+    // leave out the heading prefix.
+    let (char_start, char_end) = source
+      .locate(
+        &built.text,
+        built.overlap
+      );
+    let mut chunk_text = built.text;
+    // Counted before the heading
+    // prefix, like the offsets.
+    let chars =
+      chunk_text.chars().count();
+    let words = chunk_text
+      .split_whitespace()
+      .count();
     let mut meta = Map::new();
+    if !built.section_path.is_empty() {
+      chunk_text = format!(
+        "{}\n\n{chunk_text}",
+        built.section_path.join(" > ")
+      );
+      meta.insert(
+        "section_path".to_string(),
+        Value::from(built.section_path)
+      );
+    }
+    for (key, para) in [
+      ("page_start", built.para_start),
+      ("page_end", built.para_end)
+    ] {
+      if let Some(page) = para_pages
+        .get(para)
+        .copied()
+        .flatten()
+      {
+        meta.insert(
+          key.to_string(),
+          Value::Number(
+            (page as u64).into()
+          )
+        );
+      }
+    }
+    if config.chunk.preserve_blocks {
+      meta.insert(
+        "block_type".to_string(),
+        Value::String(
+          built
+            .block_type
+            .as_str()
+            .into()
+        )
+      );
+    }
+    if let Some(heading) =
+      &section.heading
+    {
+      meta.insert(
+        "section_heading".to_string(),
+        Value::String(heading.clone())
+      );
+      meta.insert(
+        "section_level".to_string(),
+        Value::Number(
+          section.level.into()
+        )
+      );
+    }
     if config
       .chunk
       .metadata
@@ -135,6 +1557,20 @@ fn chunk_file(
         )
       );
     }
+    if let Some(hash) = &source_hash {
+      meta.insert(
+        "source_blake3".to_string(),
+        Value::String(hash.clone())
+      );
+    }
+    if !config.run_id.is_empty() {
+      meta.insert(
+        "run_id".to_string(),
+        Value::String(
+          config.run_id.clone()
+        )
+      );
+    }
     meta.insert(
       "chunk_index".to_string(),
       Value::Number(
@@ -144,80 +1580,457 @@ fn chunk_file(
     meta.insert(
       "char_start".to_string(),
       Value::Number(
-        (cursor as u64).into()
+        (char_start as u64).into()
       )
     );
     meta.insert(
       "char_end".to_string(),
       Value::Number(
-        ((cursor + chunk_text.len())
-          as u64)
-          .into()
+        (char_end as u64).into()
       )
     );
-    cursor += chunk_text.len();
+    if config
+      .chunk
+      .metadata
+      .include_paragraph_range
+    {
+      meta.insert(
+        "para_start".to_string(),
+        Value::Number(
+          (built.para_start as u64)
+            .into()
+        )
+      );
+      meta.insert(
+        "para_end".to_string(),
+        Value::Number(
+          (built.para_end as u64)
+            .into()
+        )
+      );
+    }
+    if config
+      .chunk
+      .metadata
+      .include_counts
+    {
+      meta.insert(
+        "char_count".to_string(),
+        Value::Number(
+          (chars as u64).into()
+        )
+      );
+      meta.insert(
+        "word_count".to_string(),
+        Value::Number(
+          (words as u64).into()
+        )
+      );
+    }
 
-    if let Some(obj) =
-      file_meta.as_object_mut()
+    meta.extend(included_metadata(
+      file_meta,
+      &config.chunk
+    ));
+    if config
+      .chunk
+      .detect_chunk_language
     {
-      for (k, v) in obj.iter() {
-        if should_include_metadata(
-          k,
-          &config.chunk
-        ) {
-          meta.insert(
-            k.clone(),
-            v.clone()
-          );
-        }
-      }
+      stamp_language(
+        &mut meta,
+        &chunk_text,
+        file_meta
+      );
+    }
+    if config.chunk.detect_language {
+      stamp_detected_language(
+        &mut meta,
+        &chunk_text,
+        config
+          .chunk
+          .detect_language_threshold
+      );
     }
 
-    let record = ChunkRecord {
-      id:       Uuid::new_v4()
-        .to_string(),
+    records.push(ChunkRecord {
+      id:       chunk_id(
+        config.chunk.id_mode,
+        &rel,
+        idx
+      ),
       text:     chunk_text,
       metadata: Value::Object(meta)
-    };
-    let line =
-      serde_json::to_string(&record)?;
+    });
+  }
+
+  debug!(path = %path.display(), chunks = records.len(), "chunked file");
+  records
+}
+
+/// Sets the chunk's `language` to the
+/// one detected in its text when the
+/// guess is confident, written like the
+/// document's own code; the document's
+/// value stands when they agree or the
+/// guess is unsure.
+fn stamp_language(
+  meta: &mut Map<String, Value>,
+  text: &str,
+  file_meta: &Value
+) {
+  let Some(found) = language::detect(
+    text
+  )
+  .filter(|found| {
+    found.confidence
+      >= language::CONFIDENT
+  }) else {
+    return;
+  };
+  let doc = file_meta
+    .get("language")
+    .and_then(Value::as_str);
+  let code = match doc {
+    | Some(doc) if found.matches(doc) => {
+      doc.to_string()
+    }
+    | _ => {
+      found.code_like(doc).to_string()
+    }
+  };
+  meta.insert(
+    "language".to_string(),
+    Value::String(code)
+  );
+}
+
+/// Records the language detected in
+/// the chunk's text as
+/// `detected_language` (ISO 639-1) and
+/// `detected_language_confidence`,
+/// leaving both out below `threshold`.
+fn stamp_detected_language(
+  meta: &mut Map<String, Value>,
+  text: &str,
+  threshold: f32
+) {
+  let Some(found) = language::detect(
+    text
+  )
+  .filter(|found| {
+    found.confidence >= threshold
+  }) else {
+    return;
+  };
+  meta.insert(
+    "detected_language".to_string(),
+    Value::String(
+      found.iso1.to_string()
+    )
+  );
+  meta.insert(
+    "detected_language_confidence"
+      .to_string(),
+    json!(
+      (f64::from(found.confidence)
+        * 100.0)
+        .round()
+        / 100.0
+    )
+  );
+}
+
+/// Namespace for `uuid_v5` chunk ids.
+const CHUNK_ID_NAMESPACE: Uuid =
+  Uuid::from_u128(
+    0x6f0c_5b1e_9d2a_4c37_8e41_2b7d_a3f9_0c58
+  );
+
+/// Id of chunk `idx` of the source at
+/// `rel` under `extract_root`.
+fn chunk_id(
+  mode: ChunkIdMode,
+  rel: &Path,
+  idx: usize
+) -> String {
+  match mode {
+    | ChunkIdMode::UuidV4 => {
+      Uuid::new_v4().to_string()
+    }
+    | ChunkIdMode::UuidV5 => {
+      let name = format!(
+        "{}#{idx}",
+        rel.to_string_lossy()
+      );
+      Uuid::new_v5(
+        &CHUNK_ID_NAMESPACE,
+        name.as_bytes()
+      )
+      .to_string()
+    }
+  }
+}
+
+/// Writes `records` as JSONL to
+/// `out_path`; nothing is written when
+/// there are no records.
+pub fn write_chunks(
+  out_path: &Path,
+  records: &[ChunkRecord],
+  names: &ChunkFieldNames
+) -> anyhow::Result<()> {
+  if records.is_empty() {
+    return Ok(());
+  }
+  if let Some(parent) =
+    out_path.parent()
+  {
+    fs::create_dir_all(parent)?;
+  }
+  let mut writer =
+    fs::File::create(out_path)?;
+  for record in records {
+    let line = serde_json::to_string(
+      &encode_record(record, names)
+    )?;
     writer
       .write_all(line.as_bytes())?;
     writer.write_all(b"\n")?;
-    total += 1;
   }
+  Ok(())
+}
+
+/// Writes `records` as a Parquet file
+/// with id and text columns and the
+/// metadata as a JSON string column,
+/// named like the JSONL keys; nothing
+/// is written when there are no
+/// records.
+pub fn write_chunks_parquet(
+  out_path: &Path,
+  records: &[ChunkRecord],
+  names: &ChunkFieldNames
+) -> anyhow::Result<()> {
+  if records.is_empty() {
+    return Ok(());
+  }
+  if let Some(parent) =
+    out_path.parent()
+  {
+    fs::create_dir_all(parent)?;
+  }
+  let column = |name: &str,
+                value: fn(
+    &ChunkRecord
+  )
+    -> String| {
+    Column {
+      name:     name.to_string(),
+      required: true,
+      values:   Values::Utf8(
+        records
+          .iter()
+          .map(|r| Some(value(r)))
+          .collect()
+      )
+    }
+  };
+  parquet::write_file(out_path, &[
+    column(&names.id, |r| r.id.clone()),
+    column(&names.text, |r| {
+      r.text.clone()
+    }),
+    column(&names.metadata, |r| {
+      r.metadata.to_string()
+    })
+  ])
+}
+
+/// Reads back a file
+/// `write_chunks_parquet` wrote.
+pub fn read_chunks_parquet(
+  path: &Path,
+  names: &ChunkFieldNames
+) -> anyhow::Result<Vec<ChunkRecord>> {
+  let mut columns =
+    parquet::read_strings(path, &[
+      &names.id,
+      &names.text,
+      &names.metadata
+    ])?
+    .into_iter();
+  let (
+    Some(ids),
+    Some(texts),
+    Some(metas)
+  ) = (
+    columns.next(),
+    columns.next(),
+    columns.next()
+  )
+  else {
+    unreachable!("three columns read")
+  };
+  ids
+    .into_iter()
+    .zip(texts)
+    .zip(metas)
+    .map(|((id, text), metadata)| {
+      Ok(ChunkRecord {
+        id,
+        text,
+        metadata: serde_json::from_str(
+          &metadata
+        )
+        .with_context(|| {
+          format!(
+            "metadata of chunk in {}",
+            path.display()
+          )
+        })?
+      })
+    })
+    .collect()
+}
+
+/// `record` as a JSON object keyed by
+/// the configured field names.
+pub fn encode_record(
+  record: &ChunkRecord,
+  names: &ChunkFieldNames
+) -> Value {
+  let mut obj = Map::new();
+  obj.insert(
+    names.id.clone(),
+    Value::String(record.id.clone())
+  );
+  obj.insert(
+    names.text.clone(),
+    Value::String(record.text.clone())
+  );
+  obj.insert(
+    names.metadata.clone(),
+    record.metadata.clone()
+  );
+  Value::Object(obj)
+}
+
+/// Renames configured field names in a
+/// chunk line back to `id`, `text`,
+/// and `metadata`; lines already using
+/// those keys pass through.
+pub fn canonical_record(
+  mut value: Value,
+  names: &ChunkFieldNames
+) -> Value {
+  if let Some(obj) =
+    value.as_object_mut()
+  {
+    for (canonical, name) in [
+      ("id", &names.id),
+      ("text", &names.text),
+      ("metadata", &names.metadata)
+    ] {
+      if name != canonical
+        && !obj.contains_key(canonical)
+        && let Some(v) =
+          obj.remove(name)
+      {
+        obj.insert(
+          canonical.to_string(),
+          v
+        );
+      }
+    }
+  }
+  value
+}
 
-  debug!(path = %path.display(), chunks = total, "chunked file");
-  Ok(total)
+fn unicode_normalize(
+  input: &str,
+  form: UnicodeForm
+) -> String {
+  match form {
+    | UnicodeForm::Nfc => {
+      input.nfc().collect::<String>()
+    }
+    | UnicodeForm::Nfkc => {
+      input.nfkc().collect::<String>()
+    }
+    | UnicodeForm::Nfkd => {
+      input.nfkd().collect::<String>()
+    }
+    | UnicodeForm::None => {
+      input.to_string()
+    }
+  }
 }
 
 fn normalize_text(
   input: &str,
   cfg: &ChunkConfig
 ) -> String {
-  let mut out = input.to_string();
-  if cfg.normalize_unicode {
-    out =
-      out.nfkc().collect::<String>();
-  }
-  if cfg.collapse_whitespace {
-    let mut collapsed =
-      String::with_capacity(out.len());
-    let mut last_space = false;
-    for ch in out.chars() {
-      if ch.is_whitespace() {
-        if !last_space {
-          collapsed.push(' ');
-          last_space = true;
-        }
-      } else {
-        collapsed.push(ch);
-        last_space = false;
+  // Pages are normalized apart so
+  // collapsing cannot join them.
+  if cfg.split_on_formfeed
+    && input.contains('\x0c')
+  {
+    return input
+      .split('\x0c')
+      .map(|page| {
+        normalize_text(page, cfg)
+          .trim()
+          .to_string()
+      })
+      .filter(|page| !page.is_empty())
+      .collect::<Vec<_>>()
+      .join("\n\n");
+  }
+  match cfg.normalize_order {
+    | NormalizeOrder::UnicodeFirst => {
+      let out = unicode_normalize(
+        input,
+        cfg.unicode_form
+      );
+      collapse_whitespace(out, cfg)
+    }
+    | NormalizeOrder::CollapseFirst => {
+      unicode_normalize(
+        &collapse_whitespace(
+          input.to_string(),
+          cfg
+        ),
+        cfg.unicode_form
+      )
+    }
+  }
+}
+
+/// `text` with each whitespace run
+/// folded to one space, under
+/// `collapse_whitespace`.
+fn collapse_whitespace(
+  text: String,
+  cfg: &ChunkConfig
+) -> String {
+  if !cfg.collapse_whitespace {
+    return text;
+  }
+  let mut collapsed =
+    String::with_capacity(text.len());
+  let mut last_space = false;
+  for ch in text.chars() {
+    if ch.is_whitespace() {
+      if !last_space {
+        collapsed.push(' ');
+        last_space = true;
       }
+    } else {
+      collapsed.push(ch);
+      last_space = false;
     }
-    out = collapsed;
   }
-  out
+  collapsed
 }
 
 fn split_paragraphs(
@@ -227,16 +2040,42 @@ fn split_paragraphs(
   let mut paragraphs = Vec::new();
   let mut current = String::new();
   for line in text.lines() {
-    if line.trim().is_empty() {
+    let blank = line.trim().is_empty();
+    match cfg.paragraph_mode {
+      | ParagraphMode::BlankLine
+        if blank =>
+      {
+        push_paragraph(
+          &mut paragraphs,
+          &mut current,
+          cfg
+        );
+        continue;
+      }
+      | ParagraphMode::DoubleNewline
+        if line.is_empty() =>
+      {
+        push_paragraph(
+          &mut paragraphs,
+          &mut current,
+          cfg
+        );
+        continue;
+      }
+      | _ if blank => continue,
+      | _ => {}
+    }
+    current.push_str(line.trim());
+    current.push('\n');
+    if cfg.paragraph_mode
+      == ParagraphMode::SingleLine
+    {
       push_paragraph(
         &mut paragraphs,
         &mut current,
         cfg
       );
-      continue;
     }
-    current.push_str(line.trim());
-    current.push('\n');
   }
   push_paragraph(
     &mut paragraphs,
@@ -256,6 +2095,21 @@ fn push_paragraph(
     current.clear();
     return;
   }
+  // A heading line stays a paragraph of
+  // its own for `build_chunks` to turn
+  // into section context.
+  if cfg.heading_context {
+    let (first, rest) = trimmed
+      .split_once('\n')
+      .unwrap_or((trimmed, ""));
+    if parse_heading(first).is_some() {
+      out
+        .push(first.trim().to_string());
+      *current = rest.to_string();
+      push_paragraph(out, current, cfg);
+      return;
+    }
+  }
   if cfg.strip_headers {
     let lower =
       trimmed.to_ascii_lowercase();
@@ -272,8 +2126,15 @@ fn push_paragraph(
   if cleaned.len()
     < cfg.min_paragraph_chars
   {
-    if let Some(last) = out.last_mut() {
-      last.push(' ');
+    if let Some(last) =
+      out.last_mut().filter(|last| {
+        !cfg.heading_context
+          || parse_heading(last)
+            .is_none()
+      })
+    {
+      last
+        .push_str(&cfg.join_separator);
       last.push_str(&cleaned);
     } else {
       out.push(cleaned);
@@ -284,15 +2145,555 @@ fn push_paragraph(
   current.clear();
 }
 
-fn build_chunks(
-  paragraphs: &[String],
+/// Level and title of a markdown
+/// heading line such as `## Methods`.
+fn parse_heading(
+  line: &str
+) -> Option<(usize, &str)> {
+  let rest =
+    line.trim_start_matches('#');
+  let level = line.len() - rest.len();
+  if !(1..=6).contains(&level)
+    || !(rest.is_empty()
+      || rest.starts_with(
+        char::is_whitespace
+      ))
+  {
+    return None;
+  }
+  let title = rest
+    .trim()
+    .trim_end_matches('#')
+    .trim_end();
+  (!title.is_empty())
+    .then_some((level, title))
+}
+
+/// Broad writing-system class of a
+/// paragraph, deciding where it may be
+/// broken.
+#[derive(
+  Clone, Copy, PartialEq, Eq,
+)]
+enum Script {
+  /// Words separated by whitespace.
+  Spaced,
+  /// Chinese, Japanese, Thai: no
+  /// spaces between words.
+  Dense
+}
+
+/// Script fixed by config or by the
+/// sidecar language; `None` leaves it
+/// to per-paragraph detection.
+fn forced_script(
+  mode: SplitterMode,
+  file_meta: &Value
+) -> Option<Script> {
+  match mode {
+    | SplitterMode::Whitespace => {
+      Some(Script::Spaced)
+    }
+    | SplitterMode::Cjk => {
+      Some(Script::Dense)
+    }
+    | SplitterMode::Auto => {
+      file_meta
+        .get("language")
+        .and_then(Value::as_str)
+        .map(str::to_ascii_lowercase)
+        .filter(|lang| {
+          ["zh", "ja", "jp", "th"]
+            .iter()
+            .any(|p| {
+              lang.starts_with(p)
+            })
+        })
+        .map(|_| Script::Dense)
+    }
+  }
+}
+
+pub(crate) fn is_dense_char(
+  ch: char
+) -> bool {
+  matches!(ch,
+    | '\u{3040}'..='\u{30ff}'
+    | '\u{31f0}'..='\u{31ff}'
+    | '\u{3400}'..='\u{4dbf}'
+    | '\u{4e00}'..='\u{9fff}'
+    | '\u{f900}'..='\u{faff}'
+    | '\u{ff66}'..='\u{ff9f}'
+    | '\u{0e00}'..='\u{0e7f}'
+    | '\u{20000}'..='\u{2fa1f}')
+}
+
+/// Dense when at least a third of the
+/// letters are ideographs, kana, or
+/// Thai.
+fn detect_script(text: &str) -> Script {
+  let mut letters = 0usize;
+  let mut dense = 0usize;
+  for ch in text.chars() {
+    if is_dense_char(ch) {
+      dense += 1;
+      letters += 1;
+    } else if ch.is_alphabetic() {
+      letters += 1;
+    }
+  }
+  if dense > 0 && dense * 3 >= letters {
+    Script::Dense
+  } else {
+    Script::Spaced
+  }
+}
+
+fn is_sentence_end(
+  ch: char,
+  script: Script
+) -> bool {
+  match script {
+    | Script::Spaced => {
+      matches!(ch, '.' | '!' | '?')
+    }
+    | Script::Dense => {
+      matches!(
+        ch,
+        '。'
+          | '！'
+          | '？'
+          | '．'
+          | '!'
+          | '?'
+      )
+    }
+  }
+}
+
+/// Chunk text plus the range of
+/// `split_paragraphs` indices it
+/// draws from (overlap included).
+struct BuiltChunk {
+  text:         String,
+  para_start:   usize,
+  para_end:     usize,
+  /// Leading bytes of `text` carried
+  /// over from the previous chunk.
+  overlap:      usize,
+  /// Titles of the enclosing headings,
+  /// outermost first.
+  section_path: Vec<String>,
+  block_type:   BlockType
+}
+
+/// A file's paragraphs and blocks
+/// joined by `join_separator`: the text
+/// `char_start` and `char_end` index.
+struct ChunkSource {
+  text:  String,
+  sep:   String,
+  /// Byte and character position just
+  /// past the last located chunk's own
+  /// text.
+  byte:  usize,
+  chars: usize
+}
+
+impl ChunkSource {
+  fn new(sep: &str) -> Self {
+    Self {
+      text:  String::new(),
+      sep:   sep.to_string(),
+      byte:  0,
+      chars: 0
+    }
+  }
+
+  fn push(
+    &mut self,
+    part: &str
+  ) {
+    if !self.text.is_empty() {
+      self.text.push_str(&self.sep);
+    }
+    self.text.push_str(part);
+  }
+
+  /// Character range of a chunk, taken
+  /// in order. Its own text, after the
+  /// `overlap` bytes carried from the
+  /// previous chunk, is found at or
+  /// past the previous chunk's end; the
+  /// carried text is counted back from
+  /// there when it directly precedes
+  /// it. Text found nowhere (a short
+  /// chunk folded in with a different
+  /// separator) starts at the previous
+  /// end.
+  fn locate(
+    &mut self,
+    text: &str,
+    overlap: usize
+  ) -> (usize, usize) {
+    let own = text
+      [overlap.min(text.len())..]
+      .trim_start();
+    let carried =
+      &text[..text.len() - own.len()];
+    let own_chars = own.chars().count();
+    let Some(found) =
+      self.text[self.byte..].find(own)
+    else {
+      let start = self.chars;
+      return (start, start + own_chars);
+    };
+    let at = self.byte + found;
+    self.chars += self.text
+      [self.byte..at]
+      .chars()
+      .count();
+    let start = if self.text[..at]
+      .ends_with(carried)
+    {
+      self.chars
+        - carried.chars().count()
+    } else {
+      self.chars
+    };
+    self.byte = at + own.len();
+    self.chars += own_chars;
+    (start, self.chars)
+  }
+}
+
+/// What a chunk holds, as written to
+/// `block_type` metadata with
+/// `chunk.preserve_blocks`.
+#[derive(
+  Debug, Clone, Copy, PartialEq, Eq,
+)]
+enum BlockType {
+  Prose,
+  Code,
+  Table
+}
+
+impl BlockType {
+  fn as_str(self) -> &'static str {
+    match self {
+      | BlockType::Prose => "prose",
+      | BlockType::Code => "code",
+      | BlockType::Table => "table"
+    }
+  }
+}
+
+/// The text between the first line
+/// matching a `boilerplate_start`
+/// pattern and the last matching a
+/// `boilerplate_end` pattern after it,
+/// markers excluded. Either side is
+/// kept when its marker is missing.
+fn strip_boilerplate<'a>(
+  text: &'a str,
+  cfg: &ChunkConfig
+) -> &'a str {
+  let matches =
+    |patterns: &[Regex], line: &str| {
+      patterns.iter().any(|re| {
+        re.is_match(line.trim())
+      })
+    };
+  let mut start = 0usize;
+  let mut end = text.len();
+  let mut offset = 0usize;
+  let mut started = false;
+  for line in text.split_inclusive('\n')
+  {
+    let next = offset + line.len();
+    if !started
+      && matches(
+        &cfg.boilerplate_start,
+        line
+      )
+    {
+      start = next;
+      started = true;
+    } else if matches(
+      &cfg.boilerplate_end,
+      line
+    ) {
+      end = offset;
+    }
+    offset = next;
+  }
+  if end < start {
+    end = text.len();
+  }
+  &text[start..end]
+}
+
+/// A stretch of one section's text
+/// between code blocks and tables.
+enum Piece {
+  /// Paragraphs and the page each
+  /// starts on.
+  Prose(Vec<(String, Option<usize>)>),
+  Block(
+    BlockType,
+    String,
+    Option<usize>
+  )
+}
+
+/// Cuts `text` at form feeds under
+/// `split_on_formfeed` and at page
+/// markers under
+/// `metadata.include_pages`, dropping
+/// them. `page` is the page open
+/// before `text` and is left as open
+/// after it; text with form feeds and
+/// no page yet starts on 1. Pages are
+/// only reported under
+/// `include_pages`.
+fn split_pages<'a>(
+  text: &'a str,
+  page: &mut Option<usize>,
   cfg: &ChunkConfig
+) -> Vec<(Option<usize>, &'a str)> {
+  let markers =
+    cfg.metadata.include_pages;
+  let feeds = cfg.split_on_formfeed;
+  if !markers && !feeds {
+    return vec![(None, text)];
+  }
+  if feeds
+    && page.is_none()
+    && text.contains('\x0c')
+  {
+    *page = Some(1);
+  }
+  let parts = if feeds {
+    text.split('\x0c').collect()
+  } else {
+    vec![text]
+  };
+  let mut out = Vec::new();
+  for (idx, part) in
+    parts.into_iter().enumerate()
+  {
+    if idx > 0 {
+      *page =
+        Some(page.map_or(1, |p| p + 1));
+    }
+    let mut start = 0usize;
+    let mut offset = 0usize;
+    for line in
+      part.split_inclusive('\n')
+    {
+      if markers
+        && let Some(number) =
+          extract::parse_page_marker(
+            line.trim()
+          )
+      {
+        out.push((
+          *page,
+          &part[start..offset]
+        ));
+        *page = Some(number);
+        start = offset + line.len();
+      }
+      offset += line.len();
+    }
+    out.push((*page, &part[start..]));
+  }
+  if !markers {
+    for (page, _) in &mut out {
+      *page = None;
+    }
+  }
+  out
+}
+
+/// Cuts fenced code blocks and pipe
+/// tables out of `text`, in order,
+/// between runs of prose. A fence left
+/// open runs to the end; a table needs
+/// a `|---|` delimiter row under its
+/// header.
+fn split_blocks(
+  text: &str
+) -> Vec<(BlockType, &str)> {
+  let mut lines = Vec::new();
+  let mut offset = 0usize;
+  for line in text.split_inclusive('\n')
+  {
+    lines.push((offset, line.trim()));
+    offset += line.len();
+  }
+  let start_of = |idx: usize| {
+    lines
+      .get(idx)
+      .map_or(text.len(), |(at, _)| *at)
+  };
+  let mut out = Vec::new();
+  let mut prose_start = 0usize;
+  let mut idx = 0usize;
+  while idx < lines.len() {
+    let line = lines[idx].1;
+    let block =
+      if line.starts_with("```") {
+        let close = lines[idx + 1..]
+          .iter()
+          .position(|(_, l)| {
+            l.starts_with("```")
+          })
+          .map_or(lines.len(), |pos| {
+            idx + pos + 2
+          });
+        Some((BlockType::Code, close))
+      } else if line.starts_with('|')
+        && lines
+          .get(idx + 1)
+          .is_some_and(|(_, next)| {
+            is_table_delimiter(next)
+          })
+      {
+        let rows = lines[idx..]
+          .iter()
+          .take_while(|(_, l)| {
+            l.starts_with('|')
+          })
+          .count();
+        Some((
+          BlockType::Table,
+          idx + rows
+        ))
+      } else {
+        None
+      };
+    let Some((kind, next)) = block
+    else {
+      idx += 1;
+      continue;
+    };
+    let start = start_of(idx);
+    if prose_start < start {
+      out.push((
+        BlockType::Prose,
+        &text[prose_start..start]
+      ));
+    }
+    let stop = start_of(next);
+    out.push((
+      kind,
+      text[start..stop]
+        .trim_end_matches(['\n', '\r'])
+    ));
+    prose_start = stop;
+    idx = next;
+  }
+  if prose_start < text.len() {
+    out.push((
+      BlockType::Prose,
+      &text[prose_start..]
+    ));
+  }
+  out
+}
+
+/// `|---|:--:|`, the row under a pipe
+/// table's header.
+fn is_table_delimiter(
+  line: &str
+) -> bool {
+  line.starts_with('|')
+    && line.contains('-')
+    && line.chars().all(|c| {
+      matches!(
+        c,
+        '|' | '-' | ':' | ' ' | '\t'
+      )
+    })
+}
+
+/// Titles on `headings`, outermost
+/// first.
+fn section_path(
+  headings: &[(usize, String)]
 ) -> Vec<String> {
+  headings
+    .iter()
+    .map(|(_, title)| title.clone())
+    .collect()
+}
+
+/// `headings` is the stack of
+/// `(level, title)` open at the first
+/// paragraph and is left as open after
+/// the last.
+fn build_chunks(
+  paragraphs: &[String],
+  cfg: &ChunkConfig,
+  forced: Option<Script>,
+  headings: &mut Vec<(usize, String)>
+) -> Vec<BuiltChunk> {
+  let sep = cfg.join_separator.as_str();
+  let size =
+    tokenizer::for_unit(cfg.size_unit);
   let mut chunks = Vec::new();
   let mut current = String::new();
   let mut last_overlap = String::new();
+  let mut current_start = 0usize;
+  let mut current_end = 0usize;
+  let mut overlap_para = 0usize;
+  // Bytes at the front of `current`
+  // repeated from the previous chunk.
+  let mut current_overlap = 0usize;
 
-  for para in paragraphs {
+  for (para_idx, para) in
+    paragraphs.iter().enumerate()
+  {
+    // A heading closes the chunk
+    // before it, so no chunk or overlap
+    // crosses into a new section.
+    if cfg.heading_context
+      && let Some((level, title)) =
+        parse_heading(para)
+    {
+      if !current.is_empty() {
+        flush_chunk(
+          &mut chunks,
+          &mut current,
+          (current_start, current_end),
+          current_overlap,
+          section_path(headings),
+          cfg,
+          forced
+        );
+      }
+      last_overlap.clear();
+      headings.retain(|(open, _)| {
+        *open < level
+      });
+      headings.push((
+        level,
+        title.to_string()
+      ));
+      continue;
+    }
+    let script =
+      forced.unwrap_or_else(|| {
+        detect_script(para)
+      });
+    // Pieces of one dense paragraph
+    // were never separated by spaces.
+    let inner_sep = match script {
+      | Script::Spaced => sep,
+      | Script::Dense => ""
+    };
     let mut parts = Vec::new();
     if para.len()
       > cfg.max_paragraph_chars
@@ -300,117 +2701,334 @@ fn build_chunks(
       parts.extend(
         split_large_paragraph(
           para,
-          cfg.max_paragraph_chars
+          cfg.max_paragraph_chars,
+          inner_sep,
+          script
         )
       );
     } else {
       parts.push(para.clone());
     }
 
+    let mut first = true;
     for part in parts {
-      let bounded_parts = if part.len()
+      let bounded_parts = if size
+        .count(&part)
         > cfg.max_chunk_chars
       {
-        split_by_max_bytes(
+        split_to_size(
           &part,
-          cfg.max_chunk_chars
+          cfg.max_chunk_chars,
+          script,
+          size
         )
       } else {
         vec![part]
       };
 
       for part in bounded_parts {
-        if current.len()
-          + part.len()
-          + 1
+        let sep = if first {
+          sep
+        } else {
+          inner_sep
+        };
+        first = false;
+        if size.count(&current)
+          + size.count(&part)
+          + size.count(sep)
           > cfg.max_chunk_chars
           && !current.is_empty()
         {
-          if !last_overlap.is_empty() {
-            let mut overlap_chunk =
-              last_overlap.clone();
-            overlap_chunk.push(' ');
-            overlap_chunk
-              .push_str(&part);
-            if overlap_chunk.len()
-              > cfg.max_chunk_chars
-            {
-              current.clear();
-              current.push_str(&part);
-            } else {
-              current = overlap_chunk;
-            }
+          last_overlap = flush_chunk(
+            &mut chunks,
+            &mut current,
+            (
+              current_start,
+              current_end
+            ),
+            current_overlap,
+            section_path(headings),
+            cfg,
+            forced
+          );
+          overlap_para = current_end;
+        }
+        if current.is_empty() {
+          // Start from the previous
+          // chunk's tail when it still
+          // fits.
+          if !last_overlap.is_empty()
+            && size.count(&last_overlap)
+              + size.count(sep)
+              + size.count(&part)
+              <= cfg.max_chunk_chars
+          {
+            current
+              .push_str(&last_overlap);
+            current.push_str(sep);
+            current_start =
+              overlap_para;
+            current_overlap =
+              current.len();
           } else {
-            current.clear();
-            current.push_str(&part);
+            current_start = para_idx;
+            current_overlap = 0;
           }
         } else {
-          if !current.is_empty() {
-            current.push(' ');
-          }
-          current.push_str(&part);
+          current.push_str(sep);
         }
+        current.push_str(&part);
+        current_end = para_idx;
 
-        if current.len()
+        if size.count(&current)
           >= cfg.target_chunk_chars
         {
-          let finalized =
-            current.clone();
-          last_overlap = overlap_tail(
-            &finalized,
-            cfg.chunk_overlap_chars
+          last_overlap = flush_chunk(
+            &mut chunks,
+            &mut current,
+            (
+              current_start,
+              current_end
+            ),
+            current_overlap,
+            section_path(headings),
+            cfg,
+            forced
           );
-          chunks.push(finalized);
-          current.clear();
+          overlap_para = current_end;
         }
       }
     }
   }
 
   if !current.is_empty() {
-    chunks.push(current);
+    chunks.push(BuiltChunk {
+      text:         current,
+      para_start:   current_start,
+      para_end:     current_end,
+      overlap:      current_overlap,
+      section_path: section_path(
+        headings
+      ),
+      block_type:   BlockType::Prose
+    });
   }
 
-  chunks
+  merge_short_chunks(chunks, cfg)
 }
 
-fn split_large_paragraph(
-  paragraph: &str,
-  max_len: usize
-) -> Vec<String> {
-  let mut sentences = Vec::new();
-  let mut buf = String::new();
-  let mut chars =
-    paragraph.chars().peekable();
-  while let Some(ch) = chars.next() {
-    buf.push(ch);
-    if matches!(ch, '.' | '!' | '?')
-      && let Some(next) = chars.peek()
-      && next.is_whitespace()
+/// Windows of `target_chunk_chars`
+/// characters over `paragraphs` joined
+/// by `join_separator`, each starting
+/// `target_chunk_chars -
+/// chunk_overlap_chars` characters
+/// after the last, with no regard for
+/// paragraph or sentence ends. Under
+/// `heading_context` heading lines
+/// leave the text and update
+/// `headings`; each window takes the
+/// section path open where it starts.
+/// Windows are not merged by
+/// `min_chunk_chars`.
+fn sliding_windows(
+  paragraphs: &[String],
+  cfg: &ChunkConfig,
+  headings: &mut Vec<(usize, String)>
+) -> Vec<BuiltChunk> {
+  let mut text = String::new();
+  // Character index each paragraph
+  // starts at in `text`, and the
+  // section path open there.
+  let mut para_starts = Vec::new();
+  let mut para_paths = Vec::new();
+  let mut chars = 0usize;
+  for paragraph in paragraphs {
+    if cfg.heading_context
+      && let Some((level, title)) =
+        parse_heading(paragraph)
     {
-      sentences
-        .push(buf.trim().to_string());
-      buf.clear();
+      headings.retain(|(open, _)| {
+        *open < level
+      });
+      headings.push((
+        level,
+        title.to_string()
+      ));
+      para_starts.push(chars);
+      para_paths
+        .push(section_path(headings));
+      continue;
+    }
+    if !text.is_empty() {
+      text
+        .push_str(&cfg.join_separator);
+      chars += cfg
+        .join_separator
+        .chars()
+        .count();
+    }
+    para_starts.push(chars);
+    para_paths
+      .push(section_path(headings));
+    text.push_str(paragraph);
+    chars += paragraph.chars().count();
+  }
+  // Byte offset of every character,
+  // plus the end.
+  let bounds = text
+    .char_indices()
+    .map(|(at, _)| at)
+    .chain([text.len()])
+    .collect::<Vec<_>>();
+  let total = bounds.len() - 1;
+  let width =
+    cfg.target_chunk_chars.max(1);
+  let stride = width
+    .saturating_sub(
+      cfg.chunk_overlap_chars
+    )
+    .max(1);
+  let para_of = |ch: usize| {
+    para_starts
+      .partition_point(|&at| at <= ch)
+      .saturating_sub(1)
+  };
+  let mut out = Vec::new();
+  let mut start = 0usize;
+  let mut prev_end = 0usize;
+  while start < total {
+    let end =
+      (start + width).min(total);
+    let window =
+      &text[bounds[start]..bounds[end]];
+    let trimmed = window.trim_start();
+    let overlap = bounds
+      [prev_end.max(start)]
+      - bounds[start];
+    if !trimmed.trim_end().is_empty() {
+      out.push(BuiltChunk {
+        text:         trimmed
+          .trim_end()
+          .to_string(),
+        para_start:   para_of(start),
+        para_end:     para_of(end - 1),
+        overlap:      overlap
+          .saturating_sub(
+            window.len()
+              - trimmed.len()
+          ),
+        section_path: para_paths
+          [para_of(start)]
+        .clone(),
+        block_type:   BlockType::Prose
+      });
+    }
+    if end == total {
+      break;
     }
+    prev_end = end;
+    start += stride;
   }
-  if !buf.trim().is_empty() {
-    sentences
-      .push(buf.trim().to_string());
+  out
+}
+
+/// Folds each chunk whose own text
+/// (overlap left out) is under
+/// `min_chunk_chars` into the chunk
+/// before it in the same section. A
+/// lone short chunk is kept.
+fn merge_short_chunks(
+  chunks: Vec<BuiltChunk>,
+  cfg: &ChunkConfig
+) -> Vec<BuiltChunk> {
+  if cfg.min_chunk_chars == 0 {
+    return chunks;
+  }
+  let size =
+    tokenizer::for_unit(cfg.size_unit);
+  let mut out: Vec<BuiltChunk> =
+    Vec::with_capacity(chunks.len());
+  for chunk in chunks {
+    let own =
+      &chunk.text[chunk.overlap..];
+    if size.count(own)
+      < cfg.min_chunk_chars
+      && let Some(prev) =
+        out.last_mut().filter(|prev| {
+          prev.section_path
+            == chunk.section_path
+        })
+    {
+      prev
+        .text
+        .push_str(&cfg.join_separator);
+      prev.text.push_str(own);
+      prev.para_end = chunk.para_end;
+      continue;
+    }
+    out.push(chunk);
   }
+  out
+}
+
+/// Pushes `current` as a chunk and
+/// returns the tail the next chunk
+/// starts with.
+fn flush_chunk(
+  chunks: &mut Vec<BuiltChunk>,
+  current: &mut String,
+  (para_start, para_end): (
+    usize,
+    usize
+  ),
+  overlap_len: usize,
+  section_path: Vec<String>,
+  cfg: &ChunkConfig,
+  forced: Option<Script>
+) -> String {
+  let text = std::mem::take(current);
+  let script =
+    forced.unwrap_or_else(|| {
+      detect_script(&text)
+    });
+  let overlap =
+    overlap_tail(&text, cfg, script);
+  chunks.push(BuiltChunk {
+    text,
+    para_start,
+    para_end,
+    overlap: overlap_len,
+    section_path,
+    block_type: BlockType::Prose
+  });
+  overlap
+}
+
+fn split_large_paragraph(
+  paragraph: &str,
+  max_len: usize,
+  sep: &str,
+  script: Script
+) -> Vec<String> {
+  let sentences =
+    split_sentences(paragraph, script);
 
   let mut parts = Vec::new();
   let mut current = String::new();
   for sentence in sentences {
     let sentence_parts =
       if sentence.len() > max_len {
-        split_by_max_bytes(
-          &sentence, max_len
+        split_to_size(
+          &sentence, max_len, script,
+          &Utf8Len
         )
       } else {
         vec![sentence]
       };
     for sub in sentence_parts {
-      if current.len() + sub.len() + 1
+      if current.len()
+        + sub.len()
+        + sep.len()
         > max_len
         && !current.is_empty()
       {
@@ -420,7 +3038,7 @@ fn split_large_paragraph(
         current.clear();
       }
       if !current.is_empty() {
-        current.push(' ');
+        current.push_str(sep);
       }
       current.push_str(&sub);
     }
@@ -436,50 +3054,121 @@ fn split_large_paragraph(
   parts
 }
 
-fn split_by_max_bytes(
+/// Byte offsets where each sentence of
+/// `text` starts, the first always 0.
+fn sentence_starts(
+  text: &str,
+  script: Script
+) -> Vec<usize> {
+  let mut starts = vec![0];
+  let mut chars =
+    text.char_indices().peekable();
+  while let Some((_, ch)) = chars.next()
+  {
+    if !is_sentence_end(ch, script) {
+      continue;
+    }
+    match script {
+      | Script::Spaced => {
+        if let Some(&(idx, next)) =
+          chars.peek()
+          && next.is_whitespace()
+        {
+          starts.push(idx);
+        }
+      }
+      | Script::Dense => {
+        // Keep closing quotes and
+        // brackets with the sentence
+        // they end.
+        while let Some(&(_, next)) =
+          chars.peek()
+          && matches!(
+            next,
+            '」'
+              | '』'
+              | '）'
+              | '"'
+              | '”'
+              | ')'
+          )
+        {
+          chars.next();
+        }
+        if let Some(&(idx, _)) =
+          chars.peek()
+        {
+          starts.push(idx);
+        }
+      }
+    }
+  }
+  starts
+}
+
+fn split_sentences(
+  text: &str,
+  script: Script
+) -> Vec<String> {
+  let starts =
+    sentence_starts(text, script);
+  starts
+    .iter()
+    .enumerate()
+    .map(|(idx, &start)| {
+      let end = starts
+        .get(idx + 1)
+        .copied()
+        .unwrap_or(text.len());
+      text[start..end].trim()
+    })
+    .filter(|s| !s.is_empty())
+    .map(str::to_string)
+    .collect()
+}
+
+/// Packs words (dense text:
+/// characters) into runs of at most
+/// `max_len` as measured by `size`,
+/// splitting a word that alone is
+/// longer.
+fn split_to_size(
   text: &str,
-  max_len: usize
+  max_len: usize,
+  script: Script,
+  size: &dyn Tokenizer
 ) -> Vec<String> {
   if max_len == 0 {
     return Vec::new();
   }
-  if text.len() <= max_len {
+  if size.count(text) <= max_len {
     return vec![text.to_string()];
   }
+  if script == Script::Dense {
+    return split_between_chars(
+      text, max_len, size
+    );
+  }
   let mut out = Vec::new();
   let mut current = String::new();
   for word in text.split_whitespace() {
-    if word.len() > max_len {
+    if size.count(word) > max_len {
       if !current.is_empty() {
         out.push(
           current.trim().to_string()
         );
         current.clear();
       }
-      let mut start = 0usize;
-      for (idx, _) in
-        word.char_indices()
-      {
-        if idx - start >= max_len {
-          out.push(
-            word[start..idx]
-              .to_string()
-          );
-          start = idx;
-        }
-      }
-      if start < word.len() {
-        out.push(
-          word[start..].to_string()
-        );
-      }
+      out.extend(split_between_chars(
+        word, max_len, size
+      ));
       continue;
     }
     if current.is_empty() {
       current.push_str(word);
-    } else if current.len()
-      + 1
-      + word.len()
+    } else if size.count(&current)
+      + size.count(" ")
+      + size.count(word)
       <= max_len
     {
       current.push(' ');
@@ -498,7 +3187,87 @@ fn split_by_max_bytes(
   out
 }
 
+/// Packs whole characters into runs of
+/// at most `max_len` as measured by
+/// `size`, for text with no spaces to
+/// break on.
+fn split_between_chars(
+  text: &str,
+  max_len: usize,
+  size: &dyn Tokenizer
+) -> Vec<String> {
+  let mut out = Vec::new();
+  let mut current = String::new();
+  for ch in text.chars() {
+    if current.is_empty()
+      && ch.is_whitespace()
+    {
+      continue;
+    }
+    current.push(ch);
+    if size.count(&current) > max_len
+      && current.len() > ch.len_utf8()
+    {
+      current.pop();
+      out.push(
+        current.trim().to_string()
+      );
+      current.clear();
+      if !ch.is_whitespace() {
+        current.push(ch);
+      }
+    }
+  }
+  if !current.trim().is_empty() {
+    out
+      .push(current.trim().to_string());
+  }
+  out
+}
+
 fn overlap_tail(
+  text: &str,
+  cfg: &ChunkConfig,
+  script: Script
+) -> String {
+  match cfg.overlap_unit {
+    | OverlapUnit::Chars => {
+      char_tail(
+        text,
+        cfg.chunk_overlap_chars
+      )
+    }
+    // No sentence count: as many whole
+    // sentences as fit the character
+    // budget.
+    | OverlapUnit::Sentences
+      if cfg
+        .chunk_overlap_sentences
+        == 0 =>
+    {
+      sentence_tail(
+        text,
+        usize::MAX,
+        cfg.chunk_overlap_chars,
+        script,
+        &CharCount
+      )
+    }
+    | OverlapUnit::Sentences => {
+      sentence_tail(
+        text,
+        cfg.chunk_overlap_sentences,
+        cfg.max_chunk_chars,
+        script,
+        tokenizer::for_unit(
+          cfg.size_unit
+        )
+      )
+    }
+  }
+}
+
+fn char_tail(
   text: &str,
   overlap: usize
 ) -> String {
@@ -515,11 +3284,58 @@ fn overlap_tail(
   text.chars().skip(start).collect()
 }
 
+/// The last `count` whole sentences of
+/// `text`, dropping the earliest while
+/// they exceed `max_len` as measured by
+/// `size`.
+fn sentence_tail(
+  text: &str,
+  count: usize,
+  max_len: usize,
+  script: Script,
+  size: &dyn Tokenizer
+) -> String {
+  if count == 0 {
+    return String::new();
+  }
+  let starts =
+    sentence_starts(text, script);
+  let first =
+    starts.len().saturating_sub(count);
+  starts[first..]
+    .iter()
+    .map(|&start| text[start..].trim())
+    .find(|tail| {
+      size.count(tail) <= max_len
+    })
+    .unwrap_or_default()
+    .to_string()
+}
+
+/// The file's text and sidecar; with
+/// `chunk.skip_metadata` the sidecar is
+/// never looked up.
+fn read_source(
+  path: &Path,
+  cfg: &ChunkConfig
+) -> anyhow::Result<(String, Value)> {
+  let raw = fs::read_to_string(path)
+    .with_context(|| {
+      format!("read {}", path.display())
+    })?;
+  let file_meta = if cfg.skip_metadata {
+    json!({})
+  } else {
+    load_metadata(path)?
+  };
+  Ok((raw, file_meta))
+}
+
 fn load_metadata(
   path: &Path
 ) -> anyhow::Result<Value> {
   let meta_path =
-    replace_extension(path, "json");
+    sections::sidecar_path(path);
   if !meta_path.exists() {
     return Ok(json!({}));
   }
@@ -530,6 +3346,46 @@ fn load_metadata(
   Ok(value)
 }
 
+/// `chunk.skip_metadata` overrides
+/// `require_metadata`.
+fn requires_metadata(
+  cfg: &ChunkConfig
+) -> bool {
+  cfg.require_metadata
+    && !cfg.skip_metadata
+}
+
+/// Sidecar entries copied into every
+/// chunk's metadata.
+fn included_metadata(
+  file_meta: &Value,
+  cfg: &ChunkConfig
+) -> Map<String, Value> {
+  if cfg.skip_metadata {
+    return Map::new();
+  }
+  file_meta
+    .as_object()
+    .map(|obj| {
+      obj
+        .iter()
+        .filter(|(k, _)| {
+          should_include_metadata(
+            k, cfg
+          )
+        })
+        .map(|(k, v)| {
+          (k.clone(), v.clone())
+        })
+        .collect()
+    })
+    .unwrap_or_default()
+}
+
+/// The `include_*` toggles decide
+/// their own keys; any other key is
+/// copied when `include_keys` lists it,
+/// or always when there is no list.
 fn should_include_metadata(
   key: &str,
   cfg: &ChunkConfig
@@ -550,6 +3406,14 @@ fn should_include_metadata(
     | "language" => {
       cfg.metadata.include_language
     }
-    | _ => true
+    | _ => {
+      cfg
+        .metadata
+        .include_keys
+        .as_ref()
+        .is_none_or(|keys| {
+          keys.iter().any(|k| k == key)
+        })
+    }
   }
 }
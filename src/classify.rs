@@ -0,0 +1,205 @@
+use std::fs;
+use std::path::PathBuf;
+
+use anyhow::{
+  Context,
+  Result
+};
+use clap::Args;
+use serde::Serialize;
+use tracing::{
+  info,
+  warn
+};
+
+use crate::config::{
+  ClassifyMode,
+  Config
+};
+use crate::extract::{
+  self,
+  PdfQuality
+};
+use crate::util::walk_files;
+
+#[derive(Debug, Args)]
+pub struct ClassifyArgs {
+  /// Output mode (human vs machine
+  /// readable)
+  #[arg(long, value_enum)]
+  pub mode: Option<ClassifyMode>,
+
+  /// Also write the full JSON report
+  /// to this file
+  #[arg(long)]
+  pub out: Option<PathBuf>
+}
+
+#[derive(Debug, Serialize)]
+struct PdfClass {
+  path:    PathBuf,
+  #[serde(
+    skip_serializing_if = "Option::is_none"
+  )]
+  quality: Option<PdfQuality>,
+  #[serde(
+    skip_serializing_if = "Option::is_none"
+  )]
+  error:   Option<String>
+}
+
+#[derive(Debug, Default, Serialize)]
+struct ClassifyReport {
+  total:       usize,
+  text:        usize,
+  low_quality: usize,
+  scan:        usize,
+  errors:      usize,
+  files:       Vec<PdfClass>
+}
+
+/// Runs the text-first PDF classifier
+/// over every PDF under `calibre_root`
+/// and reports how many would take the
+/// text, low-quality, and OCR paths.
+pub fn run(
+  config: &Config,
+  args: &ClassifyArgs
+) -> Result<()> {
+  let mode = args
+    .mode
+    .unwrap_or(config.classify.mode);
+  let walked = walk_files(
+    &config.paths.calibre_root,
+    config.paths.log_walk_errors
+  );
+  let mut report =
+    ClassifyReport::default();
+  for path in walked.files {
+    let is_pdf = path
+      .extension()
+      .and_then(|s| s.to_str())
+      .is_some_and(|ext| {
+        ext.eq_ignore_ascii_case("pdf")
+      });
+    if !is_pdf {
+      continue;
+    }
+    report.total += 1;
+    let class = match extract::classify_pdf_quality(
+      &path,
+      &config.extract.pdf,
+      extract::subprocess_timeout(
+        &config.extract
+      )
+    ) {
+      | Ok(quality) => {
+        match quality {
+          | PdfQuality::Text => {
+            report.text += 1
+          }
+          | PdfQuality::LowQuality => {
+            report.low_quality += 1
+          }
+          | PdfQuality::Scan => {
+            report.scan += 1
+          }
+        }
+        PdfClass {
+          path,
+          quality: Some(quality),
+          error: None
+        }
+      }
+      | Err(err) => {
+        warn!(path = %path.display(), error = %err, "pdf classification failed");
+        report.errors += 1;
+        PdfClass {
+          path,
+          quality: None,
+          error: Some(format!("{err:#}"))
+        }
+      }
+    };
+    report.files.push(class);
+  }
+  info!(
+    total = report.total,
+    text = report.text,
+    low_quality = report.low_quality,
+    scan = report.scan,
+    errors = report.errors,
+    walk_errors = walked.errors,
+    "pdf classification complete"
+  );
+
+  if let Some(out) = &args.out {
+    fs::write(
+      out,
+      serde_json::to_vec_pretty(
+        &report
+      )?
+    )
+    .with_context(|| {
+      format!("write {}", out.display())
+    })?;
+  }
+  match mode {
+    | ClassifyMode::Human => {
+      print_human(&report)
+    }
+    | ClassifyMode::Machine => {
+      println!(
+        "{}",
+        serde_json::to_string_pretty(
+          &report
+        )?
+      );
+    }
+  }
+  Ok(())
+}
+
+fn print_human(
+  report: &ClassifyReport
+) {
+  println!("PDFs: {}", report.total);
+  for (label, count) in [
+    ("text", report.text),
+    ("low quality", report.low_quality),
+    ("scan (OCR)", report.scan),
+    ("errors", report.errors)
+  ] {
+    let pct = if report.total == 0 {
+      0.0
+    } else {
+      count as f64 * 100.0
+        / report.total as f64
+    };
+    let bar = "#".repeat(
+      (pct / 2.0).round() as usize
+    );
+    println!(
+      "  {label:<12} {count:>6} \
+       {pct:>5.1}% {bar}"
+    );
+  }
+  for file in &report.files {
+    let class = match (
+      &file.quality,
+      &file.error
+    ) {
+      | (Some(quality), _) => {
+        format!("{quality:?}")
+      }
+      | (None, Some(err)) => {
+        format!("error: {err}")
+      }
+      | (None, None) => String::new()
+    };
+    println!(
+      "{}\t{class}",
+      file.path.display()
+    );
+  }
+}
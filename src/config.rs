@@ -1,23 +1,42 @@
+use std::collections::BTreeMap;
 use std::path::PathBuf;
 
 use clap::ValueEnum;
-use serde::Deserialize;
+use regex::Regex;
+use serde::{
+  Deserialize,
+  Deserializer
+};
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct Config {
-  pub logging:   LoggingConfig,
-  pub paths:     PathsConfig,
-  pub extract:   ExtractConfig,
-  pub chunk:     ChunkConfig,
-  pub insert:    InsertConfig,
+  pub logging:      LoggingConfig,
+  pub paths:        PathsConfig,
+  pub extract:      ExtractConfig,
+  pub chunk:        ChunkConfig,
+  pub insert:       InsertConfig,
+  #[serde(default)]
+  pub dups:         DupsConfig,
+  #[serde(default)]
+  pub dup_stats:    DupsStatsConfig,
+  #[serde(default)]
+  pub classify:     ClassifyConfig,
   #[serde(default)]
-  pub dups:      DupsConfig,
+  pub calibre:      CalibreConfig,
   #[serde(default)]
-  pub dup_stats: DupsStatsConfig,
+  pub dedup:        DupsDedupConfig,
   #[serde(default)]
-  pub calibre:   CalibreConfig,
+  pub pipeline:     PipelineConfig,
   #[serde(default)]
-  pub dedup:     DupsDedupConfig
+  pub export:       ExportConfig,
+  /// Per-invocation identifier, set at
+  /// load time (or from `--run-id`).
+  #[serde(skip)]
+  pub run_id:       String,
+  /// Deprecated settings seen at load
+  /// time, logged once logging is up.
+  #[serde(skip)]
+  pub deprecations: Vec<String>
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -31,30 +50,296 @@ pub struct PathsConfig {
   pub extract_root:     PathBuf,
   pub chunk_root:       PathBuf,
   pub state_dir:        PathBuf,
-  pub examples_cfr_dir: Option<PathBuf>
+  pub examples_cfr_dir: Option<PathBuf>,
+  /// Warn for every entry a walk
+  /// cannot read; the per-stage
+  /// summary counts them either way.
+  #[serde(default = "default_true")]
+  pub log_walk_errors:  bool
 }
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct ExtractConfig {
-  pub extensions:      Vec<String>,
-  pub skip_existing:   bool,
-  pub write_metadata:  bool,
-  pub output_layout:   String,
+  /// Where sources come from.
+  #[serde(default)]
+  pub source: ExtractSource,
+  pub extensions: Vec<String>,
+  pub skip_existing: bool,
+  /// Skip sources whose content
+  /// signature and extract config
+  /// match the last recorded
+  /// extraction.
+  #[serde(default)]
+  pub skip_unchanged: bool,
+  /// Reuse extracted text keyed by the
+  /// source's blake3 digest, across
+  /// renames and duplicate copies.
+  #[serde(default)]
+  pub content_cache: bool,
+  /// Sources extracted concurrently;
+  /// also bounds concurrent docling
+  /// runs on the GPU.
+  #[serde(
+    default = "default_extract_parallel"
+  )]
+  pub max_parallel: usize,
+  /// Per-format worker caps, e.g.
+  /// `{ epub = 16, pdf = 2, default =
+  /// 8 }`, replacing `max_parallel`
+  /// when set.
+  #[serde(default)]
+  pub concurrency:
+    BTreeMap<String, usize>,
+  /// Abort once this many sources
+  /// fail (0 disables).
+  #[serde(default)]
+  pub abort_after_failures: usize,
+  #[serde(default)]
+  pub abort_counting: FailureCounting,
+  /// Kill pandoc, pdftotext, pdfinfo,
+  /// pdffonts, and ebook-convert runs
+  /// that take longer than this (0
+  /// waits forever).
+  #[serde(default)]
+  pub subprocess_timeout_seconds: u64,
+  /// Skip password-protected and
+  /// copy-restricted PDFs instead of
+  /// failing them.
+  #[serde(default)]
+  pub skip_encrypted: bool,
+  /// Remove lines repeated at the top
+  /// or bottom of most pdftotext pages
+  /// (running titles, page numbers).
+  #[serde(default)]
+  pub strip_running_headers: bool,
+  /// Share of pages a line must head
+  /// or foot to count as running.
+  #[serde(
+    default = "default_header_footer_min_ratio"
+  )]
+  pub header_footer_min_ratio: f32,
+  /// Put a `<!-- page: N -->` line
+  /// before each pdftotext page.
+  #[serde(default)]
+  pub emit_page_markers: bool,
+  /// Skip writing a source whose
+  /// extracted text matches one
+  /// already written this run.
+  #[serde(default)]
+  pub dedup_by_content: bool,
+  pub write_metadata: bool,
+  /// Copy the book directory's
+  /// `cover.jpg`/`cover.png` to
+  /// `cover_layout`.
+  #[serde(default)]
+  pub write_cover: bool,
+  pub output_layout: String,
+  /// Extension for pandoc's markdown
+  /// output and its parts, replacing
+  /// the one in `output_layout`.
+  #[serde(
+    default = "default_output_extension"
+  )]
+  pub output_extension: String,
   pub metadata_layout: String,
+  /// Where each run's `manifest.json`
+  /// goes (default `extract_root`).
+  #[serde(default)]
+  pub manifest_path: Option<PathBuf>,
+  #[serde(
+    default = "default_cover_layout"
+  )]
+  pub cover_layout: String,
   pub epub: ExtractEpubConfig,
-  pub pdf:             ExtractPdfConfig
+  pub pdf: ExtractPdfConfig,
+  #[serde(default)]
+  pub mobi: ExtractMobiConfig,
+  #[serde(default)]
+  pub output_format:
+    ExtractOutputFormat
+}
+
+/// How `abort_after_failures` counts
+/// failed files.
+#[derive(
+  Copy,
+  Clone,
+  Debug,
+  Default,
+  Deserialize,
+  PartialEq,
+  Eq,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum FailureCounting {
+  /// Failures in a row; a success
+  /// resets the count.
+  #[default]
+  Consecutive,
+  /// All failures in the run.
+  Total
+}
+
+#[derive(
+  Copy,
+  Clone,
+  Debug,
+  Default,
+  Deserialize,
+  PartialEq,
+  Eq,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum ExtractOutputFormat {
+  /// Plain text/markdown `.txt` files.
+  #[default]
+  Text,
+  /// Pandoc-derived output as
+  /// `.sections.json` with one entry
+  /// per markdown heading; other
+  /// formats stay text.
+  SectionedJson
+}
+
+impl ExtractOutputFormat {
+  pub fn name(self) -> &'static str {
+    match self {
+      | ExtractOutputFormat::Text => {
+        "text"
+      }
+      | ExtractOutputFormat::SectionedJson => {
+        "sectioned_json"
+      }
+    }
+  }
+}
+
+fn default_cover_layout() -> String {
+  "{format}/{title_slug}.jpg"
+    .to_string()
+}
+
+fn default_insert_targets()
+-> Vec<InsertTarget> {
+  vec![
+    InsertTarget::Vector,
+    InsertTarget::Lexical,
+  ]
+}
+
+fn default_extract_parallel() -> usize {
+  1
+}
+
+fn default_header_footer_min_ratio()
+-> f32 {
+  0.6
+}
+
+fn default_output_extension() -> String
+{
+  "txt".to_string()
+}
+
+/// MOBI/AZW/AZW3 conversion through
+/// Calibre's `ebook-convert`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ExtractMobiConfig {
+  #[serde(
+    default = "default_ebook_convert_bin"
+  )]
+  pub ebook_convert_bin: String
+}
+
+impl Default for ExtractMobiConfig {
+  fn default() -> Self {
+    Self {
+      ebook_convert_bin:
+        default_ebook_convert_bin()
+    }
+  }
+}
+
+fn default_ebook_convert_bin() -> String
+{
+  "ebook-convert".to_string()
 }
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct ExtractEpubConfig {
-  pub backend:           String,
-  pub pandoc_bin:        String,
-  pub toc_depth:         u8,
-  pub chapter_split:     bool,
-  pub max_chapter_bytes: u64,
-  pub max_file_bytes:    u64,
-  pub join_parts:        bool,
-  pub keep_parts:        bool
+  pub backend:             String,
+  pub pandoc_bin:          String,
+  pub toc_depth:           u8,
+  pub chapter_split:       bool,
+  pub max_chapter_bytes:   u64,
+  pub max_file_bytes:      u64,
+  pub join_parts:          bool,
+  pub keep_parts:          bool,
+  /// Lines matching this start a new
+  /// chapter part instead of lines
+  /// starting with `#`. Compiled when
+  /// the config loads, so a bad
+  /// pattern fails at startup.
+  #[serde(
+    default,
+    deserialize_with = "deserialize_regex"
+  )]
+  pub split_heading_regex:
+    Option<Regex>,
+  /// Passed to pandoc verbatim, after
+  /// the built-in flags and before
+  /// `--`.
+  #[serde(default)]
+  pub extra_args:          Vec<String>
+}
+
+fn deserialize_regex<'de, D>(
+  deserializer: D
+) -> Result<Option<Regex>, D::Error>
+where
+  D: Deserializer<'de>
+{
+  Option::<String>::deserialize(
+    deserializer
+  )?
+  .map(|pattern| {
+    Regex::new(&pattern).map_err(
+      |err| {
+        serde::de::Error::custom(
+          format!(
+            "invalid regex \
+             {pattern:?}: {err}"
+          )
+        )
+      }
+    )
+  })
+  .transpose()
+}
+
+fn deserialize_regex_list<'de, D>(
+  deserializer: D
+) -> Result<Vec<Regex>, D::Error>
+where
+  D: Deserializer<'de>
+{
+  Vec::<String>::deserialize(
+    deserializer
+  )?
+  .into_iter()
+  .map(|pattern| {
+    Regex::new(&pattern).map_err(
+      |err| {
+        serde::de::Error::custom(
+          format!(
+            "invalid regex \
+             {pattern:?}: {err}"
+          )
+        )
+      }
+    )
+  })
+  .collect()
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -79,6 +364,12 @@ pub struct ExtractPdfConfig {
   pub docling_threads:          usize,
   pub docling_tables:           bool,
   pub docling_table_mode:       String,
+  /// Docling `--to` format: `text`,
+  /// `md`, or `json`.
+  #[serde(
+    default = "default_docling_to"
+  )]
+  pub docling_to:               String,
   pub low_quality_use_ocr:      bool,
   pub low_quality_force_ocr:    bool,
   pub low_quality_tables:       bool,
@@ -94,51 +385,834 @@ pub struct ExtractPdfConfig {
   pub skip_oversize:            bool
 }
 
+fn default_docling_to() -> String {
+  "text".to_string()
+}
+
 #[derive(Debug, Clone, Deserialize)]
 pub struct ChunkConfig {
-  pub normalize_unicode:   bool,
+  /// Unicode normalization applied
+  /// around whitespace collapsing.
+  #[serde(default)]
+  pub unicode_form: UnicodeForm,
+  /// Deprecated: `true` is
+  /// `unicode_form = "nfkc"`, `false`
+  /// is `"none"`. Ignored when
+  /// `unicode_form` is set.
+  #[serde(default)]
+  pub normalize_unicode: Option<bool>,
+  /// Whether Unicode normalization
+  /// runs before or after
+  /// `collapse_whitespace`.
+  #[serde(default)]
+  pub normalize_order: NormalizeOrder,
   pub collapse_whitespace: bool,
-  pub strip_headers:       bool,
+  pub strip_headers: bool,
+  /// Keep markdown headings as a
+  /// `section_path` prepended to each
+  /// chunk under them instead of
+  /// stripping them.
+  #[serde(default)]
+  pub heading_context: bool,
   pub min_paragraph_chars: usize,
   pub max_paragraph_chars: usize,
-  pub target_chunk_chars:  usize,
-  pub max_chunk_chars:     usize,
+  /// Whether `target_chunk_chars` and
+  /// `max_chunk_chars` count UTF-8
+  /// bytes or approximate tokens.
+  #[serde(default)]
+  pub size_unit: SizeUnit,
+  pub target_chunk_chars: usize,
+  pub max_chunk_chars: usize,
+  /// Chunks shorter than this, overlap
+  /// aside, are merged into the one
+  /// before them. Unused by the
+  /// sliding-window strategy.
+  #[serde(default)]
+  pub min_chunk_chars: usize,
   pub chunk_overlap_chars: usize,
-  pub emit_jsonl:          bool,
+  /// Whether overlap is measured in
+  /// characters or whole sentences.
+  #[serde(default)]
+  pub overlap_unit: OverlapUnit,
+  /// Sentences carried into the next
+  /// chunk with `overlap_unit =
+  /// "sentences"`.
+  #[serde(default)]
+  pub chunk_overlap_sentences: usize,
+  /// Inserted between paragraphs and
+  /// sentence runs merged into one
+  /// chunk.
+  #[serde(
+    default = "default_join_separator"
+  )]
+  pub join_separator: String,
+  /// How sentences and oversized runs
+  /// are broken up.
+  #[serde(default)]
+  pub splitter: SplitterMode,
+  /// What separates paragraphs in the
+  /// source text.
+  #[serde(default)]
+  pub paragraph_mode: ParagraphMode,
+  /// How paragraphs are packed into
+  /// chunks.
+  #[serde(default)]
+  pub strategy: ChunkStrategy,
+  /// Write chunk length statistics to
+  /// `chunk_root/stats.json`.
+  #[serde(default)]
+  pub write_stats: bool,
+  /// Treat form feeds as page breaks
+  /// that end a paragraph instead of
+  /// collapsing them to a space.
+  #[serde(default)]
+  pub split_on_formfeed: bool,
+  /// Chunk fenced code blocks and pipe
+  /// tables whole and uncollapsed.
+  #[serde(default)]
+  pub preserve_blocks: bool,
+  /// Drop text outside the
+  /// `boilerplate_start` and
+  /// `boilerplate_end` markers.
+  #[serde(default)]
+  pub strip_boilerplate: bool,
+  /// Lines that end a front-matter
+  /// block such as the Project
+  /// Gutenberg license header.
+  #[serde(
+    default = "default_boilerplate_start",
+    deserialize_with = "deserialize_regex_list"
+  )]
+  pub boilerplate_start: Vec<Regex>,
+  /// Lines that start a trailing
+  /// block such as the Gutenberg
+  /// footer.
+  #[serde(
+    default = "default_boilerplate_end",
+    deserialize_with = "deserialize_regex_list"
+  )]
+  pub boilerplate_end: Vec<Regex>,
+  /// Tag each chunk with the language
+  /// detected in its own text,
+  /// overriding the document's when
+  /// confident.
+  #[serde(default)]
+  pub detect_chunk_language: bool,
+  /// Record the language detected in
+  /// each chunk's text as
+  /// `detected_language`.
+  #[serde(default)]
+  pub detect_language: bool,
+  /// Confidence below which
+  /// `detected_language` is left out.
+  #[serde(
+    default = "default_detect_language_threshold"
+  )]
+  pub detect_language_threshold: f32,
+  /// Files chunked at once (0 = one
+  /// per core).
+  #[serde(default)]
+  pub jobs: usize,
+  /// Random or path-derived chunk ids.
+  #[serde(default)]
+  pub id_mode: ChunkIdMode,
+  pub emit_jsonl: bool,
+  /// File format chunks are written
+  /// in.
+  #[serde(default)]
+  pub output_format: ChunkOutputFormat,
+  /// Treat a missing or empty metadata
+  /// sidecar as a problem, handled per
+  /// `on_missing_metadata`.
+  #[serde(default)]
+  pub require_metadata: bool,
+  #[serde(default)]
+  pub on_missing_metadata:
+    MissingMetadataPolicy,
+  /// Never read sidecars: chunks carry
+  /// only structural metadata (index,
+  /// offsets, source path).
+  /// Overrides `require_metadata`.
+  #[serde(default)]
+  pub skip_metadata: bool,
+  /// Where `chunk --normalize-only`
+  /// writes cleaned text; defaults to
+  /// `paths.chunk_root`.
+  #[serde(default)]
+  pub normalized_root: Option<PathBuf>,
+  /// Skip files whose text, included
+  /// metadata, and chunk config are
+  /// unchanged since the last run; a
+  /// metadata-only change rewrites the
+  /// existing JSONL in place.
+  #[serde(default)]
+  pub skip_unchanged: bool,
+  /// Skip files whose JSONL is newer
+  /// than the text and its sidecar.
+  #[serde(default)]
+  pub skip_existing: bool,
+  /// JSONL keys for a record's id,
+  /// text, and metadata.
+  #[serde(default)]
+  pub field_names: ChunkFieldNames,
   pub metadata: ChunkMetadataConfig
 }
 
+#[derive(
+  Copy,
+  Clone,
+  Debug,
+  Default,
+  Deserialize,
+  PartialEq,
+  Eq,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum UnicodeForm {
+  /// Canonical composition: accents
+  /// compose, ligatures and fractions
+  /// stay.
+  Nfc,
+  /// Compatibility composition: also
+  /// folds ligatures, fractions,
+  /// fullwidth forms, and superscripts.
+  #[default]
+  Nfkc,
+  /// Compatibility decomposition:
+  /// folds like NFKC and leaves
+  /// accents as combining marks.
+  Nfkd,
+  /// Text is kept as extracted.
+  None
+}
+
+#[derive(
+  Copy,
+  Clone,
+  Debug,
+  Default,
+  Deserialize,
+  PartialEq,
+  Eq,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum MissingMetadataPolicy {
+  /// Log a warning and chunk anyway.
+  #[default]
+  Warn,
+  /// Log a warning and emit no chunks
+  /// for the file.
+  Skip,
+  /// Fail the run.
+  Error
+}
+
+#[derive(
+  Copy,
+  Clone,
+  Debug,
+  Default,
+  Deserialize,
+  PartialEq,
+  Eq,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum ChunkStrategy {
+  /// Whole paragraphs and sentences up
+  /// to `target_chunk_chars`.
+  #[default]
+  Paragraph,
+  /// Fixed-width windows over the text
+  /// with a fixed stride.
+  SlidingWindow
+}
+
+#[derive(
+  Copy,
+  Clone,
+  Debug,
+  Default,
+  Deserialize,
+  PartialEq,
+  Eq,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum NormalizeOrder {
+  /// Normalize, then collapse
+  /// whitespace the normalization
+  /// produced.
+  #[default]
+  UnicodeFirst,
+  /// Collapse whitespace, then
+  /// normalize the collapsed text.
+  CollapseFirst
+}
+
+#[derive(
+  Copy,
+  Clone,
+  Debug,
+  Default,
+  Deserialize,
+  PartialEq,
+  Eq,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum ParagraphMode {
+  /// Any line that is empty or only
+  /// whitespace.
+  #[default]
+  BlankLine,
+  /// Every non-empty line, for
+  /// unwrapped text with a paragraph
+  /// per line.
+  SingleLine,
+  /// Only truly empty lines; lines of
+  /// spaces or form feeds are skipped
+  /// without ending the paragraph.
+  DoubleNewline
+}
+
+#[derive(
+  Copy,
+  Clone,
+  Debug,
+  Default,
+  Deserialize,
+  PartialEq,
+  Eq,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum SplitterMode {
+  /// Pick per paragraph from the
+  /// sidecar `language` or the script
+  /// of the text itself.
+  #[default]
+  Auto,
+  /// Split on whitespace and `.!?`.
+  Whitespace,
+  /// Split on CJK sentence punctuation
+  /// and between characters.
+  Cjk
+}
+
+#[derive(
+  Copy,
+  Clone,
+  Debug,
+  Default,
+  Deserialize,
+  PartialEq,
+  Eq,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum ChunkIdMode {
+  /// A fresh random id per chunk.
+  #[default]
+  UuidV4,
+  /// Derived from the source's
+  /// relative path and chunk index, so
+  /// re-chunking yields the same ids.
+  UuidV5
+}
+
+#[derive(
+  Copy,
+  Clone,
+  Debug,
+  Default,
+  Deserialize,
+  PartialEq,
+  Eq,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum ChunkOutputFormat {
+  /// One JSON record per line, read by
+  /// `insert` and `export`.
+  #[default]
+  Jsonl,
+  /// One Parquet file per source for
+  /// external tooling.
+  Parquet
+}
+
+#[derive(
+  Copy,
+  Clone,
+  Debug,
+  Default,
+  Deserialize,
+  PartialEq,
+  Eq,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum ExtractSource {
+  /// Walk `paths.calibre_root`.
+  #[default]
+  Filesystem,
+  /// List and download books from the
+  /// content server at
+  /// `calibre.library_url`.
+  CalibreServer
+}
+
+#[derive(
+  Copy,
+  Clone,
+  Debug,
+  Default,
+  Deserialize,
+  PartialEq,
+  Eq,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum OverlapUnit {
+  /// The last `chunk_overlap_chars`
+  /// characters.
+  #[default]
+  Chars,
+  /// The last `chunk_overlap_sentences`
+  /// whole sentences.
+  Sentences
+}
+
+#[derive(
+  Copy,
+  Clone,
+  Debug,
+  Default,
+  Deserialize,
+  PartialEq,
+  Eq,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum SizeUnit {
+  /// UTF-8 length.
+  #[default]
+  Chars,
+  /// Tokens as estimated by
+  /// `tokenizer::ApproxBpe`.
+  Tokens
+}
+
+/// Keys used for a chunk record's id,
+/// text, and metadata in the JSONL, for
+/// tools expecting e.g.
+/// `page_content`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ChunkFieldNames {
+  pub id:       String,
+  pub text:     String,
+  pub metadata: String
+}
+
+impl Default for ChunkFieldNames {
+  fn default() -> Self {
+    Self {
+      id:       "id".to_string(),
+      text:     "text".to_string(),
+      metadata: "metadata".to_string()
+    }
+  }
+}
+
+fn default_join_separator() -> String {
+  " ".to_string()
+}
+
+fn default_boilerplate_start()
+-> Vec<Regex> {
+  vec![
+    Regex::new(
+      r"(?i)^\*{3}\s*START OF\b.*\*{3}$"
+    )
+    .expect("valid regex"),
+  ]
+}
+
+fn default_boilerplate_end()
+-> Vec<Regex> {
+  vec![
+    Regex::new(
+      r"(?i)^\*{3}\s*END OF\b.*\*{3}$"
+    )
+    .expect("valid regex"),
+  ]
+}
+
+fn default_detect_language_threshold()
+-> f32 {
+  crate::language::CONFIDENT
+}
+
 #[derive(Debug, Clone, Deserialize)]
 pub struct ChunkMetadataConfig {
-  pub include_source_path: bool,
-  pub include_calibre_id:  bool,
-  pub include_title:       bool,
-  pub include_authors:     bool,
-  pub include_published:   bool,
-  pub include_language:    bool
+  pub include_source_path:     bool,
+  pub include_calibre_id:      bool,
+  pub include_title:           bool,
+  pub include_authors:         bool,
+  pub include_published:       bool,
+  pub include_language:        bool,
+  /// Add `char_count` and `word_count`
+  /// to every chunk.
+  #[serde(default = "default_true")]
+  pub include_counts:          bool,
+  /// Add `para_start` and `para_end`,
+  /// the paragraph indices a chunk
+  /// spans.
+  #[serde(default)]
+  pub include_paragraph_range: bool,
+  /// Track pages from page markers and
+  /// form feeds, dropping them from
+  /// chunk text, and add `page_start`
+  /// and `page_end`.
+  #[serde(default)]
+  pub include_pages:           bool,
+  /// Add `source_blake3`, the digest
+  /// of the extracted file being
+  /// chunked.
+  #[serde(default)]
+  pub include_source_hash:     bool,
+  /// Other sidecar keys to copy; every
+  /// key when unset.
+  #[serde(default)]
+  pub include_keys: Option<Vec<String>>
 }
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct InsertConfig {
-  pub batch_size:         usize,
-  pub retry_max:          usize,
-  pub retry_backoff_ms:   u64,
+  pub batch_size: usize,
+  pub retry_max: usize,
+  pub retry_backoff_ms: u64,
   pub max_parallel_files: usize,
+  #[serde(default)]
+  pub min_alpha_ratio: f32,
+  /// Abort once this many files fail
+  /// outright (0 disables).
+  #[serde(default)]
+  pub abort_after_failures: usize,
+  #[serde(default)]
+  pub abort_counting: FailureCounting,
+  #[serde(
+    default = "default_write_queue_depth"
+  )]
+  pub write_queue_depth: usize,
+  /// After `retry_max` retries, write
+  /// a record that still fails to
+  /// embed to `state_dir/
+  /// failed_embeddings.jsonl`
+  /// and carry on without it.
+  #[serde(default)]
+  pub record_failed_embeddings: bool,
+  /// Upsert a chunk's own `embedding`
+  /// vector, when it has one, instead
+  /// of calling the embedder.
+  #[serde(default)]
+  pub use_precomputed_embeddings: bool,
+  /// Skip chunk files a previous run
+  /// fully inserted, as recorded in
+  /// `state_dir/insert_state.jsonl`,
+  /// while their content and the
+  /// stores they went to are the same.
+  #[serde(default)]
+  pub skip_completed: bool,
+  /// Write records that fail to parse,
+  /// embed, or store to
+  /// `state_dir/dead_letter.jsonl`
+  /// with the error, and carry on with
+  /// the next batch instead of failing
+  /// the file.
+  #[serde(default)]
+  pub dead_letter: bool,
+  #[serde(default)]
+  pub http: InsertHttpConfig,
+  /// Where vectors are upserted.
+  #[serde(default)]
+  pub vector_store: VectorStore,
   pub qdrant: InsertQdrantConfig,
+  /// Required with `vector_store =
+  /// "pgvector"`.
+  #[serde(default)]
+  pub pgvector:
+    Option<InsertPgvectorConfig>,
   pub quickwit: InsertQuickwitConfig,
+  /// Where chunk text is indexed for
+  /// keyword search.
+  #[serde(default)]
+  pub lexical_store: LexicalStore,
+  /// Required with `lexical_store =
+  /// "elasticsearch"`.
+  #[serde(default)]
+  pub elasticsearch:
+    Option<InsertElasticsearchConfig>,
+  /// Which sides a run writes; leave
+  /// one out to rebuild only the
+  /// other.
+  #[serde(
+    default = "default_insert_targets"
+  )]
+  pub targets: Vec<InsertTarget>,
   pub embeddings:
     InsertEmbeddingsConfig
 }
 
+/// Settings for the HTTP client every
+/// `insert` request goes through.
+#[derive(
+  Debug, Clone, Default, Deserialize,
+)]
+pub struct InsertHttpConfig {
+  /// PEM client certificate (chain)
+  /// presented for mutual TLS.
+  pub client_cert: Option<PathBuf>,
+  /// PEM private key for
+  /// `client_cert`.
+  pub client_key:  Option<PathBuf>
+}
+
 #[derive(Debug, Clone, Deserialize)]
 pub struct InsertQdrantConfig {
-  pub url:               String,
-  pub collection:        String,
-  pub distance:          String,
-  pub vector_size:       usize,
-  pub create_collection: bool,
-  pub api_key:           Option<String>,
-  pub wait:              bool
+  pub url:                  String,
+  pub collection:           String,
+  pub distance:             String,
+  pub vector_size:          usize,
+  pub create_collection:    bool,
+  pub api_key: Option<String>,
+  pub wait:                 bool,
+  #[serde(
+    default = "default_store_concurrency"
+  )]
+  pub max_concurrency:      usize,
+  #[serde(default)]
+  pub collection_params:
+    QdrantCollectionParams,
+  /// Metadata key whose value picks
+  /// the collection:
+  /// `<collection>_<value>`.
+  #[serde(default)]
+  pub route_by: Option<String>,
+  /// Name of a sparse vector stored
+  /// next to the dense one in each
+  /// point, declared with the `idf`
+  /// modifier on collection create.
+  #[serde(default)]
+  pub sparse_vector: Option<String>,
+  /// How points are upserted;
+  /// collections are always checked
+  /// and created over REST.
+  #[serde(default)]
+  pub transport: QdrantTransport,
+  /// gRPC endpoint for `transport =
+  /// "grpc"`; defaults to `url` on
+  /// port 6334.
+  #[serde(default)]
+  pub grpc_url: Option<String>,
+  /// Deadline for each gRPC request.
+  #[serde(
+    default = "default_qdrant_grpc_timeout"
+  )]
+  pub grpc_timeout_seconds: u64
+}
+
+#[derive(
+  Copy,
+  Clone,
+  Debug,
+  Default,
+  Deserialize,
+  PartialEq,
+  Eq,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum QdrantTransport {
+  /// JSON over the REST API.
+  #[default]
+  Rest,
+  /// Protobuf over gRPC, with floats
+  /// sent as binary.
+  Grpc
+}
+
+impl QdrantTransport {
+  pub fn name(self) -> &'static str {
+    match self {
+      | QdrantTransport::Rest => "rest",
+      | QdrantTransport::Grpc => "grpc"
+    }
+  }
+}
+
+/// Creation-time collection settings;
+/// unset fields use the server
+/// defaults.
+#[derive(
+  Default, Debug, Clone, Deserialize,
+)]
+pub struct QdrantCollectionParams {
+  pub shard_number: Option<u32>,
+  pub replication_factor: Option<u32>,
+  pub write_consistency_factor:
+    Option<u32>,
+  pub on_disk_payload: Option<bool>,
+  pub on_disk_vectors: Option<bool>,
+  pub scalar_quantization:
+    Option<QdrantScalarQuantization>
+}
+
+/// Store that receives the vectors;
+/// Quickwit gets the text either way.
+#[derive(
+  Copy,
+  Clone,
+  Debug,
+  Default,
+  Deserialize,
+  PartialEq,
+  Eq,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum VectorStore {
+  #[default]
+  Qdrant,
+  /// A Postgres table with the
+  /// `vector` extension.
+  Pgvector
+}
+
+impl VectorStore {
+  pub fn name(self) -> &'static str {
+    match self {
+      | VectorStore::Qdrant => "qdrant",
+      | VectorStore::Pgvector => {
+        "pgvector"
+      }
+    }
+  }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct InsertPgvectorConfig {
+  /// libpq-style connection string,
+  /// `host=... user=... dbname=...`
+  /// or `postgres://...`.
+  pub url:             String,
+  /// Possibly schema-qualified, e.g.
+  /// `public.chunks`.
+  pub table:           String,
+  pub vector_size:     usize,
+  /// Create the `vector` extension and
+  /// the table on the first write when
+  /// they are missing.
+  #[serde(default)]
+  pub create_table:    bool,
+  #[serde(
+    default = "default_store_concurrency"
+  )]
+  pub max_concurrency: usize
+}
+
+/// Store that receives the chunk text
+/// for keyword search.
+#[derive(
+  Copy,
+  Clone,
+  Debug,
+  Default,
+  Deserialize,
+  PartialEq,
+  Eq,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum LexicalStore {
+  #[default]
+  Quickwit,
+  /// Elasticsearch or OpenSearch
+  /// through the `_bulk` API.
+  Elasticsearch
+}
+
+impl LexicalStore {
+  pub fn name(self) -> &'static str {
+    match self {
+      | LexicalStore::Quickwit => {
+        "quickwit"
+      }
+      | LexicalStore::Elasticsearch => {
+        "elasticsearch"
+      }
+    }
+  }
+}
+
+/// A side of `insert.targets`: the
+/// vector store (`vector_store`) or
+/// the lexical one (`lexical_store`).
+/// Either can also be named by its
+/// store.
+#[derive(
+  Copy,
+  Clone,
+  Debug,
+  Deserialize,
+  PartialEq,
+  Eq,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum InsertTarget {
+  #[serde(
+    alias = "qdrant",
+    alias = "pgvector"
+  )]
+  Vector,
+  #[serde(
+    alias = "quickwit",
+    alias = "elasticsearch"
+  )]
+  Lexical
+}
+
+impl InsertTarget {
+  pub fn name(self) -> &'static str {
+    match self {
+      | InsertTarget::Vector => {
+        "vector"
+      }
+      | InsertTarget::Lexical => {
+        "lexical"
+      }
+    }
+  }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct InsertElasticsearchConfig {
+  pub url:             String,
+  pub index:           String,
+  /// Sent as `Authorization: ApiKey`.
+  #[serde(default)]
+  pub api_key:         Option<String>,
+  /// Basic auth, as OpenSearch usually
+  /// wants; ignored with `api_key`.
+  #[serde(default)]
+  pub username:        Option<String>,
+  #[serde(default)]
+  pub password:        Option<String>,
+  /// `refresh` for each bulk request:
+  /// `true`, `false`, or `wait_for`.
+  #[serde(default)]
+  pub refresh:         Option<String>,
+  #[serde(
+    default = "default_store_concurrency"
+  )]
+  pub max_concurrency: usize
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct QdrantScalarQuantization {
+  pub quantile:   Option<f32>,
+  #[serde(default)]
+  pub always_ram: bool
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -147,12 +1221,45 @@ pub struct InsertQuickwitConfig {
   pub index_id:               String,
   pub commit_timeout_seconds: u64,
   pub commit_mode:            String,
-  pub commit_at_end:          bool
+  pub commit_at_end:          bool,
+  #[serde(
+    default = "default_store_concurrency"
+  )]
+  pub max_concurrency:        usize,
+  /// Metadata fields copied into a
+  /// lowercased top-level `<field>_lc`
+  /// for case-insensitive matching on
+  /// a `raw` tokenizer field.
+  #[serde(default)]
+  pub lowercase_fields: Vec<String>,
+  /// Also add `embed_model` and
+  /// `embed_dim` to each document's
+  /// metadata, as Qdrant payloads
+  /// always get.
+  #[serde(default)]
+  pub stamp_embed_model:      bool,
+  /// Ingest with `commit=force` so
+  /// each batch is searchable as
+  /// soon as the request returns,
+  /// whatever `commit_mode` says.
+  #[serde(default)]
+  pub force_commit:           bool
+}
+
+fn default_write_queue_depth() -> usize
+{
+  8
+}
+
+fn default_store_concurrency() -> usize
+{
+  4
 }
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct InsertEmbeddingsConfig {
-  pub provider:                String,
+  #[serde(default)]
+  pub provider: EmbeddingProvider,
   pub base_url:                String,
   pub model:                   String,
   pub request_timeout_seconds: u64,
@@ -160,19 +1267,140 @@ pub struct InsertEmbeddingsConfig {
   pub max_input_chars:         usize,
   pub global_max_concurrency:  usize,
   pub request_batch_size:      usize,
-  pub cache_max_entries:       usize
+  /// Cap on the summed text bytes of
+  /// one embed request, on top of
+  /// `request_batch_size`; 0 disables.
+  #[serde(default)]
+  pub max_request_bytes:       usize,
+  pub cache_max_entries:       usize,
+  /// JSON file the embedding cache is
+  /// loaded from at startup and saved
+  /// to once `insert` completes.
+  #[serde(default)]
+  pub cache_path: Option<PathBuf>,
+  /// Send one tiny embed request
+  /// before `insert` starts so an
+  /// idle embedder loads the model
+  /// first.
+  #[serde(default)]
+  pub warmup:                  bool,
+  #[serde(
+    default = "default_warmup_timeout"
+  )]
+  pub warmup_timeout_seconds:  u64,
+  /// Sent as `Authorization: Bearer`,
+  /// or raw under `api_key_header`.
+  #[serde(default)]
+  pub api_key: Option<String>,
+  /// Header for `api_key` instead of
+  /// a bearer token, e.g. Azure's
+  /// `api-key`.
+  #[serde(default)]
+  pub api_key_header: Option<String>,
+  /// Prepended to every chunk sent to
+  /// the embedder (not stored), e.g.
+  /// e5's `passage: `.
+  #[serde(default)]
+  pub document_prefix:         String,
+  /// The matching prefix for search
+  /// queries, e.g. e5's `query: `.
+  #[serde(default)]
+  pub query_prefix:            String,
+  /// `"base64"` asks an `openai`
+  /// embedder for base64 vectors.
+  #[serde(default)]
+  pub encoding_format:
+    EmbeddingEncoding
+}
+
+/// Vector encoding requested from an
+/// OpenAI-compatible embedder.
+#[derive(
+  Copy,
+  Clone,
+  Debug,
+  Default,
+  Deserialize,
+  PartialEq,
+  Eq,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum EmbeddingEncoding {
+  /// No `encoding_format` is sent, so
+  /// the server returns JSON floats.
+  #[default]
+  Float,
+  /// Sends `encoding_format:
+  /// "base64"`.
+  Base64
+}
+
+/// Request and response shape of the
+/// embedding server.
+#[derive(
+  Copy,
+  Clone,
+  Debug,
+  Default,
+  Deserialize,
+  PartialEq,
+  Eq,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum EmbeddingProvider {
+  /// `POST /api/embeddings` with
+  /// `{model, prompt}`.
+  #[default]
+  Ollama,
+  /// OpenAI-compatible
+  /// `POST /embeddings` with
+  /// `{model, input}` (OpenAI, vLLM,
+  /// Azure).
+  Openai,
+  /// Hugging Face
+  /// text-embeddings-inference:
+  /// `POST /embed` with `{inputs}`.
+  Tei
+}
+
+impl EmbeddingProvider {
+  pub fn name(self) -> &'static str {
+    match self {
+      | EmbeddingProvider::Ollama => {
+        "ollama"
+      }
+      | EmbeddingProvider::Openai => {
+        "openai"
+      }
+      | EmbeddingProvider::Tei => "tei"
+    }
+  }
+}
+
+fn default_warmup_timeout() -> u64 {
+  300
+}
+
+fn default_qdrant_grpc_timeout() -> u64
+{
+  60
 }
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct DupsConfig {
   pub output: DupsOutputFormat,
-  pub out:              Option<PathBuf>,
-  pub ext:              Vec<String>,
-  pub follow_symlinks:  bool,
-  pub threads:          usize,
-  pub min_size:         u64,
-  pub include_sidecars: bool,
-  pub hash_algorithm:   HashAlgorithm
+  pub out: Option<PathBuf>,
+  pub ext:               Vec<String>,
+  pub follow_symlinks:   bool,
+  pub threads:           usize,
+  pub min_size:          u64,
+  pub include_sidecars:  bool,
+  pub hash_algorithm:    HashAlgorithm,
+  /// gzip/zstd level for a `.gz` or
+  /// `.zst` `out` path; the tool's
+  /// default when unset.
+  #[serde(default)]
+  pub compression_level: Option<u32>
 }
 
 impl Default for DupsConfig {
@@ -180,20 +1408,21 @@ impl Default for DupsConfig {
     Self {
       output:
         DupsOutputFormat::Json,
-      out:              None,
-      ext:              vec![
+      out:               None,
+      ext:               vec![
         "epub", "mobi", "azw3", "pdf",
         "djvu",
       ]
       .into_iter()
       .map(String::from)
       .collect(),
-      follow_symlinks:  false,
-      threads:          8,
-      min_size:         1024,
-      include_sidecars: false,
+      follow_symlinks:   false,
+      threads:           8,
+      min_size:          1024,
+      include_sidecars:  false,
       hash_algorithm:
-        HashAlgorithm::XxHash64
+        HashAlgorithm::XxHash64,
+      compression_level: None
     }
   }
 }
@@ -241,6 +1470,34 @@ pub enum DupsStatsMode {
   Machine
 }
 
+#[derive(
+  Default, Debug, Clone, Deserialize,
+)]
+pub struct ClassifyConfig {
+  /// Default `classify-pdfs --mode`.
+  #[serde(default)]
+  pub mode: ClassifyMode
+}
+
+#[derive(
+  Copy,
+  Clone,
+  Debug,
+  Default,
+  Deserialize,
+  PartialEq,
+  Eq,
+  ValueEnum,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum ClassifyMode {
+  /// A histogram and per-file lines.
+  #[default]
+  Human,
+  /// The JSON report.
+  Machine
+}
+
 #[derive(
   Copy,
   Clone,
@@ -309,25 +1566,121 @@ impl Default for ScoringConfig {
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct DupsDedupConfig {
-  pub min_size: u64,
-  pub dry_run:  bool
+  pub min_size:  u64,
+  pub dry_run:   bool,
+  /// Leave a group alone when its top
+  /// two candidates score the same.
+  #[serde(default)]
+  pub skip_ties: bool
 }
 
 impl Default for DupsDedupConfig {
   fn default() -> Self {
     Self {
-      min_size: 1024,
-      dry_run:  false
+      min_size:  1024,
+      dry_run:   false,
+      skip_ties: false
+    }
+  }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct PipelineConfig {
+  /// Hand extracted text straight to
+  /// the chunker instead of reading it
+  /// back from `extract_root`.
+  #[serde(default)]
+  pub in_memory:            bool,
+  /// Still write extracted text and
+  /// metadata to disk when `in_memory`
+  /// is set.
+  #[serde(default = "default_true")]
+  pub write_extract_output: bool
+}
+
+impl Default for PipelineConfig {
+  fn default() -> Self {
+    Self {
+      in_memory:            false,
+      write_extract_output: true
+    }
+  }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ExportConfig {
+  /// Rows per Parquet shard written by
+  /// `export`.
+  #[serde(
+    default = "default_shard_records"
+  )]
+  pub shard_records: usize
+}
+
+impl Default for ExportConfig {
+  fn default() -> Self {
+    Self {
+      shard_records:
+        default_shard_records()
     }
   }
 }
 
+fn default_shard_records() -> usize {
+  10_000
+}
+
+fn default_true() -> bool {
+  true
+}
+
 pub fn load(
   path: &PathBuf
 ) -> anyhow::Result<Config> {
   let raw =
     std::fs::read_to_string(path)?;
-  let config: Config =
+  let mut config: Config =
     toml::from_str(&raw)?;
+  config.run_id =
+    uuid::Uuid::new_v4().to_string();
+  if let Some(flag) =
+    config.chunk.normalize_unicode
+  {
+    let table: toml::Table =
+      toml::from_str(&raw)?;
+    let explicit = table
+      .get("chunk")
+      .and_then(|c| {
+        c.get("unicode_form")
+      })
+      .is_some();
+    if explicit {
+      config.deprecations.push(
+        "chunk.normalize_unicode is \
+         deprecated and ignored next \
+         to chunk.unicode_form"
+          .to_string()
+      );
+    } else {
+      config.chunk.unicode_form =
+        if flag {
+          UnicodeForm::Nfkc
+        } else {
+          UnicodeForm::None
+        };
+      config.deprecations.push(
+        format!(
+          "chunk.normalize_unicode is \
+           deprecated, use \
+           chunk.unicode_form = \"{}\"",
+          if flag {
+            "nfkc"
+          } else {
+            "none"
+          }
+        )
+      );
+    }
+  }
   Ok(config)
 }
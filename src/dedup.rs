@@ -1,5 +1,9 @@
 use std::collections::HashSet;
 use std::fs;
+use std::io::{
+  self,
+  Write
+};
 use std::path::{
   Path,
   PathBuf
@@ -12,6 +16,7 @@ use anyhow::{
   bail
 };
 use clap::Args;
+use serde::Serialize;
 use serde_json::Value;
 use tracing::{
   info,
@@ -19,6 +24,7 @@ use tracing::{
 };
 
 use crate::calibre_metadata::{
+  Snapshot,
   metadata_snapshot,
   score_good_enough
 };
@@ -28,6 +34,7 @@ use crate::config::{
   PathsConfig
 };
 use crate::dups::DuplicateGroup;
+use crate::util::read_report_file;
 
 #[derive(Debug, Args)]
 pub struct DedupArgs {
@@ -43,18 +50,171 @@ pub struct DedupArgs {
 
   /// Dry-run mode (never delete)
   #[arg(long)]
-  pub dry_run: bool
+  pub dry_run: bool,
+
+  /// Write every skipped group and the
+  /// reason it was skipped to this
+  /// JSON file
+  #[arg(long)]
+  pub skip_report: Option<PathBuf>,
+
+  /// Write every group's candidates,
+  /// scores, and keep/remove decision
+  /// to this JSON file
+  #[arg(long)]
+  pub decision_report: Option<PathBuf>,
+
+  /// Show each group and ask on stdin
+  /// which copy to keep before
+  /// anything is removed
+  #[arg(long)]
+  pub review: bool
 }
 
 struct DedupSettings {
-  min_size: u64,
-  dry_run:  bool
+  min_size:  u64,
+  dry_run:   bool,
+  skip_ties: bool
+}
+
+#[derive(
+  Debug, Clone, Copy, Serialize,
+)]
+#[serde(rename_all = "snake_case")]
+enum SkipReason {
+  /// Below `min_size`.
+  TooSmall,
+  /// At most one usable copy (ids
+  /// unparsable or already removed).
+  InsufficientCandidates,
+  /// At most one usable copy because
+  /// metadata fetches failed.
+  MetadataErrors,
+  /// Top candidates tied with
+  /// `skip_ties` set.
+  NoClearKeeper,
+  /// Skipped at the `--review` prompt.
+  ReviewSkipped
+}
+
+#[derive(Debug, Serialize)]
+struct SkippedGroup {
+  reason: SkipReason,
+  detail: String,
+  bytes:  u64,
+  hash:   String,
+  files:  Vec<PathBuf>
+}
+
+impl SkippedGroup {
+  fn new(
+    group: &DuplicateGroup,
+    reason: SkipReason,
+    detail: String
+  ) -> Self {
+    info!(
+      hash = %group.hash,
+      reason = ?reason,
+      detail = %detail,
+      "dedup group skipped"
+    );
+    Self {
+      reason,
+      detail,
+      bytes: group.bytes,
+      hash: group.hash.clone(),
+      files: group.files.clone()
+    }
+  }
 }
 
 struct Candidate {
-  id:    u64,
-  path:  PathBuf,
-  score: i32
+  id:      u64,
+  path:    PathBuf,
+  score:   i32,
+  missing: Vec<String>,
+  /// Title, authors, publisher, and
+  /// date, shown by `--review`.
+  summary: String
+}
+
+/// Answer to a `--review` prompt.
+enum Review {
+  /// Keep the top-scored candidate.
+  Apply,
+  /// Keep only this candidate index.
+  KeepOnly(usize),
+  KeepAll,
+  Skip,
+  /// Stop; later groups are left
+  /// untouched.
+  Quit
+}
+
+#[derive(
+  Debug, Clone, Copy, Serialize,
+)]
+#[serde(rename_all = "snake_case")]
+enum Decision {
+  Keep,
+  Remove
+}
+
+#[derive(Debug, Serialize)]
+struct CandidateDecision {
+  book_id:         u64,
+  path:            PathBuf,
+  score:           i32,
+  missing_reasons: Vec<String>,
+  decision:        Decision
+}
+
+/// One group as `dedup` saw it. Every
+/// candidate of a skipped group is
+/// kept.
+#[derive(Debug, Serialize)]
+struct GroupDecision {
+  hash:       String,
+  bytes:      u64,
+  skipped:    Option<SkipReason>,
+  candidates: Vec<CandidateDecision>
+}
+
+impl GroupDecision {
+  fn new(
+    group: &DuplicateGroup,
+    skipped: Option<SkipReason>,
+    candidates: &[Candidate],
+    removed: &HashSet<u64>
+  ) -> Self {
+    Self {
+      hash: group.hash.clone(),
+      bytes: group.bytes,
+      skipped,
+      candidates: candidates
+        .iter()
+        .map(|cand| {
+          CandidateDecision {
+            book_id:         cand.id,
+            path:            cand
+              .path
+              .clone(),
+            score:           cand.score,
+            missing_reasons: cand
+              .missing
+              .clone(),
+            decision:        if removed
+              .contains(&cand.id)
+            {
+              Decision::Remove
+            } else {
+              Decision::Keep
+            }
+          }
+        })
+        .collect()
+    }
+  }
 }
 
 struct CalibreTarget {
@@ -71,11 +231,12 @@ pub fn run(
   args: &DedupArgs
 ) -> Result<()> {
   let settings = DedupSettings {
-    min_size: args
+    min_size:  args
       .min_size
       .unwrap_or(config.dedup.min_size),
-    dry_run:  args.dry_run
-      || config.dedup.dry_run
+    dry_run:   args.dry_run
+      || config.dedup.dry_run,
+    skip_ties: config.dedup.skip_ties
   };
   let connection =
     CalibreConnection::new(
@@ -84,7 +245,7 @@ pub fn run(
     )?;
 
   let raw =
-    fs::read_to_string(&args.input)
+    read_report_file(&args.input)
       .with_context(|| {
         format!(
           "read {}",
@@ -103,22 +264,50 @@ pub fn run(
   let mut removed_ids = HashSet::new();
   let mut total_removed = 0usize;
   let mut total_removed_bytes = 0u64;
+  let mut skipped = Vec::new();
+  let mut decisions = Vec::new();
+  let no_removals = HashSet::new();
 
-  for group in groups {
+  let group_count = groups.len();
+  for (group_idx, group) in
+    groups.into_iter().enumerate()
+  {
     if group.bytes < settings.min_size {
+      skipped.push(SkippedGroup::new(
+        &group,
+        SkipReason::TooSmall,
+        format!(
+          "{} bytes < min_size {}",
+          group.bytes,
+          settings.min_size
+        )
+      ));
+      decisions.push(
+        GroupDecision::new(
+          &group,
+          Some(SkipReason::TooSmall),
+          &[],
+          &no_removals
+        )
+      );
       continue;
     }
 
     let mut candidates = Vec::new();
+    let mut unparsable = 0usize;
+    let mut already_removed = 0usize;
+    let mut metadata_errors = 0usize;
     for path in &group.files {
       let Some(id) =
         extract_book_id(path.as_path())
       else {
         warn!(path = %path.display(), "unable to parse calibre book id");
+        unparsable += 1;
         continue;
       };
 
       if removed_ids.contains(&id) {
+        already_removed += 1;
         continue;
       }
 
@@ -146,7 +335,11 @@ pub fn run(
           candidates.push(Candidate {
             id,
             path: path.clone(),
-            score
+            score,
+            missing: reasons,
+            summary: summarize(
+              &snapshot
+            )
           });
         }
         | Err(err) => {
@@ -156,28 +349,151 @@ pub fn run(
             error = %err,
             "skipping duplicate due to metadata fetch error"
           );
+          metadata_errors += 1;
         }
       }
     }
 
     if candidates.len() <= 1 {
+      let reason = if metadata_errors
+        > 0
+      {
+        SkipReason::MetadataErrors
+      } else {
+        SkipReason::InsufficientCandidates
+      };
+      skipped.push(SkippedGroup::new(
+        &group,
+        reason,
+        format!(
+          "{} usable of {} files \
+           ({unparsable} unparsable \
+           ids, {already_removed} \
+           already removed, \
+           {metadata_errors} metadata \
+           errors)",
+          candidates.len(),
+          group.files.len()
+        )
+      ));
+      decisions.push(
+        GroupDecision::new(
+          &group,
+          Some(reason),
+          &candidates,
+          &no_removals
+        )
+      );
       continue;
     }
 
+    // Highest score wins; ties go to
+    // the alphabetically first
+    // path, then the lowest book
+    // id, so reruns pick
+    // the same keeper.
     candidates.sort_by(|a, b| {
-      b.score.cmp(&a.score).then_with(
-        || a.path.cmp(&b.path)
-      )
+      b.score
+        .cmp(&a.score)
+        .then_with(|| {
+          a.path.cmp(&b.path)
+        })
+        .then_with(|| a.id.cmp(&b.id))
     });
     let keep = &candidates[0];
+    if settings.skip_ties
+      && candidates[1].score
+        == keep.score
+    {
+      skipped.push(SkippedGroup::new(
+        &group,
+        SkipReason::NoClearKeeper,
+        format!(
+          "top candidates {} and {} \
+           both score {}",
+          keep.id,
+          candidates[1].id,
+          keep.score
+        )
+      ));
+      decisions.push(
+        GroupDecision::new(
+          &group,
+          Some(
+            SkipReason::NoClearKeeper
+          ),
+          &candidates,
+          &no_removals
+        )
+      );
+      continue;
+    }
+    if args.review {
+      let keep_idx = match review_group(
+        (group_idx + 1, group_count),
+        &group,
+        &candidates
+      )? {
+        | Review::Apply => 0,
+        | Review::KeepOnly(idx) => idx,
+        | Review::KeepAll => {
+          decisions.push(
+            GroupDecision::new(
+              &group,
+              None,
+              &candidates,
+              &no_removals
+            )
+          );
+          continue;
+        }
+        | Review::Skip => {
+          skipped.push(
+            SkippedGroup::new(
+              &group,
+              SkipReason::ReviewSkipped,
+              "skipped in review"
+                .to_string()
+            )
+          );
+          decisions
+            .push(GroupDecision::new(
+            &group,
+            Some(
+              SkipReason::ReviewSkipped
+            ),
+            &candidates,
+            &no_removals
+          ));
+          continue;
+        }
+        | Review::Quit => {
+          info!(
+            reviewed = group_idx,
+            remaining =
+              group_count - group_idx,
+            "dedup review stopped"
+          );
+          break;
+        }
+      };
+      let chosen =
+        candidates.remove(keep_idx);
+      candidates.insert(0, chosen);
+    }
+    let keep = &candidates[0];
     info!(
       group_bytes = group.bytes,
       keep_id = keep.id,
       keep_path = %keep.path.display(),
       keep_score = keep.score,
+      tie_broken = candidates[1].score
+        == keep.score,
       "keeping duplicate candidate"
     );
 
+    let mut group_removed =
+      HashSet::new();
     for cand in
       candidates.iter().skip(1)
     {
@@ -207,15 +523,43 @@ pub fn run(
       }
 
       removed_ids.insert(cand.id);
+      group_removed.insert(cand.id);
       total_removed += 1;
       total_removed_bytes +=
         group.bytes;
     }
+    decisions.push(GroupDecision::new(
+      &group,
+      None,
+      &candidates,
+      &group_removed
+    ));
+  }
+
+  if let Some(path) = &args.skip_report
+  {
+    write_report(path, &skipped)?;
+    info!(
+      path = %path.display(),
+      groups = skipped.len(),
+      "dedup skip report written"
+    );
+  }
+  if let Some(path) =
+    &args.decision_report
+  {
+    write_report(path, &decisions)?;
+    info!(
+      path = %path.display(),
+      groups = decisions.len(),
+      "dedup decision report written"
+    );
   }
 
   info!(
     removed_files = total_removed,
     removed_bytes = total_removed_bytes,
+    skipped_groups = skipped.len(),
     mode = if settings.dry_run {
       "dry-run"
     } else {
@@ -226,6 +570,141 @@ pub fn run(
   Ok(())
 }
 
+/// One line of `--review` context
+/// for a candidate.
+fn summarize(
+  snapshot: &Snapshot
+) -> String {
+  let mut out =
+    if snapshot.title.is_empty() {
+      "(untitled)".to_string()
+    } else {
+      snapshot.title.clone()
+    };
+  if !snapshot.authors.is_empty() {
+    out.push_str(" by ");
+    out.push_str(
+      &snapshot.authors.join(", ")
+    );
+  }
+  let details = [
+    &snapshot.publisher,
+    &snapshot.pubdate
+  ]
+  .into_iter()
+  .filter(|s| !s.is_empty())
+  .map(String::as_str)
+  .collect::<Vec<_>>();
+  if !details.is_empty() {
+    out.push_str(&format!(
+      " ({})",
+      details.join(", ")
+    ));
+  }
+  out
+}
+
+/// Prints `group` with its ranked
+/// candidates and reads the reviewer's
+/// choice from stdin, asking again on
+/// unrecognized input. End of input
+/// counts as quitting.
+fn review_group(
+  (position, total): (usize, usize),
+  group: &DuplicateGroup,
+  candidates: &[Candidate]
+) -> Result<Review> {
+  let mut out = io::stdout().lock();
+  writeln!(
+    out,
+    "\n== Group {position}/{total}: \
+     {} files | {} bytes | hash {} ==",
+    group.files.len(),
+    group.bytes,
+    group.hash
+  )?;
+  for (idx, cand) in
+    candidates.iter().enumerate()
+  {
+    writeln!(
+      out,
+      "  [{}] {} score {} | book {} | \
+       {}",
+      idx + 1,
+      if idx == 0 {
+        "keep  "
+      } else {
+        "remove"
+      },
+      cand.score,
+      cand.id,
+      cand.path.display()
+    )?;
+    writeln!(
+      out,
+      "      {}",
+      cand.summary
+    )?;
+    if !cand.missing.is_empty() {
+      writeln!(
+        out,
+        "      missing: {}",
+        cand.missing.join(", ")
+      )?;
+    }
+  }
+  let stdin = io::stdin();
+  loop {
+    write!(
+      out,
+      "[a]pply recommendation, [k]eep \
+       all, [s]kip, [1-{}] keep only \
+       that copy, [q]uit: ",
+      candidates.len()
+    )?;
+    out.flush()?;
+    let mut line = String::new();
+    if stdin.read_line(&mut line)? == 0
+    {
+      writeln!(out)?;
+      return Ok(Review::Quit);
+    }
+    let answer =
+      line.trim().to_ascii_lowercase();
+    match answer.as_str() {
+      | "a" => return Ok(Review::Apply),
+      | "k" => {
+        return Ok(Review::KeepAll);
+      }
+      | "s" => return Ok(Review::Skip),
+      | "q" => return Ok(Review::Quit),
+      | _ => {}
+    }
+    if let Ok(n) =
+      answer.parse::<usize>()
+      && (1..=candidates.len())
+        .contains(&n)
+    {
+      return Ok(Review::KeepOnly(
+        n - 1
+      ));
+    }
+  }
+}
+
+fn write_report<T: Serialize>(
+  path: &Path,
+  report: &T
+) -> Result<()> {
+  fs::write(
+    path,
+    serde_json::to_vec_pretty(report)?
+  )
+  .with_context(|| {
+    format!("write {}", path.display())
+  })
+}
+
 impl CalibreConnection {
   fn new(
     calibre: &CalibreConfig,
@@ -1,4 +1,3 @@
-use std::fs;
 use std::path::PathBuf;
 
 use anyhow::{
@@ -13,6 +12,7 @@ use crate::config::{
   DupsStatsMode
 };
 use crate::dups::DuplicateGroup;
+use crate::util::read_report_file;
 
 #[derive(Debug, Args)]
 pub struct DupStatsArgs {
@@ -50,7 +50,7 @@ pub fn run(
     .mode
     .unwrap_or(config.dup_stats.mode);
   let data =
-    fs::read_to_string(&args.input)
+    read_report_file(&args.input)
       .with_context(|| {
         format!(
           "read duplicate report {}",
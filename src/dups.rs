@@ -1,13 +1,22 @@
-use std::collections::HashMap;
-use std::fs::File;
+use std::collections::{
+  HashMap,
+  HashSet
+};
+use std::fs::{
+  self,
+  File
+};
 use std::io::{
+  BufRead,
   BufReader,
-  Read
+  Read,
+  Write
 };
 use std::path::{
   Path,
   PathBuf
 };
+use std::sync::Mutex;
 use std::time::Instant;
 
 use anyhow::{
@@ -36,6 +45,7 @@ use crate::config::{
   DupsOutputFormat,
   HashAlgorithm
 };
+use crate::util::write_report_file;
 
 #[derive(Debug, Args)]
 pub struct DupsArgs {
@@ -75,22 +85,42 @@ pub struct DupsArgs {
   /// Include Calibre sidecar files
   /// like metadata.opf/cover.jpg
   #[arg(long, default_value_t = false)]
-  pub include_sidecars: bool
+  pub include_sidecars: bool,
+
+  /// Continue an interrupted scan from
+  /// state_dir/dups_checkpoint.jsonl
+  #[arg(long, default_value_t = false)]
+  pub resume: bool,
+
+  /// Compression level when --out ends
+  /// in .gz or .zst (falls back to
+  /// config.dups.compression_level)
+  #[arg(long)]
+  pub compression_level: Option<u32>
 }
 
 #[derive(Debug, Clone)]
 pub struct DupsSettings {
   pub output: DupsOutputFormat,
-  pub out:              Option<PathBuf>,
-  pub ext:              Vec<String>,
-  pub follow_symlinks:  bool,
-  pub threads:          usize,
-  pub min_size:         u64,
-  pub include_sidecars: bool,
-  pub hash_algorithm:   HashAlgorithm
+  pub out: Option<PathBuf>,
+  pub ext:               Vec<String>,
+  pub follow_symlinks:   bool,
+  pub threads:           usize,
+  pub min_size:          u64,
+  pub include_sidecars:  bool,
+  pub hash_algorithm:    HashAlgorithm,
+  /// Where hashed paths are recorded
+  /// while scanning.
+  pub checkpoint: Option<PathBuf>,
+  pub resume:            bool,
+  /// gzip/zstd level for a `.gz` or
+  /// `.zst` report path.
+  pub compression_level: Option<u32>
 }
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(
+  Debug, Clone, Serialize, Deserialize,
+)]
 struct FileInfo {
   path:  PathBuf,
   bytes: u64,
@@ -149,7 +179,19 @@ pub fn run(
       || config.dups.include_sidecars,
     hash_algorithm: config
       .dups
-      .hash_algorithm
+      .hash_algorithm,
+    checkpoint: Some(
+      config
+        .paths
+        .state_dir
+        .join(CHECKPOINT_FILE)
+    ),
+    resume: args.resume,
+    compression_level: args
+      .compression_level
+      .or(
+        config.dups.compression_level
+      )
   };
 
   run_dups(&library_root, &settings)
@@ -205,20 +247,71 @@ pub fn run_dups(
       "Starting duplicate scan"
   );
 
-  let candidates = collect_candidates(
-    library,
-    &exts,
-    settings.follow_symlinks,
-    settings.min_size,
-    settings.include_sidecars
-  )?;
+  let (candidates, walk_errors) =
+    collect_candidates(
+      library,
+      &exts,
+      settings.follow_symlinks,
+      settings.min_size,
+      settings.include_sidecars
+    )?;
 
   info!(
     count = candidates.len(),
+    walk_errors,
     "Collected candidate files"
   );
 
-  let hashed: Vec<FileInfo> = candidates
+  let checkpoint =
+    match &settings.checkpoint {
+      | Some(path) => {
+        Some(Checkpoint::open(
+          path,
+          library,
+          settings.hash_algorithm,
+          settings.resume
+        )?)
+      }
+      | None => None
+    };
+  // Reuse checkpointed hashes only for
+  // files still present at the same
+  // size.
+  let present: HashSet<&Path> =
+    candidates
+      .iter()
+      .map(PathBuf::as_path)
+      .collect();
+  let mut hashed = checkpoint
+    .as_ref()
+    .map(|c| c.done.clone())
+    .unwrap_or_default();
+  hashed.retain(|f| {
+    present.contains(f.path.as_path())
+      && f.path.metadata().is_ok_and(
+        |md| md.len() == f.bytes
+      )
+  });
+  let done: HashSet<&Path> = hashed
+    .iter()
+    .map(|f| f.path.as_path())
+    .collect();
+  let pending: Vec<&PathBuf> =
+    candidates
+      .iter()
+      .filter(|p| {
+        !done.contains(p.as_path())
+      })
+      .collect();
+  if !done.is_empty() {
+    info!(
+      resumed = done.len(),
+      remaining = pending.len(),
+      "Resuming from checkpoint"
+    );
+  }
+
+  let fresh: Vec<FileInfo> = pending
         .par_iter()
         .map(|path| hash_one(path, settings.hash_algorithm))
         .filter_map(|r| match r {
@@ -228,7 +321,13 @@ pub fn run_dups(
                 None
             }
         })
+        .inspect(|info| {
+            if let Some(checkpoint) = &checkpoint {
+                checkpoint.record(info);
+            }
+        })
         .collect();
+  hashed.extend(fresh);
 
   info!(
     count = hashed.len(),
@@ -236,6 +335,9 @@ pub fn run_dups(
   );
 
   let dupes = find_duplicates(hashed);
+  if let Some(checkpoint) = checkpoint {
+    checkpoint.finish()?;
+  }
 
   info!(
     groups = dupes.len(),
@@ -246,22 +348,168 @@ pub fn run_dups(
 
   match settings.output {
     | DupsOutputFormat::Text => {
-      print_text(
-        &dupes,
-        settings.out.as_deref()
-      )?
+      print_text(&dupes, settings)?
     }
     | DupsOutputFormat::Json => {
-      print_json(
-        &dupes,
-        settings.out.as_deref()
-      )?
+      print_json(&dupes, settings)?
     }
   }
 
   Ok(())
 }
 
+const CHECKPOINT_FILE: &str =
+  "dups_checkpoint.jsonl";
+
+/// First line of a checkpoint; a resume
+/// only reuses hashes from the same
+/// library and algorithm.
+#[derive(
+  Debug,
+  Serialize,
+  Deserialize,
+  PartialEq,
+)]
+struct CheckpointHeader {
+  library:        PathBuf,
+  hash_algorithm: String
+}
+
+/// Hashed files, appended one JSON line
+/// per file as the scan progresses.
+struct Checkpoint {
+  path: PathBuf,
+  file: Mutex<File>,
+  done: Vec<FileInfo>
+}
+
+impl Checkpoint {
+  fn open(
+    path: &Path,
+    library: &Path,
+    algo: HashAlgorithm,
+    resume: bool
+  ) -> Result<Self> {
+    let header = CheckpointHeader {
+      library:        library
+        .to_path_buf(),
+      hash_algorithm: format!(
+        "{algo:?}"
+      )
+    };
+    let done = if resume {
+      Self::load(path, &header)
+    } else {
+      Vec::new()
+    };
+    if let Some(parent) = path.parent()
+    {
+      fs::create_dir_all(parent)?;
+    }
+    let mut file = File::create(path)
+      .with_context(
+      || {
+        format!(
+          "Failed to create {}",
+          path.display()
+        )
+      }
+    )?;
+    writeln!(
+      file,
+      "{}",
+      serde_json::to_string(&header)?
+    )?;
+    for info in &done {
+      writeln!(
+        file,
+        "{}",
+        serde_json::to_string(info)?
+      )?;
+    }
+    Ok(Self {
+      path: path.to_path_buf(),
+      file: Mutex::new(file),
+      done
+    })
+  }
+
+  fn load(
+    path: &Path,
+    header: &CheckpointHeader
+  ) -> Vec<FileInfo> {
+    let Ok(file) = File::open(path)
+    else {
+      warn!(path = %path.display(), "No checkpoint to resume from");
+      return Vec::new();
+    };
+    let mut lines =
+      BufReader::new(file).lines();
+    let stored = lines
+      .next()
+      .and_then(|l| l.ok())
+      .and_then(|l| {
+        serde_json::from_str::<
+          CheckpointHeader
+        >(&l)
+        .ok()
+      });
+    if stored.as_ref() != Some(header) {
+      warn!(path = %path.display(), "Checkpoint is for a different scan; starting over");
+      return Vec::new();
+    }
+    // A torn final line from an
+    // interrupted write is skipped; a
+    // path hashed twice keeps its
+    // latest entry.
+    let mut by_path = HashMap::new();
+    for info in lines
+      .map_while(|l| l.ok())
+      .filter_map(|l| {
+        serde_json::from_str::<FileInfo>(
+          &l
+        )
+        .ok()
+      })
+    {
+      by_path
+        .insert(info.path.clone(), info);
+    }
+    by_path.into_values().collect()
+  }
+
+  fn record(
+    &self,
+    info: &FileInfo
+  ) {
+    let Ok(line) =
+      serde_json::to_string(info)
+    else {
+      return;
+    };
+    let mut file =
+      self.file.lock().unwrap();
+    if let Err(e) =
+      writeln!(file, "{line}")
+    {
+      warn!(error = %e, "Failed to write checkpoint");
+    }
+  }
+
+  /// The scan completed; nothing left
+  /// to resume.
+  fn finish(self) -> Result<()> {
+    drop(self.file);
+    fs::remove_file(&self.path)
+      .with_context(|| {
+        format!(
+          "Failed to remove {}",
+          self.path.display()
+        )
+      })
+  }
+}
+
 fn default_exts() -> Vec<String> {
   [
     "epub", "pdf", "mobi", "azw",
@@ -333,8 +581,9 @@ fn collect_candidates(
   follow_symlinks: bool,
   min_size: u64,
   include_sidecars: bool
-) -> Result<Vec<PathBuf>> {
+) -> Result<(Vec<PathBuf>, usize)> {
   let mut out = Vec::new();
+  let mut errors = 0usize;
 
   let walker = WalkDir::new(library)
     .follow_links(follow_symlinks)
@@ -345,6 +594,7 @@ fn collect_candidates(
       | Ok(e) => e,
       | Err(e) => {
         warn!(error = %e, "WalkDir error");
+        errors += 1;
         continue;
       }
     };
@@ -363,7 +613,7 @@ fn collect_candidates(
     }
   }
 
-  Ok(out)
+  Ok((out, errors))
 }
 
 fn hash_one(
@@ -509,7 +759,7 @@ fn find_duplicates(
 
 fn print_text(
   groups: &[DuplicateGroup],
-  out: Option<&Path>
+  settings: &DupsSettings
 ) -> Result<()> {
   let mut buf = String::new();
   if groups.is_empty() {
@@ -542,38 +792,41 @@ fn print_text(
       buf.push('\n');
     }
   }
-  write_output(&buf, out)?;
+  write_output(&buf, settings)?;
   Ok(())
 }
 
 fn print_json(
   groups: &[DuplicateGroup],
-  out: Option<&Path>
+  settings: &DupsSettings
 ) -> Result<()> {
   let s = serde_json::to_string_pretty(
     groups
   )?;
-  write_output(&s, out)?;
+  write_output(&s, settings)?;
   Ok(())
 }
 
+/// Writes the report to `--out`,
+/// compressed when the path ends in
+/// `.gz` or `.zst`, or to stdout.
 fn write_output(
   contents: &str,
-  out: Option<&Path>
+  settings: &DupsSettings
 ) -> Result<()> {
-  if let Some(path) = out {
-    let mut file =
-      std::fs::File::create(path)
-        .with_context(|| {
-          format!(
-            "Failed to create {}",
-            path.display()
-          )
-        })?;
-    use std::io::Write;
-    file
-      .write_all(contents.as_bytes())?;
-    file.write_all(b"\n")?;
+  if let Some(path) = &settings.out {
+    write_report_file(
+      path,
+      format!("{contents}\n")
+        .as_bytes(),
+      settings.compression_level
+    )
+    .with_context(|| {
+      format!(
+        "Failed to write {}",
+        path.display()
+      )
+    })?;
   } else {
     println!("{contents}");
   }
@@ -0,0 +1,582 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{
+  Path,
+  PathBuf
+};
+
+use anyhow::{
+  Context,
+  anyhow
+};
+use clap::Args;
+use serde::Serialize;
+use serde::ser::SerializeMap;
+use serde_json::{
+  Value,
+  json
+};
+use tracing::{
+  info,
+  warn
+};
+
+use crate::chunk;
+use crate::config::{
+  ChunkFieldNames,
+  Config
+};
+use crate::parquet::{
+  self,
+  Column,
+  Values
+};
+use crate::util::walk_files;
+
+const SPLIT: &str = "train";
+const DATA_DIR: &str = "data";
+const INFOS_FILE: &str =
+  "dataset_infos.json";
+
+#[derive(Debug, Args)]
+pub struct ExportArgs {
+  /// Dataset directory: Parquet shards
+  /// under `data/` and
+  /// `dataset_infos.json`
+  #[arg(long)]
+  pub out: PathBuf
+}
+
+/// Column type inferred from every
+/// chunk's value for one metadata key.
+#[derive(
+  Debug, Clone, Copy, PartialEq, Eq,
+)]
+enum Kind {
+  Bool,
+  Int,
+  Float,
+  Str,
+  StrList,
+  /// Mixed or nested values, written
+  /// as JSON text.
+  Json
+}
+
+impl Kind {
+  fn of(value: &Value) -> Option<Self> {
+    Some(match value {
+      | Value::Null => return None,
+      | Value::Bool(_) => Kind::Bool,
+      | Value::Number(n)
+        if n.is_i64() =>
+      {
+        Kind::Int
+      }
+      | Value::Number(_) => Kind::Float,
+      | Value::String(_) => Kind::Str,
+      | Value::Array(items)
+        if items
+          .iter()
+          .all(Value::is_string) =>
+      {
+        Kind::StrList
+      }
+      | _ => Kind::Json
+    })
+  }
+
+  fn merge(
+    self,
+    other: Self
+  ) -> Self {
+    match (self, other) {
+      | (a, b) if a == b => a,
+      | (Kind::Int, Kind::Float)
+      | (Kind::Float, Kind::Int) => {
+        Kind::Float
+      }
+      | _ => Kind::Json
+    }
+  }
+
+  /// The `datasets` feature for the
+  /// column.
+  fn feature(self) -> Value {
+    let value = |dtype: &str| json!({ "dtype": dtype, "_type": "Value" });
+    match self {
+      | Kind::Bool => value("bool"),
+      | Kind::Int => value("int64"),
+      | Kind::Float => value("float64"),
+      | Kind::Str | Kind::Json => {
+        value("string")
+      }
+      | Kind::StrList => {
+        json!({
+            "feature": value("string"),
+            "_type": "Sequence",
+        })
+      }
+    }
+  }
+}
+
+/// A flattened metadata column: the
+/// metadata key and its column name.
+struct Field {
+  key:  String,
+  name: String,
+  kind: Kind
+}
+
+/// Writes every chunk under
+/// `chunk_root` as a Hugging Face
+/// dataset: a `train` split in Parquet
+/// shards of `export.shard_records`
+/// rows with `id`, `text`, and one
+/// column per metadata key, plus the
+/// `dataset_infos.json` features that
+/// `datasets.load_dataset` reads.
+pub fn run(
+  config: &Config,
+  args: &ExportArgs
+) -> anyhow::Result<()> {
+  let names = &config.chunk.field_names;
+  let walked = walk_files(
+    &config.paths.chunk_root,
+    config.paths.log_walk_errors
+  );
+  let mut files = walked
+    .files
+    .into_iter()
+    .filter(|path| {
+      !path.starts_with(&args.out)
+        && matches!(
+          path
+            .extension()
+            .and_then(|s| s.to_str()),
+          Some("jsonl" | "parquet")
+        )
+    })
+    .collect::<Vec<_>>();
+  files.sort();
+
+  let mut kinds: BTreeMap<
+    String,
+    Kind
+  > = BTreeMap::new();
+  let mut total = 0usize;
+  for_each_record(
+    &files,
+    names,
+    |record| {
+      total += 1;
+      if let Some(meta) =
+        record.metadata.as_object()
+      {
+        for (key, value) in meta {
+          if let Some(kind) =
+            Kind::of(value)
+          {
+            kinds
+              .entry(key.clone())
+              .and_modify(|k| {
+                *k = k.merge(kind)
+              })
+              .or_insert(kind);
+          }
+        }
+      }
+      Ok(())
+    }
+  )?;
+  if total == 0 {
+    return Err(anyhow!(
+      "no chunks under {}",
+      config.paths.chunk_root.display()
+    ));
+  }
+  let fields = kinds
+    .into_iter()
+    .map(|(key, kind)| {
+      let name = if key == "id"
+        || key == "text"
+      {
+        format!("metadata_{key}")
+      } else {
+        key.clone()
+      };
+      Field {
+        key,
+        name,
+        kind
+      }
+    })
+    .collect::<Vec<_>>();
+
+  let per_shard =
+    config.export.shard_records.max(1);
+  let shards =
+    total.div_ceil(per_shard);
+  let data_dir =
+    args.out.join(DATA_DIR);
+  fs::create_dir_all(&data_dir)?;
+  clear_old_shards(&data_dir)?;
+
+  let mut batch = Vec::new();
+  let mut written = 0usize;
+  let mut bytes = 0u64;
+  let mut flush =
+    |batch: &mut Vec<
+      chunk::ChunkRecord
+    >|
+     -> anyhow::Result<()> {
+      let path = data_dir.join(
+        shard_name(written, shards)
+      );
+      parquet::write_file(
+        &path,
+        &columns(batch, &fields)
+      )?;
+      bytes +=
+        fs::metadata(&path)?.len();
+      written += 1;
+      batch.clear();
+      Ok(())
+    };
+  for_each_record(
+    &files,
+    names,
+    |record| {
+      batch.push(record);
+      if batch.len() == per_shard {
+        flush(&mut batch)?;
+      }
+      Ok(())
+    }
+  )?;
+  if !batch.is_empty() {
+    flush(&mut batch)?;
+  }
+
+  let mut features = vec![
+    (
+      "id".to_string(),
+      Kind::Str.feature()
+    ),
+    (
+      "text".to_string(),
+      Kind::Str.feature()
+    ),
+  ];
+  features.extend(fields.iter().map(
+    |f| {
+      (f.name.clone(), f.kind.feature())
+    }
+  ));
+  let infos = BTreeMap::from([(
+    "default",
+    DatasetInfo {
+      description:   "",
+      citation:      "",
+      homepage:      "",
+      license:       "",
+      features:      OrderedMap(
+        &features
+      ),
+      builder_name:  "parquet",
+      config_name:   "default",
+      splits:        BTreeMap::from([
+        (SPLIT, SplitInfo {
+          name:         SPLIT,
+          num_bytes:    bytes,
+          num_examples: total
+        })
+      ]),
+      download_size: bytes,
+      dataset_size:  bytes
+    }
+  )]);
+  let infos_path =
+    args.out.join(INFOS_FILE);
+  fs::write(
+    &infos_path,
+    serde_json::to_vec_pretty(&infos)?
+  )
+  .with_context(|| {
+    format!(
+      "write {}",
+      infos_path.display()
+    )
+  })?;
+  info!(
+    rows = total,
+    shards = written,
+    columns = features.len(),
+    walk_errors = walked.errors,
+    out = %args.out.display(),
+    "export complete"
+  );
+  Ok(())
+}
+
+/// `train-00000-of-00003.parquet`, the
+/// naming `datasets` maps to a split.
+fn shard_name(
+  idx: usize,
+  shards: usize
+) -> String {
+  format!(
+    "{SPLIT}-{idx:05}-of-{shards:05}.\
+     parquet"
+  )
+}
+
+/// Reads every chunk of `files` in
+/// order, from JSONL lines or the
+/// Parquet files `chunk.output_format
+/// = "parquet"` writes.
+fn for_each_record(
+  files: &[PathBuf],
+  names: &ChunkFieldNames,
+  mut f: impl FnMut(
+    chunk::ChunkRecord
+  ) -> anyhow::Result<()>
+) -> anyhow::Result<()> {
+  for path in files {
+    if path.extension()
+      == Some("parquet".as_ref())
+    {
+      for record in
+        chunk::read_chunks_parquet(
+          path, names
+        )?
+      {
+        f(record)?;
+      }
+      continue;
+    }
+    let raw = fs::read_to_string(path)
+      .with_context(|| {
+        format!(
+          "read {}",
+          path.display()
+        )
+      })?;
+    for (idx, line) in
+      raw.lines().enumerate()
+    {
+      if line.trim().is_empty() {
+        continue;
+      }
+      let value =
+        match serde_json::from_str(line)
+        {
+          | Ok(value) => value,
+          | Err(err) => {
+            warn!(path = %path.display(), line = idx + 1, error = %err, "skip unreadable chunk");
+            continue;
+          }
+        };
+      let value =
+        chunk::canonical_record(
+          value, names
+        );
+      f(chunk::ChunkRecord {
+        id:       value["id"]
+          .as_str()
+          .unwrap_or_default()
+          .to_string(),
+        text:     value["text"]
+          .as_str()
+          .unwrap_or_default()
+          .to_string(),
+        metadata: value["metadata"]
+          .clone()
+      })?;
+    }
+  }
+  Ok(())
+}
+
+fn columns(
+  batch: &[chunk::ChunkRecord],
+  fields: &[Field]
+) -> Vec<Column> {
+  let mut columns = vec![
+    Column {
+      name:     "id".to_string(),
+      required: true,
+      values:   Values::Utf8(
+        batch
+          .iter()
+          .map(|r| Some(r.id.clone()))
+          .collect()
+      )
+    },
+    Column {
+      name:     "text".to_string(),
+      required: true,
+      values:   Values::Utf8(
+        batch
+          .iter()
+          .map(|r| Some(r.text.clone()))
+          .collect()
+      )
+    },
+  ];
+  for field in fields {
+    let cells = batch.iter().map(|r| {
+      r.metadata
+        .get(&field.key)
+        .filter(|v| !v.is_null())
+    });
+    let values = match field.kind {
+      | Kind::Bool => {
+        Values::Boolean(
+          cells
+            .map(|v| {
+              v.and_then(Value::as_bool)
+            })
+            .collect()
+        )
+      }
+      | Kind::Int => {
+        Values::Int64(
+          cells
+            .map(|v| {
+              v.and_then(Value::as_i64)
+            })
+            .collect()
+        )
+      }
+      | Kind::Float => {
+        Values::Double(
+          cells
+            .map(|v| {
+              v.and_then(Value::as_f64)
+            })
+            .collect()
+        )
+      }
+      | Kind::Str => {
+        Values::Utf8(
+          cells
+            .map(|v| {
+              v.and_then(Value::as_str)
+                .map(str::to_string)
+            })
+            .collect()
+        )
+      }
+      | Kind::StrList => {
+        Values::Utf8List(
+          cells
+            .map(|v| {
+              v.and_then(
+                Value::as_array
+              )
+              .map(
+                |items| {
+                  items
+                    .iter()
+                    .filter_map(
+                      Value::as_str
+                    )
+                    .map(str::to_string)
+                    .collect()
+                }
+              )
+            })
+            .collect()
+        )
+      }
+      | Kind::Json => {
+        Values::Utf8(
+          cells
+            .map(|v| {
+              v.map(Value::to_string)
+            })
+            .collect()
+        )
+      }
+    };
+    columns.push(Column {
+      name: field.name.clone(),
+      required: false,
+      values
+    });
+  }
+  columns
+}
+
+/// Removes shards from an earlier
+/// export so a smaller corpus does not
+/// leave stale files behind.
+fn clear_old_shards(
+  data_dir: &Path
+) -> anyhow::Result<()> {
+  for entry in fs::read_dir(data_dir)? {
+    let path = entry?.path();
+    let is_shard = path
+      .file_name()
+      .and_then(|n| n.to_str())
+      .is_some_and(|name| {
+        name.starts_with(&format!(
+          "{SPLIT}-"
+        )) && name.ends_with(".parquet")
+      });
+    if is_shard {
+      fs::remove_file(&path)?;
+    }
+  }
+  Ok(())
+}
+
+/// One config's entry in
+/// `dataset_infos.json`.
+#[derive(Serialize)]
+struct DatasetInfo<'a> {
+  description:   &'static str,
+  citation:      &'static str,
+  homepage:      &'static str,
+  license:       &'static str,
+  features:      OrderedMap<'a>,
+  builder_name:  &'static str,
+  config_name:   &'static str,
+  splits:
+    BTreeMap<&'static str, SplitInfo>,
+  download_size: u64,
+  dataset_size:  u64
+}
+
+#[derive(Serialize)]
+struct SplitInfo {
+  name:         &'static str,
+  num_bytes:    u64,
+  num_examples: usize
+}
+
+/// Serializes pairs as a JSON object in
+/// their given order, so `features`
+/// lists `id` and `text` first.
+struct OrderedMap<'a>(
+  &'a [(String, Value)]
+);
+
+impl Serialize for OrderedMap<'_> {
+  fn serialize<S: serde::Serializer>(
+    &self,
+    serializer: S
+  ) -> Result<S::Ok, S::Error> {
+    let mut map = serializer
+      .serialize_map(Some(
+        self.0.len()
+      ))?;
+    for (key, value) in self.0 {
+      map
+        .serialize_entry(key, value)?;
+    }
+    map.end()
+  }
+}
@@ -1,3 +1,13 @@
+use std::cell::{
+  Cell,
+  RefCell
+};
+use std::collections::{
+  BTreeMap,
+  BTreeSet,
+  HashMap,
+  HashSet
+};
 use std::ffi::OsStr;
 use std::fs;
 use std::io::Write;
@@ -5,97 +15,2223 @@ use std::path::{
   Path,
   PathBuf
 };
-use std::process::Command;
+use std::process::{
+  Command,
+  Output,
+  Stdio
+};
+use std::sync::{
+  Condvar,
+  Mutex,
+  MutexGuard,
+  PoisonError
+};
+use std::time::{
+  Duration,
+  UNIX_EPOCH
+};
+
+use anyhow::{
+  Context,
+  anyhow
+};
+use chrono::Utc;
+use clap::Args;
+use quick_xml::Reader;
+use quick_xml::events::{
+  BytesStart,
+  Event
+};
+use rayon::prelude::*;
+use regex::Regex;
+use serde::{
+  Deserialize,
+  Serialize
+};
+use serde_json::Value;
+use tracing::{
+  debug,
+  info,
+  warn
+};
+
+use crate::calibre_server::{
+  self,
+  CalibreServer,
+  ServerBook
+};
+use crate::config::{
+  Config,
+  ExtractConfig,
+  ExtractEpubConfig,
+  ExtractMobiConfig,
+  ExtractOutputFormat,
+  ExtractPdfConfig,
+  ExtractSource,
+  FailureCounting
+};
+use crate::sections;
+use crate::util::{
+  FailureGate,
+  JsonlAppender,
+  PhaseTimers,
+  alpha_counts,
+  apply_layout,
+  blake3_file,
+  output_with_timeout,
+  slugify,
+  status_with_timeout,
+  walk_files
+};
+
+#[derive(
+  Debug, Default, Serialize, Clone,
+)]
+struct ExtractedMetadata {
+  source_path:  String,
+  format:       String,
+  title:        Option<String>,
+  authors:      Vec<String>,
+  language:     Option<String>,
+  published:    Option<String>,
+  identifiers:  Vec<String>,
+  calibre_id:   Option<String>,
+  series:       Option<String>,
+  series_index: Option<f32>,
+  extracted_at: String,
+  /// Copied cover, relative to
+  /// `paths.extract_root`.
+  #[serde(
+    skip_serializing_if = "Option::is_none"
+  )]
+  cover_path:   Option<String>
+}
+
+/// Text destined for one output path
+/// under `paths.extract_root`.
+#[derive(Debug, Clone)]
+pub struct ExtractedText {
+  pub path: PathBuf,
+  pub text: String
+}
+
+/// Result of extracting one source
+/// file. Nothing is written until
+/// `write_extracted` runs.
+#[derive(Debug, Clone)]
+pub struct Extracted {
+  pub source:  PathBuf,
+  pub format:  String,
+  pub outputs: Vec<ExtractedText>,
+  kept_parts:  Vec<ExtractedText>,
+  metadata:    ExtractedMetadata,
+  backend:     &'static str,
+  pdf_quality: Option<PdfQuality>,
+  /// Cover image to copy, and where.
+  cover: Option<(PathBuf, PathBuf)>
+}
+
+impl Extracted {
+  /// The sidecar metadata `chunk` would
+  /// load for each output.
+  pub fn metadata_json(&self) -> Value {
+    serde_json::to_value(&self.metadata)
+      .unwrap_or_default()
+  }
+}
+
+#[derive(Debug, Default, Args)]
+pub struct ExtractArgs {
+  /// Ignore the extract state file and
+  /// extract every source again
+  #[arg(long)]
+  pub force: bool,
+
+  /// Stop starting files after the
+  /// first failure and exit with its
+  /// error
+  #[arg(long)]
+  pub fail_fast: bool
+}
+
+/// What `run_with` hands the in-memory
+/// chunker for one source.
+pub enum Handoff<'a> {
+  /// Text extracted by this run.
+  Extracted(&'a Extracted),
+  /// Outputs an earlier run left on
+  /// disk, for a skipped source.
+  Existing(&'a [PathBuf])
+}
+
+/// `pipeline.in_memory`: each source's
+/// text goes to `on_source` as it is
+/// extracted, before the state records
+/// it, so a failed handoff fails the
+/// source and it is retried next run.
+pub struct InMemory<'a> {
+  /// Also write the extracted files,
+  /// as a plain run does.
+  pub write_output: bool,
+  pub on_source: &'a (
+        dyn Fn(
+    Handoff<'_>
+  ) -> anyhow::Result<()>
+          + Sync
+      )
+}
+
+const STATE_FILE: &str =
+  "extract_state.jsonl";
+
+/// The single-document state written
+/// before the state became a log; read
+/// when no log exists yet.
+const LEGACY_STATE_FILE: &str =
+  "extract_state.json";
+
+/// Last successful extraction per
+/// source, so an interrupted run
+/// resumes where it stopped.
+/// `extract.skip_unchanged` adds the
+/// content signature. On disk it is
+/// one JSON line per recorded source;
+/// later lines win.
+#[derive(
+  Debug, Default, Serialize, Deserialize,
+)]
+struct ExtractState {
+  entries: BTreeMap<String, StateEntry>
+}
+
+#[derive(
+  Debug, Clone, Serialize, Deserialize,
+)]
+struct StateEntry {
+  #[serde(default)]
+  mtime_ns:    u64,
+  #[serde(default)]
+  size:        u64,
+  #[serde(default)]
+  signature:   Option<String>,
+  config_hash: String,
+  outputs:     Vec<PathBuf>,
+  #[serde(default)]
+  run_id:      String
+}
+
+/// One line of the extract state log.
+#[derive(
+  Debug, Clone, Serialize, Deserialize,
+)]
+struct StateLine {
+  source: String,
+  #[serde(flatten)]
+  entry:  StateEntry
+}
+
+impl ExtractState {
+  /// Replays the log at `path`, or
+  /// reads the legacy document at
+  /// `legacy` when there is no log yet.
+  fn load(
+    path: &Path,
+    legacy: &Path
+  ) -> Self {
+    let Ok(raw) =
+      fs::read_to_string(path)
+    else {
+      return Self::load_legacy(legacy);
+    };
+    let mut state = Self::default();
+    for line in raw.lines() {
+      if line.trim().is_empty() {
+        continue;
+      }
+      match serde_json::from_str::<
+        StateLine
+      >(line)
+      {
+        | Ok(line) => {
+          state.entries.insert(
+            line.source,
+            line.entry
+          );
+        }
+        | Err(err) => {
+          warn!(path = %path.display(), error = %err, "ignoring unreadable extract state line");
+        }
+      }
+    }
+    state
+  }
+
+  fn load_legacy(path: &Path) -> Self {
+    let Ok(raw) = fs::read(path) else {
+      return Self::default();
+    };
+    serde_json::from_slice(&raw)
+      .unwrap_or_else(|err| {
+        warn!(path = %path.display(), error = %err, "ignoring unreadable extract state");
+        Self::default()
+      })
+  }
+
+  /// Rewrites the log with one line per
+  /// source, through a temp file and
+  /// rename so an interrupted save
+  /// leaves the previous log intact.
+  fn compact(
+    &self,
+    path: &Path
+  ) -> anyhow::Result<()> {
+    if let Some(parent) = path.parent()
+    {
+      fs::create_dir_all(parent)?;
+    }
+    let tmp =
+      path.with_extension("jsonl.tmp");
+    let mut out =
+      fs::File::create(&tmp)?;
+    for (source, entry) in &self.entries
+    {
+      let mut line =
+        serde_json::to_string(
+          &StateLine {
+            source: source.clone(),
+            entry:  entry.clone()
+          }
+        )?;
+      line.push('\n');
+      out.write_all(line.as_bytes())?;
+    }
+    out.sync_all()?;
+    fs::rename(&tmp, path)?;
+    Ok(())
+  }
+
+  /// Same path, mtime, size, and config
+  /// as a recorded extraction; the
+  /// outputs are not checked so moved
+  /// files stay done.
+  fn is_recorded(
+    &self,
+    source: &str,
+    (mtime_ns, size): (u64, u64),
+    config_hash: &str
+  ) -> bool {
+    mtime_ns != 0
+      && self
+        .entries
+        .get(source)
+        .is_some_and(|entry| {
+          entry.mtime_ns == mtime_ns
+            && entry.size == size
+            && entry.config_hash
+              == config_hash
+        })
+  }
+
+  /// Outputs recorded for `source`.
+  fn outputs(
+    &self,
+    source: &str
+  ) -> Vec<PathBuf> {
+    self
+      .entries
+      .get(source)
+      .map(|entry| {
+        entry.outputs.clone()
+      })
+      .unwrap_or_default()
+  }
+
+  fn is_unchanged(
+    &self,
+    source: &str,
+    signature: &str,
+    config_hash: &str
+  ) -> bool {
+    self
+      .entries
+      .get(source)
+      .is_some_and(|entry| {
+        entry.signature.as_deref()
+          == Some(signature)
+          && entry.config_hash
+            == config_hash
+          && entry
+            .outputs
+            .iter()
+            .all(|out| out.exists())
+      })
+  }
+}
+
+/// The extract state shared by the
+/// workers of one run.
+struct StateStore {
+  path:        PathBuf,
+  config_hash: String,
+  force:       bool,
+  state:       Mutex<ExtractState>,
+  /// Appends each new entry to the log
+  /// at `path` as it is recorded.
+  log:         JsonlAppender,
+  /// Text claimed this run, for
+  /// `extract.dedup_by_content`.
+  claims:      Mutex<TextClaims>,
+  /// Signalled as each source's turn
+  /// ends.
+  turn_ended:  Condvar
+}
+
+/// blake3 of the text each source
+/// extracted this run, with that
+/// source and its outputs, and whose
+/// turn to claim comes next.
+#[derive(Default)]
+struct TextClaims {
+  texts: HashMap<
+    String,
+    (PathBuf, Vec<PathBuf>)
+  >,
+  next:  usize,
+  /// Later sources whose turn already
+  /// ended.
+  ended: BTreeSet<usize>
+}
+
+impl StateStore {
+  /// Loads the recorded state; with
+  /// `force` the log is emptied instead
+  /// so this run's entries replace it.
+  fn open(
+    config: &Config,
+    force: bool
+  ) -> anyhow::Result<Self> {
+    let state_dir =
+      &config.paths.state_dir;
+    let path =
+      state_dir.join(STATE_FILE);
+    let state = if force {
+      let empty =
+        ExtractState::default();
+      empty.compact(&path)?;
+      empty
+    } else {
+      ExtractState::load(
+        &path,
+        &state_dir
+          .join(LEGACY_STATE_FILE)
+      )
+    };
+    Ok(Self {
+      log: JsonlAppender::new(
+        path.clone()
+      ),
+      path,
+      config_hash: extract_config_hash(
+        config
+      ),
+      force,
+      state: Mutex::new(state),
+      claims: Mutex::default(),
+      turn_ended: Condvar::new()
+    })
+  }
+
+  /// Records `entry` for `source` and
+  /// appends it to the log, so a crash
+  /// loses at most the files in flight.
+  fn record(
+    &self,
+    source: String,
+    entry: StateEntry
+  ) -> anyhow::Result<()> {
+    self.lock()?.entries.insert(
+      source.clone(),
+      entry.clone()
+    );
+    self.log.append(&StateLine {
+      source,
+      entry
+    })
+  }
+
+  fn lock(
+    &self
+  ) -> anyhow::Result<
+    MutexGuard<'_, ExtractState>
+  > {
+    self.state.lock().map_err(|_| {
+      anyhow!(
+        "extract state lock poisoned"
+      )
+    })
+  }
+
+  /// The turn of the source at `idx`
+  /// in the dispatched order.
+  fn turn(
+    &self,
+    idx: usize
+  ) -> Turn<'_> {
+    Turn {
+      store: self,
+      idx,
+      claimed: RefCell::new(None),
+      ended: Cell::new(false)
+    }
+  }
+
+  /// Claims are kept consistent by
+  /// `Turn` even after a worker
+  /// panics, so poisoning is ignored.
+  fn claims(
+    &self
+  ) -> MutexGuard<'_, TextClaims> {
+    self.claims.lock().unwrap_or_else(
+      PoisonError::into_inner
+    )
+  }
+}
+
+/// A source's turn to claim its text.
+/// Turns run in dispatched (path)
+/// order, so among sources with
+/// identical text the first one keeps
+/// it whichever worker finishes first.
+/// A turn ends when dropped at the
+/// latest.
+struct Turn<'a> {
+  store:   &'a StateStore,
+  idx:     usize,
+  /// Digest this turn claimed, until
+  /// released.
+  claimed: RefCell<Option<String>>,
+  ended:   Cell<bool>
+}
+
+impl Turn<'_> {
+  /// Waits for every earlier source's
+  /// turn to end, then claims `doc`'s
+  /// text. Returns the earlier source
+  /// and its outputs when one already
+  /// claimed identical text.
+  fn claim(
+    &self,
+    doc: &Extracted
+  ) -> Option<(PathBuf, Vec<PathBuf>)>
+  {
+    let digest = TIMERS
+      .time("hash", || {
+        text_digest(doc)
+      });
+    let mut claims =
+      self.store.claims();
+    while claims.next < self.idx {
+      claims = self
+        .store
+        .turn_ended
+        .wait(claims)
+        .unwrap_or_else(
+          PoisonError::into_inner
+        );
+    }
+    if let Some(first) =
+      claims.texts.get(&digest)
+    {
+      return Some(first.clone());
+    }
+    claims.texts.insert(
+      digest.clone(),
+      (
+        doc.source.clone(),
+        doc
+          .outputs
+          .iter()
+          .map(|out| out.path.clone())
+          .collect()
+      )
+    );
+    *self.claimed.borrow_mut() =
+      Some(digest);
+    None
+  }
+
+  /// Gives up this turn's claim, so a
+  /// later source with the same text
+  /// is written instead.
+  fn release(&self) {
+    if let Some(digest) =
+      self.claimed.borrow_mut().take()
+    {
+      self
+        .store
+        .claims()
+        .texts
+        .remove(&digest);
+    }
+  }
+
+  /// Lets later sources claim.
+  fn end(&self) {
+    if self.ended.replace(true) {
+      return;
+    }
+    let mut guard = self.store.claims();
+    let claims = &mut *guard;
+    claims.ended.insert(self.idx);
+    while claims
+      .ended
+      .remove(&claims.next)
+    {
+      claims.next += 1;
+    }
+    drop(guard);
+    self.store.turn_ended.notify_all();
+  }
+}
+
+impl Drop for Turn<'_> {
+  fn drop(&mut self) {
+    self.end();
+  }
+}
+
+/// blake3 of `doc`'s text, part by
+/// part.
+fn text_digest(
+  doc: &Extracted
+) -> String {
+  let mut hasher =
+    blake3::Hasher::new();
+  for out in &doc.outputs {
+    hasher.update(out.text.as_bytes());
+    // Keeps part boundaries in the
+    // hash.
+    hasher.update(&[0]);
+  }
+  hasher.finalize().to_hex().to_string()
+}
+
+/// Modification time in nanoseconds
+/// since the epoch, or 0 when the
+/// platform has none.
+fn mtime_ns(
+  meta: &fs::Metadata
+) -> u64 {
+  meta
+    .modified()
+    .ok()
+    .and_then(|t| {
+      t.duration_since(UNIX_EPOCH).ok()
+    })
+    .map_or(0, |d| d.as_nanos() as u64)
+}
+
+/// Hash of the settings that shape the
+/// extracted output; changing one
+/// forces re-extraction under
+/// `skip_unchanged`. Scheduling,
+/// failure handling, and skip rules
+/// are left out so tuning them keeps
+/// the recorded state.
+fn extract_config_hash(
+  config: &Config
+) -> String {
+  let extract = &config.extract;
+  let epub = &extract.epub;
+  let pdf = &extract.pdf;
+  let fields: [(&str, String); 40] = [
+    (
+      "extract_root",
+      config
+        .paths
+        .extract_root
+        .display()
+        .to_string()
+    ),
+    (
+      "strip_running_headers",
+      extract
+        .strip_running_headers
+        .to_string()
+    ),
+    (
+      "header_footer_min_ratio",
+      extract
+        .header_footer_min_ratio
+        .to_string()
+    ),
+    (
+      "emit_page_markers",
+      extract
+        .emit_page_markers
+        .to_string()
+    ),
+    (
+      "write_metadata",
+      extract
+        .write_metadata
+        .to_string()
+    ),
+    (
+      "write_cover",
+      extract.write_cover.to_string()
+    ),
+    (
+      "output_layout",
+      extract.output_layout.clone()
+    ),
+    (
+      "output_extension",
+      extract.output_extension.clone()
+    ),
+    (
+      "metadata_layout",
+      extract.metadata_layout.clone()
+    ),
+    (
+      "cover_layout",
+      extract.cover_layout.clone()
+    ),
+    (
+      "output_format",
+      extract
+        .output_format
+        .name()
+        .into()
+    ),
+    (
+      "epub.backend",
+      epub.backend.clone()
+    ),
+    (
+      "epub.toc_depth",
+      epub.toc_depth.to_string()
+    ),
+    (
+      "epub.chapter_split",
+      epub.chapter_split.to_string()
+    ),
+    (
+      "epub.max_chapter_bytes",
+      epub
+        .max_chapter_bytes
+        .to_string()
+    ),
+    (
+      "epub.max_file_bytes",
+      epub.max_file_bytes.to_string()
+    ),
+    (
+      "epub.join_parts",
+      epub.join_parts.to_string()
+    ),
+    (
+      "epub.keep_parts",
+      epub.keep_parts.to_string()
+    ),
+    (
+      "epub.split_heading_regex",
+      epub
+        .split_heading_regex
+        .as_ref()
+        .map_or("", |re| re.as_str())
+        .to_string()
+    ),
+    (
+      "epub.extra_args",
+      epub.extra_args.join("\u{1f}")
+    ),
+    (
+      "pdf.backend",
+      pdf.backend.clone()
+    ),
+    (
+      "pdf.docling_script",
+      pdf.docling_script.clone()
+    ),
+    (
+      "pdf.text_first",
+      pdf.text_first.to_string()
+    ),
+    (
+      "pdf.text_good_min_chars",
+      pdf
+        .text_good_min_chars
+        .to_string()
+    ),
+    (
+      "pdf.text_low_min_chars",
+      pdf
+        .text_low_min_chars
+        .to_string()
+    ),
+    (
+      "pdf.text_alpha_ratio_min",
+      pdf
+        .text_alpha_ratio_min
+        .to_string()
+    ),
+    (
+      "pdf.text_sample_pages",
+      pdf.text_sample_pages.to_string()
+    ),
+    (
+      "pdf.ocr_fallback",
+      pdf.ocr_fallback.to_string()
+    ),
+    (
+      "pdf.ocr_lang",
+      pdf.ocr_lang.clone()
+    ),
+    (
+      "pdf.ocr_engine",
+      pdf.ocr_engine.clone()
+    ),
+    (
+      "pdf.docling_pipeline",
+      pdf.docling_pipeline.clone()
+    ),
+    (
+      "pdf.docling_pdf_backend",
+      pdf.docling_pdf_backend.clone()
+    ),
+    (
+      "pdf.docling_tables",
+      pdf.docling_tables.to_string()
+    ),
+    (
+      "pdf.docling_table_mode",
+      pdf.docling_table_mode.clone()
+    ),
+    (
+      "pdf.docling_to",
+      pdf.docling_to.clone()
+    ),
+    (
+      "pdf.low_quality",
+      format!(
+        "{} {} {} {}",
+        pdf.low_quality_use_ocr,
+        pdf.low_quality_force_ocr,
+        pdf.low_quality_tables,
+        pdf.low_quality_table_mode
+      )
+    ),
+    (
+      "pdf.scan",
+      format!(
+        "{} {} {}",
+        pdf.scan_force_ocr,
+        pdf.scan_tables,
+        pdf.scan_table_mode
+      )
+    ),
+    (
+      "pdf.page_batch_size",
+      pdf.page_batch_size.to_string()
+    ),
+    (
+      "pdf.max_pages_per_pass",
+      pdf
+        .max_pages_per_pass
+        .to_string()
+    ),
+    (
+      "pdf.split_text_extraction",
+      pdf
+        .split_text_extraction
+        .to_string()
+    )
+  ];
+  let mut hasher =
+    blake3::Hasher::new();
+  for (name, value) in fields {
+    hasher.update(name.as_bytes());
+    hasher.update(b"=");
+    hasher.update(value.as_bytes());
+    hasher.update(b"\n");
+  }
+  hasher.finalize().to_hex().to_string()
+}
+
+pub fn run(
+  config: &Config,
+  args: &ExtractArgs
+) -> anyhow::Result<()> {
+  run_with(config, args, None)
+}
+
+/// `run`, optionally handing each
+/// source's text to an in-memory
+/// consumer.
+pub fn run_with(
+  config: &Config,
+  args: &ExtractArgs,
+  in_memory: Option<&InMemory<'_>>
+) -> anyhow::Result<()> {
+  let store = StateStore::open(
+    config, args.force
+  )?;
+  TIMERS.reset();
+  let server = match config
+    .extract
+    .source
+  {
+    | ExtractSource::Filesystem => None,
+    | ExtractSource::CalibreServer => {
+      Some(CalibreServer::connect(
+        &config.calibre
+      )?)
+    }
+  };
+  let (mut sources, walk_errors): (
+    Vec<Source>,
+    usize
+  ) = TIMERS.time("walk", || {
+    match &server {
+      | Some(server) => {
+        server_sources(server, config)
+          .map(|sources| (sources, 0))
+      }
+      | None => {
+        let (files, errors) =
+          source_files(config);
+        Ok((
+          files
+            .into_iter()
+            .map(|(path, ext)| {
+              (path, ext, None)
+            })
+            .collect(),
+          errors
+        ))
+      }
+    }
+  })?;
+  // Duplicate text goes to the first
+  // source in path order.
+  if config.extract.dedup_by_content {
+    sources
+      .sort_by(|a, b| a.0.cmp(&b.0));
+  }
+  let total = sources.len();
+  let gate = if args.fail_fast {
+    FailureGate::new(
+      1,
+      FailureCounting::Total
+    )
+  } else {
+    FailureGate::new(
+      config
+        .extract
+        .abort_after_failures,
+      config.extract.abort_counting
+    )
+  };
+  let span = tracing::Span::current();
+  let files = dispatch(
+    &sources,
+    config,
+    |idx, (path, ext, book)| {
+      let _enter = span.enter();
+      let turn = store.turn(idx);
+      let mut entry = ManifestEntry {
+        source:       path.clone(),
+        format:       ext.clone(),
+        status:
+          FileStatus::Aborted,
+        reason:       None,
+        duplicate_of: None,
+        outputs:      Vec::new(),
+        backend:      None,
+        pdf_quality:  None,
+        error:        None
+      };
+      if gate.tripped() {
+        return entry;
+      }
+      let result = match (&server, book)
+      {
+        | (
+          Some(server),
+          Some(book)
+        ) => {
+          fetch_and_process(
+            server, book, path, ext,
+            config, &turn, in_memory
+          )
+        }
+        | _ => {
+          process_one(
+            path, ext, config, &turn,
+            in_memory
+          )
+        }
+      };
+      turn.end();
+      let result =
+        result.and_then(|outcome| {
+          if let (
+            Some(in_memory),
+            Outcome::Skipped {
+              outputs,
+              ..
+            }
+          ) = (in_memory, &outcome)
+          {
+            (in_memory.on_source)(
+              Handoff::Existing(
+                outputs
+              )
+            )?;
+          }
+          Ok(outcome)
+        });
+      match result {
+        | Ok(Outcome::Skipped {
+          reason,
+          outputs
+        }) => {
+          entry.status =
+            FileStatus::Skipped;
+          entry.reason = Some(reason);
+          entry.outputs = outputs;
+        }
+        | Ok(Outcome::Duplicate {
+          of,
+          outputs
+        }) => {
+          entry.status =
+            FileStatus::Skipped;
+          entry.reason =
+            Some("duplicate");
+          entry.duplicate_of = Some(of);
+          entry.outputs = outputs;
+        }
+        | Ok(Outcome::Extracted {
+          outputs,
+          backend,
+          pdf_quality
+        }) => {
+          gate.success();
+          entry.status =
+            FileStatus::Extracted;
+          entry.outputs = outputs;
+          entry.backend = Some(backend);
+          entry.pdf_quality =
+            pdf_quality;
+        }
+        | Err(err) => {
+          let error =
+            format!("{err:#}");
+          warn!(path = %path.display(), error = %error, "extract failed");
+          gate.failure();
+          entry.status =
+            FileStatus::Failed;
+          entry.error = Some(error);
+        }
+      }
+      entry
+    }
+  )?;
+  store.lock()?.compact(&store.path)?;
+  let skipped = files
+    .iter()
+    .filter(|f| {
+      f.status == FileStatus::Skipped
+    })
+    .count();
+  let failed = files
+    .iter()
+    .filter(|f| {
+      f.status == FileStatus::Failed
+    })
+    .collect::<Vec<_>>();
+  let errors_path = config
+    .paths
+    .state_dir
+    .join(ERRORS_FILE);
+  TIMERS.time("io", || {
+    write_errors(
+      &errors_path,
+      &failed,
+      &config.run_id
+    )
+  })?;
+  let first_failure =
+    failed.first().map(|f| {
+      format!(
+        "extract failed for {}: {}",
+        f.source.display(),
+        f.error
+          .as_deref()
+          .unwrap_or("")
+      )
+    });
+  let failed = failed.len();
+  let manifest = manifest_path(config);
+  TIMERS.time("io", || {
+    write_manifest(
+      &manifest,
+      &Manifest {
+        run_id: &config.run_id,
+        finished_at: Utc::now()
+          .to_rfc3339(),
+        total,
+        skipped,
+        walk_errors,
+        files
+      }
+    )
+  })?;
+  info!(
+    total,
+    skipped,
+    failed,
+    walk_errors,
+    manifest = %manifest.display(),
+    errors = %errors_path.display(),
+    timings = %TIMERS,
+    "extract complete"
+  );
+  if args.fail_fast
+    && let Some(message) = first_failure
+  {
+    return Err(anyhow!(message));
+  }
+  gate.check("extract")
+}
+
+/// Runs `extract_one` over `sources`,
+/// with each one's index, in worker
+/// pools and returns the entries in
+/// source order. Each pool takes its
+/// sources in index order, which
+/// `Turn::claim` relies on to make
+/// progress. Every external tool
+/// (including docling on the GPU) runs
+/// inside a worker, so the pool sizes
+/// also bound their concurrency: one
+/// pool of `max_parallel`, or with
+/// `extract.concurrency` one pool per
+/// format, all running at once.
+fn dispatch<F>(
+  sources: &[Source],
+  config: &Config,
+  extract_one: F
+) -> anyhow::Result<Vec<ManifestEntry>>
+where
+  F: Fn(usize, &Source) -> ManifestEntry
+    + Sync
+{
+  let build_pool = |workers: usize| {
+    rayon::ThreadPoolBuilder::new()
+      .num_threads(workers.max(1))
+      .build()
+      .context(
+        "build extract worker pool"
+      )
+  };
+  let limits =
+    &config.extract.concurrency;
+  if limits.is_empty() {
+    let pool = build_pool(
+      config.extract.max_parallel
+    )?;
+    return Ok(pool.install(|| {
+      sources
+        .par_iter()
+        .enumerate()
+        .map(|(idx, source)| {
+          extract_one(idx, source)
+        })
+        .collect()
+    }));
+  }
+
+  let mut by_format: BTreeMap<
+    &str,
+    Vec<usize>
+  > = BTreeMap::new();
+  for (idx, (_, ext, _)) in
+    sources.iter().enumerate()
+  {
+    by_format
+      .entry(ext.as_str())
+      .or_default()
+      .push(idx);
+  }
+  let pools = by_format
+    .into_iter()
+    .map(|(format, indexes)| {
+      let workers = format_concurrency(
+        format, config
+      );
+      info!(
+        format,
+        workers,
+        files = indexes.len(),
+        "extract pool"
+      );
+      Ok((
+        build_pool(workers)?,
+        indexes
+      ))
+    })
+    .collect::<anyhow::Result<Vec<_>>>(
+    )?;
+  let mut slots: Vec<
+    Option<ManifestEntry>
+  > = sources
+    .iter()
+    .map(|_| None)
+    .collect();
+  let extract_one = &extract_one;
+  std::thread::scope(|scope| {
+    let handles = pools
+      .iter()
+      .map(|(pool, indexes)| {
+        scope.spawn(move || {
+          pool.install(|| {
+            indexes
+              .par_iter()
+              .map(|&idx| {
+                (
+                  idx,
+                  extract_one(
+                    idx,
+                    &sources[idx]
+                  )
+                )
+              })
+              .collect::<Vec<_>>()
+          })
+        })
+      })
+      .collect::<Vec<_>>();
+    for handle in handles {
+      let done = handle
+        .join()
+        .map_err(|_| {
+          anyhow!(
+            "extract pool panicked"
+          )
+        })?;
+      for (idx, entry) in done {
+        slots[idx] = Some(entry);
+      }
+    }
+    Ok::<_, anyhow::Error>(())
+  })?;
+  Ok(
+    slots
+      .into_iter()
+      .flatten()
+      .collect()
+  )
+}
+
+/// Workers for `format` under
+/// `extract.concurrency`: its own
+/// entry, else `default`, else
+/// `max_parallel`.
+fn format_concurrency(
+  format: &str,
+  config: &Config
+) -> usize {
+  let limits =
+    &config.extract.concurrency;
+  limits
+    .get(format)
+    .or_else(|| limits.get("default"))
+    .copied()
+    .unwrap_or(
+      config.extract.max_parallel
+    )
+    .max(1)
+}
+
+/// What `extract` did with one source.
+enum Outcome {
+  Extracted {
+    outputs:     Vec<PathBuf>,
+    backend:     &'static str,
+    pdf_quality: Option<PdfQuality>
+  },
+  Skipped {
+    reason:  &'static str,
+    outputs: Vec<PathBuf>
+  },
+  /// The text matched an earlier
+  /// source's this run; nothing was
+  /// written.
+  Duplicate {
+    of:      PathBuf,
+    outputs: Vec<PathBuf>
+  }
+}
+
+/// `manifest.json`: every source the
+/// run saw and what became of it, in
+/// walk order.
+#[derive(Debug, Serialize)]
+struct Manifest<'a> {
+  run_id:      &'a str,
+  finished_at: String,
+  total:       usize,
+  skipped:     usize,
+  walk_errors: usize,
+  files:       Vec<ManifestEntry>
+}
+
+#[derive(Debug, Serialize)]
+struct ManifestEntry {
+  source:       PathBuf,
+  format:       String,
+  status:       FileStatus,
+  /// Why a skipped source was skipped:
+  /// `recorded`, `unchanged`,
+  /// `existing`, or `duplicate`.
+  #[serde(
+    skip_serializing_if = "Option::is_none"
+  )]
+  reason:       Option<&'static str>,
+  /// The source whose identical text a
+  /// `duplicate` shares.
+  #[serde(
+    skip_serializing_if = "Option::is_none"
+  )]
+  duplicate_of: Option<PathBuf>,
+  outputs:      Vec<PathBuf>,
+  #[serde(
+    skip_serializing_if = "Option::is_none"
+  )]
+  backend:      Option<&'static str>,
+  #[serde(
+    skip_serializing_if = "Option::is_none"
+  )]
+  pdf_quality:  Option<PdfQuality>,
+  #[serde(
+    skip_serializing_if = "Option::is_none"
+  )]
+  error:        Option<String>
+}
+
+#[derive(
+  Debug,
+  Clone,
+  Copy,
+  PartialEq,
+  Eq,
+  Serialize,
+  Deserialize,
+)]
+#[serde(rename_all = "snake_case")]
+enum FileStatus {
+  Extracted,
+  Skipped,
+  Failed,
+  /// Not attempted because
+  /// `abort_after_failures` stopped the
+  /// run first.
+  Aborted
+}
+
+/// `extract.manifest_path`, or
+/// `manifest.json` in `extract_root`.
+fn manifest_path(
+  config: &Config
+) -> PathBuf {
+  config
+    .extract
+    .manifest_path
+    .clone()
+    .unwrap_or_else(|| {
+      config
+        .paths
+        .extract_root
+        .join(MANIFEST_FILE)
+    })
+}
+
+const MANIFEST_FILE: &str =
+  "manifest.json";
+
+/// This run's failed sources, one JSON
+/// line each, under `state_dir`.
+const ERRORS_FILE: &str =
+  "extract_errors.jsonl";
+
+#[derive(Serialize)]
+struct ErrorLine<'a> {
+  source: &'a Path,
+  format: &'a str,
+  error:  &'a str,
+  run_id: &'a str
+}
+
+/// Replaces the errors file with this
+/// run's failures, leaving it empty
+/// when none failed.
+fn write_errors(
+  path: &Path,
+  failed: &[&ManifestEntry],
+  run_id: &str
+) -> anyhow::Result<()> {
+  if let Some(parent) = path.parent() {
+    fs::create_dir_all(parent)?;
+  }
+  let mut out = String::new();
+  for entry in failed {
+    out.push_str(
+      &serde_json::to_string(
+        &ErrorLine {
+          source: &entry.source,
+          format: &entry.format,
+          error: entry
+            .error
+            .as_deref()
+            .unwrap_or_default(),
+          run_id
+        }
+      )?
+    );
+    out.push('\n');
+  }
+  let tmp =
+    path.with_extension("jsonl.tmp");
+  fs::write(&tmp, out)?;
+  fs::rename(&tmp, path).with_context(
+    || {
+      format!(
+        "write errors {}",
+        path.display()
+      )
+    }
+  )
+}
+
+fn write_manifest(
+  path: &Path,
+  manifest: &Manifest<'_>
+) -> anyhow::Result<()> {
+  if let Some(parent) = path.parent() {
+    fs::create_dir_all(parent)?;
+  }
+  let tmp =
+    path.with_extension("json.tmp");
+  fs::write(
+    &tmp,
+    serde_json::to_vec_pretty(
+      manifest
+    )?
+  )?;
+  fs::rename(&tmp, path).with_context(
+    || {
+      format!(
+        "write manifest {}",
+        path.display()
+      )
+    }
+  )
+}
+
+/// Files written by the sources the
+/// last run extracted, as listed in its
+/// manifest.
+pub(crate) fn manifest_outputs(
+  config: &Config
+) -> anyhow::Result<Vec<PathBuf>> {
+  #[derive(Deserialize)]
+  struct Recorded {
+    files: Vec<RecordedEntry>
+  }
+  #[derive(Deserialize)]
+  struct RecordedEntry {
+    status:  FileStatus,
+    #[serde(default)]
+    outputs: Vec<PathBuf>
+  }
+  let path = manifest_path(config);
+  let raw = fs::read(&path)
+    .with_context(|| {
+      format!(
+        "read manifest {}",
+        path.display()
+      )
+    })?;
+  let manifest: Recorded =
+    serde_json::from_slice(&raw)
+      .with_context(|| {
+        format!(
+          "parse manifest {}",
+          path.display()
+        )
+      })?;
+  Ok(
+    manifest
+      .files
+      .into_iter()
+      .filter(|entry| {
+        entry.status
+          == FileStatus::Extracted
+      })
+      .flat_map(|entry| entry.outputs)
+      .collect()
+  )
+}
+
+/// A staged path, its format, and the
+/// server book it is downloaded from
+/// (`None` for local files).
+type Source =
+  (PathBuf, String, Option<ServerBook>);
+
+/// Every matching book format on the
+/// content server, staged under
+/// `state_dir/calibre_server` with its
+/// server metadata written alongside.
+fn server_sources(
+  server: &CalibreServer,
+  config: &Config
+) -> anyhow::Result<Vec<Source>> {
+  let exts = config
+    .extract
+    .extensions
+    .iter()
+    .map(|s| s.to_ascii_lowercase())
+    .collect::<Vec<_>>();
+  server
+    .list_books(&exts)?
+    .into_iter()
+    .map(|book| {
+      let staged = calibre_server::staged_path(
+        config, &book
+      );
+      calibre_server::write_book_metadata(
+        &staged, server, &book
+      )?;
+      Ok((
+        staged,
+        book.format.clone(),
+        Some(book)
+      ))
+    })
+    .collect()
+}
+
+/// Downloads `book` unless its output
+/// already exists, extracts it, and
+/// removes the download.
+fn fetch_and_process(
+  server: &CalibreServer,
+  book: &ServerBook,
+  staged: &Path,
+  format: &str,
+  config: &Config,
+  turn: &Turn<'_>,
+  in_memory: Option<&InMemory<'_>>
+) -> anyhow::Result<Outcome> {
+  let existing =
+    output_path(staged, format, config);
+  if config.extract.skip_existing
+    && existing.exists()
+  {
+    debug!(
+      book_id = book.id,
+      "skip existing"
+    );
+    return Ok(Outcome::Skipped {
+      reason:  "existing",
+      outputs: vec![existing]
+    });
+  }
+  TIMERS.time("download", || {
+    server.download(book, staged)
+  })?;
+  let result = process_one(
+    staged, format, config, turn,
+    in_memory
+  );
+  let _ = fs::remove_file(staged);
+  result
+}
+
+/// Source files under
+/// `paths.calibre_root` matching
+/// `extract.extensions`, with their
+/// lowercased extension, and the number
+/// of entries the walk could not read.
+pub fn source_files(
+  config: &Config
+) -> (Vec<(PathBuf, String)>, usize) {
+  let exts = config
+    .extract
+    .extensions
+    .iter()
+    .map(|s| s.to_ascii_lowercase())
+    .collect::<Vec<_>>();
+  let walked = walk_files(
+    &config.paths.calibre_root,
+    config.paths.log_walk_errors
+  );
+  let sources = walked
+    .files
+    .into_iter()
+    .filter_map(|path| {
+      let ext = path
+        .extension()
+        .and_then(OsStr::to_str)
+        .unwrap_or("")
+        .to_ascii_lowercase();
+      exts
+        .contains(&ext)
+        .then_some((path, ext))
+    })
+    .collect();
+  (sources, walked.errors)
+}
+
+fn process_one(
+  path: &Path,
+  format: &str,
+  config: &Config,
+  turn: &Turn<'_>,
+  in_memory: Option<&InMemory<'_>>
+) -> anyhow::Result<Outcome> {
+  let store = turn.store;
+  let source =
+    path.display().to_string();
+  let meta = fs::metadata(path)?;
+  let stat =
+    (mtime_ns(&meta), meta.len());
+  if !store.force
+    && store.lock()?.is_recorded(
+      &source,
+      stat,
+      &store.config_hash
+    )
+  {
+    debug!(path = %path.display(), "skip recorded");
+    return Ok(Outcome::Skipped {
+      reason:  "recorded",
+      outputs: store
+        .lock()?
+        .outputs(&source)
+    });
+  }
+
+  let signature = if config
+    .extract
+    .skip_unchanged
+  {
+    Some(TIMERS.time("hash", || {
+      blake3_file(path)
+    })?)
+  } else {
+    None
+  };
+  if let Some(signature) = &signature
+    && !store.force
+  {
+    let state = store.lock()?;
+    if state.is_unchanged(
+      &source,
+      signature,
+      &store.config_hash
+    ) {
+      // Touched but identical: record
+      // the new stat so the next run
+      // skips without hashing.
+      let mut entry =
+        state.entries[&source].clone();
+      drop(state);
+      (entry.mtime_ns, entry.size) =
+        stat;
+      let outputs =
+        entry.outputs.clone();
+      store.record(source, entry)?;
+      debug!(path = %path.display(), "skip unchanged");
+      return Ok(Outcome::Skipped {
+        reason: "unchanged",
+        outputs
+      });
+    }
+  }
+
+  match extract_source(
+    path,
+    format,
+    config,
+    signature.as_deref()
+  )? {
+    | Some(doc) => {
+      // Sources with no text (skipped
+      // encrypted or oversized PDFs)
+      // would all share one digest.
+      let has_text =
+        doc.outputs.iter().any(|out| {
+          !out.text.trim().is_empty()
+        });
+      let duplicate = if config
+        .extract
+        .dedup_by_content
+        && has_text
+      {
+        turn.claim(&doc)
+      } else {
+        None
+      };
+      if duplicate.is_none()
+        && in_memory.is_none_or(|m| {
+          m.write_output
+        })
+      {
+        let written =
+          TIMERS.time("io", || {
+            write_extracted(
+              &doc, config
+            )
+          });
+        if written.is_err() {
+          turn.release();
+        }
+        written?;
+      }
+      turn.end();
+      if duplicate.is_none()
+        && let Some(in_memory) =
+          in_memory
+      {
+        (in_memory.on_source)(
+          Handoff::Extracted(&doc)
+        )?;
+      }
+      let outputs = match &duplicate {
+        | Some((_, outputs)) => {
+          outputs.clone()
+        }
+        | None => {
+          doc
+            .outputs
+            .iter()
+            .map(|out| out.path.clone())
+            .collect()
+        }
+      };
+      // A duplicate is recorded with
+      // the first source's
+      // outputs, so later
+      // runs skip it too.
+      let entry = StateEntry {
+        mtime_ns: stat.0,
+        size: stat.1,
+        signature,
+        config_hash: store
+          .config_hash
+          .clone(),
+        outputs: outputs.clone(),
+        run_id: config.run_id.clone()
+      };
+      TIMERS.time("io", || {
+        store.record(source, entry)
+      })?;
+      if let Some((of, _)) = duplicate {
+        info!(path = %path.display(), duplicate_of = %of.display(), "skip duplicate text");
+        return Ok(Outcome::Duplicate {
+          of,
+          outputs
+        });
+      }
+      Ok(Outcome::Extracted {
+        outputs:     doc
+          .outputs
+          .into_iter()
+          .map(|out| out.path)
+          .collect(),
+        backend:     doc.backend,
+        pdf_quality: doc.pdf_quality
+      })
+    }
+    | None => {
+      Ok(Outcome::Skipped {
+        reason:  "existing",
+        outputs: vec![output_path(
+          path, format, config
+        )]
+      })
+    }
+  }
+}
+
+/// Extracts `path` into memory. Returns
+/// `None` when `extract.skip_existing`
+/// finds the output already on disk.
+pub fn extract_file(
+  path: &Path,
+  format: &str,
+  config: &Config
+) -> anyhow::Result<Option<Extracted>> {
+  extract_source(
+    path, format, config, None
+  )
+}
+
+/// `extract_file`, reusing a blake3
+/// `signature` of `path` when the
+/// caller already has one.
+fn extract_source(
+  path: &Path,
+  format: &str,
+  config: &Config,
+  signature: Option<&str>
+) -> anyhow::Result<Option<Extracted>> {
+  let mut metadata =
+    TIMERS.time("io", || {
+      read_metadata(
+        path, format, config
+      )
+    });
+  let output_path = layout_output(
+    path, &metadata, format, config
+  );
+
+  if config.extract.skip_existing
+    && final_path(
+      &output_path,
+      format,
+      config
+    )
+    .exists()
+  {
+    debug!(path = %path.display(), "skip existing");
+    return Ok(None);
+  }
+
+  let cache_key =
+    if config.extract.content_cache {
+      let digest = match signature {
+        | Some(sig) => sig.to_string(),
+        | None => {
+          TIMERS.time("hash", || {
+            blake3_file(path)
+          })?
+        }
+      };
+      Some((
+        cache_path(config, &digest),
+        extract_config_hash(config)
+      ))
+    } else {
+      None
+    };
+  let cached =
+    cache_key.as_ref().and_then(
+      |(cache_file, config_hash)| {
+        TIMERS.time("io", || {
+          load_cached(
+            cache_file,
+            config_hash,
+            &output_path
+          )
+        })
+      }
+    );
+  let (
+    (outputs, kept_parts),
+    backend,
+    pdf_quality
+  ) = match cached {
+    | Some(hit) => {
+      debug!(path = %path.display(), "extract cache hit");
+      (hit, "cache", None)
+    }
+    | None => {
+      let (extracted, quality) =
+        run_extractor(
+          path,
+          format,
+          &output_path,
+          config
+        )?;
+      if let Some((
+        cache_file,
+        config_hash
+      )) = &cache_key
+        && let Err(err) =
+          TIMERS.time("io", || {
+            store_cached(
+              cache_file,
+              config_hash,
+              &output_path,
+              &extracted
+            )
+          })
+      {
+        warn!(path = %cache_file.display(), error = %err, "extract cache write failed");
+      }
+      (
+        extracted,
+        backend_name(format, quality),
+        quality
+      )
+    }
+  };
 
-use anyhow::{
-  Context,
-  anyhow
-};
-use chrono::Utc;
-use quick_xml::Reader;
-use quick_xml::events::Event;
-use serde::Serialize;
-use tracing::{
-  debug,
-  info,
-  warn
-};
-use walkdir::WalkDir;
+  let (outputs, kept_parts) =
+    if writes_sections(format, config) {
+      (
+        to_sectioned(outputs)?,
+        to_sectioned(kept_parts)?
+      )
+    } else {
+      (outputs, kept_parts)
+    };
 
-use crate::config::{
-  Config,
-  ExtractEpubConfig,
-  ExtractPdfConfig
-};
-use crate::util::{
-  apply_layout,
-  replace_extension,
-  slugify
-};
+  // Server downloads already point at
+  // their URL.
+  if metadata.source_path.is_empty() {
+    metadata.source_path =
+      path.display().to_string();
+  }
+  metadata.format = format.to_string();
+  metadata.extracted_at =
+    Utc::now().to_rfc3339();
+  let cover =
+    if config.extract.write_cover {
+      find_cover(path).map(|src| {
+        let rel = cover_rel_path(
+          path, &src, &metadata,
+          format, config
+        );
+        (src, rel)
+      })
+    } else {
+      None
+    };
+  metadata.cover_path =
+    cover.as_ref().map(|(_, rel)| {
+      rel.display().to_string()
+    });
+  Ok(Some(Extracted {
+    source: path.to_path_buf(),
+    format: format.to_string(),
+    outputs,
+    kept_parts,
+    metadata,
+    backend,
+    pdf_quality,
+    cover: cover.map(|(src, rel)| {
+      (
+        src,
+        config
+          .paths
+          .extract_root
+          .join(rel)
+      )
+    })
+  }))
+}
+
+/// Whether `format` is converted to
+/// markdown by pandoc.
+fn is_pandoc_format(
+  format: &str
+) -> bool {
+  matches!(
+    format,
+    "epub"
+      | "docx"
+      | "odt"
+      | "rtf"
+      | "html"
+      | "htm"
+  )
+}
+
+/// Whether `format` goes through pandoc
+/// and is written as sectioned JSON.
+fn writes_sections(
+  format: &str,
+  config: &Config
+) -> bool {
+  config.extract.output_format
+    == ExtractOutputFormat::SectionedJson
+    && is_pandoc_format(format)
+}
+
+/// The file the main output for
+/// `format` ends up in.
+fn final_path(
+  output: &Path,
+  format: &str,
+  config: &Config
+) -> PathBuf {
+  if writes_sections(format, config) {
+    sections::sectioned_path(output)
+  } else {
+    output.to_path_buf()
+  }
+}
+
+fn to_sectioned(
+  texts: Vec<ExtractedText>
+) -> anyhow::Result<Vec<ExtractedText>>
+{
+  texts
+    .into_iter()
+    .map(|t| {
+      Ok(ExtractedText {
+        path: sections::sectioned_path(
+          &t.path
+        ),
+        text:
+          serde_json::to_string_pretty(
+            &sections::parse_markdown(
+              &t.text
+            )
+          )?
+      })
+    })
+    .collect()
+}
+
+type ExtractorOutput = (
+  Vec<ExtractedText>,
+  Vec<ExtractedText>
+);
+
+fn run_extractor(
+  path: &Path,
+  format: &str,
+  output_path: &Path,
+  config: &Config
+) -> anyhow::Result<(
+  ExtractorOutput,
+  Option<PdfQuality>
+)> {
+  let timeout =
+    subprocess_timeout(&config.extract);
+  let output = match format {
+    | "epub" => {
+      extract_epub(
+        path,
+        output_path,
+        &config.extract.epub,
+        timeout
+      )
+    }
+    | "docx" | "odt" | "rtf"
+    | "html" | "htm" => {
+      extract_pandoc(
+        path,
+        output_path,
+        pandoc_from_format(format),
+        &config.extract.epub,
+        timeout
+      )
+    }
+    | "pdf" => {
+      let (texts, quality) =
+        extract_pdf(
+          path,
+          output_path,
+          config
+        )?;
+      return Ok((
+        (texts, Vec::new()),
+        quality
+      ));
+    }
+    | "mobi" | "azw" | "azw3" => {
+      Ok((
+        extract_mobi(
+          path,
+          output_path,
+          &config.extract.mobi,
+          timeout
+        )?,
+        Vec::new()
+      ))
+    }
+    | _ => {
+      Err(anyhow!(
+        "unsupported format: {}",
+        format
+      ))
+    }
+  }?;
+  Ok((output, None))
+}
 
+const CACHE_DIR: &str = "extract_cache";
+
+/// Per-phase time for the current
+/// extract run, logged with the
+/// summary.
+pub(crate) static TIMERS: PhaseTimers =
+  PhaseTimers::new();
+
+/// Extracted text stored under
+/// `state_dir/extract_cache/<blake3>`,
+/// with output names kept relative to
+/// the main output's stem so a hit can
+/// be laid out for any title or path.
 #[derive(
-  Debug, Default, Serialize, Clone,
+  Debug, Serialize, Deserialize,
 )]
-struct ExtractedMetadata {
-  source_path:  String,
-  format:       String,
-  title:        Option<String>,
-  authors:      Vec<String>,
-  language:     Option<String>,
-  published:    Option<String>,
-  identifiers:  Vec<String>,
-  calibre_id:   Option<String>,
-  extracted_at: String
+struct CachedExtraction {
+  config_hash: String,
+  outputs:     Vec<CachedText>,
+  kept_parts:  Vec<CachedText>
 }
 
-pub fn run(
-  config: &Config
+#[derive(
+  Debug, Serialize, Deserialize,
+)]
+struct CachedText {
+  suffix: String,
+  text:   String
+}
+
+fn cache_path(
+  config: &Config,
+  digest: &str
+) -> PathBuf {
+  config
+    .paths
+    .state_dir
+    .join(CACHE_DIR)
+    .join(digest)
+}
+
+fn output_stem(output: &Path) -> &str {
+  output
+    .file_stem()
+    .and_then(OsStr::to_str)
+    .unwrap_or("book")
+}
+
+fn load_cached(
+  cache_file: &Path,
+  config_hash: &str,
+  output: &Path
+) -> Option<ExtractorOutput> {
+  let raw =
+    fs::read(cache_file).ok()?;
+  let cached: CachedExtraction =
+    serde_json::from_slice(&raw)
+      .ok()?;
+  if cached.config_hash != config_hash {
+    return None;
+  }
+  let stem = output_stem(output);
+  let restore =
+    |texts: Vec<CachedText>| {
+      texts
+        .into_iter()
+        .map(|t| {
+          ExtractedText {
+            path: output
+              .with_file_name(format!(
+                "{stem}{}",
+                t.suffix
+              )),
+            text: t.text
+          }
+        })
+        .collect()
+    };
+  Some((
+    restore(cached.outputs),
+    restore(cached.kept_parts)
+  ))
+}
+
+fn store_cached(
+  cache_file: &Path,
+  config_hash: &str,
+  output: &Path,
+  (outputs, kept_parts): &ExtractorOutput
 ) -> anyhow::Result<()> {
-  let exts = config
-    .extract
-    .extensions
-    .iter()
-    .map(|s| s.to_ascii_lowercase())
-    .collect::<Vec<_>>();
-  let mut total = 0usize;
-  let mut skipped = 0usize;
-  for entry in WalkDir::new(
-    &config.paths.calibre_root
-  )
-  .into_iter()
-  .filter_map(|e| e.ok())
-  .filter(|e| e.file_type().is_file())
+  let stem = output_stem(output);
+  let relativize =
+    |texts: &[ExtractedText]| {
+      texts
+        .iter()
+        .map(|t| {
+          let name = t
+            .path
+            .file_name()
+            .and_then(OsStr::to_str)
+            .unwrap_or("");
+          let suffix = name
+            .strip_prefix(stem)
+            .ok_or_else(|| {
+              anyhow!(
+                "output {} not named \
+                 after {}",
+                t.path.display(),
+                stem
+              )
+            })?;
+          Ok(CachedText {
+            suffix: suffix.to_string(),
+            text:   t.text.clone()
+          })
+        })
+        .collect::<anyhow::Result<Vec<_>>>()
+    };
+  let cached = CachedExtraction {
+    config_hash: config_hash
+      .to_string(),
+    outputs:     relativize(outputs)?,
+    kept_parts:  relativize(
+      kept_parts
+    )?
+  };
+  if let Some(parent) =
+    cache_file.parent()
   {
-    let path = entry.path();
-    let ext = path
-      .extension()
-      .and_then(OsStr::to_str)
-      .unwrap_or("")
-      .to_ascii_lowercase();
-    if !exts.contains(&ext) {
-      continue;
-    }
-    total += 1;
-    if process_one(path, &ext, config)
-      .unwrap_or(false)
-    {
-      skipped += 1;
-    }
+    fs::create_dir_all(parent)?;
   }
-  info!(
-    total,
-    skipped, "extract complete"
-  );
+  let tmp =
+    cache_file.with_extension("tmp");
+  fs::write(
+    &tmp,
+    serde_json::to_vec(&cached)?
+  )?;
+  fs::rename(&tmp, cache_file)?;
   Ok(())
 }
 
-fn process_one(
+/// Where `extract_file` puts the main
+/// output for `path`.
+pub fn output_path(
   path: &Path,
   format: &str,
   config: &Config
-) -> anyhow::Result<bool> {
+) -> PathBuf {
   let metadata =
-    read_metadata(path, format);
+    read_metadata(path, format, config);
+  final_path(
+    &layout_output(
+      path, &metadata, format, config
+    ),
+    format,
+    config
+  )
+}
+
+fn title_slug(
+  path: &Path,
+  metadata: &ExtractedMetadata
+) -> String {
   let title_seed = metadata
     .title
     .clone()
@@ -106,174 +2242,344 @@ fn process_one(
         .unwrap_or("book")
         .to_string()
     });
-  let title_slug = slugify(&title_seed);
-  let rel_output = apply_layout(
-    &config.extract.output_layout,
+  slugify(&title_seed)
+}
+
+/// `extract.cover_layout` for `path`,
+/// keeping the cover's own extension.
+fn cover_rel_path(
+  path: &Path,
+  cover: &Path,
+  metadata: &ExtractedMetadata,
+  format: &str,
+  config: &Config
+) -> PathBuf {
+  let rel = apply_layout(
+    &config.extract.cover_layout,
     format,
-    &title_slug
+    &title_slug(path, metadata)
   );
-  let rel_meta = apply_layout(
-    &config.extract.metadata_layout,
+  match cover.extension() {
+    | Some(ext) => {
+      rel.with_extension(ext)
+    }
+    | None => rel
+  }
+}
+
+fn layout_output(
+  path: &Path,
+  metadata: &ExtractedMetadata,
+  format: &str,
+  config: &Config
+) -> PathBuf {
+  let mut rel_output = apply_layout(
+    &config.extract.output_layout,
     format,
-    &title_slug
+    &title_slug(path, metadata)
   );
-  let output_path = config
-    .paths
-    .extract_root
-    .join(rel_output);
-  let metadata_path = config
+  // Pandoc writes markdown; PDF and
+  // MOBI text keep the layout's
+  // extension.
+  if is_pandoc_format(format) {
+    rel_output.set_extension(
+      &config.extract.output_extension
+    );
+  }
+  config
     .paths
     .extract_root
-    .join(rel_meta);
-
-  if config.extract.skip_existing
-    && output_path.exists()
-  {
-    debug!(path = %path.display(), "skip existing");
-    return Ok(true);
-  }
+    .join(rel_output)
+}
 
-  if let Some(parent) =
-    output_path.parent()
-  {
-    fs::create_dir_all(parent)?;
-  }
-  if let Some(parent) =
-    metadata_path.parent()
+/// Writes the extracted text (and
+/// metadata sidecars when
+/// `extract.write_metadata` is set).
+pub fn write_extracted(
+  doc: &Extracted,
+  config: &Config
+) -> anyhow::Result<()> {
+  for out in doc
+    .outputs
+    .iter()
+    .chain(&doc.kept_parts)
   {
-    fs::create_dir_all(parent)?;
+    if let Some(parent) =
+      out.path.parent()
+    {
+      fs::create_dir_all(parent)?;
+    }
+    fs::write(&out.path, &out.text)
+      .with_context(|| {
+        format!(
+          "write {}",
+          out.path.display()
+        )
+      })?;
   }
 
-  let outputs = match format {
-    | "epub" => {
-      extract_epub(
-        path,
-        &output_path,
-        &config.extract.epub
-      )?
-    }
-    | "pdf" => {
-      extract_pdf(
-        path,
-        &output_path,
-        &config.extract.pdf
-      )?
-    }
-    | _ => {
-      return Err(anyhow!(
-        "unsupported format: {}",
-        format
-      ))
+  if let Some((src, dst)) = &doc.cover {
+    if let Some(parent) = dst.parent() {
+      fs::create_dir_all(parent)?;
     }
-  };
+    fs::copy(src, dst).with_context(
+      || {
+        format!(
+          "copy cover {} -> {}",
+          src.display(),
+          dst.display()
+        )
+      }
+    )?;
+  }
 
   if config.extract.write_metadata {
-    for out_path in outputs {
-      let mut meta = metadata.clone();
-      meta.source_path =
-        path.display().to_string();
-      meta.format = format.to_string();
-      meta.extracted_at =
-        Utc::now().to_rfc3339();
-      let meta_path = replace_extension(
-        &out_path, "json"
-      );
+    for out in &doc.outputs {
+      let meta_path =
+        sections::sidecar_path(
+          &out.path
+        );
       write_metadata(
-        &meta_path, &meta
+        &meta_path,
+        &doc.metadata
       )?;
     }
   }
-
-  Ok(false)
+  Ok(())
 }
 
 fn extract_epub(
   input: &Path,
   output: &Path,
-  cfg: &ExtractEpubConfig
-) -> anyhow::Result<Vec<PathBuf>> {
+  cfg: &ExtractEpubConfig,
+  timeout: Option<Duration>
+) -> anyhow::Result<(
+  Vec<ExtractedText>,
+  Vec<ExtractedText>
+)> {
   if cfg.backend != "pandoc" {
     return Err(anyhow!(
       "unsupported epub backend: {}",
       cfg.backend
     ));
   }
-  info!(path = %input.display(), "extract epub");
-  let status =
-    Command::new(&cfg.pandoc_bin)
-      .arg("--from")
-      .arg("epub")
-      .arg("--to")
-      .arg("markdown")
-      .arg("--verbose")
-      .arg("--toc")
-      .arg("--toc-depth")
-      .arg(cfg.toc_depth.to_string())
-      .arg("--output")
-      .arg(output)
-      .arg("--")
-      .arg(input)
-      .status()
-      .with_context(|| {
-        format!(
-          "pandoc failed for {}",
-          input.display()
-        )
-      })?;
-  if !status.success() {
+  extract_pandoc(
+    input, output, "epub", cfg, timeout
+  )
+}
+
+/// Pandoc's `--from` reader name for a
+/// source extension.
+fn pandoc_from_format(
+  ext: &str
+) -> &str {
+  match ext {
+    | "htm" => "html",
+    | other => other
+  }
+}
+
+/// Converts `input` to markdown with
+/// pandoc, applying the
+/// `[extract.epub]` pandoc and
+/// chapter-split settings.
+fn extract_pandoc(
+  input: &Path,
+  output: &Path,
+  from: &str,
+  cfg: &ExtractEpubConfig,
+  timeout: Option<Duration>
+) -> anyhow::Result<ExtractorOutput> {
+  info!(path = %input.display(), from, "extract via pandoc");
+  let result = TIMERS
+    .time("pandoc", || {
+      output_with_timeout(
+        Command::new(&cfg.pandoc_bin)
+          .arg("--from")
+          .arg(from)
+          .arg("--to")
+          .arg("markdown")
+          .arg("--verbose")
+          .arg("--toc")
+          .arg("--toc-depth")
+          .arg(
+            cfg.toc_depth.to_string()
+          )
+          .args(&cfg.extra_args)
+          .arg("--")
+          .arg(input)
+          .stderr(Stdio::inherit()),
+        timeout
+      )
+    })
+    .with_context(|| {
+      format!(
+        "pandoc failed for {}",
+        input.display()
+      )
+    })?;
+  if !result.status.success() {
     return Err(anyhow!(
       "pandoc exit status: {}",
-      status
+      result.status
     ));
   }
+  let text = String::from_utf8_lossy(
+    &result.stdout
+  )
+  .into_owned();
 
-  let meta = fs::metadata(output)?;
   if cfg.chapter_split
-    && meta.len() > cfg.max_file_bytes
+    && text.len() as u64
+      > cfg.max_file_bytes
   {
     info!(
-      bytes = meta.len(),
-      "split epub output"
+      bytes = text.len(),
+      "split pandoc output"
     );
     let parts = split_markdown_file(
       output,
-      cfg.max_chapter_bytes
-    )?;
+      &text,
+      cfg.max_chapter_bytes,
+      cfg.split_heading_regex.as_ref()
+    );
     if cfg.join_parts {
-      join_parts_into(output, &parts)?;
-      if !cfg.keep_parts {
-        for part in &parts {
-          let _ = fs::remove_file(part);
-        }
+      let mut joined = String::new();
+      for part in &parts {
+        joined.push_str(&part.text);
+        joined.push('\n');
       }
-      return Ok(vec![
-        output.to_path_buf(),
-      ]);
+      let kept = if cfg.keep_parts {
+        parts
+      } else {
+        Vec::new()
+      };
+      return Ok((
+        vec![ExtractedText {
+          path: output.to_path_buf(),
+          text: joined
+        }],
+        kept
+      ));
     }
-    fs::remove_file(output).ok();
-    return Ok(parts);
+    return Ok((parts, Vec::new()));
   }
 
-  Ok(vec![output.to_path_buf()])
+  Ok((
+    vec![ExtractedText {
+      path: output.to_path_buf(),
+      text
+    }],
+    Vec::new()
+  ))
+}
+
+fn extract_mobi(
+  input: &Path,
+  output: &Path,
+  cfg: &ExtractMobiConfig,
+  timeout: Option<Duration>
+) -> anyhow::Result<Vec<ExtractedText>>
+{
+  info!(path = %input.display(), "extract mobi");
+  // ebook-convert picks the target
+  // format from the extension and only
+  // writes to disk, so convert next to
+  // the output and read it back.
+  let parent = output
+    .parent()
+    .ok_or_else(|| {
+      anyhow!("missing output parent")
+    })?;
+  fs::create_dir_all(parent)?;
+  let tmp = parent.join(format!(
+    "{}.ebook-convert.txt",
+    output_stem(output)
+  ));
+  let status = TIMERS
+    .time("ebook_convert", || {
+      status_with_timeout(
+        Command::new(
+          &cfg.ebook_convert_bin
+        )
+        .arg(input)
+        .arg(&tmp)
+        .stdout(Stdio::null())
+        .stderr(Stdio::inherit()),
+        timeout
+      )
+    })
+    .map_err(|err| {
+      if err.kind()
+        == std::io::ErrorKind::NotFound
+      {
+        anyhow!(
+          "ebook-convert not found at \
+           {:?}; install Calibre or \
+           set extract.mobi.\
+           ebook_convert_bin",
+          cfg.ebook_convert_bin
+        )
+      } else {
+        anyhow!(err).context(format!(
+          "ebook-convert failed for {}",
+          input.display()
+        ))
+      }
+    })?;
+  if !status.success() {
+    let _ = fs::remove_file(&tmp);
+    return Err(anyhow!(
+      "ebook-convert exit status: {}",
+      status
+    ));
+  }
+  let text = fs::read_to_string(&tmp)
+    .with_context(|| {
+      format!("read {}", tmp.display())
+    });
+  let _ = fs::remove_file(&tmp);
+  Ok(vec![ExtractedText {
+    path: output.to_path_buf(),
+    text: text?
+  }])
 }
 
 fn extract_pdf(
   input: &Path,
   output: &Path,
-  cfg: &ExtractPdfConfig
-) -> anyhow::Result<Vec<PathBuf>> {
+  config: &Config
+) -> anyhow::Result<(
+  Vec<ExtractedText>,
+  Option<PdfQuality>
+)> {
+  let cfg = &config.extract.pdf;
+  let timeout =
+    subprocess_timeout(&config.extract);
   if cfg.backend != "docling" {
     return Err(anyhow!(
       "unsupported pdf backend: {}",
       cfg.backend
     ));
   }
+  let docling_ext =
+    docling_extension(&cfg.docling_to)?;
   let meta = fs::metadata(input)?;
   if cfg.skip_oversize
     && meta.len() > cfg.max_file_bytes
   {
     warn!(bytes = meta.len(), path = %input.display(), "skip oversized pdf");
-    return Ok(Vec::new());
+    return Ok((Vec::new(), None));
+  }
+  if config.extract.skip_encrypted
+    && let Some(detail) = pdf_encryption(
+      input, cfg, timeout
+    )
+  {
+    return skip_encrypted_pdf(
+      input, &detail, config
+    )
+    .map(|texts| (texts, None));
   }
 
   let output_dir = output
@@ -282,48 +2588,106 @@ fn extract_pdf(
     .ok_or_else(|| {
       anyhow!("missing output parent")
     })?;
-  fs::create_dir_all(&output_dir)?;
 
   let quality = if cfg.text_first {
-    classify_pdf_quality(input, cfg)?
+    TIMERS.time("classify", || {
+      classify_pdf_quality(
+        input, cfg, timeout
+      )
+    })?
   } else {
     PdfQuality::Scan
   };
 
   match quality {
     | PdfQuality::Text => {
-      if cfg.split_text_extraction {
+      let text = if cfg
+        .split_text_extraction
+      {
         info!(path = %input.display(), "extract pdf (paged text)");
-        extract_pdf_text_paged(
-          input, output, cfg
-        )?;
+        TIMERS.time("pdftotext", || {
+          extract_pdf_text_paged(
+            input, cfg, timeout
+          )
+        })
+      } else {
+        info!(path = %input.display(), "extract pdf (text)");
+        TIMERS.time("pdftotext", || {
+          extract_pdf_text_single(
+            input, cfg, timeout
+          )
+        })
+      };
+      let text = match text {
+        | Err(err)
+          if config
+            .extract
+            .skip_encrypted
+            && err
+              .downcast_ref::<EncryptedPdf>()
+              .is_some() =>
+        {
+          return skip_encrypted_pdf(
+            input,
+            &err.to_string(),
+            config
+          )
+          .map(|texts| (texts, None));
+        }
+        | other => other?
+      };
+      let text = if config
+        .extract
+        .strip_running_headers
+      {
+        strip_running_headers(
+          &text,
+          config
+            .extract
+            .header_footer_min_ratio
+        )
       } else {
-        info!(path = %input.display(), "extract pdf (text)");
-        extract_pdf_text_single(
-          input, output, cfg
-        )?;
-      }
-      return Ok(vec![
-        output.to_path_buf(),
-      ]);
+        text
+      };
+      let text = if config
+        .extract
+        .emit_page_markers
+      {
+        mark_pages(&text)
+      } else {
+        text
+      };
+      return Ok((
+        vec![ExtractedText {
+          path: output.to_path_buf(),
+          text
+        }],
+        Some(quality)
+      ));
     }
     | PdfQuality::LowQuality => {
       info!(path = %input.display(), "extract pdf (low quality)");
-      run_docling(
-        input,
-        &output_dir,
-        cfg,
-        DoclingMode::LowQuality
-      )?;
+      fs::create_dir_all(&output_dir)?;
+      TIMERS.time("docling", || {
+        run_docling(
+          input,
+          &output_dir,
+          cfg,
+          DoclingMode::LowQuality
+        )
+      })?;
     }
     | PdfQuality::Scan => {
       info!(path = %input.display(), "extract pdf (scan)");
-      run_docling(
-        input,
-        &output_dir,
-        cfg,
-        DoclingMode::Scan
-      )?;
+      fs::create_dir_all(&output_dir)?;
+      TIMERS.time("docling", || {
+        run_docling(
+          input,
+          &output_dir,
+          cfg,
+          DoclingMode::Scan
+        )
+      })?;
     }
   }
 
@@ -333,8 +2697,20 @@ fn extract_pdf(
       .and_then(OsStr::to_str)
       .unwrap_or("document")
       .to_string()
-      + ".txt"
+      + "."
+      + docling_ext
   );
+  // Markdown and JSON take
+  // `output_extension` so `chunk`
+  // still picks them up.
+  let output = if docling_ext == "txt" {
+    output.to_path_buf()
+  } else {
+    output.with_extension(
+      &config.extract.output_extension
+    )
+  };
+  let output = output.as_path();
   if default_out.exists()
     && default_out != output
   {
@@ -354,30 +2730,405 @@ fn extract_pdf(
       output.display()
     ));
   }
-  Ok(vec![output.to_path_buf()])
+  // Docling only writes to disk; read
+  // its output back so callers get text
+  // the same way for every backend.
+  let text = fs::read_to_string(output)
+    .with_context(|| {
+      format!(
+        "read {}",
+        output.display()
+      )
+    })?;
+  Ok((
+    vec![ExtractedText {
+      path: output.to_path_buf(),
+      text
+    }],
+    Some(quality)
+  ))
+}
+
+/// Extension docling gives its output
+/// for `--to`.
+fn docling_extension(
+  to: &str
+) -> anyhow::Result<&'static str> {
+  match to {
+    | "text" => Ok("txt"),
+    | "md" => Ok("md"),
+    | "json" => Ok("json"),
+    | other => {
+      Err(anyhow!(
+        "unsupported \
+         extract.pdf.docling_to: \
+         {other} (expected text, md, \
+         or json)"
+      ))
+    }
+  }
 }
 
-fn join_parts_into(
-  output: &Path,
-  parts: &[PathBuf]
+/// The tool behind a `format`
+/// extraction, as recorded in the run
+/// manifest.
+fn backend_name(
+  format: &str,
+  pdf_quality: Option<PdfQuality>
+) -> &'static str {
+  match (format, pdf_quality) {
+    | (
+      "pdf",
+      Some(PdfQuality::Text)
+    ) => "pdftotext",
+    | ("pdf", Some(_)) => "docling",
+    | ("pdf", None) => "none",
+    | ("mobi" | "azw" | "azw3", _) => {
+      "ebook-convert"
+    }
+    | _ => "pandoc"
+  }
+}
+
+/// A PDF that cannot be read without a
+/// password or forbids copying text.
+#[derive(Debug)]
+struct EncryptedPdf(String);
+
+impl std::fmt::Display
+  for EncryptedPdf
+{
+  fn fmt(
+    &self,
+    f: &mut std::fmt::Formatter<'_>
+  ) -> std::fmt::Result {
+    write!(
+      f,
+      "encrypted pdf: {}",
+      self.0
+    )
+  }
+}
+
+impl std::error::Error
+  for EncryptedPdf
+{
+}
+
+/// pdftotext exits 3 when the PDF's
+/// permissions forbid copying text.
+fn check_pdftotext(
+  output: &Output
 ) -> anyhow::Result<()> {
-  let mut out =
-    fs::File::create(output)?;
-  for part in parts {
-    let bytes = fs::read(part)?;
-    out.write_all(&bytes)?;
-    out.write_all(b"\n")?;
+  if output.status.code() == Some(3) {
+    return Err(
+      EncryptedPdf(
+        "pdftotext permission error"
+          .to_string()
+      )
+      .into()
+    );
   }
   Ok(())
 }
 
-fn extract_pdf_text_paged(
+/// Why `pdfinfo` says `input` cannot be
+/// read: a required password, or
+/// `Encrypted: yes` without copy
+/// permission.
+fn pdf_encryption(
   input: &Path,
-  output: &Path,
-  cfg: &ExtractPdfConfig
+  cfg: &ExtractPdfConfig,
+  timeout: Option<Duration>
+) -> Option<String> {
+  let output = output_with_timeout(
+    Command::new(&cfg.pdfinfo_bin)
+      .arg(input)
+      .stderr(Stdio::piped()),
+    timeout
+  )
+  .ok()?;
+  if String::from_utf8_lossy(
+    &output.stderr
+  )
+  .contains("Incorrect password")
+  {
+    return Some(
+      "password required".to_string()
+    );
+  }
+  let stdout = String::from_utf8_lossy(
+    &output.stdout
+  );
+  let flags = stdout.lines().find_map(
+    |line| {
+      line.strip_prefix("Encrypted:")
+    }
+  )?;
+  let flags = flags.trim();
+  (flags.starts_with("yes")
+    && !flags.contains("copy:yes"))
+  .then(|| {
+    format!("Encrypted: {flags}")
+  })
+}
+
+const SKIPPED_LOG: &str =
+  "extract_skipped.jsonl";
+
+/// A source `extract` deliberately
+/// bypassed, as logged to
+/// `state_dir/extract_skipped.jsonl`.
+#[derive(Debug, Serialize)]
+struct SkippedSource<'a> {
+  source: &'a Path,
+  reason: &'a str,
+  detail: &'a str,
+  run_id: &'a str,
+  at:     String
+}
+
+fn skip_encrypted_pdf(
+  input: &Path,
+  detail: &str,
+  config: &Config
+) -> anyhow::Result<Vec<ExtractedText>>
+{
+  warn!(path = %input.display(), detail, "skip encrypted pdf");
+  let entry = SkippedSource {
+    source: input,
+    reason: "encrypted",
+    detail,
+    run_id: &config.run_id,
+    at: Utc::now().to_rfc3339()
+  };
+  if let Err(err) =
+    record_skipped(&entry, config)
+  {
+    warn!(error = %err, "record skipped source failed");
+  }
+  Ok(Vec::new())
+}
+
+fn record_skipped(
+  entry: &SkippedSource<'_>,
+  config: &Config
 ) -> anyhow::Result<()> {
-  let total_pages =
-    pdf_page_count(input, cfg)?;
+  let path = config
+    .paths
+    .state_dir
+    .join(SKIPPED_LOG);
+  if let Some(parent) = path.parent() {
+    fs::create_dir_all(parent)?;
+  }
+  let mut line =
+    serde_json::to_string(entry)?;
+  line.push('\n');
+  fs::OpenOptions::new()
+    .create(true)
+    .append(true)
+    .open(&path)
+    .with_context(|| {
+      format!("open {}", path.display())
+    })?
+    .write_all(line.as_bytes())?;
+  Ok(())
+}
+
+/// Non-blank lines this close to the
+/// top or bottom of a page are header
+/// and footer candidates.
+const HEADER_FOOTER_LINES: usize = 3;
+
+/// Documents with fewer pages than this
+/// keep every line.
+const MIN_HEADER_PAGES: usize = 4;
+
+/// Drops lines that recur at the top or
+/// bottom of at least `min_ratio` of
+/// the form-feed separated pages in
+/// pdftotext output. Digits are
+/// ignored when matching, so `Page 12`
+/// and `Page 13` are one running
+/// footer; lines in the middle of a
+/// page are never removed.
+fn strip_running_headers(
+  text: &str,
+  min_ratio: f32
+) -> String {
+  let pages = text
+    .split('\x0c')
+    .map(|page| {
+      page.lines().collect::<Vec<_>>()
+    })
+    .collect::<Vec<_>>();
+  let mut counts: HashMap<
+    String,
+    usize
+  > = HashMap::new();
+  let mut text_pages = 0usize;
+  for page in &pages {
+    let edges = edge_lines(page);
+    if edges.is_empty() {
+      continue;
+    }
+    text_pages += 1;
+    let keys = edges
+      .iter()
+      .map(|&idx| header_key(page[idx]))
+      .collect::<HashSet<_>>();
+    for key in keys {
+      *counts
+        .entry(key)
+        .or_default() += 1;
+    }
+  }
+  if text_pages < MIN_HEADER_PAGES {
+    return text.to_string();
+  }
+  let min_pages = ((text_pages as f32
+    * min_ratio.clamp(0.0, 1.0))
+  .ceil() as usize)
+    .max(2);
+  let running = counts
+    .into_iter()
+    .filter(|(_, n)| *n >= min_pages)
+    .map(|(key, _)| key)
+    .collect::<HashSet<_>>();
+  if running.is_empty() {
+    return text.to_string();
+  }
+  pages
+    .iter()
+    .map(|page| {
+      let edges = edge_lines(page);
+      page
+        .iter()
+        .enumerate()
+        .filter(|(idx, line)| {
+          !(edges.contains(idx)
+            && running.contains(
+              &header_key(line)
+            ))
+        })
+        .map(|(_, line)| *line)
+        .collect::<Vec<_>>()
+        .join("\n")
+    })
+    .collect::<Vec<_>>()
+    .join("\x0c")
+}
+
+/// Indexes of the first and last
+/// `HEADER_FOOTER_LINES` non-blank
+/// lines of a page.
+fn edge_lines(
+  page: &[&str]
+) -> BTreeSet<usize> {
+  let filled = page
+    .iter()
+    .enumerate()
+    .filter(|(_, line)| {
+      !line.trim().is_empty()
+    })
+    .map(|(idx, _)| idx)
+    .collect::<Vec<_>>();
+  filled
+    .iter()
+    .take(HEADER_FOOTER_LINES)
+    .chain(
+      filled
+        .iter()
+        .rev()
+        .take(HEADER_FOOTER_LINES)
+    )
+    .copied()
+    .collect()
+}
+
+/// A line with whitespace collapsed,
+/// case folded, and digits masked.
+fn header_key(line: &str) -> String {
+  line
+    .split_whitespace()
+    .collect::<Vec<_>>()
+    .join(" ")
+    .chars()
+    .map(|c| {
+      if c.is_ascii_digit() {
+        '#'
+      } else {
+        c
+      }
+    })
+    .collect::<String>()
+    .to_lowercase()
+}
+
+/// The line `mark_pages` puts before
+/// page `page` (1-based). Plain ASCII,
+/// so NFKC leaves it intact for
+/// `chunk`.
+pub fn page_marker(
+  page: usize
+) -> String {
+  format!("<!-- page: {page} -->")
+}
+
+/// The page number of a `page_marker`
+/// line.
+pub fn parse_page_marker(
+  line: &str
+) -> Option<usize> {
+  line
+    .strip_prefix("<!--")?
+    .strip_suffix("-->")?
+    .trim()
+    .strip_prefix("page:")?
+    .trim()
+    .parse()
+    .ok()
+}
+
+/// Replaces pdftotext's form feeds
+/// with a `page_marker` paragraph at
+/// the start of every page. The empty
+/// tail after the final form feed is
+/// not a page.
+fn mark_pages(text: &str) -> String {
+  let mut pages = text
+    .split('\x0c')
+    .collect::<Vec<_>>();
+  if pages.len() > 1
+    && pages.last().is_some_and(|p| {
+      p.trim().is_empty()
+    })
+  {
+    pages.pop();
+  }
+  let mut out =
+    String::with_capacity(text.len());
+  for (idx, page) in
+    pages.into_iter().enumerate()
+  {
+    out.push_str(&page_marker(idx + 1));
+    out.push_str("\n\n");
+    out.push_str(
+      page.trim_matches('\n')
+    );
+    out.push_str("\n\n");
+  }
+  out
+}
+
+fn extract_pdf_text_paged(
+  input: &Path,
+  cfg: &ExtractPdfConfig,
+  timeout: Option<Duration>
+) -> anyhow::Result<String> {
+  let total_pages = pdf_page_count(
+    input, cfg, timeout
+  )?;
   if total_pages == 0 {
     return Err(anyhow!(
       "pdf page count is zero: {}",
@@ -386,14 +3137,13 @@ fn extract_pdf_text_paged(
   }
   let pages_per_pass =
     cfg.max_pages_per_pass.max(1);
-  let mut out =
-    fs::File::create(output)?;
+  let mut out = String::new();
   let mut page = 1usize;
   while page <= total_pages {
     let end = (page + pages_per_pass
       - 1)
       .min(total_pages);
-    let output =
+    let output = output_with_timeout(
       Command::new(&cfg.pdftotext_bin)
         .arg("-f")
         .arg(page.to_string())
@@ -401,57 +3151,72 @@ fn extract_pdf_text_paged(
         .arg(end.to_string())
         .arg(input)
         .arg("-")
-        .output()
-        .with_context(|| {
-          format!(
-            "pdftotext failed for {}",
-            input.display()
-          )
-        })?;
-    out.write_all(&output.stdout)?;
-    out.write_all(b"\n")?;
+        .stderr(Stdio::null()),
+      timeout
+    )
+    .with_context(|| {
+      format!(
+        "pdftotext failed for {}",
+        input.display()
+      )
+    })?;
+    check_pdftotext(&output)?;
+    out.push_str(
+      &String::from_utf8_lossy(
+        &output.stdout
+      )
+    );
+    out.push('\n');
     page = end + 1;
   }
-  Ok(())
+  Ok(out)
 }
 
 fn extract_pdf_text_single(
   input: &Path,
-  output: &Path,
-  cfg: &ExtractPdfConfig
-) -> anyhow::Result<()> {
+  cfg: &ExtractPdfConfig,
+  timeout: Option<Duration>
+) -> anyhow::Result<String> {
   let output_text =
-    Command::new(&cfg.pdftotext_bin)
-      .arg(input)
-      .arg("-")
-      .output()
-      .with_context(|| {
-        format!(
-          "pdftotext failed for {}",
-          input.display()
-        )
-      })?;
-  fs::write(
-    output,
-    output_text.stdout
-  )?;
-  Ok(())
+    output_with_timeout(
+      Command::new(&cfg.pdftotext_bin)
+        .arg(input)
+        .arg("-")
+        .stderr(Stdio::null()),
+      timeout
+    )
+    .with_context(|| {
+      format!(
+        "pdftotext failed for {}",
+        input.display()
+      )
+    })?;
+  check_pdftotext(&output_text)?;
+  Ok(
+    String::from_utf8_lossy(
+      &output_text.stdout
+    )
+    .into_owned()
+  )
 }
 
 fn pdf_page_count(
   input: &Path,
-  cfg: &ExtractPdfConfig
+  cfg: &ExtractPdfConfig,
+  timeout: Option<Duration>
 ) -> anyhow::Result<usize> {
-  let output =
+  let output = output_with_timeout(
     Command::new(&cfg.pdfinfo_bin)
       .arg(input)
-      .output()
-      .with_context(|| {
-        format!(
-          "pdfinfo failed for {}",
-          input.display()
-        )
-      })?;
+      .stderr(Stdio::null()),
+    timeout
+  )
+  .with_context(|| {
+    format!(
+      "pdfinfo failed for {}",
+      input.display()
+    )
+  })?;
   let stdout = String::from_utf8_lossy(
     &output.stdout
   );
@@ -469,8 +3234,11 @@ fn pdf_page_count(
   Ok(0)
 }
 
-#[derive(Debug, Clone, Copy)]
-enum PdfQuality {
+#[derive(
+  Debug, Clone, Copy, Serialize,
+)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum PdfQuality {
   Text,
   LowQuality,
   Scan
@@ -482,20 +3250,23 @@ enum DoclingMode {
   Scan
 }
 
-fn classify_pdf_quality(
+pub(crate) fn classify_pdf_quality(
   input: &Path,
-  cfg: &ExtractPdfConfig
+  cfg: &ExtractPdfConfig,
+  timeout: Option<Duration>
 ) -> anyhow::Result<PdfQuality> {
-  let output =
+  let output = output_with_timeout(
     Command::new(&cfg.pdffonts_bin)
       .arg(input)
-      .output()
-      .with_context(|| {
-        format!(
-          "pdffonts failed for {}",
-          input.display()
-        )
-      })?;
+      .stderr(Stdio::null()),
+    timeout
+  )
+  .with_context(|| {
+    format!(
+      "pdffonts failed for {}",
+      input.display()
+    )
+  })?;
   let stdout = String::from_utf8_lossy(
     &output.stdout
   );
@@ -514,7 +3285,7 @@ fn classify_pdf_quality(
   let mut pages_sampled = 0usize;
   for page in 1..=cfg.text_sample_pages
   {
-    let output =
+    let output = output_with_timeout(
       Command::new(&cfg.pdftotext_bin)
         .arg("-f")
         .arg(page.to_string())
@@ -522,13 +3293,15 @@ fn classify_pdf_quality(
         .arg(page.to_string())
         .arg(input)
         .arg("-")
-        .output()
-        .with_context(|| {
-          format!(
-            "pdftotext failed for {}",
-            input.display()
-          )
-        })?;
+        .stderr(Stdio::null()),
+      timeout
+    )
+    .with_context(|| {
+      format!(
+        "pdftotext failed for {}",
+        input.display()
+      )
+    })?;
     let text = String::from_utf8_lossy(
       &output.stdout
     );
@@ -537,14 +3310,10 @@ fn classify_pdf_quality(
       continue;
     }
     pages_sampled += 1;
-    for ch in trimmed.chars() {
-      if ch.is_ascii_alphabetic() {
-        alpha_chars += 1;
-      }
-      if !ch.is_control() {
-        total_chars += 1;
-      }
-    }
+    let (alpha, total) =
+      alpha_counts(trimmed);
+    alpha_chars += alpha;
+    total_chars += total;
   }
   if pages_sampled == 0 {
     return Ok(PdfQuality::Scan);
@@ -585,7 +3354,7 @@ fn run_docling(
     .arg("--from")
     .arg("pdf")
     .arg("--to")
-    .arg("text")
+    .arg(&cfg.docling_to)
     .arg("--device")
     .arg(&cfg.docling_device)
     .arg("--pipeline")
@@ -676,26 +3445,42 @@ fn run_docling(
 
 fn split_markdown_file(
   path: &Path,
-  max_chapter_bytes: u64
-) -> anyhow::Result<Vec<PathBuf>> {
-  let raw = fs::read_to_string(path)?;
+  raw: &str,
+  max_chapter_bytes: u64,
+  heading: Option<&Regex>
+) -> Vec<ExtractedText> {
   let mut parts = Vec::new();
   let mut current = String::new();
   let mut part_index = 0usize;
 
   for line in raw.lines() {
-    let is_heading =
-      line.starts_with('#');
+    let is_heading = match heading {
+      | Some(re) => re.is_match(line),
+      | None => line.starts_with('#')
+    };
     if is_heading
       && !current.is_empty()
       && current.len() as u64
         > max_chapter_bytes
     {
-      part_index += 1;
-      parts.push(write_part(
-        path, part_index, &current
-      )?);
-      current.clear();
+      // A setext underline belongs to
+      // the title line above it, which
+      // opens the new part.
+      let carried =
+        if is_setext_underline(line) {
+          take_last_line(&mut current)
+        } else {
+          String::new()
+        };
+      if !current.is_empty() {
+        part_index += 1;
+        parts.push(write_part(
+          path,
+          part_index,
+          std::mem::take(&mut current)
+        ));
+      }
+      current = carried;
     }
     current.push_str(line);
     current.push('\n');
@@ -704,52 +3489,269 @@ fn split_markdown_file(
   if !current.is_empty() {
     part_index += 1;
     parts.push(write_part(
-      path, part_index, &current
-    )?);
+      path, part_index, current
+    ));
   }
 
-  Ok(parts)
+  parts
+}
+
+fn is_setext_underline(
+  line: &str
+) -> bool {
+  let line = line.trim();
+  line.len() >= 2
+    && (line.bytes().all(|b| b == b'=')
+      || line
+        .bytes()
+        .all(|b| b == b'-'))
+}
+
+/// Removes and returns the last
+/// newline-terminated line of `text`.
+fn take_last_line(
+  text: &mut String
+) -> String {
+  let body = text
+    .strip_suffix('\n')
+    .unwrap_or(text);
+  let start = body
+    .rfind('\n')
+    .map_or(0, |i| i + 1);
+  text.split_off(start)
 }
 
 fn write_part(
   base: &Path,
   index: usize,
-  contents: &str
-) -> anyhow::Result<PathBuf> {
+  contents: String
+) -> ExtractedText {
   let part_path =
     base.with_file_name(format!(
-      "{}-part{:04}.txt",
+      "{}-part{:04}.{}",
       base
         .file_stem()
         .and_then(OsStr::to_str)
         .unwrap_or("book"),
-      index
+      index,
+      base
+        .extension()
+        .and_then(OsStr::to_str)
+        .unwrap_or("txt")
     ));
-  let mut file =
-    fs::File::create(&part_path)?;
-  file
-    .write_all(contents.as_bytes())?;
-  Ok(part_path)
+  ExtractedText {
+    path: part_path,
+    text: contents
+  }
 }
 
 fn read_metadata(
   path: &Path,
-  format: &str
+  format: &str,
+  config: &Config
 ) -> ExtractedMetadata {
   let mut metadata =
     ExtractedMetadata {
       format: format.to_string(),
       ..Default::default()
     };
-  if let Some(opf_path) = find_opf(path)
+  if let Some(book) =
+    read_server_book(path)
+  {
+    apply_server_book(
+      &book,
+      &mut metadata
+    );
+  } else if let Some(opf_path) =
+    find_opf(path)
     && let Ok(opf) =
       fs::read_to_string(&opf_path)
   {
     parse_opf(&opf, &mut metadata);
   }
+  if format == "pdf" {
+    TIMERS.time("pdfinfo", || {
+      apply_pdfinfo(
+        path,
+        &config.extract,
+        &mut metadata
+      )
+    });
+  }
   metadata
 }
 
+/// Fills the title, authors, and
+/// published date the OPF left empty
+/// from `pdfinfo`.
+fn apply_pdfinfo(
+  path: &Path,
+  cfg: &ExtractConfig,
+  metadata: &mut ExtractedMetadata
+) {
+  let output = output_with_timeout(
+    Command::new(&cfg.pdf.pdfinfo_bin)
+      .arg("-isodates")
+      .arg(path)
+      .stderr(Stdio::null()),
+    subprocess_timeout(cfg)
+  );
+  match output {
+    | Ok(output)
+      if output.status.success() =>
+    {
+      parse_pdfinfo(
+        &String::from_utf8_lossy(
+          &output.stdout
+        ),
+        metadata
+      );
+    }
+    | Ok(output) => {
+      debug!(path = %path.display(), status = %output.status, "pdfinfo metadata unavailable");
+    }
+    | Err(err) => {
+      debug!(path = %path.display(), error = %err, "pdfinfo metadata unavailable");
+    }
+  }
+}
+
+fn parse_pdfinfo(
+  info: &str,
+  metadata: &mut ExtractedMetadata
+) {
+  for line in info.lines() {
+    let Some((key, value)) =
+      line.split_once(':')
+    else {
+      continue;
+    };
+    let value = value.trim();
+    if value.is_empty() {
+      continue;
+    }
+    match key {
+      | "Title"
+        if metadata.title.is_none() =>
+      {
+        metadata.title =
+          Some(value.to_string());
+      }
+      | "Author"
+        if metadata
+          .authors
+          .is_empty() =>
+      {
+        metadata.authors = value
+          .split(';')
+          .map(str::trim)
+          .filter(|a| !a.is_empty())
+          .map(str::to_string)
+          .collect();
+      }
+      | "CreationDate"
+        if metadata
+          .published
+          .is_none() =>
+      {
+        metadata.published =
+          Some(value.to_string());
+      }
+      | _ => {}
+    }
+  }
+}
+
+/// Calibre's `cover.jpg` (or
+/// `cover.png`) next to the source.
+fn find_cover(
+  path: &Path
+) -> Option<PathBuf> {
+  let parent = path.parent()?;
+  [
+    "cover.jpg",
+    "cover.jpeg",
+    "cover.png"
+  ]
+  .iter()
+  .map(|name| parent.join(name))
+  .find(|cover| cover.exists())
+}
+
+/// The content server's entry for a
+/// staged download, if `path` is one.
+fn read_server_book(
+  path: &Path
+) -> Option<Value> {
+  let raw =
+    fs::read(path.parent()?.join(
+      calibre_server::BOOK_METADATA
+    ))
+    .ok()?;
+  serde_json::from_slice(&raw).ok()
+}
+
+/// Maps `/ajax/books` fields onto the
+/// sidecar, as `parse_opf` does for
+/// `metadata.opf`.
+fn apply_server_book(
+  entry: &Value,
+  metadata: &mut ExtractedMetadata
+) {
+  let text = |value: &Value| {
+    value
+      .as_str()
+      .map(str::trim)
+      .filter(|s| !s.is_empty())
+      .map(str::to_string)
+  };
+  let book = &entry["book"];
+  if let Some(url) =
+    text(&entry["source_url"])
+  {
+    metadata.source_path = url;
+  }
+  metadata.title = text(&book["title"]);
+  metadata.authors = book["authors"]
+    .as_array()
+    .map(|authors| {
+      authors
+        .iter()
+        .filter_map(text)
+        .collect()
+    })
+    .unwrap_or_default();
+  metadata.language = book["languages"]
+    .as_array()
+    .and_then(|langs| langs.first())
+    .and_then(text);
+  metadata.published =
+    text(&book["pubdate"]);
+  if let Some(ids) =
+    book["identifiers"].as_object()
+  {
+    metadata.identifiers = ids
+      .iter()
+      .filter_map(|(kind, value)| {
+        text(value).map(|v| {
+          format!("{kind}:{v}")
+        })
+      })
+      .collect();
+  }
+  metadata.calibre_id = book
+    ["application_id"]
+    .as_u64()
+    .or(entry["id"].as_u64())
+    .map(|id| id.to_string());
+  metadata.series =
+    text(&book["series"]);
+  metadata.series_index = book
+    ["series_index"]
+    .as_f64()
+    .map(|idx| idx as f32);
+}
+
 fn find_opf(
   path: &Path
 ) -> Option<PathBuf> {
@@ -782,6 +3784,10 @@ fn parse_opf(
         )
         .to_string();
         current.clear();
+        apply_opf_meta(&e, metadata);
+      }
+      | Ok(Event::Empty(e)) => {
+        apply_opf_meta(&e, metadata);
       }
       | Ok(Event::Text(e)) => {
         if let Ok(text) = e.decode() {
@@ -844,6 +3850,49 @@ fn parse_opf(
   }
 }
 
+/// Reads Calibre's attribute-only
+/// `<meta name="calibre:series"
+/// content="...">` entries.
+fn apply_opf_meta(
+  e: &BytesStart<'_>,
+  metadata: &mut ExtractedMetadata
+) {
+  if e.local_name().as_ref() != b"meta"
+  {
+    return;
+  }
+  let mut name = None;
+  let mut content = None;
+  for attr in e.attributes().flatten() {
+    let value = attr
+      .unescape_value()
+      .map(|v| v.trim().to_string())
+      .unwrap_or_default();
+    match attr.key.as_ref() {
+      | b"name" => name = Some(value),
+      | b"content" => {
+        content = Some(value)
+      }
+      | _ => {}
+    }
+  }
+  let Some(content) =
+    content.filter(|c| !c.is_empty())
+  else {
+    return;
+  };
+  match name.as_deref() {
+    | Some("calibre:series") => {
+      metadata.series = Some(content)
+    }
+    | Some("calibre:series_index") => {
+      metadata.series_index =
+        content.parse().ok()
+    }
+    | _ => {}
+  }
+}
+
 fn write_metadata(
   path: &Path,
   metadata: &ExtractedMetadata
@@ -857,3 +3906,16 @@ fn write_metadata(
   fs::write(path, raw)?;
   Ok(())
 }
+
+/// `extract.subprocess_timeout_seconds`
+/// as a wait limit for external tools.
+pub(crate) fn subprocess_timeout(
+  cfg: &ExtractConfig
+) -> Option<Duration> {
+  (cfg.subprocess_timeout_seconds > 0)
+    .then(|| {
+      Duration::from_secs(
+        cfg.subprocess_timeout_seconds
+      )
+    })
+}
@@ -1,9 +1,24 @@
+use std::borrow::Cow;
 use std::collections::{
+  BTreeMap,
   HashMap,
+  HashSet,
   VecDeque
 };
 use std::fs;
-use std::path::Path;
+use std::io::{
+  BufRead,
+  BufReader,
+  Write
+};
+use std::path::{
+  Path,
+  PathBuf
+};
+use std::sync::atomic::{
+  AtomicBool,
+  Ordering
+};
 use std::sync::{
   Arc,
   Mutex
@@ -14,106 +29,937 @@ use anyhow::{
   Context,
   anyhow
 };
-use reqwest::Client;
-use serde::Deserialize;
+use base64::Engine;
+use base64::prelude::BASE64_STANDARD;
+use clap::Args;
+use qdrant_client::qdrant::{
+  NamedVectors,
+  PointStruct,
+  UpsertPoints,
+  UpsertPointsBuilder,
+  Vector,
+  Vectors
+};
+use qdrant_client::{
+  Payload,
+  Qdrant,
+  QdrantError
+};
+use reqwest::{
+  Client,
+  Identity,
+  RequestBuilder,
+  StatusCode
+};
+use serde::{
+  Deserialize,
+  Serialize
+};
 use serde_json::{
   Value,
   json
 };
-use tokio::sync::Semaphore;
+use tokio::sync::{
+  Semaphore,
+  mpsc,
+  oneshot
+};
+use tokio::task::JoinHandle;
 use tracing::{
+  Instrument,
+  Span,
   debug,
   info,
+  info_span,
   warn
 };
-use walkdir::WalkDir;
 
 use crate::config::{
+  ChunkFieldNames,
   Config,
+  EmbeddingEncoding,
+  EmbeddingProvider,
+  InsertElasticsearchConfig,
   InsertEmbeddingsConfig,
+  InsertHttpConfig,
+  InsertPgvectorConfig,
   InsertQdrantConfig,
-  InsertQuickwitConfig
+  InsertQuickwitConfig,
+  InsertTarget,
+  LexicalStore,
+  QdrantCollectionParams,
+  QdrantTransport,
+  VectorStore
 };
 use crate::logging::{
   LogOp,
   color_prefix
 };
+use crate::util::{
+  FailureGate,
+  JsonlAppender,
+  PhaseTimers,
+  WalkedFiles,
+  alpha_ratio,
+  slugify,
+  walk_files
+};
+use crate::{
+  catalog,
+  chunk
+};
+
+#[derive(Debug, Default, Args)]
+pub struct InsertArgs {
+  /// Write a catalog of the ingested
+  /// documents (title, authors,
+  /// language, chunk count) to this
+  /// path after inserting
+  #[arg(long)]
+  pub catalog: Option<PathBuf>,
+
+  /// Re-attempt only the records in
+  /// state_dir/failed_embeddings.jsonl
+  #[arg(long, default_value_t = false)]
+  pub retry_failed: bool,
+
+  /// Ingest at most this many chunks,
+  /// sampled evenly within each file
+  /// in proportion to its size
+  #[arg(long)]
+  pub max_vectors: Option<usize>,
+
+  /// Ignore insert.skip_completed and
+  /// insert every file again
+  #[arg(long)]
+  pub force: bool
+}
+
+const FAILED_EMBEDDINGS: &str =
+  "failed_embeddings.jsonl";
+const FAILED_EMBEDDINGS_RETRY: &str =
+  "failed_embeddings.retry.jsonl";
+const INSERT_STATE: &str =
+  "insert_state.jsonl";
+const DEAD_LETTER: &str =
+  "dead_letter.jsonl";
+
+/// Per-phase time for the current
+/// insert run, logged with the summary.
+/// Embedding and the two stores
+/// overlap, so their totals add past
+/// wall time.
+static TIMERS: PhaseTimers =
+  PhaseTimers::new();
 
 #[derive(Clone)]
 struct InsertDeps {
-  client:          Client,
+  client:           Client,
   embed_cfg: InsertEmbeddingsConfig,
-  qdrant_cfg:      InsertQdrantConfig,
-  quickwit_cfg:    InsertQuickwitConfig,
-  embed_semaphore: Arc<Semaphore>,
+  writers:          StoreWriters,
+  embed_semaphore:  Arc<Semaphore>,
   cache:
-    Option<Arc<Mutex<EmbeddingCache>>>
+    Option<Arc<Mutex<EmbeddingCache>>>,
+  min_alpha_ratio:  f32,
+  retry:            RetryPolicy,
+  failed: Option<Arc<JsonlAppender>>,
+  /// Set with `insert.dead_letter`.
+  dead_letter:
+    Option<Arc<JsonlAppender>>,
+  field_names:      ChunkFieldNames,
+  /// `vector_size` when chunk
+  /// `embedding`s are used instead of
+  /// the embedder.
+  precomputed_size: Option<usize>,
+  /// Cleared once the embedder turns
+  /// out not to take batched input.
+  batch_embed:      Arc<AtomicBool>,
+  vector_store:     VectorStore,
+  lexical_store:    LexicalStore,
+  /// Batches a file keeps in flight
+  /// before waiting on its oldest:
+  /// every queue slot plus every
+  /// writer slot, so no writer idles.
+  max_pending:      usize
 }
 
-#[derive(Debug, Deserialize)]
-struct ChunkRecord {
-  id:       String,
-  text:     String,
-  metadata: Value
+#[derive(Debug, Default)]
+struct FileStats {
+  inserted:          usize,
+  skipped_low_alpha: usize,
+  failed_embeddings: usize,
+  skipped_sampled:   usize,
+  dead_lettered:     usize
 }
 
-pub async fn run(
+/// Keep `keep` of a file's `of` chunk
+/// lines, spread evenly through it, for
+/// `--max-vectors`.
+#[derive(Debug, Clone, Copy)]
+struct Sample {
+  keep: usize,
+  of:   usize
+}
+
+impl Sample {
+  /// Whether the `idx`th chunk line
+  /// (0-based) is kept.
+  fn keeps(
+    self,
+    idx: usize
+  ) -> bool {
+    if idx >= self.of {
+      return false;
+    }
+    let kept = |n: usize| {
+      (n as u128 * self.keep as u128)
+        / self.of as u128
+    };
+    kept(idx + 1) > kept(idx)
+  }
+}
+
+/// Non-blank lines of `path`, read a
+/// line at a time.
+fn count_chunk_lines(
+  path: &Path
+) -> std::io::Result<usize> {
+  let mut count = 0usize;
+  for line in BufReader::new(
+    fs::File::open(path)?
+  )
+  .lines()
+  {
+    if !line?.trim().is_empty() {
+      count += 1;
+    }
+  }
+  Ok(count)
+}
+
+/// Counts the chunk lines of `files`
+/// and gives each its share of `max`.
+fn sample_files(
+  files: &[PathBuf],
+  max: usize
+) -> anyhow::Result<Vec<Option<Sample>>>
+{
+  let counts = TIMERS.time("count", || {
+    files
+      .iter()
+      .map(|path| {
+        count_chunk_lines(path)
+          .with_context(|| {
+            format!(
+              "read {}",
+              path.display()
+            )
+          })
+      })
+      .collect::<anyhow::Result<Vec<_>>>()
+  })?;
+  let available =
+    counts.iter().sum::<usize>();
+  let quotas =
+    sample_quotas(&counts, max);
+  let kept =
+    quotas.iter().sum::<usize>();
+  info!(
+    max_vectors = max,
+    available,
+    kept,
+    skipped = available - kept,
+    sample_rate = kept as f64
+      / available.max(1) as f64,
+    "insert sampling"
+  );
+  Ok(
+    quotas
+      .into_iter()
+      .zip(counts)
+      .map(|(keep, of)| {
+        Some(Sample {
+          keep,
+          of
+        })
+      })
+      .collect()
+  )
+}
+
+/// Splits `max` chunks across files in
+/// proportion to their `counts`, by
+/// largest remainder; files are taken
+/// whole when everything fits.
+fn sample_quotas(
+  counts: &[usize],
+  max: usize
+) -> Vec<usize> {
+  let total =
+    counts.iter().sum::<usize>();
+  if total <= max {
+    return counts.to_vec();
+  }
+  let share = |c: usize| {
+    let scaled =
+      c as u128 * max as u128;
+    (
+      (scaled / total as u128) as usize,
+      scaled % total as u128
+    )
+  };
+  let mut quotas = counts
+    .iter()
+    .map(|&c| share(c).0)
+    .collect::<Vec<_>>();
+  let mut order = (0..counts.len())
+    .collect::<Vec<_>>();
+  order.sort_by_key(|&i| {
+    std::cmp::Reverse(
+      share(counts[i]).1
+    )
+  });
+  let left =
+    max - quotas.iter().sum::<usize>();
+  for &i in order.iter().take(left) {
+    quotas[i] += 1;
+  }
+  quotas
+}
+
+#[derive(Debug, Clone, Copy)]
+struct RetryPolicy {
+  retry_max:  usize,
+  backoff_ms: u64
+}
+
+impl RetryPolicy {
+  fn backoff(
+    &self,
+    attempt: usize
+  ) -> Duration {
+    let factor = 1u64
+      << attempt
+        .saturating_sub(1)
+        .min(16);
+    Duration::from_millis(
+      self
+        .backoff_ms
+        .saturating_mul(factor)
+    )
+  }
+}
+
+/// Sends the request `build` makes,
+/// again after `retry.backoff` while it
+/// fails to connect, times out, or gets
+/// a 429 or 5xx, up to `retry_max`
+/// retries. The last response is
+/// returned whatever its status.
+async fn send_with_retry(
+  retry: RetryPolicy,
+  mut build: impl FnMut() -> RequestBuilder
+) -> anyhow::Result<reqwest::Response> {
+  let mut attempts = 0usize;
+  loop {
+    attempts += 1;
+    let retries_left =
+      attempts <= retry.retry_max;
+    match build().send().await {
+      | Ok(resp)
+        if retries_left
+          && (resp.status()
+            == StatusCode::TOO_MANY_REQUESTS
+            || resp
+              .status()
+              .is_server_error()) =>
+      {
+        debug!(
+          attempts,
+          status = %resp.status(),
+          "request failed, retrying"
+        );
+      }
+      | Ok(resp) => return Ok(resp),
+      | Err(err)
+        if retries_left
+          && (err.is_connect()
+            || err.is_timeout()) =>
+      {
+        debug!(
+          attempts,
+          error = %err,
+          "request failed, retrying"
+        );
+      }
+      | Err(err) => return Err(err.into())
+    }
+    tokio::time::sleep(
+      retry.backoff(attempts)
+    )
+    .await;
+  }
+}
+
+/// A record that kept failing to embed,
+/// as stored in the retry file.
+#[derive(
+  Debug, Serialize, Deserialize,
+)]
+struct FailedEmbedding {
+  record:   ChunkRecord,
+  error:    String,
+  attempts: usize
+}
+
+/// A record set aside by
+/// `insert.dead_letter`, with the stage
+/// it failed at. Lines that never
+/// parsed keep their raw text instead
+/// of a record.
+#[derive(Serialize)]
+struct DeadLetter<'a> {
+  path:   &'a str,
+  stage:  &'static str,
+  error:  String,
+  #[serde(
+    skip_serializing_if = "Option::is_none"
+  )]
+  line:   Option<&'a str>,
+  #[serde(
+    skip_serializing_if = "Option::is_none"
+  )]
+  record: Option<&'a ChunkRecord>
+}
+
+/// Writes each of `records` to the
+/// dead-letter file and returns how
+/// many there were.
+fn dead_letter_records(
+  log: &JsonlAppender,
+  path: &str,
+  stage: &'static str,
+  err: &anyhow::Error,
+  records: &[ChunkRecord]
+) -> anyhow::Result<usize> {
+  warn!(path, stage, records = records.len(), error = %format!("{err:#}"), "dead-lettered batch");
+  for record in records {
+    log.append(&DeadLetter {
+      path,
+      stage,
+      error: format!("{err:#}"),
+      line: None,
+      record: Some(record)
+    })?;
+  }
+  Ok(records.len())
+}
+
+/// A line of an insert input: a chunk,
+/// or a failed-embedding entry being
+/// retried.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum InputLine {
+  Failed(FailedEmbedding),
+  Chunk(ChunkRecord)
+}
+
+/// A chunk file every record of which
+/// reached the stores, as one line of
+/// the insert state.
+#[derive(
+  Debug, Serialize, Deserialize,
+)]
+struct CompletedFile {
+  path:         String,
+  hash:         String,
+  targets_hash: String
+}
+
+/// Files fully inserted by earlier
+/// runs, for `insert.skip_completed`.
+/// New completions are appended as
+/// each file finishes, so a run that
+/// dies halfway keeps its progress.
+struct InsertState {
+  log:          JsonlAppender,
+  targets_hash: String,
+  done:         HashMap<String, String>
+}
+
+impl InsertState {
+  /// Reads the state, keeping entries
+  /// for the current stores and model;
+  /// later lines win.
+  fn load(config: &Config) -> Self {
+    let path = config
+      .paths
+      .state_dir
+      .join(INSERT_STATE);
+    let targets_hash =
+      insert_targets_hash(config);
+    let mut done = HashMap::new();
+    if let Ok(raw) =
+      fs::read_to_string(&path)
+    {
+      for line in raw.lines() {
+        match serde_json::from_str::<
+          CompletedFile
+        >(line)
+        {
+          | Ok(entry)
+            if entry.targets_hash
+              == targets_hash =>
+          {
+            done.insert(
+              entry.path, entry.hash
+            );
+          }
+          | Ok(_) => {}
+          | Err(err) => {
+            warn!(path = %path.display(), error = %err, "ignoring unreadable insert state line");
+          }
+        }
+      }
+    }
+    Self {
+      log: JsonlAppender::new(path),
+      targets_hash,
+      done
+    }
+  }
+
+  fn is_done(
+    &self,
+    key: &str,
+    hash: &str
+  ) -> bool {
+    self
+      .done
+      .get(key)
+      .is_some_and(|h| h == hash)
+  }
+
+  fn mark(
+    &self,
+    key: String,
+    hash: String
+  ) -> anyhow::Result<()> {
+    self.log.append(&CompletedFile {
+      path: key,
+      hash,
+      targets_hash: self
+        .targets_hash
+        .clone()
+    })
+  }
+}
+
+/// Hash of where chunks go and how they
+/// are embedded; a change makes every
+/// file pending again.
+fn insert_targets_hash(
   config: &Config
-) -> anyhow::Result<()> {
-  let client = Client::builder()
-    .timeout(Duration::from_secs(
+) -> String {
+  let insert = &config.insert;
+  let vectors = match (
+    insert.vector_store,
+    &insert.pgvector
+  ) {
+    | _ if !writes(
+      config,
+      InsertTarget::Vector
+    ) =>
+    {
+      String::new()
+    }
+    | (
+      VectorStore::Pgvector,
+      Some(pg)
+    ) => {
+      format!(
+        "pgvector:{}\0{}",
+        pg.url, pg.table
+      )
+    }
+    | _ => {
+      format!(
+        "{}\0{}",
+        insert.qdrant.url,
+        insert.qdrant.collection
+      )
+    }
+  };
+  let lexical = match (
+    insert.lexical_store,
+    &insert.elasticsearch
+  ) {
+    | _ if !writes(
+      config,
+      InsertTarget::Lexical
+    ) =>
+    {
+      String::new()
+    }
+    | (
+      LexicalStore::Elasticsearch,
+      Some(es)
+    ) => {
+      format!(
+        "elasticsearch:{}\0{}",
+        es.url, es.index
+      )
+    }
+    | _ => {
+      format!(
+        "{}\0{}",
+        insert.quickwit.url,
+        insert.quickwit.index_id
+      )
+    }
+  };
+  let repr = format!(
+    "{vectors}\0{lexical}\0{}\0{}",
+    insert.embeddings.provider.name(),
+    insert.embeddings.model
+  );
+  blake3::hash(repr.as_bytes())
+    .to_hex()
+    .to_string()
+}
+
+/// Whether `insert.targets` includes
+/// `target`.
+fn writes(
+  config: &Config,
+  target: InsertTarget
+) -> bool {
+  config
+    .insert
+    .targets
+    .contains(&target)
+}
+
+/// The stores this run writes, for the
+/// start and summary logs.
+fn target_names(
+  config: &Config
+) -> String {
+  let mut names = Vec::new();
+  if writes(
+    config,
+    InsertTarget::Vector
+  ) {
+    names.push(
+      config.insert.vector_store.name()
+    );
+  }
+  if writes(
+    config,
+    InsertTarget::Lexical
+  ) {
+    names.push(
       config
         .insert
-        .embeddings
-        .request_timeout_seconds
-    ))
-    .build()?;
+        .lexical_store
+        .name()
+    );
+  }
+  names.join(",")
+}
 
-  if config
-    .insert
-    .qdrant
-    .create_collection
-  {
-    ensure_qdrant_collection(
-      &client,
-      &config.insert.qdrant
-    )
-    .await?;
+/// Moves pending failures aside so this
+/// run can record fresh ones, merging
+/// with a retry file left by an
+/// interrupted retry.
+fn take_failed_embeddings(
+  state_dir: &Path
+) -> anyhow::Result<Option<PathBuf>> {
+  let failed =
+    state_dir.join(FAILED_EMBEDDINGS);
+  let retry = state_dir
+    .join(FAILED_EMBEDDINGS_RETRY);
+  if failed.exists() {
+    if retry.exists() {
+      let pending = fs::read(&failed)?;
+      fs::OpenOptions::new()
+        .append(true)
+        .open(&retry)?
+        .write_all(&pending)?;
+      fs::remove_file(&failed)?;
+    } else {
+      fs::rename(&failed, &retry)?;
+    }
   }
+  Ok(retry.exists().then_some(retry))
+}
 
-  let mut files = Vec::new();
-  for entry in WalkDir::new(
-    &config.paths.chunk_root
-  )
-  .into_iter()
-  .filter_map(|e| e.ok())
-  .filter(|e| e.file_type().is_file())
-  {
-    let path = entry.path();
-    if path
-      .extension()
-      .and_then(|s| s.to_str())
-      != Some("jsonl")
+#[derive(
+  Debug, Clone, Serialize, Deserialize,
+)]
+pub(crate) struct ChunkRecord {
+  pub(crate) id:               String,
+  pub(crate) text:             String,
+  pub(crate) metadata:         Value,
+  /// Vector computed outside `insert`,
+  /// used as-is with
+  /// `use_precomputed_embeddings`.
+  #[serde(
+    default,
+    skip_serializing_if = "Option::is_none"
+  )]
+  pub(crate) embedding:
+    Option<Vec<f32>>,
+  /// Sparse vector computed outside
+  /// `insert` (e.g. SPLADE), sent
+  /// instead of term frequencies with
+  /// `qdrant.sparse_vector`.
+  #[serde(
+    default,
+    skip_serializing_if = "Option::is_none"
+  )]
+  pub(crate) sparse_embedding:
+    Option<SparseVector>
+}
+
+/// Qdrant's sparse vector shape:
+/// parallel term indices and weights.
+#[derive(
+  Debug, Clone, Serialize, Deserialize,
+)]
+pub(crate) struct SparseVector {
+  pub(crate) indices: Vec<u32>,
+  pub(crate) values:  Vec<f32>
+}
+
+impl SparseVector {
+  /// Term frequencies of `text`'s
+  /// lowercased alphanumeric words,
+  /// each term hashed to an index;
+  /// Qdrant's `idf` modifier turns
+  /// them into BM25-style scores.
+  fn term_frequencies(
+    text: &str
+  ) -> Self {
+    let mut counts: BTreeMap<u32, f32> =
+      BTreeMap::new();
+    for word in text
+      .split(|c: char| {
+        !c.is_alphanumeric()
+      })
+      .filter(|w| !w.is_empty())
     {
-      continue;
+      let term = word.to_lowercase();
+      let idx =
+        xxhash_rust::xxh3::xxh3_64(
+          term.as_bytes()
+        ) as u32;
+      *counts
+        .entry(idx)
+        .or_default() += 1.0;
+    }
+    Self {
+      indices: counts
+        .keys()
+        .copied()
+        .collect(),
+      values:  counts
+        .into_values()
+        .collect()
+    }
+  }
+}
+
+pub async fn run(
+  config: &Config,
+  args: &InsertArgs
+) -> anyhow::Result<()> {
+  if config.insert.targets.is_empty() {
+    return Err(anyhow!(
+      "insert.targets is empty; name \
+       \"vector\", \"lexical\", or \
+       both"
+    ));
+  }
+  let client = build_client(config)?;
+
+  let mut files = Vec::new();
+  let retry_file = if args.retry_failed
+  {
+    let retry = take_failed_embeddings(
+      &config.paths.state_dir
+    )?;
+    match &retry {
+      | Some(path) => {
+        files.push(path.clone())
+      }
+      | None => {
+        info!(
+          "no failed embeddings to \
+           retry"
+        );
+        return Ok(());
+      }
+    }
+    retry
+  } else {
+    None
+  };
+  TIMERS.reset();
+  let walked = if args.retry_failed {
+    WalkedFiles::default()
+  } else {
+    TIMERS.time("walk", || {
+      walk_files(
+        &config.paths.chunk_root,
+        config.paths.log_walk_errors
+      )
+    })
+  };
+  files.extend(
+    walked.files.into_iter().filter(
+      |path| {
+        path
+          .extension()
+          .and_then(|s| s.to_str())
+          == Some("jsonl")
+      }
+    )
+  );
+
+  // A sample depends on every file, and
+  // a retry file is not a chunk file.
+  let state = (config
+    .insert
+    .skip_completed
+    && !args.retry_failed
+    && args.max_vectors.is_none())
+  .then(|| {
+    Arc::new(InsertState::load(config))
+  });
+  let mut hashes = Vec::new();
+  let mut skipped_completed = 0usize;
+  if let Some(state) = &state {
+    let mut pending = Vec::new();
+    for path in files {
+      let key = path
+        .strip_prefix(
+          &config.paths.chunk_root
+        )
+        .unwrap_or(&path)
+        .display()
+        .to_string();
+      let mut hasher =
+        blake3::Hasher::new();
+      fs::File::open(&path)
+        .and_then(|mut file| {
+          std::io::copy(
+            &mut file,
+            &mut hasher
+          )
+        })
+        .with_context(|| {
+          format!(
+            "read {}",
+            path.display()
+          )
+        })?;
+      let hash = hasher
+        .finalize()
+        .to_hex()
+        .to_string();
+      if !args.force
+        && state.is_done(&key, &hash)
+      {
+        skipped_completed += 1;
+        continue;
+      }
+      pending.push(path);
+      hashes.push(Some((key, hash)));
     }
-    files.push(path.to_path_buf());
+    files = pending;
   }
 
   let total_files = files.len();
   if total_files == 0 {
+    if skipped_completed > 0 {
+      info!(
+        skipped_completed,
+        "every chunk file already \
+         inserted"
+      );
+      return Ok(());
+    }
     warn!(
+      walk_errors = walked.errors,
       "no chunk files found for insert"
     );
     return Ok(());
   }
+  hashes.resize(total_files, None);
   info!(
     total_files,
+    skipped_completed,
+    targets = %target_names(config),
     max_parallel_files =
       config.insert.max_parallel_files,
+    walk_errors = walked.errors,
     "insert starting"
   );
 
+  let samples = match args.max_vectors {
+    | Some(max) => {
+      // Sorted so the same corpus and
+      // budget pick the same chunks.
+      files.sort();
+      sample_files(&files, max)?
+    }
+    | None => vec![None; total_files]
+  };
+
+  let vectors = writes(
+    config,
+    InsertTarget::Vector
+  );
+  let embed_dim = if vectors
+    && config.insert.embeddings.warmup
+  {
+    warmup_embedder(config).await?
+  } else {
+    None
+  };
+  let resized;
+  let config = if vectors
+    && config.insert.vector_store
+      == VectorStore::Qdrant
+  {
+    let dim =
+      prepare_qdrant_collection(
+        &client,
+        &config.insert.qdrant,
+        embed_dim
+      )
+      .await?;
+    if dim
+      == config
+        .insert
+        .qdrant
+        .vector_size
+    {
+      config
+    } else {
+      // Routed collections are created
+      // with the detected size too.
+      let mut c = config.clone();
+      c.insert.qdrant.vector_size = dim;
+      resized = c;
+      &resized
+    }
+  } else {
+    config
+  };
+
   let file_semaphore =
     Arc::new(Semaphore::new(
       config
@@ -121,96 +967,130 @@ pub async fn run(
         .max_parallel_files
         .max(1)
     ));
-  let global_embed_limit = if config
-    .insert
-    .embeddings
-    .global_max_concurrency
-    > 0
-  {
-    config
-      .insert
-      .embeddings
-      .global_max_concurrency
-  } else {
-    config
-      .insert
-      .embeddings
-      .max_concurrency
-  };
-  let embed_semaphore =
-    Arc::new(Semaphore::new(
-      global_embed_limit.max(1)
+  let inserter =
+    Inserter::start(config, &client)?;
+  let mut tasks = Vec::new();
+  let gate =
+    Arc::new(FailureGate::new(
+      config
+        .insert
+        .abort_after_failures,
+      config.insert.abort_counting
     ));
-  let cache = if config
-    .insert
-    .embeddings
-    .cache_max_entries
-    > 0
+  for ((path, sample), done_key) in
+    files
+      .into_iter()
+      .zip(samples)
+      .zip(hashes)
   {
-    Some(Arc::new(Mutex::new(
-      EmbeddingCache::new(
-        config
-          .insert
-          .embeddings
-          .cache_max_entries
-      )
-    )))
-  } else {
-    None
-  };
-  let mut tasks = Vec::new();
-  for path in files {
     let permit = file_semaphore
       .clone()
       .acquire_owned()
       .await?;
-    let deps = InsertDeps {
-      client:          client.clone(),
-      embed_cfg:       config
-        .insert
-        .embeddings
-        .clone(),
-      qdrant_cfg:      config
-        .insert
-        .qdrant
-        .clone(),
-      quickwit_cfg:    config
-        .insert
-        .quickwit
-        .clone(),
-      embed_semaphore: embed_semaphore
-        .clone(),
-      cache:           cache.clone()
-    };
+    if gate.tripped() {
+      break;
+    }
+    let deps = inserter.deps.clone();
+    let gate = gate.clone();
+    let state = state.clone();
     let batch_size =
       config.insert.batch_size;
-    tasks.push(tokio::spawn(async move {
-            let _permit = permit;
-            let prefix = color_prefix(&path.display().to_string(), None, None);
-            info!(color_prefix = %prefix, path = %path.display(), "insert file start");
-            let count = ingest_file(
-                &path,
-                batch_size,
-                &deps
-            )
-            .await?;
-            Ok::<(usize, String), anyhow::Error>((count, path.display().to_string()))
-        }));
-  }
-
-  let mut total_chunks = 0usize;
-  for task in tasks {
-    let (count, path) = task.await??;
-    let prefix =
-      color_prefix(&path, None, None);
-    info!(color_prefix = %prefix, path, count, "insert file complete");
-    total_chunks += count;
-  }
-
-  if config
-    .insert
-    .quickwit
-    .commit_at_end
+    let span = info_span!(
+      "insert_file",
+      path = %path.display()
+    );
+    tasks.push(tokio::spawn(
+      async move {
+        let _permit = permit;
+        info!("insert file start");
+        let stats = ingest_file(
+          &path, batch_size, sample,
+          &deps
+        )
+        .await
+        .inspect_err(|_| {
+          gate.failure()
+        })?;
+        // A file whose every record
+        // failed to embed or was
+        // dead-lettered counts as
+        // failed.
+        if stats.inserted > 0 {
+          gate.success();
+        } else if stats
+          .failed_embeddings
+          > 0
+          || stats.dead_lettered > 0
+        {
+          gate.failure();
+        }
+        if let (
+          Some(state),
+          Some((key, hash))
+        ) = (&state, done_key)
+          && stats.failed_embeddings
+            == 0
+          && stats.dead_lettered == 0
+        {
+          state.mark(key, hash)?;
+        }
+        Ok::<
+          (FileStats, String),
+          anyhow::Error
+        >((
+          stats,
+          path.display().to_string()
+        ))
+      }
+      .instrument(span)
+    ));
+  }
+
+  let mut total_chunks = 0usize;
+  let mut total_skipped_low_alpha =
+    0usize;
+  let mut total_failed_embeddings =
+    0usize;
+  let mut total_skipped_sampled =
+    0usize;
+  let mut total_dead_lettered = 0usize;
+  for task in tasks {
+    let (stats, path) = task.await??;
+    let prefix =
+      color_prefix(&path, None, None);
+    info!(
+      color_prefix = %prefix,
+      path,
+      count = stats.inserted,
+      skipped_low_alpha = stats.skipped_low_alpha,
+      failed_embeddings = stats.failed_embeddings,
+      skipped_sampled = stats.skipped_sampled,
+      dead_lettered = stats.dead_lettered,
+      "insert file complete"
+    );
+    total_chunks += stats.inserted;
+    total_skipped_low_alpha +=
+      stats.skipped_low_alpha;
+    total_failed_embeddings +=
+      stats.failed_embeddings;
+    total_skipped_sampled +=
+      stats.skipped_sampled;
+    total_dead_lettered +=
+      stats.dead_lettered;
+  }
+  let global_embed_limit =
+    inserter.global_embed_limit;
+  inserter.finish().await?;
+
+  if writes(
+    config,
+    InsertTarget::Lexical
+  ) && config.insert.lexical_store
+    == LexicalStore::Quickwit
+    && config
+      .insert
+      .quickwit
+      .commit_at_end
   {
     quickwit_commit(
       &client,
@@ -220,88 +1100,853 @@ pub async fn run(
   }
   info!(
     total_files,
+    skipped_completed,
+    targets = %target_names(config),
     total_chunks,
+    total_skipped_low_alpha,
+    total_failed_embeddings,
+    total_skipped_sampled,
+    total_dead_lettered,
     global_embed_limit,
+    timings = %TIMERS,
     "insert complete"
   );
+  if total_dead_lettered > 0 {
+    warn!(
+      total_dead_lettered,
+      path = %config
+        .paths
+        .state_dir
+        .join(DEAD_LETTER)
+        .display(),
+      "records were dead-lettered"
+    );
+  }
+  gate.check("insert")?;
+  if let Some(retry) = retry_file {
+    // Anything that failed again is
+    // already in a fresh retry file.
+    fs::remove_file(&retry)?;
+  }
+  if let Some(out) = &args.catalog {
+    catalog::write_catalog(
+      &config.paths.chunk_root,
+      &config.chunk.field_names,
+      out
+    )?;
+  }
   Ok(())
 }
 
+/// Embeds a one-word probe with
+/// `warmup_timeout_seconds` so a cold
+/// Ollama loads the model before the
+/// first real batch, failing early if
+/// the embedder is down. Returns the
+/// vector dimension, or `None` when
+/// the provider is not ollama.
+pub(crate) async fn warmup_embedder(
+  config: &Config
+) -> anyhow::Result<Option<usize>> {
+  let cfg = &config.insert.embeddings;
+  if cfg.provider
+    != EmbeddingProvider::Ollama
+  {
+    warn!(
+      provider = cfg.provider.name(),
+      "embedding warmup only supports \
+       ollama, skipping"
+    );
+    return Ok(None);
+  }
+  let client = client_with_timeout(
+    config,
+    cfg.warmup_timeout_seconds
+  )?;
+  let started =
+    std::time::Instant::now();
+  let vector =
+    embed_text(&client, cfg, "warmup")
+      .await
+      .context(
+        "embedding warmup failed"
+      )?;
+  info!(
+    model = %cfg.model,
+    dim = vector.len(),
+    elapsed_ms =
+      started.elapsed().as_millis() as u64,
+    "embedding warmup complete"
+  );
+  Ok(Some(vector.len()))
+}
+
+pub(crate) fn build_client(
+  config: &Config
+) -> anyhow::Result<Client> {
+  client_with_timeout(
+    config,
+    config
+      .insert
+      .embeddings
+      .request_timeout_seconds
+  )
+}
+
+fn client_with_timeout(
+  config: &Config,
+  timeout_seconds: u64
+) -> anyhow::Result<Client> {
+  let mut builder = Client::builder()
+    .timeout(Duration::from_secs(
+      timeout_seconds
+    ));
+  let http = &config.insert.http;
+  if let Some(identity) =
+    client_identity(http)?
+  {
+    builder =
+      builder.identity(identity);
+  }
+  let client = builder
+    .build()
+    .with_context(|| {
+      match (
+        &http.client_cert,
+        &http.client_key
+      ) {
+        | (Some(cert), Some(key)) => {
+          format!(
+            "build http client with \
+             identity {} / {} (does \
+             the key match the \
+             certificate?)",
+            cert.display(),
+            key.display()
+          )
+        }
+        | _ => {
+          "build http client"
+            .to_string()
+        }
+      }
+    })?;
+  Ok(client)
+}
+
+/// Loads the mTLS client identity from
+/// `insert.http.client_cert` and
+/// `client_key`; both or neither must
+/// be set.
+fn client_identity(
+  cfg: &InsertHttpConfig
+) -> anyhow::Result<Option<Identity>> {
+  let (cert, key) = match (
+    &cfg.client_cert,
+    &cfg.client_key
+  ) {
+    | (None, None) => return Ok(None),
+    | (Some(cert), Some(key)) => {
+      (cert, key)
+    }
+    | _ => {
+      return Err(anyhow!(
+        "insert.http.client_cert and \
+         client_key must be set \
+         together"
+      ));
+    }
+  };
+  let read = |path: &PathBuf| {
+    fs::read(path).with_context(|| {
+      format!(
+        "read client identity {}",
+        path.display()
+      )
+    })
+  };
+  let mut pem = read(key)?;
+  pem.push(b'\n');
+  pem.extend(read(cert)?);
+  let identity =
+    Identity::from_pem(&pem)
+      .with_context(|| {
+        format!(
+          "parse client identity from \
+           {} and {}",
+          cert.display(),
+          key.display()
+        )
+      })?;
+  Ok(Some(identity))
+}
+
+/// Shared embedding + store-writer
+/// machinery used by `insert` and
+/// `bench`.
+pub(crate) struct Inserter {
+  deps: InsertDeps,
+  writer_handles: Vec<JoinHandle<()>>,
+  pub(crate) global_embed_limit: usize,
+  /// Where the embedding cache is
+  /// saved once the writers finish.
+  cache_path: Option<PathBuf>
+}
+
+impl Inserter {
+  pub(crate) fn start(
+    config: &Config,
+    client: &Client
+  ) -> anyhow::Result<Self> {
+    let global_embed_limit = if config
+      .insert
+      .embeddings
+      .global_max_concurrency
+      > 0
+    {
+      config
+        .insert
+        .embeddings
+        .global_max_concurrency
+    } else {
+      config
+        .insert
+        .embeddings
+        .max_concurrency
+    };
+    let embed_semaphore =
+      Arc::new(Semaphore::new(
+        global_embed_limit.max(1)
+      ));
+    let cache = if config
+      .insert
+      .embeddings
+      .cache_max_entries
+      > 0
+    {
+      let cfg =
+        &config.insert.embeddings;
+      Some(Arc::new(Mutex::new(
+        match &cfg.cache_path {
+          | Some(path) => {
+            EmbeddingCache::load(
+              path,
+              cfg.cache_max_entries,
+              &cache_key_space(cfg)
+            )
+          }
+          | None => {
+            EmbeddingCache::new(
+              cfg.cache_max_entries,
+              &cache_key_space(cfg)
+            )
+          }
+        }
+      )))
+    } else {
+      None
+    };
+    let queue_depth = config
+      .insert
+      .write_queue_depth
+      .max(1);
+    let retry = RetryPolicy {
+      retry_max:  config
+        .insert
+        .retry_max,
+      backoff_ms: config
+        .insert
+        .retry_backoff_ms
+    };
+    let vectors = writes(
+      config,
+      InsertTarget::Vector
+    );
+    let vector = vectors
+      .then(|| vector_target(config))
+      .transpose()?;
+    let vector_size = vector
+      .as_ref()
+      .map_or(0, |(_, _, size)| *size);
+    let vector_slots =
+      vector.as_ref().map_or(
+        0,
+        |(_, concurrency, _)| {
+          (*concurrency).max(1)
+        }
+      );
+    let (qdrant_tx, qdrant_writer) =
+      vector
+        .map(
+          |(target, concurrency, _)| {
+            spawn_store_writer(
+              target,
+              client.clone(),
+              concurrency,
+              queue_depth,
+              retry
+            )
+          }
+        )
+        .unzip();
+    let lexical = writes(
+      config,
+      InsertTarget::Lexical
+    )
+    .then(|| {
+      lexical_target(config, vectors)
+    })
+    .transpose()?;
+    let lexical_slots = lexical
+      .as_ref()
+      .map_or(0, |(_, concurrency)| {
+        (*concurrency).max(1)
+      });
+    let (quickwit_tx, quickwit_writer) =
+      lexical
+        .map(|(target, concurrency)| {
+          spawn_store_writer(
+            target,
+            client.clone(),
+            concurrency,
+            queue_depth,
+            retry
+          )
+        })
+        .unzip();
+    let deps = InsertDeps {
+      client: client.clone(),
+      embed_cfg: config
+        .insert
+        .embeddings
+        .clone(),
+      writers: StoreWriters {
+        qdrant:   qdrant_tx,
+        quickwit: quickwit_tx
+      },
+      field_names: config
+        .chunk
+        .field_names
+        .clone(),
+      embed_semaphore,
+      cache,
+      min_alpha_ratio: config
+        .insert
+        .min_alpha_ratio,
+      retry,
+      failed: config
+        .insert
+        .record_failed_embeddings
+        .then(|| {
+          Arc::new(JsonlAppender::new(
+            config
+              .paths
+              .state_dir
+              .join(FAILED_EMBEDDINGS)
+          ))
+        }),
+      dead_letter: config
+        .insert
+        .dead_letter
+        .then(|| {
+          Arc::new(JsonlAppender::new(
+            config
+              .paths
+              .state_dir
+              .join(DEAD_LETTER)
+          ))
+        }),
+      batch_embed: Arc::new(
+        AtomicBool::new(true)
+      ),
+      precomputed_size: config
+        .insert
+        .use_precomputed_embeddings
+        .then_some(vector_size),
+      vector_store: config
+        .insert
+        .vector_store,
+      lexical_store: config
+        .insert
+        .lexical_store,
+      max_pending: queue_depth
+        + vector_slots
+          .max(lexical_slots)
+    };
+    Ok(Self {
+      deps,
+      writer_handles: qdrant_writer
+        .into_iter()
+        .chain(quickwit_writer)
+        .collect(),
+      global_embed_limit,
+      cache_path: config
+        .insert
+        .embeddings
+        .cache_path
+        .clone()
+    })
+  }
+
+  /// Embeds one batch and queues it for
+  /// both stores.
+  pub(crate) async fn submit(
+    &self,
+    label: &Path,
+    batch_idx: usize,
+    lines_seen: usize,
+    batch: Vec<ChunkRecord>
+  ) -> anyhow::Result<PendingWrite> {
+    let ctx = BatchContext::new(
+      label, batch_idx, lines_seen,
+      &batch
+    );
+    process_batch(
+      batch, ctx, &self.deps
+    )
+    .instrument(info_span!(
+      "insert_file",
+      path = %label.display()
+    ))
+    .await
+  }
+
+  /// Closes the writer queues and waits
+  /// for the writer tasks to drain.
+  pub(crate) async fn finish(
+    self
+  ) -> anyhow::Result<()> {
+    let cache = self.deps.cache.clone();
+    drop(self.deps);
+    for handle in self.writer_handles {
+      handle.await?;
+    }
+    if let (Some(path), Some(cache)) =
+      (&self.cache_path, cache)
+    {
+      cache
+        .lock()
+        .unwrap()
+        .save(path)?;
+    }
+    Ok(())
+  }
+}
+
+/// The vector store's writer target,
+/// concurrency, and vector size.
+fn vector_target(
+  config: &Config
+) -> anyhow::Result<(
+  StoreTarget,
+  usize,
+  usize
+)> {
+  let embed_model = config
+    .insert
+    .embeddings
+    .model
+    .clone();
+  Ok(match config.insert.vector_store {
+    | VectorStore::Qdrant => {
+      let cfg = &config.insert.qdrant;
+      (
+        StoreTarget::Qdrant {
+          cfg: cfg.clone(),
+          embed_model,
+          ensured: Arc::new(
+            tokio::sync::Mutex::new(
+              HashSet::from([cfg
+                .collection
+                .clone()])
+            )
+          ),
+          grpc: match cfg.transport {
+            | QdrantTransport::Rest => {
+              None
+            }
+            | QdrantTransport::Grpc => {
+              Some(Arc::new(
+                qdrant_grpc_client(
+                  config
+                )?
+              ))
+            }
+          }
+        },
+        cfg.max_concurrency,
+        cfg.vector_size
+      )
+    }
+    | VectorStore::Pgvector => {
+      let cfg = config
+        .insert
+        .pgvector
+        .as_ref()
+        .ok_or_else(|| {
+          anyhow!(
+            "insert.vector_store = \
+             \"pgvector\" needs an \
+             [insert.pgvector] section"
+          )
+        })?;
+      (
+        StoreTarget::Pgvector {
+          cfg: cfg.clone(),
+          embed_model,
+          conn: Arc::new(
+            tokio::sync::OnceCell::new(
+            )
+          )
+        },
+        cfg.max_concurrency,
+        cfg.vector_size
+      )
+    }
+  })
+}
+
+/// The lexical store's writer target
+/// and concurrency.
+fn lexical_target(
+  config: &Config,
+  vectors: bool
+) -> anyhow::Result<(StoreTarget, usize)>
+{
+  Ok(
+    match config.insert.lexical_store {
+      | LexicalStore::Quickwit => {
+        let cfg =
+          &config.insert.quickwit;
+        (
+          StoreTarget::Quickwit {
+            cfg:         cfg.clone(),
+            // Stamped from the vector,
+            // so only when there is
+            // one.
+            embed_model: (cfg
+              .stamp_embed_model
+              && vectors)
+              .then(|| {
+                config
+                  .insert
+                  .embeddings
+                  .model
+                  .clone()
+              })
+          },
+          cfg.max_concurrency
+        )
+      }
+      | LexicalStore::Elasticsearch => {
+        let cfg = config
+          .insert
+          .elasticsearch
+          .as_ref()
+          .ok_or_else(|| {
+            anyhow!(
+              "insert.lexical_store = \
+               \"elasticsearch\" \
+               needs an [insert.\
+               elasticsearch] section"
+            )
+          })?;
+        (
+          StoreTarget::Elasticsearch {
+            cfg: cfg.clone()
+          },
+          cfg.max_concurrency
+        )
+      }
+    }
+  )
+}
+
 async fn ingest_file(
   path: &Path,
   batch_size: usize,
+  sample: Option<Sample>,
   deps: &InsertDeps
-) -> anyhow::Result<usize> {
-  let raw = fs::read_to_string(path)
-    .with_context(|| {
-      format!("read {}", path.display())
-    })?;
+) -> anyhow::Result<FileStats> {
+  // Read line by line so memory is
+  // bounded by the batch, not a
+  // multi-GB chunk file.
+  let reader = BufReader::new(
+    fs::File::open(path).with_context(
+      || {
+        format!(
+          "open {}",
+          path.display()
+        )
+      }
+    )?
+  );
+  let mut stats = FileStats::default();
   let mut total = 0usize;
   let mut buffer = Vec::new();
+  let mut pending = VecDeque::new();
   let mut lines_seen = 0usize;
   let mut batch_idx = 0usize;
 
-  for line in raw.lines() {
+  for line in reader.lines() {
+    let line =
+      line.with_context(|| {
+        format!(
+          "read {}",
+          path.display()
+        )
+      })?;
     if line.trim().is_empty() {
       continue;
     }
     lines_seen += 1;
-    let record: ChunkRecord =
-      serde_json::from_str(line)?;
+    if let Some(sample) = sample
+      && !sample.keeps(lines_seen - 1)
+    {
+      stats.skipped_sampled += 1;
+      continue;
+    }
+    let parsed =
+      serde_json::from_str(&line)
+        .and_then(|value| {
+          serde_json::from_value(
+            chunk::canonical_record(
+              value,
+              &deps.field_names
+            )
+          )
+        });
+    let record = match parsed {
+      | Ok(InputLine::Failed(
+        failed
+      )) => failed.record,
+      | Ok(InputLine::Chunk(
+        record
+      )) => record,
+      | Err(err) => {
+        let Some(log) =
+          &deps.dead_letter
+        else {
+          return Err(err.into());
+        };
+        warn!(path = %path.display(), line = lines_seen, error = %err, "dead-lettered unreadable chunk line");
+        log.append(&DeadLetter {
+          path:   &path
+            .display()
+            .to_string(),
+          stage:  "parse",
+          error:  err.to_string(),
+          line:   Some(&line),
+          record: None
+        })?;
+        stats.dead_lettered += 1;
+        continue;
+      }
+    };
+    if deps.min_alpha_ratio > 0.0
+      && alpha_ratio(&record.text)
+        < deps.min_alpha_ratio
+    {
+      stats.skipped_low_alpha += 1;
+      continue;
+    }
     buffer.push(record);
     if buffer.len() >= batch_size {
       batch_idx += 1;
       debug!(
-          path = %path.display(),
-          batch_size = buffer.len(),
-          lines_seen,
-          "insert batch start"
+        batch_size = buffer.len(),
+        lines_seen,
+        "insert batch start"
+      );
+      let ctx = BatchContext::new(
+        path, batch_idx, lines_seen,
+        &buffer
+      );
+      pending.extend(
+        queue_batch(
+          std::mem::take(&mut buffer),
+          ctx,
+          deps,
+          &mut stats
+        )
+        .await?
       );
-      total += process_batch(
-        &buffer,
-        &BatchContext::new(
-          path, batch_idx, lines_seen,
-          &buffer
-        ),
-        deps
-      )
-      .await?;
       debug!(
-          path = %path.display(),
-          total,
-          lines_seen,
-          "insert batch complete"
+        pending = pending.len(),
+        lines_seen,
+        "insert batch queued"
       );
-      buffer.clear();
+      while pending.len()
+        > deps.max_pending
+        && let Some(write) =
+          pending.pop_front()
+      {
+        total += settle_write(
+          write, path, deps, &mut stats
+        )
+        .await?;
+      }
     }
   }
 
   if !buffer.is_empty() {
     batch_idx += 1;
     debug!(
-        path = %path.display(),
-        batch_size = buffer.len(),
-        lines_seen,
-        "insert final batch start"
+      batch_size = buffer.len(),
+      lines_seen,
+      "insert final batch start"
     );
-    total += process_batch(
-      &buffer,
-      &BatchContext::new(
-        path, batch_idx, lines_seen,
-        &buffer
-      ),
-      deps
+    let ctx = BatchContext::new(
+      path, batch_idx, lines_seen,
+      &buffer
+    );
+    pending.extend(
+      queue_batch(
+        std::mem::take(&mut buffer),
+        ctx,
+        deps,
+        &mut stats
+      )
+      .await?
+    );
+    debug!(
+      pending = pending.len(),
+      lines_seen,
+      "insert final batch queued"
+    );
+  }
+
+  for write in pending {
+    total += settle_write(
+      write, path, deps, &mut stats
     )
     .await?;
-    debug!(
-        path = %path.display(),
-        total,
-        lines_seen,
-        "insert final batch complete"
+  }
+  debug!(
+    total,
+    lines_seen,
+    "insert writes complete"
+  );
+
+  if stats.failed_embeddings > 0 {
+    warn!(
+      failed_embeddings =
+        stats.failed_embeddings,
+      "recorded records that failed \
+       to embed"
+    );
+  }
+  if stats.dead_lettered > 0 {
+    warn!(
+      path = %path.display(),
+      dead_lettered = stats.dead_lettered,
+      "wrote records to the dead-letter \
+       file"
+    );
+  }
+  if stats.skipped_low_alpha > 0 {
+    info!(
+      skipped_low_alpha =
+        stats.skipped_low_alpha,
+      min_alpha_ratio =
+        deps.min_alpha_ratio,
+      "skipped low alpha-ratio records"
     );
   }
+  stats.inserted = total;
+  Ok(stats)
+}
+
+/// Waits for a queued batch to reach
+/// both stores and returns how many
+/// records it wrote. With
+/// `insert.dead_letter`, a store
+/// failure dead-letters the batch.
+async fn settle_write(
+  write: PendingWrite,
+  path: &Path,
+  deps: &InsertDeps,
+  stats: &mut FileStats
+) -> anyhow::Result<usize> {
+  stats.failed_embeddings +=
+    write.failed;
+  let batch = write.batch.clone();
+  let timings = match (
+    write.wait().await,
+    &deps.dead_letter,
+    batch
+  ) {
+    | (Ok(timings), ..) => timings,
+    | (
+      Err(err),
+      Some(log),
+      Some(batch)
+    ) => {
+      stats.dead_lettered +=
+        dead_letter_records(
+          log,
+          &path.display().to_string(),
+          "store",
+          &err,
+          &batch.records
+        )?;
+      return Ok(0);
+    }
+    | (Err(err), ..) => return Err(err)
+  };
+  if deps.writers.qdrant.is_some() {
+    TIMERS.add("embed", timings.embed);
+    TIMERS.add(
+      deps.vector_store.name(),
+      timings.qdrant
+    );
+  }
+  if deps.writers.quickwit.is_some() {
+    TIMERS.add(
+      deps.lexical_store.name(),
+      timings.quickwit
+    );
+  }
+  Ok(timings.len)
+}
 
-  Ok(total)
+/// Embeds and queues `batch`. With
+/// `insert.dead_letter`, a failed batch
+/// goes to the dead-letter file and
+/// nothing is queued.
+async fn queue_batch(
+  batch: Vec<ChunkRecord>,
+  ctx: BatchContext,
+  deps: &InsertDeps,
+  stats: &mut FileStats
+) -> anyhow::Result<Option<PendingWrite>>
+{
+  let Some(log) = &deps.dead_letter
+  else {
+    return process_batch(
+      batch, ctx, deps
+    )
+    .await
+    .map(Some);
+  };
+  let path = ctx.path.clone();
+  match process_batch(
+    batch.clone(),
+    ctx,
+    deps
+  )
+  .await
+  {
+    | Ok(write) => Ok(Some(write)),
+    | Err(err) => {
+      stats.dead_lettered +=
+        dead_letter_records(
+          log, &path, "embed", &err,
+          &batch
+        )?;
+      Ok(None)
+    }
+  }
 }
 
 #[derive(Clone)]
@@ -342,42 +1987,165 @@ impl BatchContext {
   }
 }
 
-async fn process_batch(
-  batch: &[ChunkRecord],
-  ctx: &BatchContext,
-  deps: &InsertDeps
-) -> anyhow::Result<usize> {
-  let batch_len = batch.len();
-  let batch_start =
-    std::time::Instant::now();
-  let (
-    mut min_len,
-    mut max_len,
-    mut sum_len
-  ) = (usize::MAX, 0usize, 0usize);
-  for record in batch {
+/// What cache keys are scoped to: the
+/// model, and the document prefix when
+/// one changes what it is sent.
+fn cache_key_space(
+  cfg: &InsertEmbeddingsConfig
+) -> String {
+  if cfg.document_prefix.is_empty() {
+    cfg.model.clone()
+  } else {
+    format!(
+      "{}\0{}",
+      cfg.model, cfg.document_prefix
+    )
+  }
+}
+
+/// `text` with `document_prefix` in
+/// front, as the model sees it.
+fn prefixed<'a>(
+  cfg: &InsertEmbeddingsConfig,
+  text: &'a str
+) -> Cow<'a, str> {
+  if cfg.document_prefix.is_empty() {
+    Cow::Borrowed(text)
+  } else {
+    Cow::Owned(format!(
+      "{}{text}",
+      cfg.document_prefix
+    ))
+  }
+}
+
+/// `text` cut so that it and the
+/// document prefix fit
+/// `max_input_chars`, as keyed in the
+/// cache; the prefix is added on send.
+fn embed_input(
+  text: &str,
+  cfg: &InsertEmbeddingsConfig
+) -> String {
+  let budget =
+    cfg.max_input_chars.saturating_sub(
+      cfg
+        .document_prefix
+        .chars()
+        .count()
+    );
+  if cfg.max_input_chars > 0
+    && text.len() > budget
+  {
+    text.chars().take(budget).collect()
+  } else {
+    text.to_string()
+  }
+}
+
+/// Groups texts into requests of at
+/// most `request_batch_size` texts
+/// and, when set, `max_request_bytes`
+/// total, counting the document prefix
+/// each text is sent with. A text over
+/// the byte budget on its own still
+/// gets a request.
+fn request_groups(
+  misses: Vec<(usize, String)>,
+  cfg: &InsertEmbeddingsConfig
+) -> Vec<Vec<(usize, String)>> {
+  let max_count =
+    cfg.request_batch_size.max(1);
+  let max_bytes = cfg.max_request_bytes;
+  let prefix =
+    cfg.document_prefix.len();
+  let mut groups = Vec::new();
+  let mut current = Vec::new();
+  let mut current_bytes = 0usize;
+  for (idx, text) in misses {
+    let bytes = prefix + text.len();
+    let full = current.len()
+      >= max_count
+      || (max_bytes > 0
+        && current_bytes + bytes
+          > max_bytes);
+    if full && !current.is_empty() {
+      groups.push(std::mem::take(
+        &mut current
+      ));
+      current_bytes = 0;
+    }
+    current_bytes += bytes;
+    current.push((idx, text));
+  }
+  if !current.is_empty() {
+    groups.push(current);
+  }
+  groups
+}
+
+/// Embeds `batch` and queues it for
+/// the stores, inside a span carrying
+/// the batch index and id range.
+async fn process_batch(
+  batch: Vec<ChunkRecord>,
+  ctx: BatchContext,
+  deps: &InsertDeps
+) -> anyhow::Result<PendingWrite> {
+  let span = info_span!(
+    "batch",
+    batch_idx = ctx.batch_idx,
+    first_id = %ctx.first_id,
+    last_id = %ctx.last_id
+  );
+  // Without the vector side there is
+  // nothing to embed.
+  if deps.writers.qdrant.is_none() {
+    return queue_write(
+      batch,
+      Vec::new(),
+      Duration::ZERO,
+      0,
+      deps
+    )
+    .instrument(span)
+    .await;
+  }
+  embed_batch(batch, ctx, deps)
+    .instrument(span)
+    .await
+}
+
+async fn embed_batch(
+  mut batch: Vec<ChunkRecord>,
+  ctx: BatchContext,
+  deps: &InsertDeps
+) -> anyhow::Result<PendingWrite> {
+  let batch_len = batch.len();
+  let batch_start =
+    std::time::Instant::now();
+  let (
+    mut min_len,
+    mut max_len,
+    mut sum_len
+  ) = (usize::MAX, 0usize, 0usize);
+  for record in &batch {
     let len = record.text.len();
     min_len = min_len.min(len);
     max_len = max_len.max(len);
     sum_len += len;
   }
-  let avg_len = if batch_len == 0 {
-    0
-  } else {
-    sum_len / batch_len
-  };
+  let avg_len = sum_len
+    .checked_div(batch_len)
+    .unwrap_or(0);
   info!(
-      path = %ctx.path,
-      batch_idx = ctx.batch_idx,
-      batch_len,
-      lines_seen = ctx.lines_seen,
-      first_id = %ctx.first_id,
-      last_id = %ctx.last_id,
-      min_len,
-      max_len,
-      avg_len,
-      color_prefix = %color_prefix(&ctx.path, Some(&ctx.first_id), Some(LogOp::Ollama)),
-      "embedding batch start"
+    batch_len,
+    lines_seen = ctx.lines_seen,
+    min_len,
+    max_len,
+    avg_len,
+    log_op = LogOp::Ollama.name(),
+    "embedding batch start"
   );
   let mut vectors: Vec<
     Option<Vec<f32>>
@@ -385,161 +2153,703 @@ async fn process_batch(
   let mut misses = Vec::new();
   let cache = deps.cache.clone();
   for (idx, record) in
-    batch.iter().enumerate()
+    batch.iter_mut().enumerate()
   {
+    let embedding =
+      record.embedding.take();
+    if let Some(size) =
+      deps.precomputed_size
+      && let Some(vec) = embedding
+    {
+      if vec.len() != size {
+        return Err(anyhow!(
+          "chunk {} in {} has a \
+           {}-dimension embedding, \
+           expected vector_size {size}",
+          record.id,
+          ctx.path,
+          vec.len()
+        ));
+      }
+      vectors[idx] = Some(vec);
+      continue;
+    }
+    let text = embed_input(
+      &record.text,
+      &deps.embed_cfg
+    );
     if let Some(cache) = cache.as_ref()
-      && let Some(vec) = cache
-        .lock()
-        .unwrap()
-        .get(&record.text)
+      && let Some(vec) =
+        cache.lock().unwrap().get(&text)
     {
       vectors[idx] = Some(vec);
       continue;
     }
-    misses
-      .push((idx, record.text.clone()));
+    misses.push((idx, text));
   }
 
-  let request_batch_size = deps
-    .embed_cfg
-    .request_batch_size
-    .max(1);
+  let record_failures =
+    deps.failed.is_some();
   let mut tasks = Vec::new();
-  for chunk in
-    misses.chunks(request_batch_size)
-  {
+  for chunk in request_groups(
+    misses,
+    &deps.embed_cfg
+  ) {
     let client = deps.client.clone();
-    let model =
-      deps.embed_cfg.model.clone();
-    let base_url =
-      deps.embed_cfg.base_url.clone();
+    let embed_cfg =
+      deps.embed_cfg.clone();
     let embed_semaphore =
       deps.embed_semaphore.clone();
     let cache = cache.clone();
-    let chunk = chunk.to_vec();
-    let max_input_chars =
-      deps.embed_cfg.max_input_chars;
+    let retry = deps.retry;
+    let batch_embed =
+      deps.batch_embed.clone();
     tasks.push(tokio::spawn(
       async move {
         let mut results = Vec::new();
-        for (idx, mut text) in chunk {
-          if max_input_chars > 0
-            && text.len()
-              > max_input_chars
-          {
-            text = text
-              .chars()
-              .take(max_input_chars)
-              .collect();
-          }
+        let mut chunk = chunk;
+        // One request for the group;
+        // on any error its texts go
+        // one by one below, with
+        // retries.
+        if chunk.len() > 1
+          && batch_embed
+            .load(Ordering::Relaxed)
+        {
           let permit = embed_semaphore
             .clone()
             .acquire_owned()
             .await?;
-          let vec = embed_text(
-            &client, &base_url, &model,
-            &text
+          let texts = chunk
+            .iter()
+            .map(|(_, t)| t.as_str())
+            .collect::<Vec<_>>();
+          let result = embed_texts(
+            &client, &embed_cfg, &texts
           )
-          .await?;
+          .await;
           drop(permit);
-          if let Some(cache) =
-            cache.as_ref()
-          {
-            cache
-              .lock()
-              .unwrap()
-              .insert(
-                text.clone(),
-                vec.clone()
+          match result {
+            | Ok(Some(vecs)) => {
+              for ((idx, text), vec) in
+                chunk.drain(..).zip(vecs)
+              {
+                cache_vector(
+                  cache.as_ref(),
+                  text,
+                  &vec
+                );
+                results
+                  .push((idx, Ok(vec)));
+              }
+            }
+            | Ok(None) => {
+              if batch_embed.swap(
+                false,
+                Ordering::Relaxed
+              ) {
+                warn!(
+                  provider = embed_cfg
+                    .provider
+                    .name(),
+                  "embedder does not \
+                   take batched input, \
+                   embedding one text \
+                   per request"
+                );
+              }
+            }
+            | Err(err) => {
+              debug!(
+                error = %err,
+                "batch embedding failed, \
+                 embedding texts one by one"
               );
+            }
           }
-          results.push((idx, vec));
         }
-        Ok::<
-          Vec<(usize, Vec<f32>)>,
-          anyhow::Error
-        >(results)
+        for (idx, text) in chunk {
+          let mut attempts = 0usize;
+          let result = loop {
+            attempts += 1;
+            let permit = embed_semaphore
+              .clone()
+              .acquire_owned()
+              .await?;
+            let result = embed_text(
+              &client, &embed_cfg, &text
+            )
+            .await;
+            drop(permit);
+            match result {
+              | Err(err)
+                if attempts
+                  <= retry.retry_max =>
+              {
+                debug!(
+                  attempts,
+                  error = %err,
+                  "embedding failed, retrying"
+                );
+                tokio::time::sleep(
+                  retry.backoff(attempts)
+                )
+                .await;
+              }
+              | result => break result
+            }
+          };
+          match result {
+            | Ok(vec) => {
+              cache_vector(
+                cache.as_ref(),
+                text,
+                &vec
+              );
+              results
+                .push((idx, Ok(vec)));
+            }
+            | Err(err)
+              if record_failures =>
+            {
+              results.push((
+                idx,
+                Err((err, attempts))
+              ));
+            }
+            | Err(err) => {
+              return Err(err);
+            }
+          }
+        }
+        Ok::<_, anyhow::Error>(results)
       }
+      .in_current_span()
     ));
   }
 
+  let mut failed = 0usize;
   for task in tasks {
-    for (idx, vec) in task.await?? {
-      vectors[idx] = Some(vec);
+    for (idx, result) in task.await?? {
+      match result {
+        | Ok(vec) => {
+          vectors[idx] = Some(vec)
+        }
+        | Err((err, attempts)) => {
+          let record = &batch[idx];
+          warn!(
+            id = %record.id,
+            attempts,
+            error = %err,
+            "embedding failed, recording for retry"
+          );
+          if let Some(log) =
+            &deps.failed
+          {
+            log.append(
+              &FailedEmbedding {
+                record: record.clone(),
+                error: format!(
+                  "{err:#}"
+                ),
+                attempts
+              }
+            )?;
+          }
+          failed += 1;
+        }
+      }
     }
   }
 
-  let vectors = vectors
+  let (records, vectors): (
+    Vec<ChunkRecord>,
+    Vec<Vec<f32>>
+  ) = batch
     .into_iter()
-    .map(|v| {
-      v.ok_or_else(|| {
-        anyhow!(
-          "missing embedding result"
-        )
-      })
+    .zip(vectors)
+    .filter_map(|(record, vector)| {
+      vector.map(|v| (record, v))
     })
-    .collect::<Result<Vec<_>, _>>()?;
+    .unzip();
+  let batch_len = records.len();
 
   let vector_dim = vectors
     .first()
     .map(|v| v.len())
     .unwrap_or(0);
   info!(
-      path = %ctx.path,
-      batch_idx = ctx.batch_idx,
       batch_len,
       vector_dim,
       elapsed = ?batch_start.elapsed(),
-      color_prefix = %color_prefix(&ctx.path, Some(&ctx.first_id), Some(LogOp::Ollama)),
+      log_op = LogOp::Ollama.name(),
       "embedding batch complete"
   );
-  let qdrant = upsert_qdrant(
-    &deps.client,
-    &deps.qdrant_cfg,
-    batch,
-    &vectors
-  );
-  let quickwit = ingest_quickwit(
-    &deps.client,
-    &deps.quickwit_cfg,
-    batch
-  );
-  let (qdrant_res, quickwit_res) =
-    tokio::join!(qdrant, quickwit);
-  qdrant_res?;
-  info!(
-      path = %ctx.path,
-      batch_idx = ctx.batch_idx,
-      batch_len,
-      color_prefix = %color_prefix(&ctx.path, Some(&ctx.first_id), Some(LogOp::Qdrant)),
-      "qdrant upsert complete"
-  );
-  quickwit_res?;
-  info!(
-      path = %ctx.path,
-      batch_idx = ctx.batch_idx,
-      batch_len,
-      color_prefix = %color_prefix(&ctx.path, Some(&ctx.first_id), Some(LogOp::Quickwit)),
-      "quickwit ingest complete"
+  queue_write(
+    records,
+    vectors,
+    batch_start.elapsed(),
+    failed,
+    deps
+  )
+  .await
+}
+
+/// Hands an embedded batch to both
+/// store writers.
+async fn queue_write(
+  records: Vec<ChunkRecord>,
+  vectors: Vec<Vec<f32>>,
+  embed: Duration,
+  failed: usize,
+  deps: &InsertDeps
+) -> anyhow::Result<PendingWrite> {
+  let batch_len = records.len();
+  let embedded_at =
+    std::time::Instant::now();
+  if records.is_empty() {
+    return Ok(PendingWrite::empty(
+      embed,
+      failed,
+      embedded_at
+    ));
+  }
+  let write = Arc::new(WriteBatch {
+    records,
+    vectors,
+    span: Span::current()
+  });
+  let qdrant = enqueue(
+    deps.writers.qdrant.as_ref(),
+    &write
+  )
+  .await?;
+  let quickwit = enqueue(
+    deps.writers.quickwit.as_ref(),
+    &write
+  )
+  .await?;
+  Ok(PendingWrite {
+    len: batch_len,
+    failed,
+    embed,
+    embedded_at,
+    qdrant,
+    quickwit,
+    batch: deps
+      .dead_letter
+      .is_some()
+      .then_some(write)
+  })
+}
+
+/// An embedded batch shared by the
+/// Qdrant and Quickwit writer tasks.
+struct WriteBatch {
+  records: Vec<ChunkRecord>,
+  vectors: Vec<Vec<f32>>,
+  /// The batch's span, entered by the
+  /// writer tasks.
+  span:    Span
+}
+
+type WriteAck = oneshot::Sender<
+  anyhow::Result<Duration>
+>;
+
+struct WriteJob {
+  batch: Arc<WriteBatch>,
+  ack:   WriteAck
+}
+
+/// Bounded queues feeding the per-store
+/// writer tasks; a full queue blocks
+/// the embedding side until the store
+/// catches up. A side left out of
+/// `insert.targets` has no writer.
+#[derive(Clone)]
+struct StoreWriters {
+  qdrant:
+    Option<mpsc::Sender<WriteJob>>,
+  quickwit:
+    Option<mpsc::Sender<WriteJob>>
+}
+
+/// Queues `batch` for a writer; with
+/// no writer it is acked at once.
+async fn enqueue(
+  queue: Option<
+    &mpsc::Sender<WriteJob>
+  >,
+  batch: &Arc<WriteBatch>
+) -> anyhow::Result<
+  oneshot::Receiver<
+    anyhow::Result<Duration>
+  >
+> {
+  let (ack, rx) = oneshot::channel();
+  let Some(queue) = queue else {
+    let _ =
+      ack.send(Ok(Duration::ZERO));
+    return Ok(rx);
+  };
+  queue
+    .send(WriteJob {
+      batch: batch.clone(),
+      ack
+    })
+    .await
+    .map_err(|_| {
+      anyhow!("store writer closed")
+    })?;
+  Ok(rx)
+}
+
+/// Per-batch stage latencies; store
+/// latency excludes time spent queued.
+pub(crate) struct BatchTimings {
+  pub(crate) len:         usize,
+  pub(crate) embed:       Duration,
+  /// When the batch's embeddings were
+  /// done, before it was queued.
+  pub(crate) embedded_at:
+    std::time::Instant,
+  pub(crate) qdrant:      Duration,
+  pub(crate) quickwit:    Duration
+}
+
+pub(crate) struct PendingWrite {
+  len:         usize,
+  /// Records dropped after recording
+  /// them as failed embeddings.
+  failed:      usize,
+  embed:       Duration,
+  embedded_at: std::time::Instant,
+  qdrant: oneshot::Receiver<
+    anyhow::Result<Duration>
+  >,
+  quickwit: oneshot::Receiver<
+    anyhow::Result<Duration>
+  >,
+  /// What was queued, kept for the
+  /// dead-letter file.
+  batch:       Option<Arc<WriteBatch>>
+}
+
+impl PendingWrite {
+  /// A batch with nothing left to
+  /// write.
+  fn empty(
+    embed: Duration,
+    failed: usize,
+    embedded_at: std::time::Instant
+  ) -> Self {
+    let done = || {
+      let (tx, rx) = oneshot::channel();
+      let _ =
+        tx.send(Ok(Duration::ZERO));
+      rx
+    };
+    Self {
+      len: 0,
+      failed,
+      embed,
+      embedded_at,
+      qdrant: done(),
+      quickwit: done(),
+      batch: None
+    }
+  }
+
+  pub(crate) async fn wait(
+    self
+  ) -> anyhow::Result<BatchTimings> {
+    let (qdrant, quickwit) = tokio::join!(
+      self.qdrant,
+      self.quickwit
+    );
+    let qdrant =
+      qdrant.map_err(|_| {
+        anyhow!(
+          "qdrant writer dropped batch"
+        )
+      })??;
+    let quickwit =
+      quickwit.map_err(|_| {
+        anyhow!(
+          "quickwit writer dropped \
+           batch"
+        )
+      })??;
+    Ok(BatchTimings {
+      len: self.len,
+      embed: self.embed,
+      embedded_at: self.embedded_at,
+      qdrant,
+      quickwit
+    })
+  }
+}
+
+#[derive(Clone)]
+enum StoreTarget {
+  Qdrant {
+    cfg:         InsertQdrantConfig,
+    embed_model: String,
+    /// Collections already created
+    /// this run, for `route_by`.
+    ensured: Arc<
+      tokio::sync::Mutex<
+        HashSet<String>
+      >
+    >,
+    /// Set with `transport = "grpc"`.
+    grpc:        Option<Arc<Qdrant>>
+  },
+  Pgvector {
+    cfg:         InsertPgvectorConfig,
+    embed_model: String,
+    /// Opened by the first write.
+    conn: Arc<
+      tokio::sync::OnceCell<
+        tokio_postgres::Client
+      >
+    >
+  },
+  Quickwit {
+    cfg:         InsertQuickwitConfig,
+    /// Set when
+    /// `quickwit.stamp_embed_model`.
+    embed_model: Option<String>
+  },
+  Elasticsearch {
+    cfg: InsertElasticsearchConfig
+  }
+}
+
+impl StoreTarget {
+  fn op(&self) -> LogOp {
+    match self {
+      | StoreTarget::Qdrant {
+        ..
+      } => LogOp::Qdrant,
+      | StoreTarget::Pgvector {
+        ..
+      } => LogOp::Pgvector,
+      | StoreTarget::Quickwit {
+        ..
+      } => LogOp::Quickwit,
+      | StoreTarget::Elasticsearch {
+        ..
+      } => LogOp::Elasticsearch
+    }
+  }
+
+  fn label(&self) -> &'static str {
+    match self {
+      | StoreTarget::Qdrant {
+        ..
+      } => "qdrant upsert complete",
+      | StoreTarget::Pgvector {
+        ..
+      } => "pgvector upsert complete",
+      | StoreTarget::Quickwit {
+        ..
+      } => "quickwit ingest complete",
+      | StoreTarget::Elasticsearch {
+        ..
+      } => "elasticsearch bulk complete"
+    }
+  }
+
+  async fn write(
+    &self,
+    client: &Client,
+    batch: &WriteBatch,
+    retry: RetryPolicy
+  ) -> anyhow::Result<()> {
+    match self {
+      | StoreTarget::Qdrant {
+        cfg,
+        embed_model,
+        ensured,
+        grpc
+      } => {
+        upsert_routed(
+          QdrantClients {
+            rest: client,
+            grpc: grpc.as_deref()
+          },
+          cfg,
+          embed_model,
+          ensured,
+          &batch.records,
+          &batch.vectors,
+          retry
+        )
+        .await
+      }
+      | StoreTarget::Pgvector {
+        cfg,
+        embed_model,
+        conn
+      } => {
+        upsert_pgvector(
+          cfg,
+          embed_model,
+          conn,
+          &batch.records,
+          &batch.vectors
+        )
+        .await
+      }
+      | StoreTarget::Quickwit {
+        cfg,
+        embed_model
+      } => {
+        ingest_quickwit(
+          client,
+          cfg,
+          embed_model.as_deref(),
+          &batch.records,
+          &batch.vectors,
+          retry
+        )
+        .await
+      }
+      | StoreTarget::Elasticsearch {
+        cfg
+      } => {
+        ingest_elasticsearch(
+          client,
+          cfg,
+          &batch.records,
+          retry
+        )
+        .await
+      }
+    }
+  }
+}
+
+/// Qdrant's REST client, plus the gRPC
+/// one that takes over upserts with
+/// `transport = "grpc"`.
+#[derive(Clone, Copy)]
+struct QdrantClients<'a> {
+  rest: &'a Client,
+  grpc: Option<&'a Qdrant>
+}
+
+/// Spawns a writer that drains
+/// `queue_depth` buffered batches into
+/// one store with at
+/// most `max_concurrency` requests in
+/// flight.
+fn spawn_store_writer(
+  target: StoreTarget,
+  client: Client,
+  max_concurrency: usize,
+  queue_depth: usize,
+  retry: RetryPolicy
+) -> (
+  mpsc::Sender<WriteJob>,
+  JoinHandle<()>
+) {
+  let (tx, mut rx) = mpsc::channel::<
+    WriteJob
+  >(queue_depth);
+  let semaphore =
+    Arc::new(Semaphore::new(
+      max_concurrency.max(1)
+    ));
+  let handle = tokio::spawn(
+    async move {
+      while let Some(job) =
+        rx.recv().await
+      {
+        let Ok(permit) = semaphore
+          .clone()
+          .acquire_owned()
+          .await
+        else {
+          break;
+        };
+        let target = target.clone();
+        let client = client.clone();
+        let span = job.batch.span.clone();
+        tokio::spawn(async move {
+          let _permit = permit;
+          let started =
+            std::time::Instant::now();
+          let res = target
+            .write(
+              &client, &job.batch, retry
+            )
+            .await
+            .map(|_| started.elapsed());
+          if res.is_ok() {
+            info!(
+                batch_len = job.batch.records.len(),
+                elapsed = ?started.elapsed(),
+                log_op = target.op().name(),
+                "{}",
+                target.label()
+            );
+          }
+          let _ = job.ack.send(res);
+        }.instrument(span));
+      }
+    }
+    .in_current_span()
   );
-  Ok(batch.len())
+  (tx, handle)
 }
 
+/// Embeds `text` with Ollama's
+/// `/api/embeddings` or, for
+/// `provider = "openai"`, the
+/// `/embeddings` endpoint under
+/// `base_url` (e.g. `.../v1`), or
+/// TEI's `/embed`.
 async fn embed_text(
   client: &Client,
-  base_url: &str,
-  model: &str,
+  cfg: &InsertEmbeddingsConfig,
   text: &str
 ) -> anyhow::Result<Vec<f32>> {
-  let url = format!(
-    "{}/api/embeddings",
-    base_url.trim_end_matches('/')
-  );
-  let resp = client
-        .post(url)
-        .json(&json!({ "model": model, "prompt": text }))
-        .send()
-        .await?;
+  let base_url =
+    cfg.base_url.trim_end_matches('/');
+  let input = prefixed(cfg, text);
+  let (url, body) = match cfg.provider {
+    | EmbeddingProvider::Ollama => {
+      (
+        format!(
+          "{base_url}/api/embeddings"
+        ),
+        json!({ "model": cfg.model, "prompt": input })
+      )
+    }
+    | EmbeddingProvider::Openai => {
+      (
+        format!(
+          "{base_url}/embeddings"
+        ),
+        openai_body(cfg, json!(input))
+      )
+    }
+    | EmbeddingProvider::Tei => {
+      (
+        format!("{base_url}/embed"),
+        json!({ "inputs": input })
+      )
+    }
+  };
+  let resp = with_api_key(
+    client.post(url).json(&body),
+    cfg
+  )
+  .send()
+  .await?;
   if !resp.status().is_success() {
     let status = resp.status();
     let body = resp
@@ -549,8 +2859,9 @@ async fn embed_text(
     let snippet: String =
       text.chars().take(120).collect();
     return Err(anyhow!(
-      "ollama embedding failed: {} {} \
+      "{} embedding failed: {} {} \
        (text_len={} snippet={:?})",
+      cfg.provider.name(),
       status,
       body,
       text.len(),
@@ -559,23 +2870,430 @@ async fn embed_text(
   }
   let value: Value =
     resp.json().await?;
-  let embedding = value
-    .get("embedding")
-    .and_then(|v| v.as_array())
-    .ok_or_else(|| {
-      anyhow!(
-        "missing embedding in response"
-      )
-    })?
+  // TEI answers `[[...]]` even for one
+  // input.
+  let embedding = match cfg.provider {
+    | EmbeddingProvider::Tei => {
+      value.get(0)
+    }
+    | _ => find_embedding(&value)
+  }
+  .ok_or_else(|| {
+    let body = value.to_string();
+    let snippet: String =
+      body.chars().take(500).collect();
+    anyhow!(
+      "missing embedding in response: \
+       {snippet}"
+    )
+  })?;
+  parse_embedding(embedding)
+}
+
+/// The OpenAI `/embeddings` body for
+/// `input`, with `encoding_format`
+/// when base64 is configured.
+fn openai_body(
+  cfg: &InsertEmbeddingsConfig,
+  input: Value
+) -> Value {
+  let mut body = json!({
+    "model": cfg.model,
+    "input": input
+  });
+  if cfg.encoding_format
+    == EmbeddingEncoding::Base64
+  {
+    body["encoding_format"] =
+      json!("base64");
+  }
+  body
+}
+
+/// Embeds `texts` in one request:
+/// Ollama's `/api/embed` or the
+/// OpenAI `/embeddings` endpoint with
+/// an `input` array, or TEI's `/embed`
+/// with `inputs`. `None` when the
+/// server has no batch endpoint (404,
+/// 405, 501), as older Ollama does.
+async fn embed_texts(
+  client: &Client,
+  cfg: &InsertEmbeddingsConfig,
+  texts: &[&str]
+) -> anyhow::Result<Option<Vec<Vec<f32>>>>
+{
+  let base_url =
+    cfg.base_url.trim_end_matches('/');
+  let url = match cfg.provider {
+    | EmbeddingProvider::Ollama => {
+      format!("{base_url}/api/embed")
+    }
+    | EmbeddingProvider::Openai => {
+      format!("{base_url}/embeddings")
+    }
+    | EmbeddingProvider::Tei => {
+      format!("{base_url}/embed")
+    }
+  };
+  let inputs = texts
     .iter()
-    .map(|v| {
-      v.as_f64().unwrap_or(0.0) as f32
-    })
+    .map(|text| prefixed(cfg, text))
     .collect::<Vec<_>>();
-  Ok(embedding)
+  let body = match cfg.provider {
+    | EmbeddingProvider::Tei => {
+      json!({ "inputs": inputs })
+    }
+    | EmbeddingProvider::Openai => {
+      openai_body(cfg, json!(inputs))
+    }
+    | EmbeddingProvider::Ollama => {
+      json!({ "model": cfg.model, "input": inputs })
+    }
+  };
+  let resp = with_api_key(
+    client.post(url).json(&body),
+    cfg
+  )
+  .send()
+  .await?;
+  let status = resp.status();
+  if matches!(
+    status,
+    StatusCode::NOT_FOUND
+      | StatusCode::METHOD_NOT_ALLOWED
+      | StatusCode::NOT_IMPLEMENTED
+  ) {
+    return Ok(None);
+  }
+  if !status.is_success() {
+    let body = resp
+      .text()
+      .await
+      .unwrap_or_default();
+    return Err(anyhow!(
+      "{} batch embedding failed: {} \
+       {} (texts={})",
+      cfg.provider.name(),
+      status,
+      body,
+      texts.len()
+    ));
+  }
+  let value: Value =
+    resp.json().await?;
+  let mut items = match cfg.provider {
+    | EmbeddingProvider::Ollama => {
+      value
+        .get("embeddings")
+        .and_then(Value::as_array)
+        .map(|items| {
+          items
+            .iter()
+            .enumerate()
+            .collect::<Vec<_>>()
+        })
+    }
+    // `data` may come back out of
+    // order; `index` says whose it is.
+    | EmbeddingProvider::Openai => {
+      value
+        .get("data")
+        .and_then(Value::as_array)
+        .map(|items| {
+          items
+            .iter()
+            .enumerate()
+            .map(|(pos, item)| {
+              (
+                item
+                  .get("index")
+                  .and_then(
+                    Value::as_u64
+                  )
+                  .map_or(pos, |i| {
+                    i as usize
+                  }),
+                item
+                  .get("embedding")
+                  .unwrap_or(
+                    &Value::Null
+                  )
+              )
+            })
+            .collect::<Vec<_>>()
+        })
+    }
+    // One vector per input, in order.
+    | EmbeddingProvider::Tei => {
+      value.as_array().map(|items| {
+        items
+          .iter()
+          .enumerate()
+          .collect::<Vec<_>>()
+      })
+    }
+  }
+  .ok_or_else(|| {
+    let body = value.to_string();
+    let snippet: String =
+      body.chars().take(500).collect();
+    anyhow!(
+      "missing embeddings in batch \
+       response: {snippet}"
+    )
+  })?;
+  if items.len() != texts.len() {
+    return Err(anyhow!(
+      "batch response has {} \
+       embeddings for {} texts",
+      items.len(),
+      texts.len()
+    ));
+  }
+  items.sort_by_key(|(idx, _)| *idx);
+  items
+    .into_iter()
+    .map(|(_, embedding)| {
+      parse_embedding(embedding)
+    })
+    .collect::<anyhow::Result<Vec<_>>>()
+    .map(Some)
+}
+
+/// Adds `api_key` to an embed request,
+/// as a bearer token or under
+/// `api_key_header`.
+fn with_api_key(
+  req: RequestBuilder,
+  cfg: &InsertEmbeddingsConfig
+) -> RequestBuilder {
+  let Some(key) = cfg
+    .api_key
+    .as_ref()
+    .filter(|k| !k.is_empty())
+  else {
+    return req;
+  };
+  match &cfg.api_key_header {
+    | Some(header) => {
+      req.header(header.as_str(), key)
+    }
+    | None => req.bearer_auth(key)
+  }
+}
+
+fn cache_vector(
+  cache: Option<
+    &Arc<Mutex<EmbeddingCache>>
+  >,
+  text: String,
+  vec: &[f32]
+) {
+  if let Some(cache) = cache {
+    cache
+      .lock()
+      .unwrap()
+      .insert(text, vec.to_vec());
+  }
+}
+
+/// The embedding in a response shaped
+/// like `/api/embeddings`
+/// (`embedding`), `/api/embed`
+/// (`embeddings[0]`), or an
+/// OpenAI-style proxy
+/// (`data[0].embedding`).
+fn find_embedding(
+  value: &Value
+) -> Option<&Value> {
+  [
+    "/embedding",
+    "/embeddings/0",
+    "/data/0/embedding"
+  ]
+  .iter()
+  .find_map(|ptr| value.pointer(ptr))
+}
+
+/// Reads an embedding given either as a
+/// JSON number array or as a base64
+/// string of little-endian f32s
+/// (`encoding_format: base64`).
+fn parse_embedding(
+  value: &Value
+) -> anyhow::Result<Vec<f32>> {
+  match value {
+    | Value::Array(items) => {
+      Ok(
+        items
+          .iter()
+          .map(|v| {
+            v.as_f64().unwrap_or(0.0)
+              as f32
+          })
+          .collect()
+      )
+    }
+    | Value::String(encoded) => {
+      let bytes = BASE64_STANDARD
+        .decode(encoded)
+        .context(
+          "decode base64 embedding"
+        )?;
+      if bytes.len() % 4 != 0 {
+        return Err(anyhow!(
+          "base64 embedding is {} \
+           bytes, not a multiple of 4",
+          bytes.len()
+        ));
+      }
+      Ok(
+        bytes
+          .as_chunks::<4>()
+          .0
+          .iter()
+          .map(|b| {
+            f32::from_le_bytes(*b)
+          })
+          .collect()
+      )
+    }
+    | _ => {
+      Err(anyhow!(
+        "embedding is neither an \
+         array nor a base64 string"
+      ))
+    }
+  }
+}
+
+/// Checks the collection's stored
+/// dimension against the embedder's
+/// (from warmup) or `vector_size`,
+/// failing before any batch on a
+/// mismatch. A missing collection is
+/// created with that dimension when
+/// `create_collection` is set. Returns
+/// the dimension points will carry.
+async fn prepare_qdrant_collection(
+  client: &Client,
+  cfg: &InsertQdrantConfig,
+  embed_dim: Option<usize>
+) -> anyhow::Result<usize> {
+  let dim = embed_dim
+    .unwrap_or(cfg.vector_size);
+  let source = match embed_dim {
+    | Some(_) => "the embedder returns",
+    | None => "qdrant.vector_size is"
+  };
+  match qdrant_collection_size(
+    client, cfg
+  )
+  .await?
+  {
+    | Some(Some(size)) if size != dim => {
+      Err(anyhow!(
+        "qdrant collection {} stores \
+         {size}-dimension vectors but \
+         {source} {dim}; use a new \
+         collection or the matching \
+         model",
+        cfg.collection
+      ))
+    }
+    | Some(_) => Ok(dim),
+    | None if cfg.create_collection => {
+      if dim != cfg.vector_size {
+        warn!(
+          collection = %cfg.collection,
+          vector_size = cfg.vector_size,
+          detected = dim,
+          "embedder dimension differs \
+           from qdrant.vector_size, \
+           creating the collection with \
+           the detected one"
+        );
+      }
+      ensure_qdrant_collection(
+        client,
+        &InsertQdrantConfig {
+          vector_size: dim,
+          ..cfg.clone()
+        }
+      )
+      .await?;
+      Ok(dim)
+    }
+    | None => {
+      Err(anyhow!(
+        "qdrant collection {} does \
+         not exist and \
+         qdrant.create_collection is \
+         off",
+        cfg.collection
+      ))
+    }
+  }
+}
+
+/// `None` when the collection is
+/// missing; `Some(None)` when it exists
+/// without an unnamed dense vector to
+/// compare.
+async fn qdrant_collection_size(
+  client: &Client,
+  cfg: &InsertQdrantConfig
+) -> anyhow::Result<Option<Option<usize>>>
+{
+  let url = format!(
+    "{}/collections/{}",
+    cfg.url.trim_end_matches('/'),
+    cfg.collection
+  );
+  let mut req = client.get(&url);
+  if let Some(key) = cfg
+    .api_key
+    .as_ref()
+    .filter(|k| !k.is_empty())
+  {
+    req = req.header("api-key", key);
+  }
+  let resp =
+    req.send().await.with_context(
+      || format!("fetch {url}")
+    )?;
+  if resp.status()
+    == StatusCode::NOT_FOUND
+  {
+    return Ok(None);
+  }
+  if !resp.status().is_success() {
+    let status = resp.status();
+    let text = resp
+      .text()
+      .await
+      .unwrap_or_default();
+    return Err(anyhow!(
+      "qdrant collection info failed: \
+       {} {}",
+      status,
+      text
+    ));
+  }
+  let info: Value = resp.json().await?;
+  let vectors = &info["result"]
+    ["config"]["params"]["vectors"];
+  let size = vectors["size"]
+    .as_u64()
+    .or_else(|| {
+      vectors[""]["size"].as_u64()
+    })
+    .map(|n| n as usize);
+  Ok(Some(size))
 }
 
-async fn ensure_qdrant_collection(
+pub(crate) async fn ensure_qdrant_collection(
   client: &Client,
   cfg: &InsertQdrantConfig
 ) -> anyhow::Result<()> {
@@ -584,14 +3302,82 @@ async fn ensure_qdrant_collection(
     cfg.url.trim_end_matches('/'),
     cfg.collection
   );
-  let body = json!({
+  let mut base = json!({
       "vectors": {
           "size": cfg.vector_size,
           "distance": cfg.distance,
       }
   });
+  if let Some(name) = &cfg.sparse_vector
+  {
+    base["sparse_vectors"] = json!({
+        name.as_str(): { "modifier": "idf" },
+    });
+  }
+  let body = collection_create_body(
+    base.clone(),
+    &cfg.collection_params
+  );
+  let (mut status, mut text) =
+    put_qdrant_collection(
+      client, cfg, &url, &body
+    )
+    .await?;
+  let rejected =
+    |status: StatusCode, text: &str| {
+      status.is_client_error()
+        && !text
+          .contains("already exists")
+    };
+  // Older servers reject params they do
+  // not know: drop the ones the error
+  // names first, then every param.
+  let mut retries = Vec::new();
+  if let Some(trimmed) =
+    without_named_params(
+      &body, &base, &text
+    )
+  {
+    retries.push(trimmed);
+  }
+  retries.push(base);
+  let mut sent = body;
+  for retry in retries {
+    if sent == retry
+      || !rejected(status, &text)
+    {
+      break;
+    }
+    warn!(
+      status = %status,
+      error = %text,
+      "qdrant rejected collection params, \
+       retrying without them"
+    );
+    (status, text) =
+      put_qdrant_collection(
+        client, cfg, &url, &retry
+      )
+      .await?;
+    sent = retry;
+  }
+  if !status.is_success() {
+    warn!(status = %status, error = %text, "qdrant collection create failed");
+  }
+  Ok(())
+}
+
+async fn put_qdrant_collection(
+  client: &Client,
+  cfg: &InsertQdrantConfig,
+  url: &str,
+  body: &Value
+) -> anyhow::Result<(
+  reqwest::StatusCode,
+  String
+)> {
   let mut req =
-    client.put(url).json(&body);
+    client.put(url).json(body);
   if let Some(key) = cfg
     .api_key
     .as_ref()
@@ -600,31 +3386,271 @@ async fn ensure_qdrant_collection(
     req = req.header("api-key", key);
   }
   let resp = req.send().await?;
-  if !resp.status().is_success() {
-    warn!(status = %resp.status(), "qdrant collection create failed");
+  let status = resp.status();
+  let text = resp
+    .text()
+    .await
+    .unwrap_or_default();
+  Ok((status, text))
+}
+
+/// `body` without the collection params
+/// Qdrant's `error` names, or `None`
+/// when it names none of them.
+fn without_named_params(
+  body: &Value,
+  base: &Value,
+  error: &str
+) -> Option<Value> {
+  let mut trimmed = body.clone();
+  let mut dropped = false;
+  if let Some(fields) =
+    trimmed.as_object_mut()
+  {
+    fields.retain(|key, _| {
+      let named = base
+        .get(key)
+        .is_none()
+        && error.contains(key.as_str());
+      dropped |= named;
+      !named
+    });
+  }
+  if error.contains("on_disk")
+    && let Some(vectors) =
+      trimmed["vectors"].as_object_mut()
+    && vectors
+      .remove("on_disk")
+      .is_some()
+  {
+    dropped = true;
+  }
+  dropped.then_some(trimmed)
+}
+
+fn collection_create_body(
+  mut body: Value,
+  params: &QdrantCollectionParams
+) -> Value {
+  if let Some(on_disk) =
+    params.on_disk_vectors
+  {
+    body["vectors"]["on_disk"] =
+      json!(on_disk);
+  }
+  if let Some(n) = params.shard_number {
+    body["shard_number"] = json!(n);
+  }
+  if let Some(n) =
+    params.replication_factor
+  {
+    body["replication_factor"] =
+      json!(n);
+  }
+  if let Some(n) =
+    params.write_consistency_factor
+  {
+    body["write_consistency_factor"] =
+      json!(n);
+  }
+  if let Some(on_disk) =
+    params.on_disk_payload
+  {
+    body["on_disk_payload"] =
+      json!(on_disk);
+  }
+  if let Some(scalar) =
+    params.scalar_quantization.as_ref()
+  {
+    let mut quant = json!({
+        "type": "int8",
+        "always_ram": scalar.always_ram,
+    });
+    if let Some(q) = scalar.quantile {
+      quant["quantile"] = json!(q);
+    }
+    body["quantization_config"] =
+      json!({ "scalar": quant });
+  }
+  body
+}
+
+/// Upserts `batch`, split by the
+/// collection `qdrant.route_by`
+/// resolves for each record. Routed
+/// collections are created on first
+/// use when `create_collection` is set.
+async fn upsert_routed(
+  clients: QdrantClients<'_>,
+  cfg: &InsertQdrantConfig,
+  embed_model: &str,
+  ensured: &tokio::sync::Mutex<
+    HashSet<String>
+  >,
+  batch: &[ChunkRecord],
+  vectors: &[Vec<f32>],
+  retry: RetryPolicy
+) -> anyhow::Result<()> {
+  let Some(key) =
+    cfg.route_by.as_deref()
+  else {
+    return upsert_qdrant(
+      clients,
+      cfg,
+      embed_model,
+      batch,
+      vectors,
+      retry
+    )
+    .await;
+  };
+  if batch.len() != vectors.len() {
+    return Err(anyhow!(
+      "embedding batch mismatch"
+    ));
+  }
+  let mut groups: BTreeMap<
+    String,
+    (Vec<ChunkRecord>, Vec<Vec<f32>>)
+  > = BTreeMap::new();
+  for (record, vector) in
+    batch.iter().zip(vectors)
+  {
+    let group = groups
+      .entry(routed_collection(
+        cfg,
+        key,
+        &record.metadata
+      ))
+      .or_default();
+    group.0.push(record.clone());
+    group.1.push(vector.clone());
+  }
+  for (
+    collection,
+    (records, vectors)
+  ) in groups
+  {
+    let routed = InsertQdrantConfig {
+      collection,
+      ..cfg.clone()
+    };
+    if routed.create_collection {
+      let mut ensured =
+        ensured.lock().await;
+      if !ensured
+        .contains(&routed.collection)
+      {
+        ensure_qdrant_collection(
+          clients.rest,
+          &routed
+        )
+        .await?;
+        ensured.insert(
+          routed.collection.clone()
+        );
+      }
+    }
+    upsert_qdrant(
+      clients,
+      &routed,
+      embed_model,
+      &records,
+      &vectors,
+      retry
+    )
+    .await?;
+  }
+  Ok(())
+}
+
+/// `<collection>_<slug of value>` for
+/// the record's `key` metadata value,
+/// or the base collection when the
+/// value is missing or not a scalar.
+fn routed_collection(
+  cfg: &InsertQdrantConfig,
+  key: &str,
+  metadata: &Value
+) -> String {
+  let value = match metadata.get(key) {
+    | Some(Value::String(s)) => {
+      slugify(s)
+    }
+    | Some(Value::Number(n)) => {
+      n.to_string()
+    }
+    | Some(Value::Bool(b)) => {
+      b.to_string()
+    }
+    | _ => String::new()
+  };
+  if value.is_empty() {
+    cfg.collection.clone()
+  } else {
+    format!(
+      "{}_{value}",
+      cfg.collection
+    )
   }
-  Ok(())
 }
 
 async fn upsert_qdrant(
-  client: &Client,
+  clients: QdrantClients<'_>,
   cfg: &InsertQdrantConfig,
+  embed_model: &str,
   batch: &[ChunkRecord],
-  vectors: &[Vec<f32>]
+  vectors: &[Vec<f32>],
+  retry: RetryPolicy
 ) -> anyhow::Result<()> {
   if batch.len() != vectors.len() {
     return Err(anyhow!(
       "embedding batch mismatch"
     ));
   }
+  if let Some(grpc) = clients.grpc {
+    return upsert_qdrant_grpc(
+      grpc,
+      cfg,
+      embed_model,
+      batch,
+      vectors,
+      retry
+    )
+    .await;
+  }
+  let client = clients.rest;
   let points = batch
     .iter()
     .zip(vectors.iter())
     .map(|(record, vector)| {
+      let point_vector = match &cfg
+        .sparse_vector
+      {
+        | Some(name) => {
+          let sparse = record
+            .sparse_embedding
+            .clone()
+            .unwrap_or_else(|| {
+              SparseVector::term_frequencies(
+                &record.text
+              )
+            });
+          json!({
+              "": vector,
+              name.as_str(): sparse,
+          })
+        }
+        | None => json!(vector)
+      };
       json!({
           "id": record.id,
-          "vector": vector,
-          "payload": record.metadata,
+          "vector": point_vector,
+          "payload": stamped_metadata(
+            &record.metadata,
+            embed_model,
+            vector.len()
+          ),
       })
     })
     .collect::<Vec<_>>();
@@ -639,17 +3665,24 @@ async fn upsert_qdrant(
     cfg.collection,
     wait
   );
-  let mut req = client
-    .put(url)
-    .json(&json!({ "points": points }));
-  if let Some(key) = cfg
-    .api_key
-    .as_ref()
-    .filter(|k| !k.is_empty())
-  {
-    req = req.header("api-key", key);
-  }
-  let resp = req.send().await?;
+  let body =
+    json!({ "points": points });
+  let resp =
+    send_with_retry(retry, || {
+      let req =
+        client.put(&url).json(&body);
+      match cfg
+        .api_key
+        .as_ref()
+        .filter(|k| !k.is_empty())
+      {
+        | Some(key) => {
+          req.header("api-key", key)
+        }
+        | None => req
+      }
+    })
+    .await?;
   if !resp.status().is_success() {
     let status = resp.status();
     let text = resp
@@ -665,17 +3698,417 @@ async fn upsert_qdrant(
   Ok(())
 }
 
+/// `upsert_qdrant` over gRPC: the same
+/// ids, vectors, payloads, and `wait`,
+/// with floats sent as binary.
+async fn upsert_qdrant_grpc(
+  grpc: &Qdrant,
+  cfg: &InsertQdrantConfig,
+  embed_model: &str,
+  batch: &[ChunkRecord],
+  vectors: &[Vec<f32>],
+  retry: RetryPolicy
+) -> anyhow::Result<()> {
+  let points = batch
+    .iter()
+    .zip(vectors)
+    .map(|(record, vector)| {
+      let point_vectors: Vectors =
+        match &cfg.sparse_vector {
+          | Some(name) => {
+            let sparse = record
+              .sparse_embedding
+              .clone()
+              .unwrap_or_else(|| {
+                SparseVector::term_frequencies(
+                  &record.text
+                )
+              });
+            NamedVectors::default()
+              .add_vector(
+                "",
+                vector.clone()
+              )
+              .add_vector(
+                name.as_str(),
+                Vector::new_sparse(
+                  sparse.indices,
+                  sparse.values
+                )
+              )
+              .into()
+          }
+          | None => {
+            vector.clone().into()
+          }
+        };
+      let payload =
+        Payload::try_from(
+          stamped_metadata(
+            &record.metadata,
+            embed_model,
+            vector.len()
+          )
+        )?;
+      Ok(PointStruct::new(
+        record.id.clone(),
+        point_vectors,
+        payload
+      ))
+    })
+    .collect::<anyhow::Result<Vec<_>>>()?;
+  let request: UpsertPoints =
+    UpsertPointsBuilder::new(
+      cfg.collection.as_str(),
+      points
+    )
+    .wait(cfg.wait)
+    .into();
+  let mut attempts = 0usize;
+  loop {
+    attempts += 1;
+    match grpc
+      .upsert_points(request.clone())
+      .await
+    {
+      | Ok(_) => return Ok(()),
+      | Err(err)
+        if attempts
+          <= retry.retry_max
+          && grpc_retryable(&err) =>
+      {
+        debug!(
+          attempts,
+          error = %err,
+          "request failed, retrying"
+        );
+      }
+      | Err(err) => {
+        return Err(anyhow!(
+          "qdrant grpc upsert failed: \
+           {err}"
+        ));
+      }
+    }
+    tokio::time::sleep(
+      retry.backoff(attempts)
+    )
+    .await;
+  }
+}
+
+/// The gRPC side of `send_with_retry`:
+/// rate limits, an unreachable server,
+/// and timeouts are retried.
+fn grpc_retryable(
+  err: &QdrantError
+) -> bool {
+  match err {
+    | QdrantError::ResourceExhaustedError {
+      ..
+    } => true,
+    | QdrantError::ResponseError {
+      status
+    } => matches!(
+      status.code(),
+      tonic::Code::Unavailable
+        | tonic::Code::DeadlineExceeded
+        | tonic::Code::ResourceExhausted
+    ),
+    | _ => false
+  }
+}
+
+/// Builds the client for `transport =
+/// "grpc"`; it connects on the first
+/// upsert.
+fn qdrant_grpc_client(
+  config: &Config
+) -> anyhow::Result<Qdrant> {
+  let cfg = &config.insert.qdrant;
+  // The gRPC client has no hook for a
+  // client certificate.
+  let http = &config.insert.http;
+  if http.client_cert.is_some()
+    || http.client_key.is_some()
+  {
+    return Err(anyhow!(
+      "insert.qdrant.transport = \
+       \"grpc\" cannot present the \
+       insert.http client \
+       certificate; use transport = \
+       \"rest\" with mutual TLS"
+    ));
+  }
+  let url = match &cfg.grpc_url {
+    | Some(url) => url.clone(),
+    | None => {
+      let mut url =
+        reqwest::Url::parse(&cfg.url)
+          .with_context(|| {
+          format!(
+            "parse insert.qdrant.url \
+             {}",
+            cfg.url
+          )
+        })?;
+      if url.port_or_known_default()
+        != Some(6333)
+      {
+        return Err(anyhow!(
+          "insert.qdrant.transport = \
+           \"grpc\" needs \
+           insert.qdrant.grpc_url \
+           when url is not on port \
+           6333"
+        ));
+      }
+      let _ = url.set_port(Some(6334));
+      url
+        .as_str()
+        .trim_end_matches('/')
+        .to_string()
+    }
+  };
+  Qdrant::from_url(&url)
+    .api_key(
+      cfg
+        .api_key
+        .clone()
+        .filter(|k| !k.is_empty())
+    )
+    .timeout(Duration::from_secs(
+      cfg.grpc_timeout_seconds
+    ))
+    // The collection was already
+    // checked over REST.
+    .skip_compatibility_check()
+    .build()
+    .with_context(|| {
+      format!(
+        "build qdrant grpc client for \
+         {url}"
+      )
+    })
+}
+
+/// Rows per `INSERT`, well under the
+/// 65535 bind parameters Postgres
+/// allows in one statement.
+const PGVECTOR_ROWS_PER_STATEMENT:
+  usize = 1000;
+
+/// The open pgvector connection,
+/// connecting (and with `create_table`
+/// creating the table) on first use.
+async fn pgvector_client<'a>(
+  cfg: &InsertPgvectorConfig,
+  conn: &'a tokio::sync::OnceCell<
+    tokio_postgres::Client
+  >
+) -> anyhow::Result<
+  &'a tokio_postgres::Client
+> {
+  conn
+    .get_or_try_init(|| {
+      async {
+        let (client, connection) =
+          tokio_postgres::connect(
+            &cfg.url,
+            tokio_postgres::NoTls
+          )
+          .await
+          .context(
+            "connect to pgvector \
+             postgres"
+          )?;
+        tokio::spawn(async move {
+          if let Err(err) =
+            connection.await
+          {
+            warn!(error = %err, "pgvector connection closed");
+          }
+        });
+        if cfg.create_table {
+          ensure_pgvector_table(
+            &client, cfg
+          )
+          .await?;
+        }
+        Ok(client)
+      }
+    })
+    .await
+}
+
+/// Creates the `vector` extension and
+/// `id text primary key, embedding
+/// vector(N), payload jsonb` table
+/// when missing.
+async fn ensure_pgvector_table(
+  client: &tokio_postgres::Client,
+  cfg: &InsertPgvectorConfig
+) -> anyhow::Result<()> {
+  let table = quote_table(&cfg.table);
+  client
+    .batch_execute(&format!(
+      "CREATE EXTENSION IF NOT EXISTS \
+       vector; CREATE TABLE IF NOT \
+       EXISTS {table} (id text \
+       PRIMARY KEY, embedding \
+       vector({}), payload jsonb)",
+      cfg.vector_size
+    ))
+    .await
+    .with_context(|| {
+      format!(
+        "create pgvector table {}",
+        cfg.table
+      )
+    })?;
+  info!(
+    table = %cfg.table,
+    vector_size = cfg.vector_size,
+    "pgvector table ready"
+  );
+  Ok(())
+}
+
+/// Quotes each dot-separated part of a
+/// table name as an identifier.
+fn quote_table(name: &str) -> String {
+  name
+    .split('.')
+    .map(|part| {
+      format!(
+        "\"{}\"",
+        part.replace('"', "\"\"")
+      )
+    })
+    .collect::<Vec<_>>()
+    .join(".")
+}
+
+/// `[0.1,0.2]`, the text form pgvector
+/// parses.
+fn vector_literal(
+  vector: &[f32]
+) -> String {
+  let parts = vector
+    .iter()
+    .map(f32::to_string)
+    .collect::<Vec<_>>();
+  format!("[{}]", parts.join(","))
+}
+
+/// Upserts a batch with multi-row
+/// `INSERT ... ON CONFLICT (id) DO
+/// UPDATE`; the payload is the same
+/// stamped metadata Qdrant gets.
+async fn upsert_pgvector(
+  cfg: &InsertPgvectorConfig,
+  embed_model: &str,
+  conn: &tokio::sync::OnceCell<
+    tokio_postgres::Client
+  >,
+  batch: &[ChunkRecord],
+  vectors: &[Vec<f32>]
+) -> anyhow::Result<()> {
+  if batch.len() != vectors.len() {
+    return Err(anyhow!(
+      "embedding batch mismatch"
+    ));
+  }
+  let client =
+    pgvector_client(cfg, conn).await?;
+  let table = quote_table(&cfg.table);
+  for (records, vectors) in batch
+    .chunks(PGVECTOR_ROWS_PER_STATEMENT)
+    .zip(vectors.chunks(
+      PGVECTOR_ROWS_PER_STATEMENT
+    ))
+  {
+    let mut rows = Vec::new();
+    let mut params: Vec<String> =
+      Vec::new();
+    for (record, vector) in
+      records.iter().zip(vectors)
+    {
+      let n = params.len();
+      rows.push(format!(
+        "(${}, ${}::text::vector, \
+         ${}::text::jsonb)",
+        n + 1,
+        n + 2,
+        n + 3
+      ));
+      params.push(record.id.clone());
+      params
+        .push(vector_literal(vector));
+      params.push(
+        stamped_metadata(
+          &record.metadata,
+          embed_model,
+          vector.len()
+        )
+        .to_string()
+      );
+    }
+    let sql = format!(
+      "INSERT INTO {table} (id, \
+       embedding, payload) VALUES {} \
+       ON CONFLICT (id) DO UPDATE SET \
+       embedding = \
+       EXCLUDED.embedding, payload = \
+       EXCLUDED.payload",
+      rows.join(", ")
+    );
+    let refs = params
+      .iter()
+      .map(|p| {
+        p as &(dyn tokio_postgres::types::ToSql
+          + Sync)
+      })
+      .collect::<Vec<_>>();
+    client
+      .execute(sql.as_str(), &refs)
+      .await
+      .with_context(|| {
+        format!(
+          "pgvector upsert into {} \
+           failed",
+          cfg.table
+        )
+      })?;
+  }
+  Ok(())
+}
+
 async fn ingest_quickwit(
   client: &Client,
   cfg: &InsertQuickwitConfig,
-  batch: &[ChunkRecord]
+  embed_model: Option<&str>,
+  batch: &[ChunkRecord],
+  vectors: &[Vec<f32>],
+  retry: RetryPolicy
 ) -> anyhow::Result<()> {
-  let commit_mode =
-    if cfg.commit_mode.is_empty() {
-      "auto"
-    } else {
-      cfg.commit_mode.as_str()
-    };
+  // No vectors when only the lexical
+  // side is written.
+  if !vectors.is_empty()
+    && batch.len() != vectors.len()
+  {
+    return Err(anyhow!(
+      "embedding batch mismatch"
+    ));
+  }
+  let commit_mode = if cfg.force_commit
+  {
+    "force"
+  } else if cfg.commit_mode.is_empty() {
+    "auto"
+  } else {
+    cfg.commit_mode.as_str()
+  };
   let url = format!(
     "{}/api/v1/{}/ingest?commit={}&\
      commit_timeout_seconds={}",
@@ -685,25 +4118,37 @@ async fn ingest_quickwit(
     cfg.commit_timeout_seconds
   );
   let mut body = String::new();
-  for record in batch {
-    let doc = json!({
-        "id": record.id,
-        "text": record.text,
-        "metadata": record.metadata,
-    });
+  for (idx, record) in
+    batch.iter().enumerate()
+  {
+    let mut doc =
+      quickwit_doc(record, cfg);
+    if let Some(model) = embed_model
+      && let Some(vector) =
+        vectors.get(idx)
+    {
+      doc["metadata"] =
+        stamped_metadata(
+          &record.metadata,
+          model,
+          vector.len()
+        );
+    }
     body.push_str(
       &serde_json::to_string(&doc)?
     );
     body.push('\n');
   }
-  let resp = client
-    .post(url)
-    .header(
-      "content-type",
-      "application/json"
-    )
-    .body(body)
-    .send()
+  let resp =
+    send_with_retry(retry, || {
+      client
+        .post(&url)
+        .header(
+          "content-type",
+          "application/json"
+        )
+        .body(body.clone())
+    })
     .await?;
   if !resp.status().is_success() {
     let status = resp.status();
@@ -720,7 +4165,188 @@ async fn ingest_quickwit(
   Ok(())
 }
 
-async fn quickwit_commit(
+/// Indexes a batch with one `_bulk`
+/// request of `index` actions keyed by
+/// chunk id, so a rerun overwrites the
+/// same documents. A 200 whose items
+/// report errors still fails.
+async fn ingest_elasticsearch(
+  client: &Client,
+  cfg: &InsertElasticsearchConfig,
+  batch: &[ChunkRecord],
+  retry: RetryPolicy
+) -> anyhow::Result<()> {
+  let mut url = format!(
+    "{}/{}/_bulk",
+    cfg.url.trim_end_matches('/'),
+    cfg.index
+  );
+  if let Some(refresh) = &cfg.refresh {
+    url.push_str(&format!(
+      "?refresh={refresh}"
+    ));
+  }
+  let mut body = String::new();
+  for record in batch {
+    body.push_str(
+      &json!({ "index": { "_id": record.id } })
+        .to_string()
+    );
+    body.push('\n');
+    body.push_str(
+      &json!({
+          "id": record.id,
+          "text": record.text,
+          "metadata": record.metadata,
+      })
+      .to_string()
+    );
+    body.push('\n');
+  }
+  let resp =
+    send_with_retry(retry, || {
+      let req = client
+        .post(&url)
+        .header(
+          "content-type",
+          "application/x-ndjson"
+        )
+        .body(body.clone());
+      match (
+        &cfg.api_key,
+        &cfg.username
+      ) {
+        | (Some(key), _) => {
+          req.header(
+            "authorization",
+            format!("ApiKey {key}")
+          )
+        }
+        | (None, Some(user)) => {
+          req.basic_auth(
+            user,
+            cfg.password.as_ref()
+          )
+        }
+        | (None, None) => req
+      }
+    })
+    .await?;
+  let status = resp.status();
+  let text = resp
+    .text()
+    .await
+    .unwrap_or_default();
+  if !status.is_success() {
+    return Err(anyhow!(
+      "elasticsearch bulk failed: {} \
+       {}",
+      status,
+      text
+    ));
+  }
+  let parsed: Value =
+    serde_json::from_str(&text)
+      .context(
+        "parse elasticsearch bulk \
+         response"
+      )?;
+  if parsed["errors"].as_bool()
+    == Some(true)
+  {
+    let items = parsed["items"]
+      .as_array()
+      .map(Vec::as_slice)
+      .unwrap_or_default();
+    let failed = items
+      .iter()
+      .filter_map(|item| {
+        item["index"]
+          .get("error")
+          .filter(|e| !e.is_null())
+      })
+      .collect::<Vec<_>>();
+    return Err(anyhow!(
+      "elasticsearch bulk rejected {} \
+       of {} documents; first error: \
+       {}",
+      failed.len(),
+      items.len(),
+      failed
+        .first()
+        .map(|e| e.to_string())
+        .unwrap_or_default()
+    ));
+  }
+  Ok(())
+}
+
+/// `metadata` plus the model and
+/// dimension that produced the chunk's
+/// vector, so mixed-model
+/// collections stay auditable.
+fn stamped_metadata(
+  metadata: &Value,
+  embed_model: &str,
+  embed_dim: usize
+) -> Value {
+  let mut metadata = metadata.clone();
+  if let Some(obj) =
+    metadata.as_object_mut()
+  {
+    obj.insert(
+      "embed_model".to_string(),
+      json!(embed_model)
+    );
+    obj.insert(
+      "embed_dim".to_string(),
+      json!(embed_dim)
+    );
+  }
+  metadata
+}
+
+fn quickwit_doc(
+  record: &ChunkRecord,
+  cfg: &InsertQuickwitConfig
+) -> Value {
+  let mut doc = json!({
+      "id": record.id,
+      "text": record.text,
+      "metadata": record.metadata,
+  });
+  for field in &cfg.lowercase_fields {
+    let lowered = match record
+      .metadata
+      .get(field)
+    {
+      | Some(Value::String(text)) => {
+        Value::String(
+          text.to_lowercase()
+        )
+      }
+      | Some(Value::Array(items)) => {
+        Value::Array(
+          items
+            .iter()
+            .filter_map(Value::as_str)
+            .map(|text| {
+              Value::String(
+                text.to_lowercase()
+              )
+            })
+            .collect()
+        )
+      }
+      | _ => continue
+    };
+    doc[format!("{field}_lc")] =
+      lowered;
+  }
+  doc
+}
+
+pub(crate) async fn quickwit_commit(
   client: &Client,
   cfg: &InsertQuickwitConfig
 ) -> anyhow::Result<()> {
@@ -762,26 +4388,180 @@ async fn quickwit_commit(
   Ok(())
 }
 
+/// Polls the index until it reports
+/// at least `expected` documents, so a
+/// search right after `insert` sees
+/// everything that was ingested.
+/// Returns the visible count, or an
+/// error once `timeout` passes.
+pub async fn quickwit_wait_searchable(
+  client: &Client,
+  cfg: &InsertQuickwitConfig,
+  expected: u64,
+  timeout: Duration
+) -> anyhow::Result<u64> {
+  let url = format!(
+    "{}/api/v1/{}/search",
+    cfg.url.trim_end_matches('/'),
+    cfg.index_id
+  );
+  let deadline =
+    tokio::time::Instant::now()
+      + timeout;
+  loop {
+    let resp = client
+      .post(&url)
+      .json(&json!({
+          "query": "*",
+          "max_hits": 0,
+      }))
+      .send()
+      .await?;
+    if !resp.status().is_success() {
+      let status = resp.status();
+      let text = resp
+        .text()
+        .await
+        .unwrap_or_default();
+      return Err(anyhow!(
+        "quickwit search failed: {} {}",
+        status,
+        text
+      ));
+    }
+    let visible = resp
+      .json::<Value>()
+      .await?
+      .get("num_hits")
+      .and_then(Value::as_u64)
+      .unwrap_or(0);
+    if visible >= expected {
+      return Ok(visible);
+    }
+    if tokio::time::Instant::now()
+      >= deadline
+    {
+      return Err(anyhow!(
+        "quickwit index {} has {} of \
+         {} documents searchable \
+         after {}s",
+        cfg.index_id,
+        visible,
+        expected,
+        timeout.as_secs()
+      ));
+    }
+    tokio::time::sleep(
+      Duration::from_millis(250)
+    )
+    .await;
+  }
+}
+
 struct EmbeddingCache {
   max_entries: usize,
+  model:       String,
   order:       VecDeque<u64>,
   values:      HashMap<u64, Vec<f32>>
 }
 
+/// `embeddings.cache_path` contents:
+/// keys and vectors, oldest first.
+#[derive(Serialize, Deserialize)]
+struct CacheFile {
+  entries: Vec<(u64, Vec<f32>)>
+}
+
 impl EmbeddingCache {
-  fn new(max_entries: usize) -> Self {
+  fn new(
+    max_entries: usize,
+    model: &str
+  ) -> Self {
     Self {
       max_entries,
+      model: model.to_string(),
       order: VecDeque::new(),
       values: HashMap::new()
     }
   }
 
+  /// Reads a cache saved by an earlier
+  /// run, starting empty when the file
+  /// is missing or unreadable.
+  fn load(
+    path: &Path,
+    max_entries: usize,
+    model: &str
+  ) -> Self {
+    let mut cache =
+      Self::new(max_entries, model);
+    let Ok(raw) = fs::read(path) else {
+      return cache;
+    };
+    match serde_json::from_slice::<
+      CacheFile
+    >(&raw)
+    {
+      | Ok(file) => {
+        for (key, vec) in file.entries {
+          cache.insert_key(key, vec);
+        }
+        info!(
+          path = %path.display(),
+          entries = cache.values.len(),
+          "embedding cache loaded"
+        );
+      }
+      | Err(err) => {
+        warn!(path = %path.display(), error = %err, "ignoring unreadable embedding cache");
+      }
+    }
+    cache
+  }
+
+  fn save(
+    &self,
+    path: &Path
+  ) -> anyhow::Result<()> {
+    if let Some(parent) = path.parent()
+    {
+      fs::create_dir_all(parent)?;
+    }
+    let file = CacheFile {
+      entries: self
+        .order
+        .iter()
+        .filter_map(|key| {
+          self.values.get(key).map(
+            |vec| (*key, vec.clone())
+          )
+        })
+        .collect()
+    };
+    let tmp =
+      path.with_extension("tmp");
+    fs::write(
+      &tmp,
+      serde_json::to_vec(&file)?
+    )
+    .with_context(|| {
+      format!("write {}", tmp.display())
+    })?;
+    fs::rename(&tmp, path)?;
+    info!(
+      path = %path.display(),
+      entries = file.entries.len(),
+      "embedding cache saved"
+    );
+    Ok(())
+  }
+
   fn get(
     &self,
     text: &str
   ) -> Option<Vec<f32>> {
-    let key = hash_text(text);
+    let key =
+      hash_text(&self.model, text);
     self.values.get(&key).cloned()
   }
 
@@ -790,7 +4570,16 @@ impl EmbeddingCache {
     text: String,
     vec: Vec<f32>
   ) {
-    let key = hash_text(&text);
+    let key =
+      hash_text(&self.model, &text);
+    self.insert_key(key, vec);
+  }
+
+  fn insert_key(
+    &mut self,
+    key: u64,
+    vec: Vec<f32>
+  ) {
     if !self.values.contains_key(&key) {
       self.order.push_back(key);
     }
@@ -809,12 +4598,22 @@ impl EmbeddingCache {
   }
 }
 
-fn hash_text(text: &str) -> u64 {
-  use std::hash::{
-    Hash,
-    Hasher
-  };
-  let mut hasher = std::collections::hash_map::DefaultHasher::new();
-  text.hash(&mut hasher);
-  hasher.finish()
+/// Cache key for `text` under `model`,
+/// stable across builds so a saved
+/// cache stays valid.
+fn hash_text(
+  model: &str,
+  text: &str
+) -> u64 {
+  let mut hasher =
+    blake3::Hasher::new();
+  hasher.update(model.as_bytes());
+  hasher.update(&[0]);
+  hasher.update(text.as_bytes());
+  let hash = hasher.finalize();
+  let mut key = [0u8; 8];
+  key.copy_from_slice(
+    &hash.as_bytes()[..8]
+  );
+  u64::from_le_bytes(key)
 }
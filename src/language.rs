@@ -0,0 +1,287 @@
+/// A language `detect` can name, with
+/// the words that give it away in Latin
+/// script.
+struct Lang {
+  iso1:      &'static str,
+  iso3:      &'static str,
+  stopwords: &'static [&'static str]
+}
+
+/// Latin-script languages told apart
+/// by stopwords. No word appears in two
+/// lists.
+const LATIN: &[Lang] = &[
+  Lang {
+    iso1:      "en",
+    iso3:      "eng",
+    stopwords: &[
+      "the", "and", "of", "to", "is",
+      "that", "with", "for", "this",
+      "was", "are", "not", "but",
+      "have", "from", "which", "you",
+      "they", "be", "it"
+    ]
+  },
+  Lang {
+    iso1:      "fr",
+    iso3:      "fra",
+    stopwords: &[
+      "le", "les", "et", "des", "est",
+      "une", "dans", "qui", "pour",
+      "pas", "sur", "avec", "au", "du",
+      "ce", "sont", "mais", "nous",
+      "vous", "cette"
+    ]
+  },
+  Lang {
+    iso1:      "de",
+    iso3:      "deu",
+    stopwords: &[
+      "der", "die", "und", "ist",
+      "nicht", "ein", "eine", "mit",
+      "den", "zu", "von", "auf",
+      "sich", "dem", "auch", "ich",
+      "wir", "sie", "wird", "werden"
+    ]
+  },
+  Lang {
+    iso1:      "es",
+    iso3:      "spa",
+    stopwords: &[
+      "el", "los", "las", "y", "es",
+      "más", "pero", "como", "está",
+      "muy", "todo", "hay", "sus",
+      "este", "esta", "también",
+      "entre", "cuando", "sin",
+      "sobre"
+    ]
+  },
+  Lang {
+    iso1:      "it",
+    iso3:      "ita",
+    stopwords: &[
+      "di", "che", "è", "della",
+      "sono", "gli", "anche", "come",
+      "più", "nel", "alla", "questo",
+      "ma", "delle", "sul", "degli",
+      "molto", "quando", "essere",
+      "perché"
+    ]
+  },
+  Lang {
+    iso1:      "pt",
+    iso3:      "por",
+    stopwords: &[
+      "o", "os", "é", "um", "uma",
+      "não", "com", "do", "em", "dos",
+      "das", "são", "ao", "também",
+      "pelo", "pela", "isso", "ele",
+      "foi", "muito"
+    ]
+  },
+  Lang {
+    iso1:      "nl",
+    iso3:      "nld",
+    stopwords: &[
+      "het", "een", "van", "niet",
+      "dat", "op", "te", "met", "zijn",
+      "voor", "ook", "er", "maar",
+      "aan", "als", "bij", "wordt",
+      "heeft", "naar", "deze"
+    ]
+  }
+];
+
+/// Stopword hits the winner needs
+/// before a Latin-script guess counts.
+const MIN_HITS: usize = 3;
+
+/// Confidence `detect` results need to
+/// replace a document's language.
+pub const CONFIDENT: f32 = 0.6;
+
+/// A guessed language and how sure the
+/// guess is, from 0 to 1.
+#[derive(Debug, Clone, Copy)]
+pub struct Detection {
+  /// ISO 639-1 code, e.g. `fr`.
+  pub iso1:       &'static str,
+  /// ISO 639-2 code, e.g. `fra`, as
+  /// Calibre stores languages.
+  pub iso3:       &'static str,
+  pub confidence: f32
+}
+
+impl Detection {
+  /// Whether `code` (`en`, `eng`,
+  /// `en-US`) names this language.
+  pub fn matches(
+    &self,
+    code: &str
+  ) -> bool {
+    let code =
+      code.to_ascii_lowercase();
+    let base = code
+      .split(['-', '_'])
+      .next()
+      .unwrap_or_default();
+    base == self.iso1
+      || base == self.iso3
+  }
+
+  /// The code in the same form as
+  /// `like` (three letters or two),
+  /// two letters without one.
+  pub fn code_like(
+    &self,
+    like: Option<&str>
+  ) -> &'static str {
+    match like {
+      | Some(code) if code.len() == 3 => {
+        self.iso3
+      }
+      | _ => self.iso1
+    }
+  }
+}
+
+/// Writing system of a letter, for the
+/// languages named by script alone.
+fn script_lang(
+  ch: char
+) -> Option<(&'static str, &'static str)>
+{
+  Some(match ch {
+    | '\u{3040}'..='\u{30ff}' => {
+      ("ja", "jpn")
+    }
+    | '\u{4e00}'..='\u{9fff}'
+    | '\u{3400}'..='\u{4dbf}' => {
+      ("zh", "zho")
+    }
+    | '\u{ac00}'..='\u{d7af}'
+    | '\u{1100}'..='\u{11ff}' => {
+      ("ko", "kor")
+    }
+    | '\u{0400}'..='\u{04ff}' => {
+      ("ru", "rus")
+    }
+    | '\u{0370}'..='\u{03ff}' => {
+      ("el", "ell")
+    }
+    | '\u{0600}'..='\u{06ff}' => {
+      ("ar", "ara")
+    }
+    | '\u{0590}'..='\u{05ff}' => {
+      ("he", "heb")
+    }
+    | '\u{0e00}'..='\u{0e7f}' => {
+      ("th", "tha")
+    }
+    | '\u{0900}'..='\u{097f}' => {
+      ("hi", "hin")
+    }
+    | _ => return None
+  })
+}
+
+/// Guesses the language of `text`.
+/// Non-Latin scripts are named by their
+/// share of the letters (any kana makes
+/// Han text Japanese); Latin text by
+/// which stopword list it hits most,
+/// with confidence the winner's lead
+/// over the runner-up.
+pub fn detect(
+  text: &str
+) -> Option<Detection> {
+  let mut letters = 0usize;
+  let mut latin = 0usize;
+  let mut scripts: Vec<(
+    (&'static str, &'static str),
+    usize
+  )> = Vec::new();
+  for ch in text
+    .chars()
+    .filter(|c| c.is_alphabetic())
+  {
+    letters += 1;
+    match script_lang(ch) {
+      | Some(lang) => {
+        match scripts
+          .iter_mut()
+          .find(|(l, _)| *l == lang)
+        {
+          | Some((_, n)) => *n += 1,
+          | None => {
+            scripts.push((lang, 1))
+          }
+        }
+      }
+      | None => latin += 1
+    }
+  }
+  if letters == 0 {
+    return None;
+  }
+  let kana = scripts
+    .iter()
+    .find(|(l, _)| l.0 == "ja")
+    .map_or(0, |(_, n)| *n);
+  if let Some(&((iso1, iso3), n)) =
+    scripts
+      .iter()
+      .max_by_key(|(_, n)| *n)
+      .filter(|(_, n)| *n > latin)
+  {
+    let (iso1, iso3, n) =
+      if iso1 == "zh" && kana > 0 {
+        ("ja", "jpn", n + kana)
+      } else {
+        (iso1, iso3, n)
+      };
+    return Some(Detection {
+      iso1,
+      iso3,
+      confidence: n as f32
+        / letters as f32
+    });
+  }
+
+  let mut hits =
+    vec![0usize; LATIN.len()];
+  for word in text
+    .split(|c: char| !c.is_alphabetic())
+    .filter(|w| !w.is_empty())
+  {
+    let word = word.to_lowercase();
+    if let Some(idx) =
+      LATIN.iter().position(|lang| {
+        lang
+          .stopwords
+          .contains(&word.as_str())
+      })
+    {
+      hits[idx] += 1;
+    }
+  }
+  let mut ranked = hits
+    .iter()
+    .copied()
+    .enumerate()
+    .collect::<Vec<_>>();
+  ranked.sort_by_key(|&(_, n)| {
+    std::cmp::Reverse(n)
+  });
+  let (best, top) = ranked[0];
+  if top < MIN_HITS {
+    return None;
+  }
+  let second = ranked[1].1;
+  Some(Detection {
+    iso1:       LATIN[best].iso1,
+    iso3:       LATIN[best].iso3,
+    confidence: (top - second) as f32
+      / top as f32
+  })
+}
@@ -1,10 +1,20 @@
+pub mod bench;
 pub mod calibre_metadata;
+pub mod calibre_server;
+pub mod catalog;
 pub mod chunk;
+pub mod classify;
 pub mod config;
 pub mod dedup;
 pub mod dup_stats;
 pub mod dups;
+pub mod export;
 pub mod extract;
 pub mod insert;
+pub mod language;
 pub mod logging;
+pub mod parquet;
+pub mod pipeline;
+pub mod sections;
+pub mod tokenizer;
 pub mod util;
@@ -1,6 +1,10 @@
 use std::fmt;
 
 use chrono::Utc;
+use tracing::span::{
+  Attributes,
+  Id
+};
 use tracing::{
   Event,
   Subscriber
@@ -8,9 +12,15 @@ use tracing::{
 use tracing_subscriber::fmt::format::Writer;
 use tracing_subscriber::fmt::{
   FmtContext,
-  FormatEvent
+  FormatEvent,
+  FormattedFields
+};
+use tracing_subscriber::layer::{
+  Context,
+  Layer,
+  SubscriberExt
 };
-use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::registry::LookupSpan;
 use tracing_subscriber::util::SubscriberInitExt;
 use tracing_subscriber::{
   EnvFilter,
@@ -34,10 +44,93 @@ pub fn init(config: &LoggingConfig) {
       .event_format(ColorPrefixFormat);
   Registry::default()
     .with(filter)
+    .with(SpanKeysLayer)
     .with(fmt_layer)
     .init();
 }
 
+/// The span fields an event's color
+/// prefix is built from: the file
+/// `path` and the batch `first_id`.
+#[derive(Default)]
+struct SpanKeys {
+  path:     Option<String>,
+  first_id: Option<String>
+}
+
+impl tracing::field::Visit
+  for SpanKeys
+{
+  fn record_str(
+    &mut self,
+    field: &tracing::field::Field,
+    value: &str
+  ) {
+    self.set(field.name(), value);
+  }
+
+  fn record_debug(
+    &mut self,
+    field: &tracing::field::Field,
+    value: &dyn fmt::Debug
+  ) {
+    self.set(
+      field.name(),
+      format!("{value:?}")
+        .trim_matches('"')
+    );
+  }
+}
+
+impl SpanKeys {
+  fn set(
+    &mut self,
+    name: &str,
+    value: &str
+  ) {
+    match name {
+      | "path" => {
+        self.path =
+          Some(value.to_string())
+      }
+      | "first_id" => {
+        self.first_id =
+          Some(value.to_string())
+      }
+      | _ => {}
+    }
+  }
+}
+
+/// Keeps each new span's `SpanKeys` in
+/// its extensions for
+/// `ColorPrefixFormat`.
+struct SpanKeysLayer;
+
+impl<S> Layer<S> for SpanKeysLayer
+where
+  S:
+    Subscriber + for<'a> LookupSpan<'a>
+{
+  fn on_new_span(
+    &self,
+    attrs: &Attributes<'_>,
+    id: &Id,
+    ctx: Context<'_, S>
+  ) {
+    let mut keys = SpanKeys::default();
+    attrs.record(&mut keys);
+    if (keys.path.is_some()
+      || keys.first_id.is_some())
+      && let Some(span) = ctx.span(id)
+    {
+      span
+        .extensions_mut()
+        .insert(keys);
+    }
+  }
+}
+
 struct ColorPrefixFormat;
 
 impl<S, N> FormatEvent<S, N> for ColorPrefixFormat
@@ -47,7 +140,7 @@ where
 {
     fn format_event(
         &self,
-        _ctx: &FmtContext<'_, S, N>,
+        ctx: &FmtContext<'_, S, N>,
         mut writer: Writer<'_>,
         event: &Event<'_>,
     ) -> fmt::Result {
@@ -60,7 +153,20 @@ where
         let mut visitor = FieldVisitor::default();
         event.record(&mut visitor);
 
-        if let Some(prefix) = visitor.color_prefix.take() {
+        // An explicit prefix wins; otherwise it comes from the
+        // innermost file and batch spans.
+        let prefix = visitor.color_prefix.take().or_else(|| {
+            let mut keys = SpanKeys::default();
+            for span in ctx.event_scope()?.from_root() {
+                if let Some(found) = span.extensions().get::<SpanKeys>() {
+                    keys.path = found.path.clone().or(keys.path);
+                    keys.first_id = found.first_id.clone().or(keys.first_id);
+                }
+            }
+            let op = visitor.log_op.as_deref().and_then(LogOp::from_name);
+            keys.path.map(|path| color_prefix(&path, keys.first_id.as_deref(), op))
+        });
+        if let Some(prefix) = prefix {
             write!(writer, "{prefix}")?;
         }
         if let Some(message) = visitor.message.take() {
@@ -70,6 +176,17 @@ where
         for (k, v) in visitor.fields {
             write!(writer, " {k}={v}")?;
         }
+
+        if let Some(scope) = ctx.event_scope() {
+            for span in scope.from_root() {
+                let exts = span.extensions();
+                if let Some(fields) = exts.get::<FormattedFields<N>>()
+                    && !fields.is_empty()
+                {
+                    write!(writer, " {fields}")?;
+                }
+            }
+        }
         writeln!(writer)
     }
 }
@@ -78,6 +195,9 @@ where
 struct FieldVisitor {
   color_prefix: Option<String>,
   message:      Option<String>,
+  /// `LogOp::name` of the store or
+  /// embedder the event is about.
+  log_op:       Option<String>,
   fields:       Vec<(String, String)>
 }
 
@@ -98,6 +218,10 @@ impl tracing::field::Visit
         self.message =
           Some(value.to_string())
       }
+      | "log_op" => {
+        self.log_op =
+          Some(value.to_string())
+      }
       | name => {
         self.fields.push((
           name.to_string(),
@@ -127,6 +251,13 @@ impl tracing::field::Visit
             .to_string()
         )
       }
+      | "log_op" => {
+        self.log_op = Some(
+          format!("{value:?}")
+            .trim_matches('"')
+            .to_string()
+        )
+      }
       | name => {
         self.fields.push((
           name.to_string(),
@@ -141,7 +272,39 @@ impl tracing::field::Visit
 pub enum LogOp {
   Ollama,
   Qdrant,
-  Quickwit
+  Pgvector,
+  Quickwit,
+  Elasticsearch
+}
+
+impl LogOp {
+  /// The `log_op` event field value
+  /// that picks this op's color.
+  pub fn name(self) -> &'static str {
+    match self {
+      | LogOp::Ollama => "ollama",
+      | LogOp::Qdrant => "qdrant",
+      | LogOp::Pgvector => "pgvector",
+      | LogOp::Quickwit => "quickwit",
+      | LogOp::Elasticsearch => {
+        "elasticsearch"
+      }
+    }
+  }
+
+  fn from_name(
+    name: &str
+  ) -> Option<Self> {
+    [
+      LogOp::Ollama,
+      LogOp::Qdrant,
+      LogOp::Pgvector,
+      LogOp::Quickwit,
+      LogOp::Elasticsearch
+    ]
+    .into_iter()
+    .find(|op| op.name() == name)
+  }
 }
 
 pub fn color_prefix(
@@ -173,7 +336,9 @@ fn op_color(op: LogOp) -> u8 {
   match op {
     | LogOp::Ollama => 39,
     | LogOp::Qdrant => 82,
-    | LogOp::Quickwit => 220
+    | LogOp::Pgvector => 75,
+    | LogOp::Quickwit => 220,
+    | LogOp::Elasticsearch => 208
   }
 }
 
@@ -1,19 +1,29 @@
 use std::path::PathBuf;
 
 use chunkr::{
+  bench,
   chunk,
+  classify,
   config,
   dedup,
   dup_stats,
   dups,
+  export,
   extract,
   insert,
-  logging
+  logging,
+  pipeline
 };
 use clap::{
   Parser,
   Subcommand
 };
+use tracing::{
+  Instrument,
+  info,
+  info_span,
+  warn
+};
 
 #[derive(Debug, Parser)]
 #[command(
@@ -29,45 +39,94 @@ struct Cli {
     default_value = "config.toml"
   )]
   config:  PathBuf,
+  /// Identifier for this invocation
+  /// (defaults to a random UUID)
+  #[arg(long, global = true)]
+  run_id:  Option<String>,
   #[command(subcommand)]
   command: Commands
 }
 
 #[derive(Debug, Subcommand)]
 enum Commands {
-  Extract,
-  Chunk,
-  Insert,
+  Extract(extract::ExtractArgs),
+  Chunk(chunk::ChunkArgs),
+  Insert(insert::InsertArgs),
+  /// Extract, chunk, and insert in one
+  /// run
+  #[command(alias = "all")]
+  Pipeline(insert::InsertArgs),
+  /// Classify every PDF as text, low
+  /// quality, or scan without
+  /// extracting
+  ClassifyPdfs(classify::ClassifyArgs),
   Dups(dups::DupsArgs),
   DupStats(dup_stats::DupStatsArgs),
-  Dedup(dedup::DedupArgs)
+  Dedup(dedup::DedupArgs),
+  Bench(bench::BenchArgs),
+  /// Write the chunks as a Hugging Face
+  /// dataset of Parquet shards
+  Export(export::ExportArgs)
 }
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
   let cli = Cli::parse();
-  let config =
+  let mut config =
     config::load(&cli.config)?;
+  if let Some(run_id) = cli.run_id {
+    config.run_id = run_id;
+  }
   logging::init(&config.logging);
 
-  match cli.command {
-    | Commands::Extract => {
-      extract::run(&config)?
+  let span = info_span!(
+    "run",
+    run_id = %config.run_id
+  );
+  run(&config, cli.command)
+    .instrument(span)
+    .await
+}
+
+async fn run(
+  config: &config::Config,
+  command: Commands
+) -> anyhow::Result<()> {
+  info!("run started");
+  for note in &config.deprecations {
+    warn!("{note}");
+  }
+  match command {
+    | Commands::Extract(args) => {
+      extract::run(config, &args)?
+    }
+    | Commands::Chunk(args) => {
+      chunk::run(config, &args)?
+    }
+    | Commands::Insert(args) => {
+      insert::run(config, &args).await?
     }
-    | Commands::Chunk => {
-      chunk::run(&config)?
+    | Commands::Pipeline(args) => {
+      pipeline::run(config, &args)
+        .await?
     }
-    | Commands::Insert => {
-      insert::run(&config).await?
+    | Commands::ClassifyPdfs(args) => {
+      classify::run(config, &args)?
     }
     | Commands::Dups(args) => {
-      dups::run(&config, &args)?
+      dups::run(config, &args)?
     }
     | Commands::DupStats(args) => {
-      dup_stats::run(&config, &args)?
+      dup_stats::run(config, &args)?
     }
     | Commands::Dedup(args) => {
-      dedup::run(&config, &args)?
+      dedup::run(config, &args)?
+    }
+    | Commands::Bench(args) => {
+      bench::run(config, &args).await?
+    }
+    | Commands::Export(args) => {
+      export::run(config, &args)?
     }
   }
 
@@ -0,0 +1,269 @@
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+use ::parquet::arrow::ArrowWriter;
+use ::parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use ::parquet::basic::Compression;
+use ::parquet::file::properties::WriterProperties;
+use anyhow::{
+  Context,
+  anyhow
+};
+use arrow_array::builder::{
+  ListBuilder,
+  StringBuilder
+};
+use arrow_array::cast::AsArray;
+use arrow_array::{
+  ArrayRef,
+  BooleanArray,
+  Float64Array,
+  Int64Array,
+  RecordBatch,
+  StringArray
+};
+use arrow_schema::{
+  Field,
+  Schema
+};
+
+/// One column's values, one entry per
+/// row.
+#[derive(Debug, Clone)]
+pub enum Values {
+  Utf8(Vec<Option<String>>),
+  Int64(Vec<Option<i64>>),
+  Double(Vec<Option<f64>>),
+  Boolean(Vec<Option<bool>>),
+  /// A list of strings per row,
+  /// written with the standard
+  /// three-level `LIST` layout.
+  Utf8List(Vec<Option<Vec<String>>>)
+}
+
+impl Values {
+  fn len(&self) -> usize {
+    match self {
+      | Values::Utf8(v) => v.len(),
+      | Values::Int64(v) => v.len(),
+      | Values::Double(v) => v.len(),
+      | Values::Boolean(v) => v.len(),
+      | Values::Utf8List(v) => v.len()
+    }
+  }
+
+  fn has_missing(&self) -> bool {
+    match self {
+      | Values::Utf8(v) => {
+        v.iter().any(Option::is_none)
+      }
+      | Values::Int64(v) => {
+        v.iter().any(Option::is_none)
+      }
+      | Values::Double(v) => {
+        v.iter().any(Option::is_none)
+      }
+      | Values::Boolean(v) => {
+        v.iter().any(Option::is_none)
+      }
+      | Values::Utf8List(_) => false
+    }
+  }
+
+  fn to_array(&self) -> ArrayRef {
+    match self {
+      | Values::Utf8(v) => {
+        Arc::new(StringArray::from(
+          v.clone()
+        ))
+      }
+      | Values::Int64(v) => {
+        Arc::new(Int64Array::from(
+          v.clone()
+        ))
+      }
+      | Values::Double(v) => {
+        Arc::new(Float64Array::from(
+          v.clone()
+        ))
+      }
+      | Values::Boolean(v) => {
+        Arc::new(BooleanArray::from(
+          v.clone()
+        ))
+      }
+      | Values::Utf8List(v) => {
+        let mut builder =
+          ListBuilder::new(
+            StringBuilder::new()
+          );
+        for row in v {
+          match row {
+            | Some(items) => {
+              for item in items {
+                builder
+                  .values()
+                  .append_value(item);
+              }
+              builder.append(true);
+            }
+            | None => {
+              builder.append(false)
+            }
+          }
+        }
+        Arc::new(builder.finish())
+      }
+    }
+  }
+}
+
+#[derive(Debug, Clone)]
+pub struct Column {
+  pub name:     String,
+  /// Rejects missing values instead of
+  /// writing the column as optional.
+  /// Ignored for lists.
+  pub required: bool,
+  pub values:   Values
+}
+
+/// Writes `columns` as one
+/// Snappy-compressed row group through
+/// the `parquet` crate's Arrow writer.
+/// Every column must have the same
+/// number of rows.
+pub fn write_file(
+  path: &Path,
+  columns: &[Column]
+) -> anyhow::Result<()> {
+  let rows = columns
+    .first()
+    .map(|c| c.values.len())
+    .unwrap_or(0);
+  if let Some(bad) = columns
+    .iter()
+    .find(|c| c.values.len() != rows)
+  {
+    return Err(anyhow!(
+      "parquet column {} has {} rows, \
+       expected {}",
+      bad.name,
+      bad.values.len(),
+      rows
+    ));
+  }
+
+  let mut fields = Vec::new();
+  let mut arrays = Vec::new();
+  for column in columns {
+    let required = column.required
+      && !matches!(
+        column.values,
+        Values::Utf8List(_)
+      );
+    if required
+      && column.values.has_missing()
+    {
+      return Err(anyhow!(
+        "parquet column {} is \
+         required but has missing \
+         values",
+        column.name
+      ));
+    }
+    let array =
+      column.values.to_array();
+    fields.push(Field::new(
+      &column.name,
+      array.data_type().clone(),
+      !required
+    ));
+    arrays.push(array);
+  }
+  let schema =
+    Arc::new(Schema::new(fields));
+  let batch = RecordBatch::try_new(
+    schema.clone(),
+    arrays
+  )?;
+
+  if let Some(parent) = path.parent() {
+    fs::create_dir_all(parent)?;
+  }
+  let tmp =
+    path.with_extension("parquet.tmp");
+  let file = fs::File::create(&tmp)
+    .with_context(|| {
+      format!("write {}", tmp.display())
+    })?;
+  let props =
+    WriterProperties::builder()
+      .set_compression(
+        Compression::SNAPPY
+      )
+      .build();
+  let mut writer =
+    ArrowWriter::try_new(
+      file,
+      schema,
+      Some(props)
+    )?;
+  writer.write(&batch)?;
+  writer.close()?;
+  fs::rename(&tmp, path)?;
+  Ok(())
+}
+
+/// Reads the string columns `names`
+/// from a file `write_file` wrote, one
+/// `Vec` per column in `names` order.
+/// Missing values read as empty
+/// strings.
+pub fn read_strings(
+  path: &Path,
+  names: &[&str]
+) -> anyhow::Result<Vec<Vec<String>>> {
+  let file = fs::File::open(path)
+    .with_context(|| {
+      format!("read {}", path.display())
+    })?;
+  let reader =
+    ParquetRecordBatchReaderBuilder::try_new(
+      file
+    )?
+    .build()?;
+  let mut columns =
+    vec![Vec::new(); names.len()];
+  for batch in reader {
+    let batch = batch?;
+    for (name, out) in
+      names.iter().zip(&mut columns)
+    {
+      let array = batch
+        .column_by_name(name)
+        .ok_or_else(|| {
+          anyhow!(
+            "{} has no column {name}",
+            path.display()
+          )
+        })?
+        .as_string_opt::<i32>()
+        .ok_or_else(|| {
+          anyhow!(
+            "{} column {name} is not \
+             a string column",
+            path.display()
+          )
+        })?;
+      out.extend(array.iter().map(
+        |v| {
+          v.unwrap_or_default()
+            .to_string()
+        }
+      ));
+    }
+  }
+  Ok(columns)
+}
@@ -0,0 +1,109 @@
+use serde_json::json;
+
+use crate::config::Config;
+use crate::{
+  chunk,
+  extract,
+  insert
+};
+
+/// Runs extract, chunk, and insert back
+/// to back. With `pipeline.in_memory`
+/// the chunker works on the text each
+/// source is extracted to instead of
+/// re-reading `extract_root`, through
+/// the same per-file steps as `extract`
+/// and `chunk`.
+pub async fn run(
+  config: &Config,
+  args: &insert::InsertArgs
+) -> anyhow::Result<()> {
+  if config.pipeline.in_memory {
+    extract_and_chunk(config)?;
+  } else {
+    extract::run(
+      config,
+      &extract::ExtractArgs::default()
+    )?;
+    chunk::run(
+      config,
+      &chunk::ChunkArgs::default()
+    )?;
+  }
+  insert::run(config, args).await
+}
+
+fn extract_and_chunk(
+  config: &Config
+) -> anyhow::Result<()> {
+  chunk::TIMERS.reset();
+  let chunker = chunk::ChunkRun::new(
+    config, false
+  )?;
+  let on_source =
+    |handoff: extract::Handoff<'_>| {
+      chunker.pool().install(|| {
+        hand_off(
+          &chunker, handoff, config
+        )
+      })
+    };
+  extract::run_with(
+    config,
+    &extract::ExtractArgs::default(),
+    Some(&extract::InMemory {
+      write_output: config
+        .pipeline
+        .write_extract_output,
+      on_source:    &on_source
+    })
+  )?;
+  chunker.finish(0)
+}
+
+fn hand_off(
+  chunker: &chunk::ChunkRun<'_>,
+  handoff: extract::Handoff<'_>,
+  config: &Config
+) -> anyhow::Result<()> {
+  match handoff {
+    | extract::Handoff::Extracted(
+      doc
+    ) => {
+      // Mirror what `chunk` sees: no
+      // sidecar means no file metadata.
+      let file_meta = if config
+        .extract
+        .write_metadata
+      {
+        doc.metadata_json()
+      } else {
+        json!({})
+      };
+      for out in &doc.outputs {
+        chunker.chunk_text(
+          &out.text, &file_meta,
+          &out.path
+        )?;
+      }
+    }
+    // Skipped by the extract state or
+    // `skip_existing`: chunk what is on
+    // disk, as a plain `chunk` pass
+    // would.
+    | extract::Handoff::Existing(
+      outputs
+    ) => {
+      for path in outputs {
+        if path.is_file()
+          && chunk::is_chunk_input(
+            path, config
+          )
+        {
+          chunker.chunk_path(path)?;
+        }
+      }
+    }
+  }
+  Ok(())
+}
@@ -0,0 +1,170 @@
+use std::path::{
+  Path,
+  PathBuf
+};
+
+use serde::{
+  Deserialize,
+  Serialize
+};
+
+/// Extension of extracted files written
+/// with `extract.output_format =
+/// "sectioned_json"`.
+pub const SECTIONED_EXTENSION: &str =
+  "sections.json";
+
+/// A document split at its markdown
+/// headings.
+#[derive(
+  Debug,
+  Clone,
+  Default,
+  Serialize,
+  Deserialize,
+)]
+pub struct SectionedDoc {
+  pub sections: Vec<Section>
+}
+
+/// One heading and the text up to the
+/// next heading. Text before the first
+/// heading has no heading and level 0.
+#[derive(
+  Debug, Clone, Serialize, Deserialize,
+)]
+pub struct Section {
+  pub heading: Option<String>,
+  pub level:   u8,
+  pub text:    String
+}
+
+/// Splits markdown at ATX headings
+/// (`#` to `######`), ignoring `#`
+/// lines inside fenced code blocks.
+/// Pandoc attribute blocks (`{#id}`)
+/// are dropped from headings.
+pub fn parse_markdown(
+  raw: &str
+) -> SectionedDoc {
+  let mut sections = Vec::new();
+  let mut current = Section {
+    heading: None,
+    level:   0,
+    text:    String::new()
+  };
+  let mut in_fence = false;
+  for line in raw.lines() {
+    let trimmed = line.trim_start();
+    if trimmed.starts_with("```")
+      || trimmed.starts_with("~~~")
+    {
+      in_fence = !in_fence;
+    }
+    let heading = if in_fence {
+      None
+    } else {
+      parse_heading(line)
+    };
+    match heading {
+      | Some((level, heading)) => {
+        let done = std::mem::replace(
+          &mut current,
+          Section {
+            heading: Some(heading),
+            level,
+            text: String::new()
+          }
+        );
+        if done.heading.is_some()
+          || !done
+            .text
+            .trim()
+            .is_empty()
+        {
+          sections.push(done);
+        }
+      }
+      | None => {
+        current.text.push_str(line);
+        current.text.push('\n');
+      }
+    }
+  }
+  if current.heading.is_some()
+    || !current.text.trim().is_empty()
+  {
+    sections.push(current);
+  }
+  SectionedDoc {
+    sections
+  }
+}
+
+fn parse_heading(
+  line: &str
+) -> Option<(u8, String)> {
+  let level = line
+    .bytes()
+    .take_while(|b| *b == b'#')
+    .count();
+  if level == 0 || level > 6 {
+    return None;
+  }
+  let rest = &line[level..];
+  if !rest.is_empty()
+    && !rest.starts_with(' ')
+  {
+    return None;
+  }
+  let mut heading =
+    rest.trim().trim_end_matches('#');
+  if heading.ends_with('}')
+    && let Some(idx) =
+      heading.rfind('{')
+  {
+    heading = &heading[..idx];
+  }
+  Some((
+    level as u8,
+    heading.trim().to_string()
+  ))
+}
+
+/// Whether `path` holds a sectioned
+/// JSON document.
+pub fn is_sectioned(
+  path: &Path
+) -> bool {
+  path
+    .file_name()
+    .and_then(|name| name.to_str())
+    .is_some_and(|name| {
+      name.ends_with(&format!(
+        ".{SECTIONED_EXTENSION}"
+      ))
+    })
+}
+
+/// Where the sectioned form of the text
+/// output `path` is written.
+pub fn sectioned_path(
+  path: &Path
+) -> PathBuf {
+  path
+    .with_extension(SECTIONED_EXTENSION)
+}
+
+/// The metadata sidecar next to an
+/// extracted file, shared by its text
+/// and sectioned forms.
+pub fn sidecar_path(
+  path: &Path
+) -> PathBuf {
+  let base = if is_sectioned(path) {
+    path.with_extension("")
+  } else {
+    path.to_path_buf()
+  };
+  base.with_extension("json")
+}
@@ -0,0 +1,87 @@
+use crate::chunk::is_dense_char;
+use crate::config::SizeUnit;
+
+/// Measures chunk text in the unit
+/// `target_chunk_chars` and
+/// `max_chunk_chars` are read in.
+pub trait Tokenizer: Sync {
+  fn count(
+    &self,
+    text: &str
+  ) -> usize;
+}
+
+/// `size_unit = "chars"`: UTF-8 length,
+/// the measure chunks always used.
+pub struct Utf8Len;
+
+impl Tokenizer for Utf8Len {
+  fn count(
+    &self,
+    text: &str
+  ) -> usize {
+    text.len()
+  }
+}
+
+/// Characters, the unit of
+/// `chunk_overlap_chars`.
+pub struct CharCount;
+
+impl Tokenizer for CharCount {
+  fn count(
+    &self,
+    text: &str
+  ) -> usize {
+    text.chars().count()
+  }
+}
+
+/// `size_unit = "tokens"`: a rough
+/// stand-in for a BPE vocabulary. A run
+/// of letters and digits costs a token
+/// per four characters; punctuation and
+/// each ideograph, kana, or Thai
+/// character cost one; whitespace is
+/// free.
+pub struct ApproxBpe;
+
+/// Characters a BPE merge typically
+/// covers in English-like text.
+const CHARS_PER_TOKEN: usize = 4;
+
+impl Tokenizer for ApproxBpe {
+  fn count(
+    &self,
+    text: &str
+  ) -> usize {
+    let mut tokens = 0usize;
+    let mut run = 0usize;
+    for ch in text.chars() {
+      if ch.is_alphanumeric()
+        && !is_dense_char(ch)
+      {
+        run += 1;
+        continue;
+      }
+      tokens +=
+        run.div_ceil(CHARS_PER_TOKEN);
+      run = 0;
+      if !ch.is_whitespace() {
+        tokens += 1;
+      }
+    }
+    tokens
+      + run.div_ceil(CHARS_PER_TOKEN)
+  }
+}
+
+/// The tokenizer for `chunk.size_unit`.
+pub fn for_unit(
+  unit: SizeUnit
+) -> &'static dyn Tokenizer {
+  match unit {
+    | SizeUnit::Chars => &Utf8Len,
+    | SizeUnit::Tokens => &ApproxBpe
+  }
+}
@@ -1,7 +1,44 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::{
+  self,
+  File
+};
+use std::io::{
+  self,
+  Read,
+  Write
+};
 use std::path::{
   Path,
   PathBuf
 };
+use std::process::{
+  Child,
+  Command,
+  ExitStatus,
+  Output,
+  Stdio
+};
+use std::sync::Mutex;
+use std::sync::atomic::{
+  AtomicBool,
+  Ordering
+};
+use std::time::{
+  Duration,
+  Instant
+};
+
+use anyhow::{
+  Context,
+  anyhow
+};
+use serde::Serialize;
+use tracing::warn;
+use walkdir::WalkDir;
+
+use crate::config::FailureCounting;
 
 pub fn slugify(input: &str) -> String {
   let mut out =
@@ -42,3 +79,551 @@ pub fn replace_extension(
   p.set_extension(ext);
   p
 }
+
+/// Counts `(ASCII letter, total)`
+/// characters in `text`, where total
+/// excludes control characters.
+pub fn alpha_counts(
+  text: &str
+) -> (usize, usize) {
+  let mut alpha = 0usize;
+  let mut total = 0usize;
+  for ch in text.chars() {
+    if ch.is_ascii_alphabetic() {
+      alpha += 1;
+    }
+    if !ch.is_control() {
+      total += 1;
+    }
+  }
+  (alpha, total)
+}
+
+/// Ratio of letters in any script
+/// (`char::is_alphabetic`, so CJK,
+/// Cyrillic, and Greek count) to
+/// non-control characters (0.0 for
+/// empty text).
+pub fn alpha_ratio(text: &str) -> f32 {
+  let mut alpha = 0usize;
+  let mut total = 0usize;
+  for ch in text.chars() {
+    if ch.is_alphabetic() {
+      alpha += 1;
+    }
+    if !ch.is_control() {
+      total += 1;
+    }
+  }
+  if total == 0 {
+    0.0
+  } else {
+    alpha as f32 / total as f32
+  }
+}
+
+/// Appends JSON lines to a file under
+/// `state_dir`, opening it on the
+/// first entry.
+pub struct JsonlAppender {
+  path: PathBuf,
+  file: Mutex<Option<fs::File>>
+}
+
+impl JsonlAppender {
+  pub fn new(path: PathBuf) -> Self {
+    Self {
+      path,
+      file: Mutex::new(None)
+    }
+  }
+
+  pub fn append(
+    &self,
+    entry: &impl Serialize
+  ) -> anyhow::Result<()> {
+    let mut line =
+      serde_json::to_string(entry)?;
+    line.push('\n');
+    let mut file =
+      self.file.lock().unwrap();
+    if file.is_none() {
+      if let Some(parent) =
+        self.path.parent()
+      {
+        fs::create_dir_all(parent)?;
+      }
+      *file = Some(
+        fs::OpenOptions::new()
+          .create(true)
+          .append(true)
+          .open(&self.path)
+          .with_context(|| {
+            format!(
+              "open {}",
+              self.path.display()
+            )
+          })?
+      );
+    }
+    file
+      .as_mut()
+      .expect("opened above")
+      .write_all(line.as_bytes())?;
+    Ok(())
+  }
+}
+
+/// Hex blake3 digest of the file
+/// contents at `path`.
+pub fn blake3_file(
+  path: &Path
+) -> anyhow::Result<String> {
+  let mut file = File::open(path)?;
+  let mut hasher =
+    blake3::Hasher::new();
+  let mut buf = vec![0u8; 1024 * 1024];
+  loop {
+    let n = file.read(&mut buf)?;
+    if n == 0 {
+      break;
+    }
+    hasher.update(&buf[..n]);
+  }
+  Ok(
+    hasher
+      .finalize()
+      .to_hex()
+      .to_string()
+  )
+}
+
+/// Regular files found under a root,
+/// plus how many entries the walk could
+/// not read.
+#[derive(Debug, Default)]
+pub struct WalkedFiles {
+  pub files:  Vec<PathBuf>,
+  pub errors: usize
+}
+
+/// Walks `root` for regular files,
+/// counting entries that fail
+/// (permission denied, symlink loops)
+/// instead of dropping them silently.
+/// `log_errors` warns for each one.
+pub fn walk_files(
+  root: &Path,
+  log_errors: bool
+) -> WalkedFiles {
+  let mut walked =
+    WalkedFiles::default();
+  for entry in WalkDir::new(root) {
+    match entry {
+      | Ok(entry) => {
+        if entry.file_type().is_file() {
+          walked
+            .files
+            .push(entry.into_path());
+        }
+      }
+      | Err(err) => {
+        if log_errors {
+          warn!(
+            root = %root.display(),
+            path = ?err.path(),
+            error = %err,
+            "walk error, skipping entry"
+          );
+        }
+        walked.errors += 1;
+      }
+    }
+  }
+  walked
+}
+
+/// Coarse wall-clock totals per named
+/// phase of a stage, shared across
+/// workers. Concurrent phases add up,
+/// so totals can exceed the run's
+/// elapsed time.
+#[derive(Debug, Default)]
+pub struct PhaseTimers {
+  totals: Mutex<
+    BTreeMap<&'static str, Duration>
+  >
+}
+
+impl PhaseTimers {
+  pub const fn new() -> Self {
+    Self {
+      totals: Mutex::new(
+        BTreeMap::new()
+      )
+    }
+  }
+
+  pub fn add(
+    &self,
+    phase: &'static str,
+    elapsed: Duration
+  ) {
+    if let Ok(mut totals) =
+      self.totals.lock()
+    {
+      *totals
+        .entry(phase)
+        .or_default() += elapsed;
+    }
+  }
+
+  /// Runs `f`, charging its time to
+  /// `phase`.
+  pub fn time<T>(
+    &self,
+    phase: &'static str,
+    f: impl FnOnce() -> T
+  ) -> T {
+    let started = Instant::now();
+    let out = f();
+    self.add(phase, started.elapsed());
+    out
+  }
+
+  pub fn reset(&self) {
+    if let Ok(mut totals) =
+      self.totals.lock()
+    {
+      totals.clear();
+    }
+  }
+}
+
+/// `phase=secs (share%)` pairs, largest
+/// first.
+impl fmt::Display for PhaseTimers {
+  fn fmt(
+    &self,
+    f: &mut fmt::Formatter<'_>
+  ) -> fmt::Result {
+    let Ok(totals) = self.totals.lock()
+    else {
+      return Ok(());
+    };
+    let sum: f64 = totals
+      .values()
+      .map(Duration::as_secs_f64)
+      .sum();
+    let mut phases: Vec<_> =
+      totals.iter().collect();
+    phases.sort_by(|a, b| b.1.cmp(a.1));
+    for (idx, (phase, elapsed)) in
+      phases.into_iter().enumerate()
+    {
+      let secs = elapsed.as_secs_f64();
+      let share = if sum > 0.0 {
+        secs / sum * 100.0
+      } else {
+        0.0
+      };
+      if idx > 0 {
+        f.write_str(" ")?;
+      }
+      write!(
+        f,
+        "{phase}={secs:.2}s \
+         ({share:.0}%)"
+      )?;
+    }
+    Ok(())
+  }
+}
+
+/// Trips once `threshold` files have
+/// failed, consecutively or in total,
+/// so a systemically broken run stops
+/// early. A threshold of 0 never trips.
+#[derive(Debug)]
+pub struct FailureGate {
+  threshold: usize,
+  counting:  FailureCounting,
+  /// `(consecutive, total)` failures.
+  counts:    Mutex<(usize, usize)>,
+  tripped:   AtomicBool
+}
+
+impl FailureGate {
+  pub fn new(
+    threshold: usize,
+    counting: FailureCounting
+  ) -> Self {
+    Self {
+      threshold,
+      counting,
+      counts: Mutex::new((0, 0)),
+      tripped: AtomicBool::new(false)
+    }
+  }
+
+  pub fn success(&self) {
+    if let Ok(mut counts) =
+      self.counts.lock()
+    {
+      counts.0 = 0;
+    }
+  }
+
+  pub fn failure(&self) {
+    let Ok(mut counts) =
+      self.counts.lock()
+    else {
+      return;
+    };
+    counts.0 += 1;
+    counts.1 += 1;
+    let seen = match self.counting {
+      | FailureCounting::Consecutive => {
+        counts.0
+      }
+      | FailureCounting::Total => {
+        counts.1
+      }
+    };
+    if self.threshold > 0
+      && seen >= self.threshold
+    {
+      self
+        .tripped
+        .store(true, Ordering::SeqCst);
+    }
+  }
+
+  pub fn tripped(&self) -> bool {
+    self.tripped.load(Ordering::SeqCst)
+  }
+
+  /// Errors once the gate has tripped.
+  pub fn check(
+    &self,
+    stage: &str
+  ) -> anyhow::Result<()> {
+    if !self.tripped() {
+      return Ok(());
+    }
+    let counting = match self.counting {
+      | FailureCounting::Consecutive => {
+        "consecutive"
+      }
+      | FailureCounting::Total => "total"
+    };
+    Err(anyhow!(
+      "too many failures, aborting \
+       {stage}: {} {counting} files \
+       failed",
+      self.threshold
+    ))
+  }
+}
+
+/// External compressor for a report
+/// path: `gzip` for `.gz`, `zstd` for
+/// `.zst`.
+fn report_compressor(
+  path: &Path
+) -> Option<&'static str> {
+  match path
+    .extension()
+    .and_then(|ext| ext.to_str())
+  {
+    | Some("gz") => Some("gzip"),
+    | Some("zst") => Some("zstd"),
+    | _ => None
+  }
+}
+
+/// Writes `contents` to `path`, piped
+/// through `gzip` or `zstd` when the
+/// path ends in `.gz` or `.zst`;
+/// `level` is passed as `-<level>`.
+pub fn write_report_file(
+  path: &Path,
+  contents: &[u8],
+  level: Option<u32>
+) -> anyhow::Result<()> {
+  let Some(bin) =
+    report_compressor(path)
+  else {
+    fs::write(path, contents)?;
+    return Ok(());
+  };
+  let file = File::create(path)?;
+  let mut cmd = Command::new(bin);
+  cmd.arg("-cq");
+  if let Some(level) = level {
+    cmd.arg(format!("-{level}"));
+  }
+  let mut child = cmd
+    .stdin(Stdio::piped())
+    .stdout(file)
+    .stderr(Stdio::piped())
+    .spawn()
+    .with_context(|| {
+      format!("run {bin}")
+    })?;
+  if let Some(mut stdin) =
+    child.stdin.take()
+  {
+    stdin.write_all(contents)?;
+  }
+  let output =
+    child.wait_with_output()?;
+  if !output.status.success() {
+    return Err(anyhow!(
+      "{bin} failed: {} {}",
+      output.status,
+      String::from_utf8_lossy(
+        &output.stderr
+      )
+      .trim()
+    ));
+  }
+  Ok(())
+}
+
+/// Reads a report written by
+/// `write_report_file`, decompressing
+/// `.gz` and `.zst` paths.
+pub fn read_report_file(
+  path: &Path
+) -> anyhow::Result<String> {
+  let Some(bin) =
+    report_compressor(path)
+  else {
+    return Ok(fs::read_to_string(
+      path
+    )?);
+  };
+  let output = Command::new(bin)
+    .arg("-dcq")
+    .arg(path)
+    .output()
+    .with_context(|| {
+      format!("run {bin}")
+    })?;
+  if !output.status.success() {
+    return Err(anyhow!(
+      "{bin} failed: {} {}",
+      output.status,
+      String::from_utf8_lossy(
+        &output.stderr
+      )
+      .trim()
+    ));
+  }
+  Ok(String::from_utf8(output.stdout)?)
+}
+
+/// Like `Command::output`, but kills
+/// the child once `timeout` passes.
+/// stdout is always captured; stderr
+/// only when `cmd` pipes it.
+pub fn output_with_timeout(
+  cmd: &mut Command,
+  timeout: Option<Duration>
+) -> io::Result<Output> {
+  let child = cmd
+    .stdout(Stdio::piped())
+    .spawn()?;
+  wait_child(child, timeout)
+}
+
+/// Like `Command::status`, but kills
+/// the child once `timeout` passes.
+pub fn status_with_timeout(
+  cmd: &mut Command,
+  timeout: Option<Duration>
+) -> io::Result<ExitStatus> {
+  let child = cmd.spawn()?;
+  Ok(wait_child(child, timeout)?.status)
+}
+
+type PipeReader =
+  std::thread::JoinHandle<
+    io::Result<Vec<u8>>
+  >;
+
+/// Reads `pipe` to the end on a thread
+/// so a chatty child cannot block on a
+/// full pipe while we poll.
+fn drain(
+  pipe: Option<
+    impl Read + Send + 'static
+  >
+) -> Option<PipeReader> {
+  pipe.map(|mut pipe| {
+    std::thread::spawn(move || {
+      let mut buf = Vec::new();
+      pipe.read_to_end(&mut buf)?;
+      Ok(buf)
+    })
+  })
+}
+
+fn collect(
+  reader: Option<PipeReader>
+) -> io::Result<Vec<u8>> {
+  match reader {
+    | Some(handle) => {
+      handle.join().map_err(|_| {
+        io::Error::other(
+          "pipe reader panicked"
+        )
+      })?
+    }
+    | None => Ok(Vec::new())
+  }
+}
+
+fn wait_child(
+  mut child: Child,
+  timeout: Option<Duration>
+) -> io::Result<Output> {
+  let stdout =
+    drain(child.stdout.take());
+  let stderr =
+    drain(child.stderr.take());
+  let status = match timeout {
+    | None => child.wait()?,
+    | Some(limit) => {
+      let deadline =
+        Instant::now() + limit;
+      loop {
+        if let Some(status) =
+          child.try_wait()?
+        {
+          break status;
+        }
+        if Instant::now() >= deadline {
+          let _ = child.kill();
+          let _ = child.wait();
+          return Err(io::Error::new(
+            io::ErrorKind::TimedOut,
+            format!(
+              "timed out after {}s",
+              limit.as_secs()
+            )
+          ));
+        }
+        std::thread::sleep(
+          Duration::from_millis(50)
+        );
+      }
+    }
+  };
+  Ok(Output {
+    status,
+    stdout: collect(stdout)?,
+    stderr: collect(stderr)?
+  })
+}
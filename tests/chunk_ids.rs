@@ -0,0 +1,143 @@
+mod common;
+
+use std::fs;
+
+use anyhow::Result;
+use chunkr::chunk::{
+  self,
+  ChunkArgs
+};
+use chunkr::config::{
+  ChunkIdMode,
+  Config
+};
+use serde_json::json;
+
+fn ids(
+  raw: &str,
+  name: &str,
+  config: &Config
+) -> Vec<String> {
+  common::chunk_as(
+    raw,
+    &json!({}),
+    name,
+    config
+  )
+  .into_iter()
+  .map(|r| r.id)
+  .collect()
+}
+
+fn load() -> Result<Config> {
+  let mut config =
+    common::test_config()?;
+  config.chunk.target_chunk_chars = 40;
+  config.chunk.max_chunk_chars = 60;
+  config.chunk.chunk_overlap_chars = 0;
+  Ok(config)
+}
+
+const TEXT: &str =
+  "First paragraph of the \
+   book.\n\nSecond paragraph follows \
+   here.\n\nThird and last paragraph.";
+
+#[test]
+fn uuid_v5_ids_are_stable_across_runs()
+-> Result<()> {
+  let mut config = load()?;
+  config.chunk.id_mode =
+    ChunkIdMode::UuidV5;
+  let first =
+    ids(TEXT, "a/book.txt", &config);
+  assert!(first.len() > 1, "{first:?}");
+  assert_eq!(
+    first,
+    ids(TEXT, "a/book.txt", &config)
+  );
+  // Another source gets its own ids.
+  let other =
+    ids(TEXT, "b/book.txt", &config);
+  assert!(
+    first.iter().all(|id| {
+      !other.contains(id)
+    })
+  );
+  Ok(())
+}
+
+#[test]
+fn uuid_v4_ids_differ_across_runs()
+-> Result<()> {
+  let config = load()?;
+  assert_eq!(
+    config.chunk.id_mode,
+    ChunkIdMode::UuidV4
+  );
+  assert_ne!(
+    ids(TEXT, "a/book.txt", &config),
+    ids(TEXT, "a/book.txt", &config)
+  );
+  Ok(())
+}
+
+#[test]
+fn skip_unchanged_ignores_scheduling_settings()
+-> Result<()> {
+  let mut config = load()?;
+  let root =
+    std::env::temp_dir().join(format!(
+      "chunkr-ids-{}",
+      uuid::Uuid::new_v4()
+    ));
+  config.paths.extract_root =
+    root.join("extract");
+  config.paths.chunk_root =
+    root.join("chunked");
+  config.chunk.skip_unchanged = true;
+  config.chunk.skip_metadata = true;
+  fs::create_dir_all(
+    &config.paths.extract_root
+  )?;
+  fs::write(
+    config
+      .paths
+      .extract_root
+      .join("book.txt"),
+    TEXT
+  )?;
+  let out = config
+    .paths
+    .chunk_root
+    .join("book.jsonl");
+  chunk::run(
+    &config,
+    &ChunkArgs::default()
+  )?;
+  // Random ids: a re-chunk would
+  // rewrite the file with new ones.
+  let first = fs::read_to_string(&out)?;
+  config.chunk.jobs = 3;
+  config.chunk.write_stats =
+    !config.chunk.write_stats;
+  chunk::run(
+    &config,
+    &ChunkArgs::default()
+  )?;
+  assert_eq!(
+    fs::read_to_string(&out)?,
+    first
+  );
+  config.chunk.target_chunk_chars = 20;
+  chunk::run(
+    &config,
+    &ChunkArgs::default()
+  )?;
+  assert_ne!(
+    fs::read_to_string(&out)?,
+    first
+  );
+  fs::remove_dir_all(&root)?;
+  Ok(())
+}
@@ -0,0 +1,65 @@
+//! Fixtures shared by the chunk tests.
+// Each test crate uses its own subset.
+#![allow(dead_code)]
+
+use anyhow::Result;
+use chunkr::chunk::{
+  self,
+  ChunkRecord
+};
+use chunkr::config::{
+  self,
+  Config
+};
+use serde_json::Value;
+
+/// `test.toml` with
+/// `min_paragraph_chars = 0`, so short
+/// test paragraphs are kept.
+pub fn test_config() -> Result<Config> {
+  let mut config = config::load(
+    &concat!(
+      env!("CARGO_MANIFEST_DIR"),
+      "/test.toml"
+    )
+    .into()
+  )?;
+  config.chunk.min_paragraph_chars = 0;
+  Ok(config)
+}
+
+/// Chunks `raw` with sidecar `meta` as
+/// if read from `name` under
+/// `extract_root`.
+pub fn chunk_as(
+  raw: &str,
+  meta: &Value,
+  name: &str,
+  config: &Config
+) -> Vec<ChunkRecord> {
+  let path = config
+    .paths
+    .extract_root
+    .join(name);
+  chunk::chunk_text(
+    raw, meta, &path, config
+  )
+}
+
+/// The text of each chunk of `raw`,
+/// read as `sample.txt`.
+pub fn texts(
+  raw: &str,
+  meta: &Value,
+  config: &Config
+) -> Vec<String> {
+  chunk_as(
+    raw,
+    meta,
+    "sample.txt",
+    config
+  )
+  .into_iter()
+  .map(|r| r.text)
+  .collect()
+}
@@ -0,0 +1,114 @@
+mod common;
+
+use anyhow::Result;
+use chunkr::config::Config;
+use serde_json::json;
+
+/// `test.toml` with one paragraph per
+/// chunk.
+fn config() -> Result<Config> {
+  let mut config =
+    common::test_config()?;
+  config.chunk.collapse_whitespace =
+    false;
+  config.chunk.target_chunk_chars = 1;
+  config.chunk.chunk_overlap_chars = 0;
+  Ok(config)
+}
+
+fn languages(
+  raw: &str,
+  doc_language: &str
+) -> Result<Vec<String>> {
+  let mut config = config()?;
+  config.chunk.detect_chunk_language =
+    true;
+  Ok(
+    common::chunk_as(
+      raw,
+      &json!({ "language": doc_language }),
+      "book.txt",
+      &config
+    )
+    .into_iter()
+    .map(|r| {
+      r.metadata["language"]
+        .as_str()
+        .unwrap_or_default()
+        .to_string()
+    })
+    .collect()
+  )
+}
+
+#[test]
+fn confident_chunks_override_document_language()
+-> Result<()> {
+  let raw = [
+    "The storm was over and the town \
+     woke to find that the river had \
+     risen in the night.",
+    "Le village est calme et les \
+     enfants sont dans la rue avec \
+     leurs amis pour jouer.",
+    "他说今天天气很好。"
+  ]
+  .join("\n\n");
+  assert_eq!(
+    languages(&raw, "eng")?,
+    ["eng", "fra", "zho"]
+  );
+  assert_eq!(languages(&raw, "en")?, [
+    "en", "fr", "zh"
+  ]);
+  Ok(())
+}
+
+#[test]
+fn unsure_chunks_keep_document_language()
+-> Result<()> {
+  assert_eq!(
+    languages("Paris, 1924.", "eng")?,
+    ["eng"]
+  );
+  Ok(())
+}
+
+#[test]
+fn detected_language_is_omitted_below_threshold()
+-> Result<()> {
+  let mut config = config()?;
+  config.chunk.detect_language = true;
+  let raw = [
+    "Der Hund ist nicht mit den \
+     Kindern auf der Straße, und sie \
+     wird ihn suchen.",
+    "Paris, 1924."
+  ]
+  .join("\n\n");
+  let chunks = common::chunk_as(
+    &raw,
+    &json!({ "language": "eng" }),
+    "book.txt",
+    &config
+  );
+  assert_eq!(chunks.len(), 2);
+  let meta = &chunks[0].metadata;
+  assert_eq!(
+    meta["detected_language"],
+    "de"
+  );
+  assert_eq!(
+    meta
+      ["detected_language_confidence"],
+    1.0
+  );
+  assert_eq!(meta["language"], "eng");
+  assert!(
+    chunks[1]
+      .metadata
+      .get("detected_language")
+      .is_none()
+  );
+  Ok(())
+}
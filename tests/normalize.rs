@@ -0,0 +1,217 @@
+mod common;
+
+use std::fs;
+
+use anyhow::Result;
+use chunkr::config::{
+  self,
+  Config,
+  NormalizeOrder,
+  UnicodeForm
+};
+use serde_json::json;
+
+const SAMPLE: &str =
+  "The ﬁrst ½ cup of café au lait.";
+
+fn normalized(
+  form: UnicodeForm
+) -> Result<String> {
+  let mut config =
+    common::test_config()?;
+  config.chunk.unicode_form = form;
+  let texts = common::texts(
+    SAMPLE,
+    &json!({}),
+    &config
+  );
+  assert_eq!(texts.len(), 1);
+  Ok(texts[0].clone())
+}
+
+#[test]
+fn nfc_keeps_ligatures_and_fractions()
+-> Result<()> {
+  let text =
+    normalized(UnicodeForm::Nfc)?;
+  assert_eq!(
+    text,
+    "The ﬁrst ½ cup of café au lait."
+  );
+  Ok(())
+}
+
+#[test]
+fn nfkc_folds_ligatures_and_fractions()
+-> Result<()> {
+  let text =
+    normalized(UnicodeForm::Nfkc)?;
+  assert_eq!(
+    text,
+    "The first 1⁄2 cup of café au \
+     lait."
+  );
+  Ok(())
+}
+
+#[test]
+fn nfkd_folds_and_decomposes_accents()
+-> Result<()> {
+  let text =
+    normalized(UnicodeForm::Nfkd)?;
+  assert_eq!(
+    text,
+    "The first 1⁄2 cup of cafe\u{301} \
+     au lait."
+  );
+  Ok(())
+}
+
+#[test]
+fn none_keeps_decomposed_input()
+-> Result<()> {
+  let mut config =
+    common::test_config()?;
+  let decomposed = "cafe\u{301}";
+  for (form, expected) in [
+    (UnicodeForm::None, decomposed),
+    (UnicodeForm::Nfc, "café")
+  ] {
+    config.chunk.unicode_form = form;
+    let texts = common::texts(
+      decomposed,
+      &json!({}),
+      &config
+    );
+    assert_eq!(
+      texts[0], expected,
+      "{form:?}"
+    );
+  }
+  Ok(())
+}
+
+#[test]
+fn normalize_order_decides_which_spaces_collapse()
+-> Result<()> {
+  let mut config =
+    common::test_config()?;
+  config.chunk.collapse_whitespace =
+    true;
+  // NFKC turns the diaeresis into a
+  // space and a combining mark.
+  let raw = "a \u{a8}b";
+  for (order, expected) in [
+    (
+      NormalizeOrder::UnicodeFirst,
+      "a \u{308}b"
+    ),
+    (
+      NormalizeOrder::CollapseFirst,
+      "a  \u{308}b"
+    )
+  ] {
+    config.chunk.normalize_order =
+      order;
+    let texts = common::texts(
+      raw,
+      &json!({}),
+      &config
+    );
+    assert_eq!(
+      texts[0], expected,
+      "{order:?}"
+    );
+  }
+  Ok(())
+}
+
+#[test]
+fn legacy_normalize_unicode_maps_to_form()
+-> Result<()> {
+  let raw =
+    fs::read_to_string(concat!(
+      env!("CARGO_MANIFEST_DIR"),
+      "/test.toml"
+    ))?;
+  let dir =
+    std::env::temp_dir().join(format!(
+      "chunkr-legacy-{}",
+      uuid::Uuid::new_v4()
+    ));
+  fs::create_dir_all(&dir)?;
+  let path = dir.join("config.toml");
+  let legacy = raw
+    .lines()
+    .map(|line| {
+      if line
+        .starts_with("unicode_form")
+      {
+        "normalize_unicode = false"
+      } else {
+        line
+      }
+    })
+    .collect::<Vec<_>>()
+    .join("\n");
+  fs::write(&path, legacy)?;
+  let config = config::load(&path)?;
+  assert_eq!(
+    config.chunk.unicode_form,
+    UnicodeForm::None
+  );
+  assert_eq!(
+    config.deprecations.len(),
+    1
+  );
+  fs::remove_dir_all(&dir)?;
+  Ok(())
+}
+
+#[test]
+fn strip_boilerplate_drops_gutenberg_header_and_footer()
+-> Result<()> {
+  let mut config =
+    common::test_config()?;
+  config.chunk.strip_boilerplate = true;
+  let raw = [
+    "The Project Gutenberg eBook of \
+     Frankenstein",
+    "This eBook is for the use of \
+     anyone anywhere at no cost.",
+    "*** START OF THE PROJECT \
+     GUTENBERG EBOOK FRANKENSTEIN ***",
+    "You will rejoice to hear that no \
+     disaster has accompanied the \
+     commencement.",
+    "*** END OF THE PROJECT GUTENBERG \
+     EBOOK FRANKENSTEIN ***",
+    "Updated editions will replace \
+     the previous one."
+  ]
+  .join("\n\n");
+  let text = |config: &Config| {
+    common::chunk_as(
+      &raw,
+      &json!({}),
+      "pg84.txt",
+      config
+    )
+    .into_iter()
+    .map(|c| c.text)
+    .collect::<Vec<_>>()
+    .join(" ")
+  };
+  assert_eq!(
+    text(&config),
+    "You will rejoice to hear that no \
+     disaster has accompanied the \
+     commencement."
+  );
+  config.chunk.strip_boilerplate =
+    false;
+  assert!(text(&config).starts_with(
+    "The Project Gutenberg"
+  ));
+  Ok(())
+}
@@ -0,0 +1,202 @@
+use std::fs;
+
+use anyhow::Result;
+use arrow_array::cast::AsArray;
+use arrow_array::types::{
+  Float64Type,
+  Int64Type
+};
+use arrow_array::{
+  Array,
+  RecordBatch
+};
+use chunkr::chunk::{
+  self,
+  ChunkRecord
+};
+use chunkr::config::ChunkFieldNames;
+use chunkr::parquet::{
+  self as chunkr_parquet,
+  Column,
+  Values
+};
+use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use serde_json::json;
+use uuid::Uuid;
+
+fn read_back(
+  path: &std::path::Path
+) -> Result<RecordBatch> {
+  let reader =
+    ParquetRecordBatchReaderBuilder::try_new(
+      fs::File::open(path)?
+    )?
+    .build()?;
+  let batches = reader
+    .collect::<Result<Vec<_>, _>>()?;
+  assert_eq!(batches.len(), 1);
+  Ok(
+    batches.into_iter().next().unwrap()
+  )
+}
+
+#[test]
+fn chunks_parquet_reads_back()
+-> Result<()> {
+  let dir =
+    std::env::temp_dir().join(format!(
+      "chunkr-parquet-{}",
+      Uuid::new_v4()
+    ));
+  let path = dir.join("book.parquet");
+  let names =
+    ChunkFieldNames::default();
+  let records = vec![
+    ChunkRecord {
+      id:       "a".to_string(),
+      text:     "Première partie."
+        .to_string(),
+      metadata: json!({ "page": 1 })
+    },
+    ChunkRecord {
+      id:       "b".to_string(),
+      text:     "第二部分。"
+        .to_string(),
+      metadata: json!({ "page": 2 })
+    },
+  ];
+  chunk::write_chunks_parquet(
+    &path, &records, &names
+  )?;
+
+  let batch = read_back(&path)?;
+  assert_eq!(batch.num_rows(), 2);
+  let column = |name: &str| {
+    batch
+      .column_by_name(name)
+      .expect("column present")
+      .as_string::<i32>()
+      .clone()
+  };
+  let ids = column(&names.id);
+  let texts = column(&names.text);
+  let metadata =
+    column(&names.metadata);
+  for (row, record) in
+    records.iter().enumerate()
+  {
+    assert_eq!(
+      ids.value(row),
+      record.id
+    );
+    assert_eq!(
+      texts.value(row),
+      record.text
+    );
+    assert_eq!(
+      serde_json::from_str::<
+        serde_json::Value
+      >(metadata.value(row))?,
+      record.metadata
+    );
+  }
+  let read =
+    chunk::read_chunks_parquet(
+      &path, &names
+    )?;
+  assert_eq!(read.len(), records.len());
+  for (got, want) in
+    read.iter().zip(&records)
+  {
+    assert_eq!(got.id, want.id);
+    assert_eq!(got.text, want.text);
+    assert_eq!(
+      got.metadata,
+      want.metadata
+    );
+  }
+  fs::remove_dir_all(&dir)?;
+  Ok(())
+}
+
+#[test]
+fn typed_columns_read_back()
+-> Result<()> {
+  let dir =
+    std::env::temp_dir().join(format!(
+      "chunkr-parquet-{}",
+      Uuid::new_v4()
+    ));
+  let path = dir.join("typed.parquet");
+  chunkr_parquet::write_file(
+    &path,
+    &[
+      Column {
+        name:     "n".to_string(),
+        required: false,
+        values:   Values::Int64(vec![
+          Some(7),
+          None,
+        ])
+      },
+      Column {
+        name:     "score".to_string(),
+        required: true,
+        values:   Values::Double(vec![
+          Some(0.5),
+          Some(1.25),
+        ])
+      },
+      Column {
+        name:     "flag".to_string(),
+        required: false,
+        values:   Values::Boolean(
+          vec![None, Some(true)]
+        )
+      },
+      Column {
+        name:     "tags".to_string(),
+        required: false,
+        values:   Values::Utf8List(
+          vec![
+            Some(vec![
+              "x".to_string(),
+              "y".to_string(),
+            ]),
+            None,
+          ]
+        )
+      }
+    ]
+  )?;
+
+  let batch = read_back(&path)?;
+  let n = batch
+    .column_by_name("n")
+    .unwrap()
+    .as_primitive::<Int64Type>();
+  assert_eq!(n.value(0), 7);
+  assert!(n.is_null(1));
+  let score = batch
+    .column_by_name("score")
+    .unwrap()
+    .as_primitive::<Float64Type>();
+  assert_eq!(score.value(1), 1.25);
+  let flag = batch
+    .column_by_name("flag")
+    .unwrap()
+    .as_boolean();
+  assert!(flag.is_null(0));
+  assert!(flag.value(1));
+  let tags = batch
+    .column_by_name("tags")
+    .unwrap()
+    .as_list::<i32>();
+  let first = tags.value(0);
+  let first = first.as_string::<i32>();
+  assert_eq!(first.value(0), "x");
+  assert_eq!(first.value(1), "y");
+  assert!(tags.is_null(1));
+  fs::remove_dir_all(&dir)?;
+  Ok(())
+}
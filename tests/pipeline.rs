@@ -94,6 +94,11 @@ async fn chunk_and_insert_pipeline()
     chunk_root.clone();
   config.paths.state_dir =
     state_dir.clone();
+  // Each ingest commits before it
+  // returns, so the search below never
+  // races the commit timeout.
+  config.insert.quickwit.force_commit =
+    true;
 
   let embed_dim = detect_embedding_dim(
     &client,
@@ -184,6 +189,13 @@ async fn chunk_and_insert_pipeline()
     &sample_query.embed_text
   )
   .await?;
+  insert::quickwit_wait_searchable(
+    &client,
+    &config.insert.quickwit,
+    count_chunks(&chunk_root)?,
+    Duration::from_secs(60)
+  )
+  .await?;
   verify_quickwit(
     &client,
     &config.insert.quickwit.url,
@@ -210,10 +222,17 @@ async fn run_in_process(
 ) -> Result<()> {
   match command {
     | CommandKind::Chunk => {
-      chunk::run(config)?;
+      chunk::run(
+        config,
+        &chunk::ChunkArgs::default()
+      )?;
     }
     | CommandKind::Insert => {
-      insert::run(config).await?;
+      insert::run(
+        config,
+        &insert::InsertArgs::default()
+      )
+      .await?;
     }
   }
   Ok(())
@@ -638,6 +657,34 @@ struct SampleQuery {
   term:       String
 }
 
+fn count_chunks(
+  chunk_root: &Path
+) -> Result<u64> {
+  let mut total = 0u64;
+  for entry in
+    walkdir::WalkDir::new(chunk_root)
+      .into_iter()
+      .filter_map(|e| e.ok())
+      .filter(|e| {
+        e.file_type().is_file()
+      })
+  {
+    let path = entry.path();
+    if path
+      .extension()
+      .and_then(|s| s.to_str())
+      != Some("jsonl")
+    {
+      continue;
+    }
+    total += fs::read_to_string(path)?
+      .lines()
+      .filter(|l| !l.trim().is_empty())
+      .count() as u64;
+  }
+  Ok(total)
+}
+
 fn sample_query_from_chunks(
   chunk_root: &Path
 ) -> Result<SampleQuery> {
@@ -0,0 +1,765 @@
+mod common;
+
+use anyhow::Result;
+use chunkr::config::{
+  ChunkStrategy,
+  Config,
+  OverlapUnit,
+  ParagraphMode,
+  SizeUnit,
+  SplitterMode,
+  UnicodeForm
+};
+use chunkr::tokenizer::{
+  ApproxBpe,
+  Tokenizer
+};
+use common::texts;
+use serde_json::json;
+
+fn small_config() -> Result<Config> {
+  let mut config =
+    common::test_config()?;
+  let chunk = &mut config.chunk;
+  // NFKC would fold fullwidth `！？`
+  // to ASCII and spoil the round trip.
+  chunk.unicode_form =
+    UnicodeForm::None;
+  chunk.collapse_whitespace = true;
+  chunk.strip_headers = false;
+  chunk.max_paragraph_chars = 90;
+  chunk.target_chunk_chars = 60;
+  chunk.max_chunk_chars = 90;
+  chunk.chunk_overlap_chars = 0;
+  chunk.splitter = SplitterMode::Auto;
+  Ok(config)
+}
+
+const CHINESE: &str = "今天天气很好，我们去公园散步。\
+                       公园里有很多人在跑步。\
+                       孩子们在草地上玩耍！\
+                       你想一起去吗？\
+                       晚上我们在家里吃饭。\
+                       饭后大家一起看电视。";
+
+#[test]
+fn chinese_splits_on_sentence_punctuation()
+-> Result<()> {
+  let config = small_config()?;
+  let chunks =
+    texts(CHINESE, &json!({}), &config);
+  assert!(
+    chunks.len() > 1,
+    "{chunks:?}"
+  );
+  for chunk in &chunks {
+    assert!(
+      chunk.len() <= 90,
+      "{chunk}"
+    );
+    assert!(
+      chunk
+        .ends_with(['。', '！', '？']),
+      "chunk cut mid-sentence: {chunk}"
+    );
+    assert!(!chunk.contains(' '));
+  }
+  assert_eq!(chunks.concat(), CHINESE);
+  Ok(())
+}
+
+#[test]
+fn char_offsets_count_characters()
+-> Result<()> {
+  let config = small_config()?;
+  let records = common::chunk_as(
+    CHINESE,
+    &json!({}),
+    "sample.txt",
+    &config
+  );
+  assert!(records.len() > 1);
+  let chars =
+    CHINESE.chars().collect::<Vec<_>>();
+  for record in &records {
+    let offset = |key: &str| {
+      record.metadata[key]
+        .as_u64()
+        .expect(key) as usize
+    };
+    let slice =
+      chars[offset("char_start")
+        ..offset("char_end")]
+        .iter()
+        .collect::<String>();
+    assert_eq!(slice, record.text);
+  }
+  Ok(())
+}
+
+#[test]
+fn char_offsets_map_back_across_overlap_and_paragraphs()
+-> Result<()> {
+  let mut config = small_config()?;
+  config.chunk.chunk_overlap_chars = 20;
+  config.chunk.max_chunk_chars = 200;
+  let paragraphs = [
+    "L’été dernier, nous sommes allés \
+     à la mer. Le soleil était très \
+     chaud.",
+    "Les enfants ont joué sur la \
+     plage pendant des heures. Ils \
+     étaient épuisés.",
+    "Le soir, on a mangé des crêpes \
+     près du port. C’était délicieux."
+  ];
+  let raw = paragraphs.join("\n\n");
+  // Paragraphs joined by
+  // `join_separator`, which is also
+  // the collapsed text.
+  let source = paragraphs
+    .join(&config.chunk.join_separator)
+    .chars()
+    .collect::<Vec<_>>();
+  for collapse in [true, false] {
+    config.chunk.collapse_whitespace =
+      collapse;
+    let records = common::chunk_as(
+      &raw,
+      &json!({}),
+      "sample.txt",
+      &config
+    );
+    assert!(records.len() > 2);
+    // The overlap repeats source text.
+    assert!(
+      records[1].metadata["char_start"]
+        .as_u64()
+        < records[0].metadata
+          ["char_end"]
+          .as_u64()
+    );
+    for record in &records {
+      let offset = |key: &str| {
+        record.metadata[key]
+          .as_u64()
+          .expect(key) as usize
+      };
+      let slice =
+        source[offset("char_start")
+          ..offset("char_end")]
+          .iter()
+          .collect::<String>();
+      assert_eq!(slice, record.text);
+    }
+  }
+  Ok(())
+}
+
+#[test]
+fn japanese_keeps_closing_quotes_and_breaks_long_runs()
+-> Result<()> {
+  let config = small_config()?;
+  let long = "あ".repeat(40);
+  let raw = format!(
+    "彼は「こんにちは。」と言った。\
+     東京は日本の首都です！{long}"
+  );
+  let chunks =
+    texts(&raw, &json!({}), &config);
+  assert!(
+    chunks.len() > 1,
+    "{chunks:?}"
+  );
+  for chunk in &chunks {
+    assert!(
+      chunk.len() <= 90,
+      "{chunk}"
+    );
+    assert!(
+      !chunk.starts_with('」'),
+      "quote split from its sentence: \
+       {chunk}"
+    );
+  }
+  assert_eq!(chunks.concat(), raw);
+  Ok(())
+}
+
+#[test]
+fn sidecar_language_forces_cjk_splitting()
+-> Result<()> {
+  let config = small_config()?;
+  // Mostly Latin letters, so detection
+  // alone would not pick CJK.
+  let raw = "Tokyo Station。".repeat(8);
+  let spaced =
+    texts(&raw, &json!({}), &config);
+  let dense = texts(
+    &raw,
+    &json!({ "language": "ja" }),
+    &config
+  );
+  assert_ne!(spaced, dense);
+  for chunk in &dense {
+    assert!(chunk.ends_with('。'));
+  }
+  assert_eq!(dense.concat(), raw);
+  Ok(())
+}
+
+#[test]
+fn latin_text_still_splits_on_whitespace()
+-> Result<()> {
+  let config = small_config()?;
+  let raw = "The quick brown fox \
+             jumps. Over the lazy dog \
+             again. Then it runs far \
+             away. And never comes \
+             back home.";
+  let chunks =
+    texts(raw, &json!({}), &config);
+  assert!(chunks.len() > 1);
+  for chunk in &chunks {
+    assert!(chunk.len() <= 90);
+    assert!(chunk.ends_with('.'));
+  }
+  Ok(())
+}
+
+fn sentences(count: usize) -> String {
+  (1..=count)
+    .map(|i| format!("Line {i} ends."))
+    .collect::<Vec<_>>()
+    .join(" ")
+}
+
+#[test]
+fn sentence_overlap_repeats_last_k_sentences()
+-> Result<()> {
+  let mut config = small_config()?;
+  config.chunk.overlap_unit =
+    OverlapUnit::Sentences;
+  config
+    .chunk
+    .chunk_overlap_sentences = 2;
+  // Room for the overlap on top of each
+  // paragraph piece.
+  config.chunk.max_paragraph_chars = 45;
+  config.chunk.max_chunk_chars = 120;
+  let chunks = texts(
+    &sentences(20),
+    &json!({}),
+    &config
+  );
+  assert!(
+    chunks.len() > 2,
+    "{chunks:?}"
+  );
+  for pair in chunks.windows(2) {
+    let prev: Vec<&str> = pair[0]
+      .split_inclusive(". ")
+      .map(str::trim)
+      .collect();
+    let tail =
+      prev[prev.len() - 2..].join(" ");
+    assert!(
+      pair[1].starts_with(&format!(
+        "{tail} "
+      )),
+      "{:?} does not start with \
+       {tail:?}",
+      pair[1]
+    );
+  }
+  Ok(())
+}
+
+#[test]
+fn sentence_overlap_never_exceeds_max_chunk_chars()
+-> Result<()> {
+  let mut config = small_config()?;
+  config.chunk.overlap_unit =
+    OverlapUnit::Sentences;
+  config
+    .chunk
+    .chunk_overlap_sentences = 3;
+  let long = format!(
+    "{} ends.",
+    "word ".repeat(15).trim_end()
+  );
+  let raw = format!(
+    "{long} {long} {long} {}",
+    sentences(6)
+  );
+  for chunk in
+    texts(&raw, &json!({}), &config)
+  {
+    assert!(
+      chunk.len() <= 90,
+      "{chunk}"
+    );
+  }
+  Ok(())
+}
+
+#[test]
+fn sentence_overlap_without_count_fits_char_budget()
+-> Result<()> {
+  let mut config = small_config()?;
+  config.chunk.overlap_unit =
+    OverlapUnit::Sentences;
+  config.chunk.chunk_overlap_chars = 30;
+  config.chunk.max_paragraph_chars = 45;
+  config.chunk.max_chunk_chars = 120;
+  let chunks = texts(
+    &sentences(20),
+    &json!({}),
+    &config
+  );
+  assert!(
+    chunks.len() > 2,
+    "{chunks:?}"
+  );
+  for pair in chunks.windows(2) {
+    // Two `Line N ends.` sentences fit
+    // in 30 characters, three do not.
+    let prev: Vec<&str> = pair[0]
+      .split_inclusive(". ")
+      .map(str::trim)
+      .collect();
+    let tail =
+      prev[prev.len() - 2..].join(" ");
+    assert!(
+      pair[1].starts_with(&format!(
+        "{tail} "
+      )),
+      "{:?} does not start with \
+       {tail:?}",
+      pair[1]
+    );
+  }
+  Ok(())
+}
+
+#[test]
+fn char_overlap_keeps_every_paragraph()
+-> Result<()> {
+  let mut config = small_config()?;
+  config.chunk.chunk_overlap_chars = 10;
+  let raw = ["a", "b", "c"]
+    .map(|ch| ch.repeat(50))
+    .join("\n\n");
+  let chunks =
+    texts(&raw, &json!({}), &config);
+  for ch in ["a", "b", "c"] {
+    assert!(
+      chunks.iter().any(|c| {
+        c.contains(&ch.repeat(50))
+      }),
+      "{ch} paragraph lost: {chunks:?}"
+    );
+  }
+  assert!(
+    chunks[1]
+      .starts_with(&"a".repeat(10)),
+    "{chunks:?}"
+  );
+  Ok(())
+}
+
+#[test]
+fn token_size_unit_bounds_chunks_by_estimate()
+-> Result<()> {
+  let mut config = small_config()?;
+  config.chunk.size_unit =
+    SizeUnit::Tokens;
+  config.chunk.max_paragraph_chars =
+    10_000;
+  config.chunk.target_chunk_chars = 20;
+  config.chunk.max_chunk_chars = 30;
+  let chunks = texts(
+    &sentences(40),
+    &json!({}),
+    &config
+  );
+  assert!(
+    chunks.len() > 1,
+    "{chunks:?}"
+  );
+  for chunk in &chunks {
+    assert!(
+      ApproxBpe.count(chunk) <= 30,
+      "{chunk}"
+    );
+  }
+  // Thirty tokens of this text run
+  // well past thirty bytes.
+  assert!(
+    chunks.iter().any(|c| c.len() > 60),
+    "{chunks:?}"
+  );
+  Ok(())
+}
+
+#[test]
+fn heading_context_prefixes_section_path()
+-> Result<()> {
+  let mut config = small_config()?;
+  config.chunk.heading_context = true;
+  config.chunk.collapse_whitespace =
+    false;
+  let raw = [
+    "# Chapter 3",
+    "Intro.",
+    "## Methods\nWe measured.",
+    "# Chapter 4",
+    "More."
+  ]
+  .join("\n\n");
+  let records = common::chunk_as(
+    &raw,
+    &json!({}),
+    "sample.txt",
+    &config
+  );
+  let texts: Vec<&str> = records
+    .iter()
+    .map(|r| r.text.as_str())
+    .collect();
+  assert_eq!(texts, [
+    "Chapter 3\n\nIntro.",
+    "Chapter 3 > Methods\n\nWe \
+     measured.",
+    "Chapter 4\n\nMore."
+  ]);
+  assert_eq!(
+    records[1].metadata["section_path"],
+    json!(["Chapter 3", "Methods"])
+  );
+  // Counts leave out the prefix.
+  assert_eq!(
+    records[1].metadata["char_count"],
+    12
+  );
+  assert_eq!(
+    records[1].metadata["word_count"],
+    2
+  );
+  Ok(())
+}
+
+#[test]
+fn min_chunk_chars_merges_short_tail_without_overlap()
+-> Result<()> {
+  let mut config = small_config()?;
+  config.chunk.chunk_overlap_chars = 10;
+  config.chunk.collapse_whitespace =
+    false;
+  let body = "word ".repeat(13);
+  let body = body.trim_end();
+  let raw = format!("{body}\n\nTail.");
+  assert_eq!(
+    texts(&raw, &json!({}), &config)
+      .len(),
+    2
+  );
+  config.chunk.min_chunk_chars = 20;
+  assert_eq!(
+    texts(&raw, &json!({}), &config),
+    [format!("{body} Tail.")]
+  );
+  // A lone short chunk stays.
+  assert_eq!(
+    texts("Tail.", &json!({}), &config),
+    ["Tail."]
+  );
+  Ok(())
+}
+
+#[test]
+fn paragraph_mode_picks_boundaries()
+-> Result<()> {
+  let mut config = small_config()?;
+  config.chunk.collapse_whitespace =
+    false;
+  config.chunk.target_chunk_chars = 1;
+  let raw =
+    "One line.\nTwo line.\n \nThree.";
+  for (mode, expected) in [
+    (ParagraphMode::BlankLine, vec![
+      "One line. Two line.",
+      "Three.",
+    ]),
+    (ParagraphMode::SingleLine, vec![
+      "One line.",
+      "Two line.",
+      "Three.",
+    ]),
+    (
+      ParagraphMode::DoubleNewline,
+      vec![
+        "One line. Two line. Three.",
+      ]
+    )
+  ] {
+    config.chunk.paragraph_mode = mode;
+    assert_eq!(
+      texts(raw, &json!({}), &config),
+      expected,
+      "{mode:?}"
+    );
+  }
+  Ok(())
+}
+
+#[test]
+fn preserve_blocks_keeps_code_and_tables_whole()
+-> Result<()> {
+  let mut config = small_config()?;
+  config.chunk.preserve_blocks = true;
+  config.chunk.max_chunk_chars = 40;
+  let code =
+    "```rust\nfn main() {\n    \
+     println!(\"hi\");\n}\n```";
+  let table =
+    "| a | b |\n|---|---|\n| 1 | 2 |";
+  let raw = [
+    "Intro   text.",
+    code,
+    table,
+    "Outro."
+  ]
+  .join("\n\n");
+  let chunks = common::chunk_as(
+    &raw,
+    &json!({}),
+    "sample.md",
+    &config
+  );
+  let got = chunks
+    .iter()
+    .map(|c| {
+      (
+        c.metadata["block_type"]
+          .as_str()
+          .unwrap_or_default(),
+        c.text.as_str()
+      )
+    })
+    .collect::<Vec<_>>();
+  assert_eq!(got, [
+    ("prose", "Intro text."),
+    ("code", code),
+    ("table", table),
+    ("prose", "Outro.")
+  ]);
+  Ok(())
+}
+
+#[test]
+fn page_markers_become_page_range()
+-> Result<()> {
+  let mut config = small_config()?;
+  let raw = [
+    "<!-- page: 1 -->",
+    "First page text.",
+    "<!-- page: 2 -->",
+    "Second page text.",
+    "<!-- page: 3 -->",
+    &"Third page runs long. ".repeat(4)
+  ]
+  .join("\n\n");
+  let pages = |config: &Config| {
+    common::chunk_as(
+      &raw,
+      &json!({}),
+      "book.txt",
+      config
+    )
+    .into_iter()
+    .map(|c| {
+      assert!(
+        !c.text.contains("<!--"),
+        "{}",
+        c.text
+      );
+      (
+        c.metadata["page_start"]
+          .clone(),
+        c.metadata["page_end"].clone()
+      )
+    })
+    .collect::<Vec<_>>()
+  };
+  // Off by default: markers stay text.
+  let plain = common::chunk_as(
+    &raw,
+    &json!({}),
+    "book.txt",
+    &config
+  );
+  assert!(
+    plain[0].text.contains("<!--")
+  );
+  assert!(
+    plain[0]
+      .metadata
+      .get("page_start")
+      .is_none()
+  );
+  config.chunk.metadata.include_pages =
+    true;
+  assert_eq!(pages(&config), [
+    (json!(1), json!(2)),
+    (json!(3), json!(3))
+  ]);
+  let feeds = "One.\x0cTwo.\x0cThree.";
+  config
+    .chunk
+    .metadata
+    .include_paragraph_range = true;
+  // Form feeds collapse unless
+  // `split_on_formfeed` is set.
+  let collapsed = common::chunk_as(
+    feeds,
+    &json!({}),
+    "book.txt",
+    &config
+  );
+  assert_eq!(
+    collapsed[0].metadata["para_end"],
+    0
+  );
+  assert!(
+    collapsed[0]
+      .metadata
+      .get("page_start")
+      .is_none()
+  );
+  config.chunk.split_on_formfeed = true;
+  let chunks = common::chunk_as(
+    feeds,
+    &json!({}),
+    "book.txt",
+    &config
+  );
+  assert_eq!(chunks.len(), 1);
+  assert_eq!(
+    chunks[0].metadata["para_end"],
+    2
+  );
+  assert_eq!(
+    chunks[0].metadata["page_start"],
+    1
+  );
+  assert_eq!(
+    chunks[0].metadata["page_end"],
+    3
+  );
+  Ok(())
+}
+
+#[test]
+fn sliding_window_advances_by_fixed_stride()
+-> Result<()> {
+  let mut config = small_config()?;
+  config.chunk.strategy =
+    ChunkStrategy::SlidingWindow;
+  config.chunk.target_chunk_chars = 20;
+  config.chunk.chunk_overlap_chars = 5;
+  let raw = [
+    "abcdefghij".repeat(3),
+    "klmnopqrst".repeat(2)
+  ]
+  .join("\n\n");
+  let chunks =
+    texts(&raw, &json!({}), &config);
+  // 51 characters with the separator:
+  // windows at 0, 15, 30 and 45, the
+  // third trimmed of its leading
+  // space.
+  assert_eq!(chunks, [
+    "abcdefghijabcdefghij",
+    "fghijabcdefghij klmn",
+    "klmnopqrstklmnopqrs",
+    "opqrst"
+  ]);
+  Ok(())
+}
+
+#[test]
+fn sliding_window_tracks_headings_per_window()
+-> Result<()> {
+  let mut config = small_config()?;
+  config.chunk.strategy =
+    ChunkStrategy::SlidingWindow;
+  config.chunk.heading_context = true;
+  config.chunk.collapse_whitespace =
+    false;
+  config.chunk.target_chunk_chars = 20;
+  config.chunk.chunk_overlap_chars = 0;
+  let raw = [
+    "# One",
+    &"a".repeat(18),
+    "# Two",
+    &"b".repeat(18)
+  ]
+  .join("\n\n");
+  let records = common::chunk_as(
+    &raw,
+    &json!({}),
+    "sample.txt",
+    &config
+  );
+  let paths: Vec<_> = records
+    .iter()
+    .map(|r| {
+      r.metadata["section_path"].clone()
+    })
+    .collect();
+  assert_eq!(paths, [
+    json!(["One"]),
+    json!(["Two"])
+  ]);
+  assert!(
+    records
+      .iter()
+      .all(|r| !r.text.contains('#')),
+    "{records:?}"
+  );
+  Ok(())
+}
+
+#[test]
+fn include_keys_limits_other_sidecar_keys()
+-> Result<()> {
+  let mut config =
+    common::test_config()?;
+  config.chunk.metadata.include_title =
+    false;
+  config.chunk.metadata.include_keys =
+    Some(vec![
+      "series".to_string(),
+      "title".to_string(),
+    ]);
+  let chunks = common::chunk_as(
+    "Some text.",
+    &json!({
+      "title": "Dune",
+      "language": "eng",
+      "series": "Dune",
+      "calibre_path": "/internal"
+    }),
+    "book.txt",
+    &config
+  );
+  let meta = &chunks[0].metadata;
+  assert_eq!(meta["series"], "Dune");
+  assert_eq!(meta["language"], "eng");
+  assert!(meta.get("title").is_none());
+  assert!(
+    meta.get("calibre_path").is_none()
+  );
+  Ok(())
+}